@@ -0,0 +1,111 @@
+// ================= 校验和 =================
+// 改了包头 (分片重写 IP 头、MSS 钳制改 TCP 选项、ECN 打标 / DSCP 重标) 之后必须把校验和补上，
+// 不然下一跳直接把包扔了；TOS 不在 TCP/UDP 伪首部里，改 TOS 时四层校验和不用动
+
+// 反码求和，折叠到 16 位 (RFC 1071)
+fn ones_complement_sum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+// 整个头部重算一遍：校验和字段 (第 10-11 字节) 先清零再求和
+// 不是完整 IPv4 头的输入原样不动
+pub fn recompute_ipv4_header_checksum(packet: &mut [u8]) {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return;
+    }
+    let ihl = (packet[0] & 0x0F) as usize * 4;
+    if ihl < 20 || packet.len() < ihl {
+        return;
+    }
+
+    packet[10] = 0;
+    packet[11] = 0;
+    let checksum = !ones_complement_sum(&packet[..ihl]);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+}
+
+// 只改了一个 16 位字时的增量更新 (RFC 1624 式 3)：HC' = ~(~HC + ~m + m')
+// 比整头重算省事，而且不会像 RFC 1141 的老公式那样算出 0x0000 / 0xFFFF 的歧义
+pub fn incremental_update(checksum: u16, old_word: u16, new_word: u16) -> u16 {
+    let mut sum = (!checksum) as u32 + (!old_word) as u32 + new_word as u32;
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// 改写 TOS 字节 (DSCP 高 6 位 + ECN 低 2 位) 并就地增量修好头部校验和
+// TOS 和版本/IHL 同在第 0-1 字节这个 16 位字里；还没有打标的修改器用它
+#[allow(dead_code)]
+pub fn rewrite_ipv4_tos(packet: &mut [u8], tos: u8) {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[1] == tos {
+        return;
+    }
+
+    let old_word = u16::from_be_bytes([packet[0], packet[1]]);
+    let new_word = u16::from_be_bytes([packet[0], tos]);
+    let checksum = u16::from_be_bytes([packet[10], packet[11]]);
+
+    packet[1] = tos;
+    let checksum = incremental_update(checksum, old_word, new_word);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 常见的教科书例子：UDP 192.168.0.1 -> 192.168.0.199，正确的校验和是 0xb861
+    const SAMPLE: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    fn checksum_of(header: &[u8]) -> u16 {
+        u16::from_be_bytes([header[10], header[11]])
+    }
+
+    #[test]
+    fn recompute_matches_a_known_header() {
+        let mut header = SAMPLE;
+        header[10] = 0xde;
+        header[11] = 0xad;
+        recompute_ipv4_header_checksum(&mut header);
+        assert_eq!(checksum_of(&header), 0xb861);
+        // 带着正确校验和整头求和，结果是全 1
+        assert_eq!(ones_complement_sum(&header), 0xFFFF);
+    }
+
+    #[test]
+    fn tos_flip_equals_a_full_recompute() {
+        for tos in [0x01, 0x02, 0x03, 0xb8, 0xff] {
+            let mut incremental = SAMPLE;
+            rewrite_ipv4_tos(&mut incremental, tos);
+            assert_eq!(incremental[1], tos);
+
+            let mut full = incremental;
+            recompute_ipv4_header_checksum(&mut full);
+            assert_eq!(
+                checksum_of(&incremental),
+                checksum_of(&full),
+                "tos={tos:#04x}"
+            );
+        }
+
+        // 不是 IPv4 头的输入原样不动
+        let mut short = [0x45, 0x00, 0x00];
+        rewrite_ipv4_tos(&mut short, 0xb8);
+        assert_eq!(short, [0x45, 0x00, 0x00]);
+    }
+}
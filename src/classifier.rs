@@ -0,0 +1,79 @@
+// ================= 分类器积木 =================
+
+use crate::five_tuple::FiveTuple;
+use crate::packet_context::PacketContext;
+
+// 各个调度器分类器槽位收的就是这个类型，积木拼出来可以直接塞进去 (流键不是五元组的调度器自己指定 K)
+pub type Classifier<T, K = FiveTuple> = Box<dyn Fn(&PacketContext<T, K>) -> bool>;
+
+pub fn by_queue<T: 'static>(queue_num: usize) -> Classifier<T> {
+    Box::new(move |ctx| ctx.queue_num == queue_num)
+}
+
+// IP 层协议号：6 = TCP，17 = UDP
+// 默认拓扑和配置文件只按队列号和戳分类，下面两块积木留给手写拓扑用
+#[allow(dead_code)]
+pub fn by_proto<T: 'static>(proto: u8) -> Classifier<T> {
+    Box::new(move |ctx| ctx.key.proto == proto)
+}
+
+#[allow(dead_code)]
+pub fn by_dst_port<T: 'static>(port: u16) -> Classifier<T> {
+    Box::new(move |ctx| ctx.key.dst_port == port)
+}
+
+// 修改器盖的戳、手写的条件之类，用闭包包一层就能接着拼
+pub fn when<T: 'static>(
+    f: impl Fn(&PacketContext<T, FiveTuple>) -> bool + 'static,
+) -> Classifier<T> {
+    Box::new(f)
+}
+
+// 积木之间的组合：by_proto(6).and(by_dst_port(443)).or(by_queue(2))
+pub trait Combine<T> {
+    #[allow(dead_code)]
+    fn and(self, other: Classifier<T>) -> Classifier<T>;
+    fn or(self, other: Classifier<T>) -> Classifier<T>;
+}
+
+impl<T: 'static> Combine<T> for Classifier<T> {
+    fn and(self, other: Classifier<T>) -> Classifier<T> {
+        Box::new(move |ctx| self(ctx) && other(ctx))
+    }
+
+    fn or(self, other: Classifier<T>) -> Classifier<T> {
+        Box::new(move |ctx| self(ctx) || other(ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn packet(proto: u8, dst_port: u16) -> PacketContext<Vec<u8>, FiveTuple> {
+        let key = FiveTuple {
+            src: Ipv4Addr::new(192, 168, 1, 10),
+            dst: Ipv4Addr::new(1, 1, 1, 1),
+            proto,
+            src_port: 40000,
+            dst_port,
+        };
+        PacketContext::new(Vec::new(), key, 1, 0, 0, 100)
+    }
+
+    #[test]
+    fn proto_and_port_compose() {
+        let https = by_proto(6).and(by_dst_port(443));
+        assert!(https(&packet(6, 443)));
+        assert!(!https(&packet(17, 53))); // DNS
+        assert!(!https(&packet(17, 443))); // QUIC：端口对上了，协议不对
+        assert!(!https(&packet(6, 80)));
+
+        let either = by_proto(17).or(by_dst_port(443));
+        assert!(either(&packet(17, 53)));
+        assert!(either(&packet(6, 443)));
+        assert!(!either(&packet(6, 80)));
+    }
+}
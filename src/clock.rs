@@ -0,0 +1,102 @@
+// ================= 时钟 =================
+
+use std::cell::Cell;
+#[cfg(test)]
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+// thread::sleep 醒来常常晚几十微秒：离终点还剩这么点的时候不睡了，原地自旋等过去
+const SPIN_MARGIN: Duration = Duration::from_micros(50);
+
+// 回放用的虚拟时间：本线程拨过表之后 SystemClock / clock::now() 都读它，不再读真时钟
+// 整棵树 (令牌桶补水、TTL、流表老化) 不用一个个换时钟，就能按抓包的时间线确定性地跑，跑多快都一样
+thread_local! {
+    static VIRTUAL_NOW: Cell<Option<Instant>> = const { Cell::new(None) };
+    // 第一次拨表那一刻的 (单调, 墙上) 对照：之后的墙上时间按虚拟时间走了多远往后推
+    static VIRTUAL_WALL: Cell<Option<(Instant, SystemTime)>> = const { Cell::new(None) };
+}
+
+// 不持有 Clock 的地方 (包的到达时间、桶的起算点) 取 "现在" 走这里，回放时一样跟着虚拟时间
+pub fn now() -> Instant {
+    VIRTUAL_NOW.with(Cell::get).unwrap_or_else(Instant::now)
+}
+
+// 把本线程的虚拟时间拨到 at (只往前拨，不倒退)；第一次调用起本线程就一直走虚拟时间
+pub fn set_virtual_now(at: Instant) {
+    VIRTUAL_WALL.with(|wall| {
+        if wall.get().is_none() {
+            wall.set(Some((at, SystemTime::now())));
+        }
+    });
+    VIRTUAL_NOW.with(|now| now.set(Some(now.get().map_or(at, |now| now.max(at)))));
+}
+
+// 墙上时间 (只给日志对 pcap 用)：走虚拟时间时跟着虚拟时间推，和 now() 拿到的单调时刻始终对得上
+pub fn wall_now() -> SystemTime {
+    match VIRTUAL_WALL.with(Cell::get) {
+        Some((at, wall)) => wall + now().saturating_duration_since(at),
+        None => SystemTime::now(),
+    }
+}
+
+// 所有跟时间较劲的组件 (令牌桶补水、延迟丢弃、流表老化) 都从这里取 "现在"，
+// 默认就是系统单调时钟 (回放时是上面的虚拟时间)；换成 MockClock 就能手动拨表，让时间相关的逻辑可以确定性地测
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        now()
+    }
+}
+
+// 按真时钟精确睡到 deadline (已经过了就马上返回)：大头交给 thread::sleep，最后 SPIN_MARGIN 自旋补齐
+pub fn sleep_until(deadline: Instant) {
+    let coarse = deadline.saturating_duration_since(Instant::now());
+    if coarse > SPIN_MARGIN {
+        std::thread::sleep(coarse - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+// 手动拨的表：clone 出来的几份共用同一个指针，测试手里留一份，塞给 qdisc / 桶各一份
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Rc<Cell<Instant>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Rc::new(Cell::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    // 模拟表往回跳 (挂起恢复、虚拟机校时)
+    pub fn rewind(&self, by: Duration) {
+        self.now.set(self.now.get() - by);
+    }
+
+    pub fn set(&self, at: Instant) {
+        self.now.set(at);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
@@ -0,0 +1,1001 @@
+// ==========================================
+// TOML 驱动的流水线装配 (改参数不用再重新编译)
+// ==========================================
+use std::{collections::HashMap, fmt, net::Ipv4Addr, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{
+    classifier::Classifier,
+    five_tuple::{FiveTuple, FlowKeyPolicy},
+    modifier::{
+        BogonFilterModifier, DnsPriorityModifier, FlowRateModifier, FragmentModifier, MarkModifier,
+        ModifierChainBuilder, MssClampModifier, OverheadModifier, PacketModifier, PaddingModifier,
+        PaddingPolicy,
+        QuicModifier, TcpAckModifier, TcpControlModifier, TcpSeqModifier, TrueLengthModifier,
+        TtlAction, TtlGuardModifier,
+    },
+    packet_context::{ConnState, PacketContext, ReleaseVerdict},
+    qdisc::{
+        Qdisc,
+        leaf::{DropPolicy, HeadDropFifo, PacingQdisc, PassthroughQdisc},
+        scheduler::{
+            ClassDrrQdisc, DualFairMode, DualFairQdisc, FairQdisc, HtbQdisc, PartitionQdisc,
+            QuantumScaling, ShaperQdisc, SparseQdisc,
+        },
+        wrapper::{
+            CircuitBreakerQdisc, CoalesceQdisc, FlowOrderQdisc, MonitorQdisc, NewFlowGraceQdisc,
+            SfbQdisc, TcpAckFilterQdisc, TtlDropWrapper,
+        },
+    },
+    token_bucket::FrameAwareTokenBucket,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error), // 语法错误 / 未知节点类型都会落在这里，带行列号
+    Invalid(String),        // 语法对但语义不对，比如 quantum <= 0
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "读取配置失败: {}", e),
+            ConfigError::Parse(e) => write!(f, "解析配置失败: {}", e),
+            ConfigError::Invalid(msg) => write!(f, "配置不合法: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(value: std::io::Error) -> Self {
+        ConfigError::Io(value)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(value: toml::de::Error) -> Self {
+        ConfigError::Parse(value)
+    }
+}
+
+// ================= 配置结构 =================
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineConfig {
+    #[serde(default)]
+    pub nfqueue: NfqueueConfig,
+    #[serde(default)]
+    pub modifiers: Vec<ModifierChainConfig>,
+    pub root: NodeConfig,
+}
+
+// 内核 NFQUEUE 那一侧的参数，没写的项用 main 里的默认值
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct NfqueueConfig {
+    pub copy_range: Option<u16>, // 每个包拷进用户态的字节数，true_length 按 IP 头算长度，只拷头部就够整形
+    pub max_len: Option<u32>,    // 内核队列最多压多少个包等用户态来取
+    pub fail_open: Option<bool>, // 内核队列满了 (用户态跟不上) 时直接放行而不是丢，这些包不经过整形
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BucketConfig {
+    pub rate_mbps: f64,
+    pub burst_kb: f64,
+    #[serde(default)]
+    pub frame_bytes: usize, // 每帧额外计费的字节数 (按帧收空口费的链路)，0 = 只按字节
+    #[serde(default)]
+    pub leaky_mtu: Option<usize>, // 设了就换成漏桶：严格匀速，最多攒一个 MTU，burst_kb 不再生效
+    #[serde(default)]
+    pub idle_decay_ms: Option<u64>, // 闲置超过这么久，余额回落到这段时间按速率能攒的量 (不再是满桶)
+}
+
+impl BucketConfig {
+    fn rate_bytes(&self) -> f64 {
+        self.rate_mbps * 1000.0 * 1000.0 / 8.0
+    }
+
+    fn burst_bytes(&self) -> f64 {
+        self.burst_kb * 1024.0
+    }
+
+    fn build(&self, name: &str) -> FrameAwareTokenBucket {
+        match self.leaky_mtu {
+            Some(mtu) => {
+                FrameAwareTokenBucket::leaky(self.rate_bytes(), mtu, self.frame_bytes, name)
+            }
+            None => match self.idle_decay_ms {
+                Some(ms) => FrameAwareTokenBucket::decaying(
+                    self.rate_bytes(),
+                    self.burst_bytes(),
+                    Duration::from_millis(ms),
+                    self.frame_bytes,
+                    name,
+                ),
+                None => FrameAwareTokenBucket::new(
+                    self.rate_bytes(),
+                    self.burst_bytes(),
+                    self.frame_bytes,
+                    name,
+                ),
+            },
+        }
+    }
+}
+
+// 一组 NFQUEUE 共用的修改器链 (顺序即执行顺序)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModifierChainConfig {
+    pub queues: Vec<usize>,
+    pub chain: Vec<ModifierConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModifierConfig {
+    TrueLength,
+    TcpAck,
+    TcpSeq,
+    TcpControl,
+    Dns,
+    Padding {
+        #[serde(default)]
+        block_size: usize,
+        #[serde(default)]
+        buckets: Vec<usize>, // 非空就按档位补齐 (如 [576, 1280])，忽略 block_size
+        #[serde(default)]
+        min_size: usize, // 只补齐 cost 在 [min_size, max_size] 之间的包
+        #[serde(default = "default_padding_max_size")]
+        max_size: usize,
+    },
+    Fragment {
+        mtu: usize,
+        #[serde(default)]
+        split: bool, // 放行前真切成 IP 分片 (DF 路径上内核不会分)，需要拷全包和 CAP_NET_RAW
+        #[serde(default)]
+        precise: bool, // 按 IP 分片的真实切法数帧，后续分片的 IP 头也记账 (split 自带)
+    },
+    Overhead {
+        bytes: usize,
+        #[serde(default)]
+        last_fragment_bytes: Option<usize>, // 多帧的包尾片开销另算
+    },
+    TtlGuard { threshold: u8, drop: bool },
+    Bogon {
+        #[serde(default)]
+        extra: Vec<PrefixConfig>, // 内置火星网段之外再丢哪些源地址，比如 WAN 入方向上的私网段
+    },
+    FlowRate {
+        #[serde(default = "default_rate_time_constant_ms")]
+        time_constant_ms: u64, // 越大越平滑，反应越慢
+        #[serde(default = "default_rate_idle_timeout_ms")]
+        idle_timeout_ms: u64, // 闲置这么久的流从流表里忘掉
+    },
+    Mark {
+        mark: u32,
+        #[serde(default)]
+        verdict: ReleaseVerdict, // accept (默认) / repeat / stop
+    },
+    Quic {
+        #[serde(default = "default_short_cid_len")]
+        short_cid_len: usize,
+    },
+    MssClamp {
+        mss: u16, // 握手包通告的 MSS 超过它就改成它，一般填路径 MTU - 40
+    },
+}
+
+fn default_rate_time_constant_ms() -> u64 {
+    500
+}
+
+fn default_rate_idle_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_padding_max_size() -> usize {
+    usize::MAX
+}
+
+fn default_short_cid_len() -> usize {
+    8
+}
+
+// DRR 大类怎么划分：queues 命中的包按 by 取 class_id，都没命中就按 default_by
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClassRule {
+    pub queues: Vec<usize>,
+    pub by: ClassBy,
+    #[serde(default)]
+    pub inner: Option<Box<NodeConfig>>, // 这条规则划出来的大类单独用的子队列，不写就用 drr 的 inner
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassBy {
+    Flow,
+    Src,
+    Dst,
+    HostPair,
+    SrcSubnet(u8), // by = { src_subnet = 24 }：源地址按前缀归成一个大类
+}
+
+impl ClassBy {
+    fn policy(self) -> FlowKeyPolicy {
+        match self {
+            ClassBy::Flow => FlowKeyPolicy::Full,
+            ClassBy::Src => FlowKeyPolicy::SrcOnly,
+            ClassBy::Dst => FlowKeyPolicy::DstOnly,
+            ClassBy::HostPair => FlowKeyPolicy::HostPair,
+            ClassBy::SrcSubnet(prefix_len) => FlowKeyPolicy::SrcSubnet(prefix_len),
+        }
+    }
+}
+
+// HTB 的参数单独放一个结构体，NodeConfig::Htb 装箱带着它
+#[derive(Debug, Clone, Deserialize)]
+pub struct HtbConfig {
+    pub high_queues: Vec<usize>, // 命中的进高优，其余进低优 (打了 is_dns 戳的包一律进高优)
+    #[serde(default)]
+    pub skip_ahead: usize, // 高优队头太大时往后找小包的深度，0 = 严格 FIFO
+    pub global_bucket: BucketConfig,
+    pub high_bucket: BucketConfig,
+    pub low_bucket: BucketConfig,
+    #[serde(default)]
+    pub queue_buckets: Vec<QueueBucketConfig>,
+    #[serde(default)]
+    pub high_committed: Option<BucketConfig>, // 保底速率：付得起的包最先走，不会被另一类吃光全局桶
+    #[serde(default)]
+    pub low_committed: Option<BucketConfig>,
+    #[serde(default)]
+    pub elephant_mbps: Option<f64>, // 测得流速超过它的流一律进低优 (要在修改器链里挂 flow_rate)
+    #[serde(default)]
+    pub new_conn_high: bool, // conntrack 报 NEW 的包 (握手) 不管哪个队列都进高优，建连不被大流压住
+    #[serde(default)]
+    pub reserve_idle_ms: Option<u64>, // 一类队列空着并闲了这么久，另一类借全局桶时不再给它留准备金
+    #[serde(default)]
+    pub interleave: bool, // 同一档里两类都放得行时轮流出包，不再高优先倒空 (默认严格优先级)
+    #[serde(default)]
+    pub global_wire_bytes: bool, // 全局桶按线上真实字节 (pkt_len) 扣，高优 / 低优的桶照旧按整形后的 cost 扣
+    #[serde(default)]
+    pub class_wire_bytes: bool, // 反过来：高优 / 低优的桶 (含保底桶) 按 pkt_len 扣，类的限速管的是线上字节
+    #[serde(default)]
+    pub control_bucket: Option<BucketConfig>, // 控制包 (要挂 tcp_control) 的专用小桶，付得起就不看全局桶直接走
+    pub high: Box<NodeConfig>,
+    pub low: Box<NodeConfig>,
+}
+
+// HTB 上按入口队列加的一道闸，queues 里的队列共用这只桶
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueBucketConfig {
+    pub queues: Vec<usize>,
+    pub bucket: BucketConfig,
+}
+
+// 一个 IPv4 网段：{ net = "10.0.0.0", len = 8 }
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrefixConfig {
+    pub net: Ipv4Addr,
+    pub len: u8,
+}
+
+// 多路公平的一条通道：queues 命中的包进这里，都没命中的进最后一条
+#[derive(Debug, Clone, Deserialize)]
+pub struct FairLaneConfig {
+    #[serde(default)]
+    pub queues: Vec<usize>,
+    pub inner: NodeConfig,
+}
+
+// 硬分区的一格：queues 命中的包进这里，都没命中的进最后一格
+#[derive(Debug, Clone, Deserialize)]
+pub struct PartitionConfig {
+    #[serde(default)]
+    pub queues: Vec<usize>,
+    pub bucket: BucketConfig,
+    pub inner: NodeConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LatencyOverride {
+    pub queues: Vec<usize>,
+    pub max_latency_ms: u64,
+}
+
+fn default_sparse_threshold() -> usize {
+    1
+}
+
+fn default_pacing_limit() -> usize {
+    10_000
+}
+
+// SFB 默认 8 层 × 16 格，和 Linux sch_sfb 一样
+fn default_sfb_levels() -> usize {
+    8
+}
+
+fn default_sfb_bins() -> usize {
+    16
+}
+
+fn default_sfb_target_backlog() -> u32 {
+    25
+}
+
+fn default_quantum() -> i32 {
+    1500
+}
+
+fn default_class_by() -> ClassBy {
+    ClassBy::Flow
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeConfig {
+    Fifo {
+        limit: usize,
+        #[serde(default)]
+        drop: DropPolicy, // head (默认) / tail
+    },
+    Passthrough, // 不限长不丢包的直通 FIFO，A/B 对比时顶替整形叶子
+    Pacing {
+        rate_mbps: f64, // 每条流各自的匀速上限
+        #[serde(default = "default_pacing_limit")]
+        limit: usize,
+    },
+    TtlDrop {
+        max_latency_ms: u64,
+        #[serde(default)]
+        overrides: Vec<LatencyOverride>, // 按 queue_num 覆盖延迟上限，第一个命中的生效
+        #[serde(default)]
+        strict: bool, // 新流宽限期的豁免也不认，套在根上当全流水线的总延迟上限
+        inner: Box<NodeConfig>,
+    },
+    AckFilter {
+        #[serde(default)]
+        max_tracked: Option<usize>, // 最多记多少条流的 ACK 情报 (默认 65536)，满了踢最久没见的
+        inner: Box<NodeConfig>,
+    },
+    NewFlowGrace {
+        grace_ms: u64,
+        inner: Box<NodeConfig>,
+    },
+    Monitor {
+        name: String, // 子树内部的观测点，静默运行，报表跟着根监控一起打印
+        inner: Box<NodeConfig>,
+    },
+    CircuitBreaker {
+        high_water_kb: usize, // 积压超过它并持续 hold_ms，暂停整形直接放行
+        low_water_kb: usize,  // 积压回落到它以下恢复整形，必须小于 high_water_kb
+        #[serde(default)]
+        hold_ms: u64,
+        inner: Box<NodeConfig>,
+    },
+    FlowOrder {
+        inner: Box<NodeConfig>, // 里面怎么调度都行，同一条流出来时一定按到达顺序
+    },
+    Coalesce {
+        overhead_bytes: usize,  // 和这条路径上 overhead 修改器的 bytes 保持一致
+        max_frame_bytes: usize, // 一帧能装的真实字节，一般填隧道 MTU
+        inner: Box<NodeConfig>,
+    },
+    Sfb {
+        #[serde(default = "default_sfb_levels")]
+        levels: usize,
+        #[serde(default = "default_sfb_bins")]
+        bins: usize,
+        #[serde(default = "default_sfb_target_backlog")]
+        target_backlog: u32, // 单个格子的积压目标 (包数)，超了就调高这个格子的丢包概率
+        inner: Box<NodeConfig>,
+    },
+    Drr {
+        #[serde(default = "default_quantum")]
+        quantum: i32,
+        #[serde(default)]
+        rules: Vec<ClassRule>,
+        #[serde(default = "default_class_by")]
+        default_by: ClassBy,
+        #[serde(default)]
+        mem_limit_kb: Option<usize>, // 所有大类合计的积压上限，超了丢全局最胖那条流的最老包
+        #[serde(default)]
+        auto_quantum: bool, // 量子至少取最近的最大包 cost，加了隧道开销的满 MTU 包也能一轮发走
+        #[serde(default)]
+        borrow: bool, // 排空的大类把没花完的额度让给下一个，只剩一个大类时不空转攒赤字
+        #[serde(default)]
+        scaling: QuantumScaling, // fixed (默认) / per_flow / inverse：量子按大类里在排队的流数缩放
+        inner: Box<NodeConfig>,
+    },
+    Sparse {
+        #[serde(default = "default_sparse_threshold")]
+        threshold: usize, // 在队包数不超过它就算稀疏流
+        #[serde(default)]
+        cooldown_ms: u64, // 降级成大流之后，排空再闲置这么久才恢复
+        sparse: Box<NodeConfig>,
+        bulk: Box<NodeConfig>,
+    },
+    Shaper {
+        threshold_mbps: f64, // 流速持续超过它就降级进 slow
+        window_ms: u64,      // 要连续超标这么久才降级，偶尔冒一下尖不算
+        cooldown_ms: u64,    // 降级之后速率回落 (或者干脆不发) 满这么久才升回 fast
+        fast: Box<NodeConfig>,
+        slow: Box<NodeConfig>,
+    },
+    DualFair {
+        #[serde(default = "default_quantum")]
+        quantum: i32,
+        a_queues: Vec<usize>, // 命中的进 A，其余进 B
+        #[serde(default)]
+        frame_charge: Option<i32>, // 按空口时间公平：每帧额外记这么多字节的等效时长 (帧间隔 / 前导码)
+        #[serde(default)]
+        edf_budget_ms: Option<[u64; 2]>, // [A, B] 两边的延迟预算，设了就改成最早截止时间优先，quantum 不再生效
+        a: Box<NodeConfig>,
+        b: Box<NodeConfig>,
+    },
+    Fair {
+        #[serde(default = "default_quantum")]
+        quantum: i32,
+        lanes: Vec<FairLaneConfig>, // 各通道按字节 1:1:...:1
+    },
+    Htb(Box<HtbConfig>), // 字段多，单独拆成结构体装箱，别把每个节点都撑到它那么大
+    Partition {
+        partitions: Vec<PartitionConfig>,
+    },
+}
+
+impl PipelineConfig {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text)?;
+        // 没拷全的包 fragment_ipv4 不拆，split 就成了摆设：与其运行时悄悄不生效，不如启动就报错
+        if config.splits() && config.nfqueue.copy_range != Some(u16::MAX) {
+            return Err(ConfigError::Invalid(
+                "fragment.split = true 要拷全包，[nfqueue] copy_range 得设成 65535".to_string(),
+            ));
+        }
+        // tcp_control 认的是 tcp_ack 盖的纯 ACK 戳，前面没有 tcp_ack 纯 ACK 就永远进不了快车道
+        for group in &config.modifiers {
+            let ack = group
+                .chain
+                .iter()
+                .position(|c| matches!(c, ModifierConfig::TcpAck));
+            let control = group
+                .chain
+                .iter()
+                .position(|c| matches!(c, ModifierConfig::TcpControl));
+            if let Some(control) = control
+                && ack.is_none_or(|ack| ack > control)
+            {
+                return Err(ConfigError::Invalid(
+                    "tcp_control 要排在 tcp_ack 后面 (纯 ACK 用的是它盖的戳)".to_string(),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    // 有没有哪条修改器链要真拆分片 (要不要开 raw socket)
+    pub fn splits(&self) -> bool {
+        self.modifiers.iter().any(|m| {
+            m.chain
+                .iter()
+                .any(|c| matches!(c, ModifierConfig::Fragment { split: true, .. }))
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+// ================= 装配器 =================
+
+pub type ModifierMap<T, K> = HashMap<usize, Vec<Box<dyn PacketModifier<T, K>>>>;
+
+pub fn build_modifiers<T: AsRef<[u8]> + AsMut<[u8]>, K>(
+    config: &PipelineConfig,
+) -> ModifierMap<T, K> {
+    let mut modifiers: ModifierMap<T, K> = HashMap::new();
+    for group in &config.modifiers {
+        for &q in &group.queues {
+            let chain = group
+                .chain
+                .iter()
+                .fold(ModifierChainBuilder::new(), add_modifier);
+            if chain.reordered() {
+                println!(
+                    "⚠️ 队列 {} 的修改器链顺序不对 (比如 overhead 写在 fragment 前面)，已按阶段重排",
+                    q
+                );
+            }
+            modifiers.insert(q, chain.build());
+        }
+    }
+    modifiers
+}
+
+fn add_modifier<T: AsRef<[u8]> + AsMut<[u8]>, K>(
+    chain: ModifierChainBuilder<T, K>,
+    config: &ModifierConfig,
+) -> ModifierChainBuilder<T, K> {
+    match *config {
+        ModifierConfig::TrueLength => chain.with(TrueLengthModifier::new()),
+        ModifierConfig::TcpAck => chain.with(TcpAckModifier::new()),
+        ModifierConfig::TcpSeq => chain.with(TcpSeqModifier::new()),
+        ModifierConfig::Dns => chain.with(DnsPriorityModifier::new()),
+        ModifierConfig::Padding {
+            block_size,
+            ref buckets,
+            min_size,
+            max_size,
+        } => {
+            let policy = if buckets.is_empty() {
+                PaddingPolicy::BlockAlign(block_size)
+            } else {
+                PaddingPolicy::Buckets(buckets.clone())
+            };
+            chain.with(PaddingModifier::with_policy(policy, min_size, max_size))
+        }
+        ModifierConfig::Fragment { mtu, split, precise } => {
+            let fragment = if split {
+                FragmentModifier::splitting(mtu)
+            } else {
+                FragmentModifier::new(mtu)
+            };
+            chain.with(if precise { fragment.precise() } else { fragment })
+        }
+        ModifierConfig::Overhead {
+            bytes,
+            last_fragment_bytes,
+        } => {
+            let overhead = OverheadModifier::new(bytes);
+            chain.with(match last_fragment_bytes {
+                Some(last) => overhead.with_last_fragment(last),
+                None => overhead,
+            })
+        }
+        ModifierConfig::FlowRate {
+            time_constant_ms,
+            idle_timeout_ms,
+        } => chain.with(FlowRateModifier::new(
+            Duration::from_millis(time_constant_ms),
+            Duration::from_millis(idle_timeout_ms),
+        )),
+        ModifierConfig::Mark { mark, verdict } => chain.with(MarkModifier::new(mark, verdict)),
+        ModifierConfig::TtlGuard { threshold, drop } => {
+            let action = if drop { TtlAction::Drop } else { TtlAction::Flag };
+            chain.with(TtlGuardModifier::new(threshold, action))
+        }
+        ModifierConfig::Bogon { ref extra } => {
+            let extra: Vec<(Ipv4Addr, u8)> = extra.iter().map(|p| (p.net, p.len)).collect();
+            chain.with(BogonFilterModifier::new(&extra))
+        }
+        ModifierConfig::TcpControl => chain.with(TcpControlModifier::new()),
+        ModifierConfig::Quic { short_cid_len } => chain.with(QuicModifier::new(short_cid_len)),
+        ModifierConfig::MssClamp { mss } => chain.with(MssClampModifier::new(mss)),
+    }
+}
+
+pub fn build_qdisc<T: 'static>(
+    config: &NodeConfig,
+) -> Result<Box<dyn Qdisc<T, FiveTuple>>, ConfigError> {
+    let qdisc: Box<dyn Qdisc<T, FiveTuple>> = match config {
+        NodeConfig::Fifo { limit, drop } => {
+            if *limit == 0 {
+                return Err(ConfigError::Invalid("fifo.limit 必须大于 0".to_string()));
+            }
+            Box::new(HeadDropFifo::with_policy(*limit, *drop))
+        }
+        NodeConfig::Passthrough => Box::new(PassthroughQdisc::new()),
+        NodeConfig::Pacing { rate_mbps, limit } => {
+            if *rate_mbps <= 0.0 {
+                return Err(ConfigError::Invalid(
+                    "pacing.rate_mbps 必须大于 0".to_string(),
+                ));
+            }
+            let mut pacing = PacingQdisc::new(rate_mbps * 1000.0 * 1000.0 / 8.0);
+            pacing.set_limit(*limit);
+            Box::new(pacing)
+        }
+        NodeConfig::TtlDrop {
+            max_latency_ms,
+            overrides,
+            strict,
+            inner,
+        } => {
+            let inner = build_qdisc(inner)?;
+            let mut wrapper = if overrides.is_empty() {
+                TtlDropWrapper::new(*max_latency_ms, inner)
+            } else {
+                let overrides = overrides.clone();
+                let fallback = Duration::from_millis(*max_latency_ms);
+                TtlDropWrapper::with_latency_fn(
+                    *max_latency_ms,
+                    inner,
+                    Box::new(move |ctx: &PacketContext<T, FiveTuple>| {
+                        overrides
+                            .iter()
+                            .find(|o| o.queues.contains(&ctx.queue_num))
+                            .map_or(fallback, |o| Duration::from_millis(o.max_latency_ms))
+                    }),
+                )
+            };
+            wrapper.set_strict(*strict);
+            Box::new(wrapper)
+        }
+        NodeConfig::AckFilter { max_tracked, inner } => {
+            let mut filter = TcpAckFilterQdisc::new(build_qdisc(inner)?);
+            if let Some(max) = max_tracked {
+                filter.set_max_tracked(*max);
+            }
+            Box::new(filter)
+        }
+        NodeConfig::NewFlowGrace { grace_ms, inner } => {
+            Box::new(NewFlowGraceQdisc::new(*grace_ms, build_qdisc(inner)?))
+        }
+        NodeConfig::Monitor { name, inner } => {
+            let mut monitor = MonitorQdisc::new(name, build_qdisc(inner)?);
+            monitor.set_silent(true);
+            Box::new(monitor)
+        }
+        NodeConfig::CircuitBreaker {
+            high_water_kb,
+            low_water_kb,
+            hold_ms,
+            inner,
+        } => {
+            if low_water_kb >= high_water_kb {
+                return Err(ConfigError::Invalid(
+                    "circuit_breaker.low_water_kb 必须小于 high_water_kb".to_string(),
+                ));
+            }
+            Box::new(CircuitBreakerQdisc::new(
+                high_water_kb * 1024,
+                low_water_kb * 1024,
+                Duration::from_millis(*hold_ms),
+                build_qdisc(inner)?,
+            ))
+        }
+        NodeConfig::FlowOrder { inner } => Box::new(FlowOrderQdisc::new(build_qdisc(inner)?)),
+        NodeConfig::Coalesce {
+            overhead_bytes,
+            max_frame_bytes,
+            inner,
+        } => Box::new(CoalesceQdisc::new(
+            *overhead_bytes,
+            *max_frame_bytes,
+            build_qdisc(inner)?,
+        )),
+        NodeConfig::Sfb {
+            levels,
+            bins,
+            target_backlog,
+            inner,
+        } => {
+            if *levels == 0 || *bins == 0 {
+                return Err(ConfigError::Invalid(
+                    "sfb.levels 和 sfb.bins 必须大于 0".to_string(),
+                ));
+            }
+            Box::new(SfbQdisc::new(
+                *levels,
+                *bins,
+                *target_backlog,
+                build_qdisc(inner)?,
+            ))
+        }
+        NodeConfig::Drr {
+            quantum,
+            rules,
+            default_by,
+            mem_limit_kb,
+            auto_quantum,
+            borrow,
+            scaling,
+            inner,
+        } => {
+            if *quantum <= 0 {
+                return Err(ConfigError::Invalid("drr.quantum 必须大于 0".to_string()));
+            }
+            // 先造一个样品把子树校验一遍，工厂闭包里就可以放心 expect 了
+            build_qdisc::<T>(inner)?;
+            for rule in rules {
+                if let Some(rule_inner) = &rule.inner {
+                    build_qdisc::<T>(rule_inner)?;
+                }
+            }
+
+            let quantum = *quantum;
+            let default_by = *default_by;
+            // 大类 id 带上命中的规则号，工厂据此挑子队列；没命中任何规则的是 None
+            let routes: Vec<(Vec<usize>, ClassBy)> =
+                rules.iter().map(|r| (r.queues.clone(), r.by)).collect();
+            let leaves: Vec<NodeConfig> = rules
+                .iter()
+                .map(|r| (**r.inner.as_ref().unwrap_or(inner)).clone())
+                .collect();
+            let inner = (**inner).clone();
+            let mut drr = ClassDrrQdisc::new(
+                Box::new(move |ctx: &PacketContext<T, FiveTuple>| {
+                    let rule = routes.iter().position(|(q, _)| q.contains(&ctx.queue_num));
+                    let by = rule.map_or(default_by, |i| routes[i].1);
+                    ((rule, by.policy().apply(&ctx.key)), quantum)
+                }),
+                Box::new(move |(rule, _): &(Option<usize>, FiveTuple)| {
+                    let leaf = rule.map_or(&inner, |i| &leaves[i]);
+                    build_qdisc(leaf).expect("子树已在装配时校验过")
+                }),
+                *scaling,
+                mem_limit_kb.map(|kb| kb * 1024),
+            );
+            // 空壳按叶子分拣：没自带 inner 的规则和兜底类用的是同一种叶子
+            let own_leaf: Vec<bool> = rules.iter().map(|r| r.inner.is_some()).collect();
+            drr.set_leaf_kind(Box::new(move |(rule, _): &(Option<usize>, FiveTuple)| {
+                rule.filter(|&i| own_leaf[i]).map_or(0, |i| i + 1)
+            }));
+            drr.set_auto_quantum(*auto_quantum);
+            drr.set_borrow(*borrow);
+            Box::new(drr)
+        }
+        NodeConfig::Sparse {
+            threshold,
+            cooldown_ms,
+            sparse,
+            bulk,
+        } => Box::new(SparseQdisc::new_with(
+            build_qdisc(sparse)?,
+            build_qdisc(bulk)?,
+            *threshold,
+            Duration::from_millis(*cooldown_ms),
+        )),
+        NodeConfig::Shaper {
+            threshold_mbps,
+            window_ms,
+            cooldown_ms,
+            fast,
+            slow,
+        } => Box::new(ShaperQdisc::new(
+            build_qdisc(fast)?,
+            build_qdisc(slow)?,
+            threshold_mbps * 1_000_000.0,
+            Duration::from_millis(*window_ms),
+            Duration::from_millis(*cooldown_ms),
+        )),
+        NodeConfig::DualFair {
+            quantum,
+            a_queues,
+            frame_charge,
+            edf_budget_ms,
+            a,
+            b,
+        } => {
+            if *quantum <= 0 {
+                return Err(ConfigError::Invalid("dual_fair.quantum 必须大于 0".to_string()));
+            }
+            let a_queues = a_queues.clone();
+            let mut dual = DualFairQdisc::new(
+                build_qdisc(a)?,
+                build_qdisc(b)?,
+                *quantum,
+                queue_classifier(a_queues),
+            );
+            if let Some(per_frame) = *frame_charge {
+                dual.set_cost_fn(Box::new(move |ctx| {
+                    let frames = ctx.frames.max(1) as i32;
+                    (ctx.cost as i32).saturating_add(per_frame.saturating_mul(frames))
+                }));
+            }
+            if let Some([a_ms, b_ms]) = *edf_budget_ms {
+                dual.set_mode(DualFairMode::Edf {
+                    budget_a: Duration::from_millis(a_ms),
+                    budget_b: Duration::from_millis(b_ms),
+                });
+            }
+            Box::new(dual)
+        }
+        NodeConfig::Htb(htb) => {
+            let HtbConfig {
+                high_queues,
+                skip_ahead,
+                global_bucket,
+                high_bucket,
+                low_bucket,
+                queue_buckets,
+                high_committed,
+                low_committed,
+                elephant_mbps,
+                new_conn_high,
+                reserve_idle_ms,
+                interleave,
+                global_wire_bytes,
+                class_wire_bytes,
+                control_bucket,
+                high,
+                low,
+            } = &**htb;
+            let high_queues = high_queues.clone();
+            let elephant_bps = elephant_mbps.map_or(f64::INFINITY, |mbps| mbps * 1_000_000.0);
+            let new_conn_high = *new_conn_high;
+            let mut htb = HtbQdisc::new(
+                build_qdisc(high)?,
+                build_qdisc(low)?,
+                high_bucket.build("high_priority"),
+                low_bucket.build("low_priority"),
+                global_bucket.build("Global"),
+                Box::new(move |ctx: &PacketContext<T, FiveTuple>| {
+                    let mouse = ctx.flow_rate_bps <= elephant_bps;
+                    let handshake = new_conn_high && ctx.conn_state == Some(ConnState::New);
+                    ctx.is_dns || handshake || (high_queues.contains(&ctx.queue_num) && mouse)
+                }),
+            );
+            htb.set_reserves(
+                high_bucket.burst_bytes() as usize,
+                low_bucket.burst_bytes() as usize,
+            );
+            htb.set_skip_ahead(*skip_ahead);
+            htb.set_interleave(*interleave);
+            if *global_wire_bytes {
+                htb.set_global_cost_fn(Box::new(|ctx| ctx.pkt_len));
+            }
+            if *class_wire_bytes {
+                htb.set_class_cost_fn(Box::new(|ctx| ctx.pkt_len));
+            }
+            if let Some(ms) = reserve_idle_ms {
+                htb.set_reserve_idle(Duration::from_millis(*ms));
+            }
+            if let Some(bucket) = high_committed {
+                htb.set_high_committed(bucket.build("high_committed"));
+            }
+            if let Some(bucket) = low_committed {
+                htb.set_low_committed(bucket.build("low_committed"));
+            }
+            if let Some(bucket) = control_bucket {
+                htb.set_control_bucket(bucket.build("control"));
+            }
+            for (i, qb) in queue_buckets.iter().enumerate() {
+                htb.add_queue_bucket(&qb.queues, qb.bucket.build(&format!("queue_{}", i)));
+            }
+            Box::new(htb)
+        }
+        NodeConfig::Fair { quantum, lanes } => {
+            if *quantum <= 0 {
+                return Err(ConfigError::Invalid("fair.quantum 必须大于 0".to_string()));
+            }
+            if lanes.is_empty() {
+                return Err(ConfigError::Invalid("fair.lanes 不能为空".to_string()));
+            }
+            let mut built = Vec::with_capacity(lanes.len());
+            for lane in lanes {
+                built.push(build_qdisc(&lane.inner)?);
+            }
+            let routes: Vec<Vec<usize>> = lanes.iter().map(|l| l.queues.clone()).collect();
+            let last = routes.len() - 1;
+            Box::new(FairQdisc::new(
+                built,
+                *quantum,
+                Box::new(move |ctx: &PacketContext<T, FiveTuple>| {
+                    routes
+                        .iter()
+                        .position(|q| q.contains(&ctx.queue_num))
+                        .unwrap_or(last)
+                }),
+            ))
+        }
+        NodeConfig::Partition { partitions } => {
+            if partitions.is_empty() {
+                return Err(ConfigError::Invalid("partition.partitions 不能为空".to_string()));
+            }
+            let mut built = Vec::with_capacity(partitions.len());
+            for (i, p) in partitions.iter().enumerate() {
+                built.push((build_qdisc(&p.inner)?, p.bucket.build(&format!("partition_{}", i))));
+            }
+            let routes: Vec<Vec<usize>> = partitions.iter().map(|p| p.queues.clone()).collect();
+            let last = routes.len() - 1;
+            Box::new(PartitionQdisc::new(
+                built,
+                Box::new(move |ctx: &PacketContext<T, FiveTuple>| {
+                    routes
+                        .iter()
+                        .position(|q| q.contains(&ctx.queue_num))
+                        .unwrap_or(last)
+                }),
+            ))
+        }
+    };
+    Ok(qdisc)
+}
+
+// 按 queue_num 白名单分流：命中返回 true
+fn queue_classifier<T, K>(queues: Vec<usize>) -> Classifier<T, K> {
+    Box::new(move |ctx| queues.contains(&ctx.queue_num))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(toml_src: &str) -> NodeConfig {
+        toml::from_str(toml_src).expect("节点配置应能解析")
+    }
+
+    #[test]
+    fn example_pipeline_builds() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/pipeline.example.toml");
+        let cfg = PipelineConfig::load(path).expect("示例配置应能解析");
+        assert!(matches!(cfg.root, NodeConfig::Htb(_)));
+        build_qdisc::<Vec<u8>>(&cfg.root).expect("示例配置应能装配");
+    }
+
+    #[test]
+    fn split_requires_full_copy_range() {
+        let chain = "[[modifiers]]\nqueues = [0]\nchain = [{ type = \"fragment\", mtu = 1280, split = true }]\n[root]\ntype = \"fifo\"\nlimit = 16\n";
+        let err = PipelineConfig::from_toml(chain).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(_)), "{err}");
+        let short = format!("[nfqueue]\ncopy_range = 1500\n{chain}");
+        assert!(PipelineConfig::from_toml(&short).is_err());
+
+        let full = format!("[nfqueue]\ncopy_range = 65535\n{chain}");
+        assert!(PipelineConfig::from_toml(&full).unwrap().splits());
+        let counted = chain.replace("split = true", "split = false");
+        assert!(!PipelineConfig::from_toml(&counted).unwrap().splits());
+    }
+
+    #[test]
+    fn tcp_control_must_follow_tcp_ack() {
+        let chain = |chain: &str| {
+            format!(
+                "[[modifiers]]\nqueues = [0]\nchain = [{chain}]\n[root]\ntype = \"fifo\"\nlimit = 16\n"
+            )
+        };
+        let ok = chain("{ type = \"tcp_ack\" }, { type = \"tcp_control\" }");
+        assert!(PipelineConfig::from_toml(&ok).is_ok());
+        let reversed = chain("{ type = \"tcp_control\" }, { type = \"tcp_ack\" }");
+        assert!(PipelineConfig::from_toml(&reversed).is_err());
+        let alone = chain("{ type = \"tcp_control\" }");
+        assert!(matches!(
+            PipelineConfig::from_toml(&alone),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn drr_rule_can_group_by_src_subnet() {
+        let cfg = node(
+            "type = \"drr\"\nrules = [{ queues = [4, 5], by = { src_subnet = 24 } }]\ninner = { type = \"fifo\", limit = 16 }",
+        );
+        let NodeConfig::Drr { rules, .. } = &cfg else {
+            panic!("应解析成 drr");
+        };
+        assert_eq!(rules[0].by.policy(), FlowKeyPolicy::SrcSubnet(24));
+        build_qdisc::<Vec<u8>>(&cfg).expect("按网段分类的 drr 应能装配");
+    }
+
+    #[test]
+    fn drr_scaling_defaults_to_fixed() {
+        let cfg = node("type = \"drr\"\ninner = { type = \"fifo\", limit = 16 }");
+        assert!(matches!(
+            cfg,
+            NodeConfig::Drr {
+                scaling: QuantumScaling::Fixed,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn drr_scaling_is_read_from_config() {
+        let cfg =
+            node("type = \"drr\"\nscaling = \"per_flow\"\ninner = { type = \"fifo\", limit = 16 }");
+        assert!(matches!(
+            cfg,
+            NodeConfig::Drr {
+                scaling: QuantumScaling::PerFlow,
+                ..
+            }
+        ));
+        build_qdisc::<Vec<u8>>(&cfg).expect("per_flow 的 drr 应能装配");
+    }
+}
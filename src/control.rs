@@ -0,0 +1,237 @@
+// ==========================================
+// 运行时控制通道 (Unix Domain Socket + 一行一条命令)
+//   set-rate global 8000000   # 单位 bit/s
+//   set-vip 2,3               # 哪些 NFQUEUE 走高优
+//   reset-counters            # 监控的累计计数清零 (压测前打一下)，回话里带上清掉的那份
+// 只影响之后入队的包，已经在排队的包保持原路由
+// ==========================================
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::qdisc::{Qdisc, monitor_reports};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketId {
+    Global,
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    SetRate { bucket: BucketId, rate_bps: f64 },
+    SetVip(Vec<usize>),
+    ResetCounters,
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("set-rate") => {
+                let bucket = match parts.next() {
+                    Some("global") => BucketId::Global,
+                    Some("high") => BucketId::High,
+                    Some("low") => BucketId::Low,
+                    other => return Err(format!("未知的桶: {:?}", other)),
+                };
+                let rate_bps = parts
+                    .next()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite() && *v > 0.0)
+                    .ok_or_else(|| "速率必须是正数 (bit/s)".to_string())?;
+                Ok(ControlCommand::SetRate { bucket, rate_bps })
+            }
+            Some("set-vip") => {
+                let queues = parts
+                    .next()
+                    .ok_or_else(|| "缺少队列列表".to_string())?
+                    .split(',')
+                    .map(|q| q.trim().parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("队列号不合法: {}", e))?;
+                Ok(ControlCommand::SetVip(queues))
+            }
+            Some("reset-counters") => Ok(ControlCommand::ResetCounters),
+            other => Err(format!("未知命令: {:?}", other)),
+        }
+    }
+}
+
+// 一行命令最长这么多字节，攒到这么长还没等来换行的连接直接踢掉，别让它把缓冲撑爆
+const MAX_LINE: usize = 4096;
+
+pub struct ControlServer {
+    listener: UnixListener,
+    conns: Vec<Connection>,
+}
+
+// 一条控制连接：非阻塞读，读到半行先攒着，等换行来了再执行
+struct Connection {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+impl ControlServer {
+    pub fn bind(path: &str) -> std::io::Result<Self> {
+        // 上次没清理干净的 socket 文件会让 bind 失败，先删掉
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            conns: Vec::new(),
+        })
+    }
+
+    // 主循环每一轮调一次：收新连接、把各连接已经到了的整行命令执行掉，读不到就立刻返回，绝不阻塞调度
+    pub fn poll<T, K>(&mut self, pipeline: &mut dyn Qdisc<T, K>) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.conns.push(Connection {
+                        stream,
+                        buf: Vec::new(),
+                    }),
+                    Err(e) => eprintln!("⚠️ 控制连接设置非阻塞失败: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("⚠️ 控制通道 accept 失败: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.conns.retain_mut(|conn| match conn.serve(pipeline) {
+            Ok(open) => open,
+            Err(e) => {
+                eprintln!("⚠️ 控制连接异常: {}", e);
+                false
+            }
+        });
+    }
+}
+
+impl Connection {
+    // 返回连接是否还开着
+    fn serve<T, K>(&mut self, pipeline: &mut dyn Qdisc<T, K>) -> std::io::Result<bool> {
+        let mut open = true;
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    open = false; // 对端关了写端：攒下的整行照样执行完再断
+                    break;
+                }
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = match ControlCommand::parse(&line) {
+                Ok(ControlCommand::ResetCounters) => reset_counters(pipeline),
+                Ok(cmd) if pipeline.apply_control(&cmd) => "ok".to_string(),
+                Ok(_) => "err 没有节点认领这条命令".to_string(),
+                Err(e) => format!("err {}", e),
+            };
+            writeln!(self.stream, "{}", reply)?;
+        }
+
+        if self.buf.len() > MAX_LINE {
+            writeln!(self.stream, "err 命令太长")?;
+            return Ok(false);
+        }
+        Ok(open)
+    }
+}
+
+// 先读后清：调度和控制通道跑在同一个线程上，两步之间不会有包进出，读到的就是清掉的那份
+// 回话一行，树里每个监控一段
+fn reset_counters<T, K>(pipeline: &mut dyn Qdisc<T, K>) -> String {
+    let cleared = monitor_reports(pipeline);
+    if !pipeline.apply_control(&ControlCommand::ResetCounters) {
+        return "err 没有节点认领这条命令".to_string();
+    }
+    let totals: Vec<String> = cleared
+        .iter()
+        .map(|(path, snap)| {
+            let t = snap.totals;
+            format!(
+                "{} in {} out {} dropped {} bytes {}",
+                path, t.total_in, t.total_out, t.total_dropped, t.total_bytes
+            )
+        })
+        .collect();
+    format!("ok {}", totals.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::{leaf::HeadDropFifo, wrapper::MonitorQdisc};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            ControlCommand::parse("set-rate high 8000000"),
+            Ok(ControlCommand::SetRate {
+                bucket: BucketId::High,
+                rate_bps: 8_000_000.0
+            })
+        );
+        assert_eq!(
+            ControlCommand::parse("set-vip 2,3"),
+            Ok(ControlCommand::SetVip(vec![2, 3]))
+        );
+        assert!(ControlCommand::parse("set-rate low -1").is_err());
+    }
+
+    #[test]
+    fn half_lines_wait_for_the_newline_without_blocking() {
+        let path = std::env::temp_dir().join(format!("nfq_shaper_{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut server = ControlServer::bind(path).unwrap();
+        let mut root: MonitorQdisc<Vec<u8>, u64> =
+            MonitorQdisc::new("Root", Box::new(HeadDropFifo::new(16)));
+
+        let mut client = UnixStream::connect(path).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client.write_all(b"reset-co").unwrap();
+        server.poll(&mut root); // 半行：攒着，不回话也不卡住
+        assert_eq!(server.conns.len(), 1);
+        assert!(server.conns[0].buf.starts_with(b"reset-co"));
+
+        root.enqueue(test_packet(1, 0, 100));
+        client.write_all(b"unters\nbogus\n").unwrap();
+        server.poll(&mut root);
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut reply = String::new();
+        reader.read_line(&mut reply).unwrap();
+        assert_eq!(reply, "ok Monitor in 1 out 0 dropped 0 bytes 0\n");
+        assert_eq!(root.totals().total_in, 0);
+        reply.clear();
+        reader.read_line(&mut reply).unwrap();
+        assert!(reply.starts_with("err 未知命令"), "{reply}");
+
+        drop(reader);
+        drop(client);
+        server.poll(&mut root); // 对端关了，连接跟着回收
+        assert!(server.conns.is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+}
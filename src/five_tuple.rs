@@ -1,5 +1,7 @@
 // five_tuple.rs
+use std::hash::{BuildHasher, RandomState};
 use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use nfq::Message;
 
@@ -72,13 +74,218 @@ impl From<&[u8]> for FiveTuple {
 
         // 5. 解析传输层端口 (仅 TCP=6 和 UDP=17)
         // 需要确保 payload 长度足够包含端口号 (源端口 + 目的端口 = 4 字节)
-        if t.proto == 6 || t.proto == 17 {
-            if payload.len() >= ihl + 4 {
-                t.src_port = u16::from_be_bytes([payload[ihl], payload[ihl + 1]]);
-                t.dst_port = u16::from_be_bytes([payload[ihl + 2], payload[ihl + 3]]);
-            }
+        // 拷贝被截到读不到端口时端口留 0，按 (源, 目的, 协议) 归流：不同主机之间照样分得开，
+        // 只是同一对主机的几条连接并成一条。别拿 IP ID 之类逐包变化的字段来凑，
+        // 那样同一条连接的包会散进不同的流队列、出队乱序；COPY_RANGE >= 64 时 (最长 IP 头 60 + 4) 不会走到这里
+        if (t.proto == 6 || t.proto == 17) && payload.len() >= ihl + 4 {
+            t.src_port = u16::from_be_bytes([payload[ihl], payload[ihl + 1]]);
+            t.dst_port = u16::from_be_bytes([payload[ihl + 2], payload[ihl + 3]]);
         }
 
         t
     }
 }
+
+// FNV-1a：不依赖进程随机种子，同样的字节永远得到同一个值
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET;
+    for b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+// flow_hash 的种子：元组布局是公开的，种子固定的话外面的人能算好一批撞到同一个桶的流
+// 进程启动时定一次，之后不许再改 (已经在各层流表里的哈希会全部对不上号)
+static FLOW_HASH_SEED: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_flow_hash_seed(seed: u64) {
+    FLOW_HASH_SEED.store(seed, Ordering::Relaxed);
+}
+
+pub fn flow_hash_seed() -> u64 {
+    FLOW_HASH_SEED.load(Ordering::Relaxed)
+}
+
+// 每个进程不一样的种子，借标准库 HashMap 用的那份随机源
+pub fn random_seed() -> u64 {
+    RandomState::new().hash_one(std::process::id())
+}
+
+impl FiveTuple {
+    // 各层 qdisc 的流表都拿它当键，省得每一层都把 13 字节的元组重新哈希一遍
+    // 种子垫在最前面：同一个元组换个种子就落到完全不同的位置
+    pub fn flow_hash(&self) -> u64 {
+        self.hash_with_seed(flow_hash_seed())
+    }
+
+    fn hash_with_seed(&self, seed: u64) -> u64 {
+        fnv1a(
+            seed.to_le_bytes()
+                .into_iter()
+                .chain(self.src.octets())
+                .chain(self.dst.octets())
+                .chain([self.proto])
+                .chain(self.src_port.to_be_bytes())
+                .chain(self.dst_port.to_be_bytes()),
+        )
+    }
+}
+
+// ==========================================
+// 流标识粒度策略：同一套 DRR，换个策略就换了公平粒度
+// ==========================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowKeyPolicy {
+    Full,          // 完整五元组 (按连接公平)
+    SrcOnly,       // 只看源地址 (按内网主机公平)
+    DstOnly,       // 只看目的地址 (按远端主机公平)
+    HostPair,      // 源 + 目的地址 (按主机对公平)
+    SrcSubnet(u8), // 源地址前缀 (按网段公平，参数为前缀长度)
+}
+
+impl FlowKeyPolicy {
+    // 把解析出来的五元组裁剪成调度用的 key，没用到的字段一律清零
+    pub fn apply(&self, t: &FiveTuple) -> FiveTuple {
+        let unspecified = Ipv4Addr::new(0, 0, 0, 0);
+        match *self {
+            FlowKeyPolicy::Full => t.clone(),
+            FlowKeyPolicy::SrcOnly => FiveTuple {
+                src: t.src,
+                dst: unspecified,
+                proto: 0,
+                src_port: 0,
+                dst_port: 0,
+            },
+            FlowKeyPolicy::DstOnly => FiveTuple {
+                src: unspecified,
+                dst: t.dst,
+                proto: 0,
+                src_port: 0,
+                dst_port: 0,
+            },
+            FlowKeyPolicy::HostPair => FiveTuple {
+                src: t.src,
+                dst: t.dst,
+                proto: 0,
+                src_port: 0,
+                dst_port: 0,
+            },
+            FlowKeyPolicy::SrcSubnet(prefix_len) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0);
+                FiveTuple {
+                    src: Ipv4Addr::from_bits(t.src.to_bits() & mask),
+                    dst: unspecified,
+                    proto: 0,
+                    src_port: 0,
+                    dst_port: 0,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    fn tuple(src: [u8; 4], dst: [u8; 4], src_port: u16) -> FiveTuple {
+        FiveTuple {
+            src: Ipv4Addr::from(src),
+            dst: Ipv4Addr::from(dst),
+            proto: 6,
+            src_port,
+            dst_port: 443,
+        }
+    }
+
+    // 两台同网段主机 + 一台别的网段的主机，各开两条连接，分别连两个远端
+    fn flows(policy: FlowKeyPolicy) -> usize {
+        let mut keys = HashSet::new();
+        for (src, dst) in [
+            ([10, 0, 0, 1], [1, 1, 1, 1]),
+            ([10, 0, 0, 2], [1, 1, 1, 1]),
+            ([10, 0, 1, 3], [8, 8, 8, 8]),
+        ] {
+            for port in [40000, 40001] {
+                keys.insert(policy.apply(&tuple(src, dst, port)));
+            }
+        }
+        keys.len()
+    }
+
+    #[test]
+    fn policies_group_into_expected_flow_counts() {
+        assert_eq!(flows(FlowKeyPolicy::Full), 6);
+        assert_eq!(flows(FlowKeyPolicy::SrcOnly), 3);
+        assert_eq!(flows(FlowKeyPolicy::DstOnly), 2);
+        assert_eq!(flows(FlowKeyPolicy::HostPair), 3);
+        assert_eq!(flows(FlowKeyPolicy::SrcSubnet(24)), 2);
+        assert_eq!(flows(FlowKeyPolicy::SrcSubnet(16)), 1);
+        assert_eq!(flows(FlowKeyPolicy::SrcSubnet(0)), 1);
+        assert_eq!(flows(FlowKeyPolicy::SrcSubnet(32)), 3);
+    }
+
+    #[test]
+    fn seed_changes_the_hash_and_a_fixed_seed_reproduces_it() {
+        let flow = tuple([10, 0, 0, 1], [1, 1, 1, 1], 40000);
+        assert_ne!(flow.hash_with_seed(1), flow.hash_with_seed(2));
+        assert_eq!(flow.hash_with_seed(7), flow.hash_with_seed(7));
+    }
+
+    // 流表改按 flow_hash 查之后分组要和按元组查一模一样：同一个元组哈希不变，不同的元组不撞
+    #[test]
+    fn hash_keyed_lookup_groups_like_tuple_keying() {
+        for policy in [
+            FlowKeyPolicy::Full,
+            FlowKeyPolicy::SrcOnly,
+            FlowKeyPolicy::DstOnly,
+            FlowKeyPolicy::HostPair,
+            FlowKeyPolicy::SrcSubnet(24),
+        ] {
+            let mut by_hash: HashMap<u64, FiveTuple> = HashMap::new();
+            for src in 1..=20 {
+                for port in 40000..40010 {
+                    let key = policy.apply(&tuple([10, 0, 0, src], [1, 1, 1, src % 3], port));
+                    let seen = by_hash.entry(key.hash_with_seed(9)).or_insert(key.clone());
+                    assert_eq!(*seen, key, "{policy:?} 两个不同的元组撞到同一个哈希");
+                }
+            }
+            let by_tuple: HashSet<FiveTuple> = by_hash.values().cloned().collect();
+            assert_eq!(by_hash.len(), by_tuple.len());
+        }
+    }
+
+    // 带 4 字节 IP 选项 (ihl = 24) 的 TCP 包，拷贝只截到 26 字节：端口读不全
+    fn truncated_tcp(src: [u8; 4], ip_id: u16, src_port: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x46;
+        packet[4..6].copy_from_slice(&ip_id.to_be_bytes());
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&src);
+        packet[16..20].copy_from_slice(&[1, 1, 1, 1]);
+        packet[24..26].copy_from_slice(&src_port.to_be_bytes());
+        packet[26..28].copy_from_slice(&443u16.to_be_bytes());
+        packet.truncate(26);
+        packet
+    }
+
+    // 截断的包退到不带端口的键：不同源照样分开，同一对主机的连接并成一条，逐包变化的 IP ID 不掺进来
+    #[test]
+    fn truncated_packets_keep_hosts_apart_and_drop_the_ports() {
+        let a = FiveTuple::from(truncated_tcp([10, 0, 0, 1], 1, 40000).as_slice());
+        let b = FiveTuple::from(truncated_tcp([10, 0, 0, 2], 2, 40000).as_slice());
+        assert_ne!(a, b);
+        assert_ne!(a.flow_hash(), b.flow_hash());
+        assert_eq!((a.src_port, a.dst_port), (0, 0));
+
+        let same_host = FiveTuple::from(truncated_tcp([10, 0, 0, 1], 77, 40001).as_slice());
+        assert_eq!(same_host, a);
+    }
+}
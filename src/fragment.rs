@@ -0,0 +1,291 @@
+// ================= IPv4 真分片 =================
+// FragmentModifier 默认只按 MTU 估帧数记账，真正分片交给内核；
+// 可 DF 置位又碰上 PMTUD 黑洞的路径上，内核不会分，大包只会被路上默默吃掉
+// 这里在出口按 RFC 791 把整包切成 MTU 大小的分片：首片改写 payload 走原来的 verdict，
+// 其余分片从 raw socket 直接发出去。四层校验和覆盖的是整个数据报，分片不用动它
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum::recompute_ipv4_header_checksum;
+
+const MIN_IPV4_HEADER: usize = 20;
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const OFFSET_MASK: u16 = 0x1FFF;
+
+// 后续分片只带 "复制位" 置位的选项 (RFC 791)，补齐到 4 字节
+fn copied_options(options: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => break,  // 选项表结束
+            1 => i += 1, // NOP 只是占位，不复制
+            kind => {
+                let Some(&len) = options.get(i + 1) else {
+                    break;
+                };
+                let len = len as usize;
+                // 长度不对的坏选项，后面的一律不认
+                if len < 2 || i + len > options.len() {
+                    break;
+                }
+                if kind & 0x80 != 0 {
+                    out.extend_from_slice(&options[i..i + len]);
+                }
+                i += len;
+            }
+        }
+    }
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+    out
+}
+
+// 把一个完整的 IPv4 数据报切成不超过 mtu 的分片，每一片都是可以直接上线的 IP 包
+// 本来就装得下、不是 IPv4、没拷全 (set_copy_range 截断了) 或 mtu 小得放不下 8 字节数据时返回 None
+// 原包 DF 置位说明它是 "原子数据报"，IP ID 没有意义 (Linux 常年填 0)，换成 fresh_id 免得对端重组撞车
+pub fn fragment_ipv4(packet: &[u8], mtu: usize, fresh_id: u16) -> Option<Vec<Vec<u8>>> {
+    if packet.len() < MIN_IPV4_HEADER || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = (packet[0] & 0x0F) as usize * 4;
+    let total_length = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if ihl < MIN_IPV4_HEADER || total_length < ihl || total_length > packet.len() {
+        return None;
+    }
+    if total_length <= mtu {
+        return None;
+    }
+
+    let flags_offset = u16::from_be_bytes([packet[6], packet[7]]);
+    let id = if flags_offset & FLAG_DF != 0 {
+        fresh_id.to_be_bytes()
+    } else {
+        [packet[4], packet[5]]
+    };
+    // 切的本身就是个分片：偏移接着它往后排，最后一片沿用它原来的 MF
+    let base_offset = (flags_offset & OFFSET_MASK) as usize * 8;
+    let tail_mf = flags_offset & FLAG_MF;
+
+    let first_header = &packet[..ihl];
+    let rest_header = [
+        &packet[..MIN_IPV4_HEADER],
+        &copied_options(&packet[MIN_IPV4_HEADER..ihl])[..],
+    ]
+    .concat();
+    let payload = &packet[ihl..total_length];
+
+    let mut fragments = Vec::new();
+    let mut pos = 0;
+    while pos < payload.len() {
+        let header: &[u8] = if pos == 0 { first_header } else { &rest_header };
+        // 偏移字段以 8 字节为单位，除了最后一片，每片的数据长度都得是 8 的倍数
+        let room = mtu.checked_sub(header.len())? / 8 * 8;
+        if room == 0 {
+            return None;
+        }
+        let end = (pos + room).min(payload.len());
+        let offset = (base_offset + pos) / 8;
+        if offset > OFFSET_MASK as usize {
+            return None;
+        }
+        let mf = if end == payload.len() {
+            tail_mf
+        } else {
+            FLAG_MF
+        };
+
+        let mut frag = Vec::with_capacity(header.len() + end - pos);
+        frag.extend_from_slice(header);
+        frag.extend_from_slice(&payload[pos..end]);
+        frag[0] = 0x40 | (header.len() / 4) as u8;
+        let len = frag.len() as u16;
+        frag[2..4].copy_from_slice(&len.to_be_bytes());
+        frag[4..6].copy_from_slice(&id);
+        frag[6..8].copy_from_slice(&(mf | offset as u16).to_be_bytes());
+        recompute_ipv4_header_checksum(&mut frag);
+        fragments.push(frag);
+        pos = end;
+    }
+    Some(fragments)
+}
+
+// ==========================================
+// 后续分片的发射口：IPPROTO_RAW 自带 IP_HDRINCL，IP 头完全由我们填
+// 需要 CAP_NET_RAW。发出去的分片会重新走一遍 OUTPUT / POSTROUTING，
+// iptables 的 NFQUEUE 规则要按 mark 放过它们，不然会被再排一次队
+// ==========================================
+pub struct FragmentSender {
+    fd: OwnedFd,
+    next_id: u16,
+    pub datagrams: u64, // 真拆了的数据报
+    pub fragments: u64, // 从 raw socket 发出去的分片 (不含走 verdict 的首片)
+    pub failed: u64,    // 分片没发出去、退回原样放行的数据报
+}
+
+impl FragmentSender {
+    pub fn open(mark: u32) -> io::Result<Self> {
+        let raw = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW) };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // 不让 conntrack 在 OUTPUT 上把我们的分片扣下来等重组：首片走的是 verdict，它永远等不到
+        set_int_option(&fd, libc::IPPROTO_IP, libc::IP_NODEFRAG, 1)?;
+        if mark != 0 {
+            set_int_option(&fd, libc::SOL_SOCKET, libc::SO_MARK, mark as libc::c_int)?;
+        }
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u16)
+            .unwrap_or(0)
+            .max(1);
+        Ok(Self {
+            fd,
+            next_id: seed,
+            datagrams: 0,
+            fragments: 0,
+            failed: 0,
+        })
+    }
+
+    fn send(&self, fragment: &[u8]) -> io::Result<()> {
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes([
+                    fragment[16],
+                    fragment[17],
+                    fragment[18],
+                    fragment[19],
+                ]),
+            },
+            sin_zero: [0; 8],
+        };
+        let ret = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                fragment.as_ptr() as *const libc::c_void,
+                fragment.len(),
+                0,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    // 拆包：后续分片当场发走，返回留给 verdict 的首片
+    // 不用拆 / 拆不了 / 发失败都返回 None，调用方原样放行 (发失败时已经出去的分片在对端重组超时后作废)
+    pub fn split(&mut self, packet: &[u8], mtu: usize) -> Option<Vec<u8>> {
+        let mut fragments = fragment_ipv4(packet, mtu, self.next_id)?;
+        self.next_id = next_fragment_id(self.next_id);
+        for frag in &fragments[1..] {
+            if self.send(frag).is_err() {
+                self.failed += 1;
+                return None;
+            }
+        }
+        self.datagrams += 1;
+        self.fragments += (fragments.len() - 1) as u64;
+        Some(fragments.swap_remove(0))
+    }
+}
+
+// 0 是 Linux 给原子数据报填的 ID，拿它当分片 ID 容易和别的流撞车，转一圈时跳过去
+fn next_fragment_id(id: u16) -> u16 {
+    id.wrapping_add(1).max(1)
+}
+
+fn set_int_option(
+    fd: &OwnedFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // DF 置位、ID 为 0 (Linux 原子数据报的样子) 的 UDP 数据报，载荷按下标填满
+    fn datagram(total: usize) -> Vec<u8> {
+        let mut packet: Vec<u8> = (0..total).map(|i| i as u8).collect();
+        packet[..20].copy_from_slice(&[
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ]);
+        packet[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+        recompute_ipv4_header_checksum(&mut packet);
+        packet
+    }
+
+    #[test]
+    fn splits_a_df_datagram_into_mtu_sized_fragments() {
+        let packet = datagram(3000);
+        let fragments = fragment_ipv4(&packet, 1500, 0x1234).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let mut payload = Vec::new();
+        for (i, frag) in fragments.iter().enumerate() {
+            assert!(frag.len() <= 1500);
+            assert_eq!(u16::from_be_bytes([frag[2], frag[3]]) as usize, frag.len());
+            // 每一片都换成同一个新 ID，DF 摘掉
+            assert_eq!(u16::from_be_bytes([frag[4], frag[5]]), 0x1234);
+            let flags_offset = u16::from_be_bytes([frag[6], frag[7]]);
+            assert_eq!(flags_offset & FLAG_DF, 0);
+            assert_eq!(flags_offset & FLAG_MF != 0, i < 2);
+            assert_eq!((flags_offset & OFFSET_MASK) as usize * 8, payload.len());
+
+            let mut check = frag.clone();
+            recompute_ipv4_header_checksum(&mut check);
+            assert_eq!(check[10..12], frag[10..12]);
+            payload.extend_from_slice(&frag[20..]);
+        }
+        assert_eq!(payload, packet[20..]);
+    }
+
+    #[test]
+    fn keeps_the_id_of_a_fragmentable_datagram() {
+        let mut packet = datagram(3000);
+        packet[4..6].copy_from_slice(&[0xab, 0xcd]);
+        packet[6] = 0x00; // 清掉 DF
+        recompute_ipv4_header_checksum(&mut packet);
+        let fragments = fragment_ipv4(&packet, 1500, 0x1234).unwrap();
+        assert!(fragments.iter().all(|frag| frag[4..6] == [0xab, 0xcd]));
+
+        // 分片 ID 一个个往后发，转一圈跳过 0
+        assert_eq!(next_fragment_id(7), 8);
+        assert_eq!(next_fragment_id(u16::MAX), 1);
+
+        // 装得下的、没拷全的都不拆
+        assert!(fragment_ipv4(&packet, 3000, 1).is_none());
+        assert!(fragment_ipv4(&packet[..1000], 500, 1).is_none());
+    }
+}
@@ -0,0 +1,156 @@
+// ==========================================
+// 收包调度器 (按 NFQUEUE 编号加权轮询)
+// 在 qdisc 之前先控制 "谁能多快进流水线"：每圈每个队列最多收 weight 个包，
+// 批次在半路截断时记住断在哪个队列、还剩多少额度，下一批接着收
+// ==========================================
+
+// 一次 recv 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    Got,       // 收到一个包，已经送进流水线
+    Empty,     // 这个队列暂时没包了
+    Saturated, // 收到了，但下游已经满到丢包：本批次别再从这个队列收
+}
+
+pub struct IngestScheduler {
+    weights: Vec<usize>,
+    deficit: Vec<usize>, // 本圈剩余额度
+    start: usize,        // 本圈从哪个队列起
+    offset: usize,       // 本圈已经走完了几个队列 (批次截断在圈中间时，下一批从这里接着走完这一圈)
+}
+
+impl IngestScheduler {
+    // weights[i] 是队列 i 每圈的额度，0 按 1 算 (不能把一个队列彻底饿死)
+    pub fn new(weights: Vec<usize>) -> Self {
+        let weights: Vec<usize> = weights.into_iter().map(|w| w.max(1)).collect();
+        Self {
+            deficit: weights.clone(),
+            weights,
+            start: 0,
+            offset: 0,
+        }
+    }
+
+    // 一批最多收 batch_limit 个包，返回实际收到的个数
+    pub fn run(&mut self, batch_limit: usize, mut recv: impl FnMut(usize) -> Pull) -> usize {
+        let n = self.weights.len();
+        let mut saturated = vec![false; n];
+        let mut received = 0;
+
+        loop {
+            // 接着上一批没走完的半圈时，这圈前面的队列本圈已经收过了，空转一圈不说明大家都没包
+            let full_round = self.offset == 0;
+            let mut no_packet = true;
+            while self.offset < n {
+                let q = (self.start + self.offset) % n;
+                while self.deficit[q] > 0 && !saturated[q] {
+                    if received >= batch_limit {
+                        // 截断在这里：下一批从 q 接着收，q 剩余的额度保留
+                        return received;
+                    }
+                    match recv(q) {
+                        Pull::Empty => break,
+                        pull => {
+                            received += 1;
+                            no_packet = false;
+                            self.deficit[q] -= 1;
+                            saturated[q] = pull == Pull::Saturated;
+                        }
+                    }
+                }
+                // 走完这个队列 (用光额度 / 空了 / 满了)，下一圈重新充值
+                self.deficit[q] = self.weights[q];
+                self.offset += 1;
+            }
+            // 整圈结束，起点轮转一格
+            self.start = (self.start + 1) % n;
+            self.offset = 0;
+            if no_packet && full_round {
+                return received;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // 所有队列都一直有包：跑 batches 批，记下每批各自收了哪些队列
+    fn backlogged(
+        sched: &mut IngestScheduler,
+        batch_limit: usize,
+        batches: usize,
+    ) -> Vec<Vec<usize>> {
+        (0..batches)
+            .map(|_| {
+                let mut order = Vec::new();
+                sched.run(batch_limit, |q| {
+                    order.push(q);
+                    Pull::Got
+                });
+                order
+            })
+            .collect()
+    }
+
+    #[test]
+    fn starting_queue_rotates_across_batches() {
+        // 一批只收 2 个、3 个队列：固定从 0 开始的话 2 号永远轮不上
+        let mut sched = IngestScheduler::new(vec![1, 1, 1]);
+        let batches = backlogged(&mut sched, 2, 30);
+        for q in 0..3 {
+            assert!(
+                batches.iter().any(|order| order[0] == q),
+                "{q} 从没打过头阵"
+            );
+        }
+
+        let mut counts = [0; 3];
+        for q in batches.into_iter().flatten() {
+            counts[q] += 1;
+        }
+        assert_eq!(counts, [20, 20, 20]);
+    }
+
+    #[test]
+    fn saturated_queue_is_not_polled_again_in_the_batch() {
+        let mut sched = IngestScheduler::new(vec![4, 4]);
+        let calls = [Cell::new(0), Cell::new(0)];
+        let polled = || [calls[0].get(), calls[1].get()];
+        let mut left = 10;
+        let mut recv = |q: usize| {
+            calls[q].set(calls[q].get() + 1);
+            match q {
+                0 => Pull::Saturated,
+                _ if left > 0 => {
+                    left -= 1;
+                    Pull::Got
+                }
+                _ => Pull::Empty,
+            }
+        };
+        // 1 号收了好几圈直到问出空，0 号满了之后这一批里只被问过那一次
+        assert_eq!(sched.run(100, &mut recv), 11);
+        assert_eq!(polled(), [1, 12]);
+
+        // 下一批重新给 0 号机会
+        assert_eq!(sched.run(100, &mut recv), 1);
+        assert_eq!(polled(), [2, 14]);
+    }
+
+    #[test]
+    fn weights_set_the_pull_ratio_under_saturation() {
+        // 批次大小故意不整除一圈的额度，截断后剩下的额度要能接上
+        let mut sched = IngestScheduler::new(vec![3, 1]);
+        let mut counts = [0usize; 2];
+        for q in backlogged(&mut sched, 7, 400 / 7).into_iter().flatten() {
+            counts[q] += 1;
+        }
+        let total = counts[0] + counts[1];
+        assert_eq!(total, 7 * (400 / 7));
+        assert!(counts[0].abs_diff(3 * counts[1]) <= 3, "counts={counts:?}");
+    }
+}
@@ -1,52 +1,514 @@
 use std::{
     collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
     time::{Duration, Instant},
 };
 // 引入模块
+mod checksum;
+mod classifier;
+mod clock;
+mod config;
+mod control;
 mod five_tuple;
+mod fragment;
+mod ingest;
 mod modifier;
 mod nfq_message;
 mod packet_context;
+mod pcap;
+mod pipeline;
 mod qdisc;
+mod rate_estimator;
+mod recv;
+mod replay;
+#[cfg(feature = "stats-http")]
+mod stats_http;
 mod token_bucket;
+mod verdict;
 
-use five_tuple::FiveTuple;
-use nfq::{Message as InnerMessage, Queue, Verdict};
+use classifier::{Combine, by_queue, when};
+use five_tuple::{FiveTuple, FlowKeyPolicy, random_seed, set_flow_hash_seed};
+use fragment::FragmentSender;
+use ingest::{IngestScheduler, Pull};
+use nfq::{Queue, Verdict};
+use recv::{RecvFault, RecvStats};
+use replay::Replayer;
 use token_bucket::TokenBucket;
+use verdict::{VerdictStats, send_release, send_verdict};
 
 use crate::{
+    config::{
+        ConfigError, ModifierMap, NfqueueConfig, PipelineConfig, build_modifiers, build_qdisc,
+    },
+    control::ControlServer,
     modifier::{
-        FragmentModifier, OverheadModifier, PacketModifier, PaddingModifier, TcpAckModifier,
-        TrueLengthModifier,
+        CostModel, DnsPriorityModifier, FragmentModifier, ModifierChainBuilder, OverheadModifier,
+        PaddingModifier, TcpAckModifier, TcpSeqModifier, TrueLengthModifier, apply_modifiers,
     },
     nfq_message::NfqMessage as Message,
     packet_context::PacketContext,
+    pipeline::{Packet, StandardPipeline},
     qdisc::{
-        Qdisc,
+        Qdisc, bucket_breakdown, drop_breakdown,
         leaf::HeadDropFifo,
-        scheduler::{ClassDrrQdisc, DualFairQdisc, HtbQdisc, SparseQdisc},
-        wrapper::{MonitorQdisc, TcpAckFilterQdisc, TtlDropWrapper},
+        scheduler::{ClassDrrQdisc, DualFairQdisc, HtbQdisc, QuantumScaling, SparseQdisc},
+        wrapper::{DropEvent, TcpAckFilterQdisc, TtlDropWrapper},
     },
 };
 
 const OVERHEAD: usize = 14 + 4 + 20 + 60;
 const OVERHEAD2: usize = 18 + 20;
 
+// WG 路径的加密块对齐
+const WG_BLOCK: usize = 16;
+
 const WG_MTU: usize = 1280;
 const ETH_MTU: usize = 1500;
 const BATCH_LIMIT: usize = 10000;
+// 每个包拷进用户态的字节数：整形只看头部就够了；
+// 要用 fragment 修改器的 split 模式真拆分片，得改成 0xFFFF 拷全包 (没拷全的包不拆，原样放行)
+const COPY_RANGE: u16 = 128;
+// 内核队列长度：满了之后新来的包按 FAIL_OPEN 处理
+const QUEUE_MAX_LEN: u32 = 10000;
+// 默认满了就丢 (宁可丢也不放没整形的包过去)；配置文件 [nfqueue] 里三项都能改
+const FAIL_OPEN: bool = false;
+// raw socket 发出去的后续分片打这个 mark，NFQUEUE 规则要用 `-m mark ! --mark` 放过它们
+const FRAGMENT_MARK: u32 = 0x4e51;
+// 每圈从各 NFQUEUE 收包的额度 (下标就是队列号)：VIP 的 2/3 号收得是别人的两倍
+const RX_WEIGHTS: [usize; 6] = [1, 1, 2, 2, 1, 1];
+// 调度 key 的粒度，main 里的大类分类器要读 src/dst，所以默认保留完整五元组
+const FLOW_KEY_POLICY: FlowKeyPolicy = FlowKeyPolicy::Full;
+// flow_hash 的种子：None 每次启动随机一个 (流状态存档是按上一次的种子哈希的，跟着作废)，
+// 想让两次运行的哈希完全一样 (对比回放结果、跨重启保留流状态) 就写死一个；回放模式没配就用 0
+const FLOW_HASH_SEED: Option<u64> = None;
+const IDLE_TIMEOUT: Duration = Duration::from_micros(100); // 稍微缩短 sleep 时间以提高响应
+// 发包节奏对齐令牌：树里积着包、只差令牌时，精确睡到最早能放的那一刻再出队，
+// 而不是一轮轮空转、令牌一冒头就整批倒出去 (出包更匀，代价是多一点 CPU 自旋)
+const TX_PACING: bool = false;
+// 按令牌睡也最多睡这么久，期间内核队列里新来的包 (可能是更高优的) 得有机会收上来
+const PACING_MAX_SLEEP: Duration = Duration::from_millis(1);
+
+// 打开后每个被丢的包打一行 (墙上时间 + 原因)，方便和 pcap 对时间线；量大时别开
+const LOG_DROPS: bool = false;
+// 打开后起一个后台线程订阅丢包事件流，每个报表周期打印丢得最狠的几条流
+const TRACE_DROP_FLOWS: bool = false;
+// 打开后监控面板每秒多报一行调度器自身的 enqueue/dequeue 耗时 p50/p99
+const MEASURE_DECISION_LATENCY: bool = false;
+// 监控面板刷新间隔；完全空闲的周期不打印
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// 运行时控制通道，用法: echo "set-rate global 8000000" | socat - UNIX-CONNECT:/run/nfq_shaper.sock
+const CONTROL_SOCKET: &str = "/run/nfq_shaper.sock";
+// 统计 HTTP 接口监听的地址 (编译时开了 stats-http 才有)，GET /stats 拿 JSON
+#[cfg(feature = "stats-http")]
+const STATS_ADDR: &str = "127.0.0.1:9100";
+
+// 退出前把流状态存在这里，下次启动读回来，长寿大流不会因为重启被当成新流插队
+const STATE_FILE: &str = "/run/nfq_shaper.state";
+
+// 退出时还压在 qdisc 里的包统一怎么判：Accept 放行 (宁可不限速也别丢)，想更狠可以改成 Drop
+const SHUTDOWN_VERDICT: Verdict = Verdict::Accept;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_shutdown_signal(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+fn install_shutdown_handler() {
+    let handler = on_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+// 默认 T 是 NFQUEUE 的包；回放模式拿同一套装配代码，包换成抓包里的字节
+type Topology<T = Message> = (Box<dyn Qdisc<T, FiveTuple>>, ModifierMap<T, FiveTuple>);
+
+// 每个 NFQUEUE 队列绑定时交给内核的参数
+#[derive(Debug, Clone, Copy)]
+struct QueueSettings {
+    copy_range: u16,
+    max_len: u32,
+    fail_open: bool,
+}
+
+impl QueueSettings {
+    fn from_config(config: &NfqueueConfig) -> Self {
+        Self {
+            copy_range: config.copy_range.unwrap_or(COPY_RANGE),
+            max_len: config.max_len.unwrap_or(QUEUE_MAX_LEN),
+            fail_open: config.fail_open.unwrap_or(FAIL_OPEN),
+        }
+    }
+}
+
+// configure_queue 要动的那几个 nfq::Queue 设置，抽出来好在测试里换成只记账的假队列
+trait QueueKnobs {
+    fn bind(&mut self, queue_num: u16) -> std::io::Result<()>;
+    fn set_copy_range(&mut self, queue_num: u16, range: u16) -> std::io::Result<()>;
+    fn set_recv_conntrack(&mut self, queue_num: u16, enabled: bool) -> std::io::Result<()>;
+    fn set_queue_max_len(&mut self, queue_num: u16, len: u32) -> std::io::Result<()>;
+    fn set_fail_open(&mut self, queue_num: u16, enabled: bool) -> std::io::Result<()>;
+    fn set_nonblocking(&mut self, nonblocking: bool);
+}
 
-fn make_queue(queue_num: usize) -> Result<Queue, std::io::Error> {
+impl QueueKnobs for Queue {
+    fn bind(&mut self, queue_num: u16) -> std::io::Result<()> {
+        Queue::bind(self, queue_num)
+    }
+
+    fn set_copy_range(&mut self, queue_num: u16, range: u16) -> std::io::Result<()> {
+        Queue::set_copy_range(self, queue_num, range)
+    }
+
+    fn set_recv_conntrack(&mut self, queue_num: u16, enabled: bool) -> std::io::Result<()> {
+        Queue::set_recv_conntrack(self, queue_num, enabled)
+    }
+
+    fn set_queue_max_len(&mut self, queue_num: u16, len: u32) -> std::io::Result<()> {
+        Queue::set_queue_max_len(self, queue_num, len)
+    }
+
+    fn set_fail_open(&mut self, queue_num: u16, enabled: bool) -> std::io::Result<()> {
+        Queue::set_fail_open(self, queue_num, enabled)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) {
+        Queue::set_nonblocking(self, nonblocking)
+    }
+}
+
+fn make_queue(queue_num: usize, settings: QueueSettings) -> Result<Queue, std::io::Error> {
     let mut q = Queue::open()?;
+    configure_queue(&mut q, queue_num, settings)?;
+    Ok(q)
+}
+
+fn configure_queue(
+    q: &mut impl QueueKnobs,
+    queue_num: usize,
+    settings: QueueSettings,
+) -> Result<(), std::io::Error> {
     let queue_num: u16 = queue_num as u16;
     q.bind(queue_num)?;
-    q.set_copy_range(queue_num, 128)?;
-    q.set_queue_max_len(queue_num, 10000)?;
+    q.set_copy_range(queue_num, settings.copy_range)?;
+    // 让内核把 conntrack 状态带上来 (ctx.conn_state)；没加载 nf_conntrack_netlink 会失败，只是少个戳，照样能跑
+    if let Err(e) = q.set_recv_conntrack(queue_num, true) {
+        eprintln!("⚠️ 队列 {} 拿不到 conntrack 信息: {}", queue_num, e);
+    }
+    q.set_queue_max_len(queue_num, settings.max_len)?;
+    q.set_fail_open(queue_num, settings.fail_open)?;
     q.set_nonblocking(true);
-    Ok(q)
+    Ok(())
+}
+
+// socket 坏了就换一个：旧的必须先关掉 (队列号还被它占着，新 socket 绑不上)，
+// 所以先开一个空 socket 顶替，旧的析构之后再绑
+// 旧 socket 上没回 verdict 的包由内核回收，事后补发的 verdict 会失败并记进 VerdictStats
+fn rebind_queue(
+    queues: &mut [Queue],
+    queue_num: usize,
+    settings: QueueSettings,
+) -> Result<(), std::io::Error> {
+    let fresh = Queue::open()?;
+    drop(std::mem::replace(&mut queues[queue_num], fresh));
+    configure_queue(&mut queues[queue_num], queue_num, settings)
+}
+
+// 收发包那一侧的全部家当，pump 每一轮都要用
+struct Dataplane {
+    queues: Vec<Queue>,
+    stats: VerdictStats,
+    recv_stats: RecvStats,
+    ingest: IngestScheduler,
+    fragmenter: Option<FragmentSender>, // 只有配了 split 的链才开
+    settings: QueueSettings,            // 重建 socket 时照原样再配一遍
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--replay") {
+        replay(&args[2..]);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("--cost") {
+        cost_check(&args[2..]);
+        return;
+    }
+
+    // 带一个参数就按 TOML 配置装配，不带就用下面写死的默认拓扑
+    let ((root, modifiers), nfqueue, splits) = match std::env::args().nth(1) {
+        Some(path) => match load_pipeline(&path) {
+            Ok(built) => built,
+            Err(e) => {
+                eprintln!("❌ {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => (default_pipeline(), NfqueueConfig::default(), false),
+    };
+    let queue_settings = QueueSettings::from_config(&nfqueue);
+
+    // 4. 最外层套上监控大屏
+    let mut pipeline = StandardPipeline::new(root, modifiers, FLOW_KEY_POLICY);
+    pipeline.set_report_interval(REPORT_INTERVAL);
+    if MEASURE_DECISION_LATENCY {
+        pipeline.enable_decision_latency();
+    }
+    if TRACE_DROP_FLOWS {
+        spawn_drop_tracer(pipeline.subscribe_drops(4096));
+    }
+    println!("🌳 拓扑: {}", pipeline.root().describe());
+    set_flow_hash_seed(FLOW_HASH_SEED.unwrap_or_else(random_seed));
+    if let Ok(blob) = std::fs::read(STATE_FILE) {
+        match pipeline.restore(&blob) {
+            Some(restored) => println!("♻️ 从 {} 恢复了 {} 个节点的流状态", STATE_FILE, restored),
+            None => println!("♻️ {} 是按另一个 flow_hash 种子存的，流状态作废", STATE_FILE),
+        }
+    }
+
+    let queues: Vec<Queue> = (0..RX_WEIGHTS.len())
+        .map(|i| make_queue(i, queue_settings).expect("failed to create queue"))
+        .collect();
+
+    // 只有配了 split 的链才要 raw socket；没有 CAP_NET_RAW 开不了：要求真分片的包退回原样放行，其它不受影响
+    let fragmenter = if splits {
+        match FragmentSender::open(FRAGMENT_MARK) {
+            Ok(sender) => Some(sender),
+            Err(e) => {
+                eprintln!("⚠️ 分片 raw socket 打开失败，split 模式不生效: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut dataplane = Dataplane {
+        queues,
+        stats: VerdictStats::new(),
+        recv_stats: RecvStats::new(),
+        ingest: IngestScheduler::new(RX_WEIGHTS.to_vec()),
+        fragmenter,
+        settings: queue_settings,
+    };
+
+    // 控制通道起不来不影响整形，只是没法热调参
+    let mut control = match ControlServer::bind(CONTROL_SOCKET) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            eprintln!("⚠️ 控制通道 {} 启动失败: {}", CONTROL_SOCKET, e);
+            None
+        }
+    };
+
+    #[cfg(feature = "stats-http")]
+    let mut stats = match stats_http::StatsServer::bind(STATS_ADDR, REPORT_INTERVAL) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            eprintln!("⚠️ 统计接口 {} 启动失败: {}", STATS_ADDR, e);
+            None
+        }
+    };
+
+    install_shutdown_handler();
+
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        if let Some(control) = control.as_mut() {
+            control.poll(pipeline.root_mut());
+        }
+        #[cfg(feature = "stats-http")]
+        if let Some(stats) = stats.as_mut() {
+            stats.publish(pipeline.root());
+        }
+        pump(&mut dataplane, &mut pipeline, IDLE_TIMEOUT);
+    }
+
+    // 先存状态再清仓：flush 会把流表一起清掉
+    if let Err(e) = std::fs::write(STATE_FILE, pipeline.snapshot()) {
+        eprintln!("⚠️ 流状态保存到 {} 失败: {}", STATE_FILE, e);
+    }
+
+    // 收到 Ctrl-C / SIGTERM：把肚子里的包全倒出来补发 verdict，一个都不许漏
+    let Dataplane {
+        mut queues,
+        stats: mut verdict_stats,
+        recv_stats,
+        fragmenter,
+        ..
+    } = dataplane;
+    drain(&mut queues, &mut pipeline, &mut verdict_stats, SHUTDOWN_VERDICT);
+    println!(
+        "👋 退出：verdict 成功 {} 次，失败 {} 次",
+        verdict_stats.sent, verdict_stats.failed
+    );
+    if recv_stats.overruns + recv_stats.faults > 0 {
+        println!(
+            "📥 recv：缓冲溢出 {} 次，其它错误 {} 次，重建 socket {} 次",
+            recv_stats.overruns, recv_stats.faults, recv_stats.rebinds
+        );
+    }
+    if let Some(f) = fragmenter.as_ref()
+        && f.datagrams + f.failed > 0
+    {
+        println!(
+            "🧩 真分片：拆了 {} 个包，另发 {} 片，失败 {} 次",
+            f.datagrams, f.fragments, f.failed
+        );
+    }
+    if pipeline.truncated_copies() > 0 {
+        println!(
+            "✂️ 截断拷贝 {} 次 (按 IP 头里的长度计费)",
+            pipeline.truncated_copies()
+        );
+    }
+    for (path, name, stats) in bucket_breakdown(pipeline.root()) {
+        println!(
+            "🪣 {} @ {}: 放行 {:.1}MB，拒绝 {} 次 / {:.1}MB",
+            name,
+            path,
+            stats.bytes_passed as f64 / 1e6,
+            stats.deny_events,
+            stats.bytes_denied as f64 / 1e6
+        );
+    }
+    for origin in drop_breakdown(pipeline.root()) {
+        println!(
+            "🪦 {:>8} 个 [{:?}] @ {}",
+            origin.count, origin.reason, origin.path
+        );
+    }
+}
+
+// ==========================================
+// 记账自检：nfq_shaper --cost <len> [pipeline.toml]
+// 造一个真实长度 len 的 UDP 包，挨个队列过一遍修改器链，打印最终 cost / 帧数；
+// 没给配置就是写死的默认拓扑，此时再拿 CostModel 按同样的参数算一遍对账，对不上退出码非零；
+// 多帧的包顺带打一份精确分片下的账
+// ==========================================
+fn cost_check(args: &[String]) {
+    let Some(Ok(len)) = args.first().map(|a| a.parse::<usize>()) else {
+        eprintln!("用法: nfq_shaper --cost <len> [pipeline.toml]");
+        std::process::exit(2);
+    };
+    let (modifiers, models) = match args.get(1) {
+        Some(config) => match load_pipeline::<Vec<u8>>(config) {
+            Ok(((_, modifiers), _, _)) => (modifiers, HashMap::new()),
+            Err(e) => {
+                eprintln!("❌ {}: {}", config, e);
+                std::process::exit(1);
+            }
+        },
+        None => (default_pipeline::<Vec<u8>>().1, default_cost_models()),
+    };
+
+    // 最小的 IPv4/UDP 头，让 true_length 按 IP 头实测
+    let len = len.clamp(20, u16::MAX as usize);
+    let mut packet = vec![0u8; len];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = 17;
+
+    let mut queues: Vec<_> = modifiers.keys().copied().collect();
+    queues.sort_unstable();
+    let mut mismatches = 0;
+    for q in queues {
+        let key = FiveTuple::from(packet.as_slice());
+        let mut ctx = PacketContext::new(packet.clone(), key, 0, q, 0, len);
+        apply_modifiers(&mut ctx, &modifiers[&q]);
+        print!(
+            "📐 队列 {}: {} 字节 → cost {} ({} 帧)",
+            q, ctx.pkt_len, ctx.cost, ctx.frames
+        );
+        match models.get(&q) {
+            Some(model) if model.cost(ctx.pkt_len) != ctx.cost => {
+                mismatches += 1;
+                println!(" ❌ 模型算的是 {}", model.cost(ctx.pkt_len));
+            }
+            // 顺带给出精确分片 (FragmentModifier::precise) 下的账，看默认的 ceil 算法偏了多少
+            Some(model) if ctx.frames > 1 => {
+                let precise = model.precise();
+                println!(
+                    " ✅ 精确分片: cost {} ({} 帧)",
+                    precise.cost(ctx.pkt_len),
+                    precise.frames(ctx.pkt_len)
+                );
+            }
+            Some(_) => println!(" ✅"),
+            None => println!(),
+        }
+    }
+    if mismatches > 0 {
+        std::process::exit(1);
+    }
+}
+
+// 默认拓扑里每个队列的记账参数，和 default_pipeline 里的修改器链一一对应
+fn default_cost_models() -> HashMap<usize, CostModel> {
+    let wg = CostModel::new(WG_MTU, OVERHEAD).padded(WG_BLOCK);
+    let eth = CostModel::new(ETH_MTU, OVERHEAD2);
+    [(0, wg), (1, wg), (2, wg), (3, wg), (4, eth), (5, eth)]
+        .into_iter()
+        .collect()
+}
+
+// ==========================================
+// 回放模式：nfq_shaper --replay <pcap> <queue_num> [pipeline.toml]
+// 不开 NFQUEUE、不要 root，抓包里的 IPv4 包全当成从 queue_num 收上来的，
+// 过一遍和线上一样的修改器链 + 调度树，放完打印监控面板和丢包溯源
+// ==========================================
+fn replay(args: &[String]) {
+    // --tx-pacing 可以放在任意位置，同一个文件开关各放一遍就能对比出包间隔的抖动
+    let tx_pacing = TX_PACING || args.iter().any(|a| a == "--tx-pacing");
+    let args: Vec<&String> = args.iter().filter(|a| *a != "--tx-pacing").collect();
+    let (Some(path), Some(queue_num)) = (args.first(), args.get(1)) else {
+        eprintln!("用法: nfq_shaper --replay <pcap> <queue_num> [pipeline.toml] [--tx-pacing]");
+        std::process::exit(2);
+    };
+    let Ok(queue_num) = queue_num.parse::<usize>() else {
+        eprintln!("❌ 队列号得是个数字: {}", queue_num);
+        std::process::exit(2);
+    };
+    let (root, modifiers) = match args.get(2) {
+        Some(config) => match load_pipeline(config) {
+            Ok((built, _, _)) => built, // 回放不碰 NFQUEUE，[nfqueue] 不看
+            Err(e) => {
+                eprintln!("❌ {}: {}", config, e);
+                std::process::exit(1);
+            }
+        },
+        None => default_pipeline(),
+    };
+
+    set_flow_hash_seed(FLOW_HASH_SEED.unwrap_or(0));
+    let mut replayer = Replayer::new(root, modifiers, FLOW_KEY_POLICY, queue_num);
+    replayer.set_report_interval(REPORT_INTERVAL);
+    replayer.set_idle_timeout(IDLE_TIMEOUT);
+    replayer.set_tx_pacing(tx_pacing);
+    println!("🌳 拓扑: {}", replayer.root().describe());
+    if let Err(e) = replayer.run(path) {
+        eprintln!("❌ 回放 {} 失败: {}", path, e);
+        std::process::exit(1);
+    }
+    replayer.print_summary();
+}
+
+// 第三项：有没有哪条链要真拆分片 (要不要开 raw socket)
+fn load_pipeline<T: AsRef<[u8]> + AsMut<[u8]> + 'static>(
+    path: &str,
+) -> Result<(Topology<T>, NfqueueConfig, bool), ConfigError> {
+    let config = PipelineConfig::load(path)?;
+    let topology = (build_qdisc(&config.root)?, build_modifiers(&config));
+    Ok((topology, config.nfqueue, config.splits()))
+}
+
+fn default_pipeline<T: AsRef<[u8]> + AsMut<[u8]> + 'static>() -> Topology<T> {
     let global_rate = 6.9 * 1000.0 * 1000.0 / 8.0;
     let global_burst = 1024.0 * 290.0;
     let global_bucket = TokenBucket::new(global_rate, global_burst, "Global");
@@ -61,30 +523,34 @@ fn main() {
     let low_priority_bucket =
         TokenBucket::new(low_priority_rate, low_priority_burst, "low_priority");
 
-    let mut modifiers: HashMap<usize, Vec<Box<dyn PacketModifier<_, _>>>> = HashMap::new();
+    let mut modifiers: ModifierMap<T, FiveTuple> = HashMap::new();
 
     for q in [0, 1, 2, 3] {
         modifiers.insert(
             q,
-            vec![
-                Box::new(TrueLengthModifier::new()),
-                Box::new(TcpAckModifier::new()),
-                Box::new(PaddingModifier::new(16)),
-                Box::new(FragmentModifier::new(WG_MTU)),
-                Box::new(OverheadModifier::new(OVERHEAD)),
-            ],
+            ModifierChainBuilder::new()
+                .with(TrueLengthModifier::new())
+                .with(TcpAckModifier::new())
+                .with(TcpSeqModifier::new())
+                .with(DnsPriorityModifier::new())
+                .with(PaddingModifier::new(WG_BLOCK, 0, usize::MAX))
+                .with(FragmentModifier::new(WG_MTU))
+                .with(OverheadModifier::new(OVERHEAD))
+                .build(),
         );
     }
 
     for q in [4, 5] {
         modifiers.insert(
             q,
-            vec![
-                Box::new(TrueLengthModifier::new()),
-                Box::new(TcpAckModifier::new()),
-                Box::new(FragmentModifier::new(ETH_MTU)),
-                Box::new(OverheadModifier::new(OVERHEAD2)),
-            ],
+            ModifierChainBuilder::new()
+                .with(TrueLengthModifier::new())
+                .with(TcpAckModifier::new())
+                .with(TcpSeqModifier::new())
+                .with(DnsPriorityModifier::new())
+                .with(FragmentModifier::new(ETH_MTU))
+                .with(OverheadModifier::new(OVERHEAD2))
+                .build(),
         );
     }
 
@@ -95,22 +561,26 @@ fn main() {
     //    - 内部的小包流走 Fifo
     //    - 内部的大文件流走 Drr (公平分配，量子设为 1500)
     let default_qdisc = {
-        let sparse_leaf: Box<dyn Qdisc<Message, FiveTuple>> =
+        let sparse_leaf: Box<dyn Qdisc<T, FiveTuple>> =
             Box::new(TtlDropWrapper::new(10, Box::new(HeadDropFifo::new(2048))));
-        let class_bulk_leaf: Box<dyn Qdisc<Message, FiveTuple>> = Box::new(ClassDrrQdisc::new(
+        let class_bulk_leaf: Box<dyn Qdisc<T, FiveTuple>> = Box::new(ClassDrrQdisc::new(
             Box::new(
-                |ctx: &PacketContext<Message, FiveTuple>| match ctx.queue_num {
+                // 分类器在每个包的热路径上，panic 一次整台机器断网：没想到的队列号按源地址兜底
+                |ctx: &PacketContext<T, FiveTuple>| match ctx.queue_num {
                     0 | 1 => (ctx.key.dst, 1500),
-                    4 | 5 => (ctx.key.src, 1500),
-                    _ => panic!(),
+                    _ => (ctx.key.src, 1500), // 4 | 5
                 },
             ),
-            Box::new(|| {
+            Box::new(|_| {
                 Box::new(ClassDrrQdisc::new(
-                    Box::new(|ctx: &PacketContext<Message, FiveTuple>| (ctx.key.clone(), 1500)),
-                    Box::new(|| Box::new(HeadDropFifo::new(2048))),
+                    Box::new(|ctx: &PacketContext<T, FiveTuple>| (ctx.key.clone(), 1500)),
+                    Box::new(|_| Box::new(HeadDropFifo::new(2048))),
+                    QuantumScaling::Fixed,
+                    None,
                 ))
             }),
+            QuantumScaling::Fixed,
+            None,
         ));
         let class_bulk_leaf_ack_filter = Box::new(TtlDropWrapper::new(
             100,
@@ -120,10 +590,12 @@ fn main() {
     };
 
     let high_qdisc = {
-        let sparse_leaf: Box<dyn Qdisc<Message, FiveTuple>> = Box::new(HeadDropFifo::new(2048));
-        let drr_leaf: Box<dyn Qdisc<Message, FiveTuple>> = Box::new(ClassDrrQdisc::new(
-            Box::new(|ctx: &PacketContext<Message, FiveTuple>| (ctx.key.clone(), 1500)),
-            Box::new(|| Box::new(HeadDropFifo::new(2048))),
+        let sparse_leaf: Box<dyn Qdisc<T, FiveTuple>> = Box::new(HeadDropFifo::new(2048));
+        let drr_leaf: Box<dyn Qdisc<T, FiveTuple>> = Box::new(ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<T, FiveTuple>| (ctx.key.clone(), 1500)),
+            Box::new(|_| Box::new(HeadDropFifo::new(2048))),
+            QuantumScaling::Fixed,
+            None,
         ));
         let drr_leaf_ack_filter = Box::new(TcpAckFilterQdisc::new(drr_leaf));
         let long_leaf = Box::new(TtlDropWrapper::new(
@@ -136,111 +608,319 @@ fn main() {
             short_leaf,
             long_leaf,
             1500,
-            Box::new(|ctx| match ctx.queue_num {
-                2 => true,
-                3 => false,
-                _ => panic!(),
-            }),
+            // 只有 2 号走短连接通道，3 号和没想到的队列号一律进长连接通道，不 panic
+            by_queue(2),
         ))
     };
 
-    let htb = HtbQdisc::new(
+    let mut htb = HtbQdisc::new(
         high_qdisc,
         default_qdisc,
         high_priority_bucket,
         low_priority_bucket,
         global_bucket,
-        high_priority_burst as usize,
-        low_priority_burst as usize,
-        Box::new(|ctx| ctx.queue_num == 2 || ctx.queue_num == 3),
+        when(|ctx| ctx.is_dns).or(by_queue(2)).or(by_queue(3)),
     );
+    htb.set_reserves(high_priority_burst as usize, low_priority_burst as usize);
 
-    // 4. 最外层套上监控大屏
-    let mut pipeline = MonitorQdisc::new("Root", Box::new(htb));
-
-    let mut queues: Vec<Queue> = (0..6)
-        .map(|i| make_queue(i).expect("failed to create queue"))
-        .collect();
+    (Box::new(htb), modifiers)
+}
 
-    loop {
-        let mut working = false;
+// ==========================================
+// 退出前清仓：flush 整棵树，逐个按 queue_num 归还 verdict，判过死刑的照样发 Drop
+// ==========================================
+fn drain(
+    queues: &mut [Queue],
+    pipeline: &mut StandardPipeline,
+    stats: &mut VerdictStats,
+    verdict: Verdict,
+) {
+    let (queued, dropped) = pipeline.flush();
+    for ctx in queued {
+        let q = ctx.queue_num;
+        send_verdict(&mut queues[q], q, ctx.msg.into(), verdict, stats);
+    }
+    for ctx in dropped {
+        let q = ctx.queue_num;
+        send_verdict(&mut queues[q], q, ctx.msg.into(), Verdict::Drop, stats);
+    }
+}
 
-        let mut packet_count = 0;
+// 丢包事件流的消费端：在自己的线程里按流攒一个周期，只报字节数最多的前 5 条
+fn spawn_drop_tracer(rx: mpsc::Receiver<DropEvent>) {
+    std::thread::spawn(move || {
+        // flow_hash -> (最近一次丢包的事件, 包数, 字节数)
+        let mut window: HashMap<u64, (DropEvent, u64, u64)> = HashMap::new();
+        let mut last_report = Instant::now();
         loop {
-            if packet_count >= BATCH_LIMIT {
-                break;
-            }
-            let mut no_packet = true;
-            for i in 0..6 {
-                let queue_num = i;
-                match queues[i].recv() {
-                    Ok(msg) => {
-                        working = true;
-                        packet_count += 1;
-                        no_packet = false;
-
-                        let key = FiveTuple::from(msg.get_payload());
-
-                        let mut ctx = PacketContext {
-                            msg: Message::from(msg),
-                            key,
-                            pkt_len: 0,
-                            cost: 0,
-                            queue_num,
-                            arrival_time: Instant::now(),
-                            frames: 1,
-                            is_pure_ack: false,
-                            tcp_ack_num: 0,
-                        };
-
-                        if let Some(modifiers) = modifiers.get(&queue_num) {
-                            for modifier in modifiers {
-                                modifier.process(&mut ctx);
-                            }
-                        }
-
-                        pipeline.enqueue(ctx);
-                    }
-                    Err(_) => {
-                        continue;
-                    }
+            match rx.recv_timeout(REPORT_INTERVAL) {
+                Ok(event) => {
+                    let entry = window.entry(event.flow_hash).or_insert((event, 0, 0));
+                    entry.0 = event;
+                    entry.1 += 1;
+                    entry.2 += event.cost as u64;
                 }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
             }
-            if no_packet {
-                break;
+            if last_report.elapsed() < REPORT_INTERVAL {
+                continue;
+            }
+            last_report = Instant::now();
+            let mut flows: Vec<_> = window.drain().map(|(_, v)| v).collect();
+            flows.sort_unstable_by_key(|f| std::cmp::Reverse(f.2));
+            for (event, pkts, bytes) in flows.iter().take(5) {
+                println!(
+                    "🎯 流 {:016x} (队列 {}) 丢 {} 包 / {}B，最近一次 {:?} 前 ({:?})",
+                    event.flow_hash,
+                    event.queue_num,
+                    pkts,
+                    bytes,
+                    event.at.elapsed(),
+                    event.reason
+                );
             }
         }
+    });
+}
 
-        loop {
-            let mut send_work_done = false;
-
-            if let Some(msg) = pipeline.dequeue() {
-                send_work_done = true;
+// 墙上时间精确到微秒，和 tcpdump 默认的时间戳格式对得上
+fn log_drop(ctx: &Packet) {
+    let wall = match ctx.arrival_wall {
+        Some(t) => chrono::DateTime::<chrono::Local>::from(t)
+            .format("%H:%M:%S%.6f")
+            .to_string(),
+        None => "-".to_string(),
+    };
+    println!(
+        "🗑️ [{}] 队列 {} #{} 丢弃 {}B ({:?})，排队 {:?}",
+        wall,
+        ctx.queue_num,
+        ctx.packet_id,
+        ctx.pkt_len,
+        ctx.drop_reason,
+        ctx.arrival_time.elapsed()
+    );
+}
 
-                let mut inner_msg: InnerMessage = msg.msg.into();
-                inner_msg.set_verdict(Verdict::Accept);
-                queues[msg.queue_num].verdict(inner_msg).ok();
+// ==========================================
+// 一轮完整的收包 -> 调度 -> 发 verdict
+// 收包最多 BATCH_LIMIT 个，全程没活干就小睡 idle_timeout
+// 已知限制：nfq 0.2.5 的 Queue 没有暴露底层 fd (没实现 AsRawFd)，收包挂不上 epoll，
+// 空闲时照旧每 idle_timeout 醒一次轮询，做不到 "空闲接近零 CPU"；开了 TX_PACING 也只有积着包时才睡到 next_ready
+// 手测：规则挂上、没有流量时跑起来，`pidstat -u -p $(pidof nfq_shaper) 1` 看空闲占用；
+// 换上能拿到 fd 的 nfq 版本后把下面的小睡换成 epoll_wait(fd, min(idle_timeout, next_ready))，再按同样的办法对比
+// ==========================================
+fn pump(dataplane: &mut Dataplane, pipeline: &mut StandardPipeline, idle_timeout: Duration) {
+    let Dataplane {
+        queues,
+        stats,
+        recv_stats,
+        ingest,
+        fragmenter,
+        settings,
+    } = dataplane;
+    // 本批次里 qdisc 已经满到丢包的队列不再 recv：收进来也只是为了丢，不如留在内核队列里
+    let received = ingest.run(BATCH_LIMIT, |queue_num| match queues[queue_num].recv() {
+        Ok(msg) => {
+            recv_stats.record_ok(queue_num);
+            if let Some(rejected) = pipeline.enqueue(queue_num, msg) {
+                let q = &mut queues[queue_num];
+                send_verdict(q, queue_num, rejected.msg.into(), Verdict::Drop, stats);
             }
-
-            if !send_work_done {
-                break;
+            if pipeline.last_enqueue_saturated() {
+                Pull::Saturated
             } else {
-                working = true;
+                Pull::Got
             }
         }
+        Err(e) => {
+            if recv_stats.record_err(queue_num, &e) == RecvFault::Fatal {
+                match rebind_queue(queues, queue_num, *settings) {
+                    Ok(()) => recv_stats.rebinds += 1,
+                    Err(e) => eprintln!("⚠️ 队列 {} 重建 socket 失败: {}", queue_num, e),
+                }
+            }
+            Pull::Empty
+        }
+    });
+    let mut working = received > 0;
+
+    while let Some(msg) = pipeline.dequeue() {
+        working = true;
+
+        let q = msg.queue_num;
+        send_release(&mut queues[q], msg, fragmenter.as_mut(), stats);
+    }
 
-        let expired_pkts = pipeline.collect_dropped();
-        if !expired_pkts.is_empty() {
-            working = true; // 处理垃圾也是在干活，别睡
-            for ctx in expired_pkts {
-                let mut msg: InnerMessage = ctx.msg.into(); // 注意这里你结构体里叫 msg
-                msg.set_verdict(Verdict::Drop);
-                queues[ctx.queue_num].verdict(msg).ok();
+    let expired_pkts = pipeline.collect_dropped();
+    if !expired_pkts.is_empty() {
+        working = true; // 处理垃圾也是在干活，别睡
+        for ctx in expired_pkts {
+            if LOG_DROPS {
+                log_drop(&ctx);
             }
+            let q = ctx.queue_num;
+            send_verdict(&mut queues[q], q, ctx.msg.into(), Verdict::Drop, stats);
+        }
+    }
+
+    if working {
+        return;
+    }
+    // 估出来的时刻已经过了却还是放不出来时退回小睡，免得原地空转
+    if TX_PACING
+        && let Some(at) = pipeline.next_ready()
+        && at > Instant::now()
+    {
+        clock::sleep_until(at.min(Instant::now() + PACING_MAX_SLEEP));
+    } else {
+        // 挂不上 epoll (见上面的已知限制)，只能退化成限时小睡
+        std::thread::sleep(idle_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::ClassId;
+
+    // 只记下 configure_queue 调了什么的假队列；conntrack 照线上没加载模块的样子报错
+    #[derive(Default)]
+    struct RecordingQueue {
+        calls: Vec<String>,
+    }
+
+    impl QueueKnobs for RecordingQueue {
+        fn bind(&mut self, queue_num: u16) -> std::io::Result<()> {
+            self.calls.push(format!("bind {queue_num}"));
+            Ok(())
+        }
+
+        fn set_copy_range(&mut self, queue_num: u16, range: u16) -> std::io::Result<()> {
+            self.calls.push(format!("copy_range {queue_num} {range}"));
+            Ok(())
+        }
+
+        fn set_recv_conntrack(&mut self, _queue_num: u16, _enabled: bool) -> std::io::Result<()> {
+            Err(std::io::Error::other("no nf_conntrack_netlink"))
+        }
+
+        fn set_queue_max_len(&mut self, queue_num: u16, len: u32) -> std::io::Result<()> {
+            self.calls.push(format!("max_len {queue_num} {len}"));
+            Ok(())
         }
 
-        if !working {
-            std::thread::sleep(Duration::from_micros(100)); // 稍微缩短 sleep 时间以提高响应
+        fn set_fail_open(&mut self, queue_num: u16, enabled: bool) -> std::io::Result<()> {
+            self.calls.push(format!("fail_open {queue_num} {enabled}"));
+            Ok(())
+        }
+
+        fn set_nonblocking(&mut self, nonblocking: bool) {
+            self.calls.push(format!("nonblocking {nonblocking}"));
+        }
+    }
+
+    fn settings(toml: &str) -> QueueSettings {
+        let config =
+            PipelineConfig::from_toml(&format!("{toml}\n[root]\ntype = \"fifo\"\nlimit = 16\n"))
+                .unwrap();
+        QueueSettings::from_config(&config.nfqueue)
+    }
+
+    fn configure(settings: QueueSettings) -> Vec<String> {
+        let mut queue = RecordingQueue::default();
+        configure_queue(&mut queue, 3, settings).unwrap();
+        queue.calls
+    }
+
+    #[test]
+    fn configured_queue_settings_reach_the_kernel() {
+        let tuned = settings("[nfqueue]\ncopy_range = 64\nmax_len = 2048\nfail_open = true");
+        assert_eq!(
+            configure(tuned),
+            [
+                "bind 3",
+                "copy_range 3 64",
+                "max_len 3 2048",
+                "fail_open 3 true",
+                "nonblocking true"
+            ]
+        );
+
+        // 没写的项用 main 里的默认值
+        let partial = settings("[nfqueue]\nmax_len = 512");
+        assert_eq!(
+            configure(partial),
+            [
+                "bind 3".to_string(),
+                format!("copy_range 3 {COPY_RANGE}"),
+                "max_len 3 512".to_string(),
+                format!("fail_open 3 {FAIL_OPEN}"),
+                "nonblocking true".to_string()
+            ]
+        );
+        let none = settings("");
+        assert_eq!(none.max_len, QUEUE_MAX_LEN);
+    }
+
+    #[test]
+    fn default_chains_match_their_cost_models() {
+        let (_, modifiers) = default_pipeline::<Vec<u8>>();
+        let models = default_cost_models();
+        assert_eq!(modifiers.len(), models.len());
+        for len in [40, 576, 1400, 1500, 9000] {
+            let mut packet = vec![0u8; len];
+            packet[0] = 0x45;
+            packet[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            packet[9] = 17;
+            for (q, model) in &models {
+                let key = FiveTuple::from(packet.as_slice());
+                let mut ctx = PacketContext::new(packet.clone(), key, 0, *q, 0, len);
+                apply_modifiers(&mut ctx, &modifiers[q]);
+                assert_eq!(ctx.cost, model.cost(len), "队列 {q}, {len} 字节");
+            }
+        }
+    }
+
+    #[test]
+    fn default_topology_routes_dns_into_the_high_class() {
+        let (mut root, modifiers) = default_pipeline::<Vec<u8>>();
+        // 4 号队列 (以太网路径) 不在高优名单里：只有 DNS 被拉进高优
+        for (dst_port, class) in [(53u16, ClassId::Vip), (443, ClassId::Default)] {
+            let mut packet = vec![0u8; 60];
+            packet[0] = 0x45;
+            packet[2..4].copy_from_slice(&60u16.to_be_bytes());
+            packet[9] = 17;
+            packet[22..24].copy_from_slice(&dst_port.to_be_bytes());
+            let key = FiveTuple::from(packet.as_slice());
+            let mut ctx = PacketContext::new(packet, key, 0, 4, 0, 60);
+            apply_modifiers(&mut ctx, &modifiers[&4]);
+            root.enqueue(ctx);
+            assert!(root.peek().is_some());
+            let ctx = root.dequeue().unwrap();
+            assert_eq!(ctx.egress_class, Some(class), "端口 {dst_port}");
+        }
+    }
+
+    // 没想到的队列号 (这里是 9，modifiers 里也没它的链) 两个分支都不 panic：DNS 进高优的长连接通道，其余走低优按源地址分
+    #[test]
+    fn unknown_queue_numbers_fall_back_instead_of_panicking() {
+        let (mut root, _) = default_pipeline::<Vec<u8>>();
+        for (dst_port, class) in [(53u16, ClassId::Vip), (443, ClassId::Default)] {
+            let mut packet = vec![0u8; 60];
+            packet[0] = 0x45;
+            packet[2..4].copy_from_slice(&60u16.to_be_bytes());
+            packet[9] = 17;
+            packet[22..24].copy_from_slice(&dst_port.to_be_bytes());
+            let key = FiveTuple::from(packet.as_slice());
+            let mut ctx = PacketContext::new(packet, key, 0, 9, 0, 60);
+            ctx.is_dns = dst_port == 53;
+            root.enqueue(ctx);
+            assert!(root.peek().is_some());
+            let ctx = root.dequeue().unwrap();
+            assert_eq!(ctx.queue_num, 9);
+            assert_eq!(ctx.egress_class, Some(class), "端口 {dst_port}");
         }
     }
 }
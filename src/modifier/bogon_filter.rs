@@ -0,0 +1,101 @@
+use std::net::Ipv4Addr;
+
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+// 任何接口上都不该作为源地址出现的网段：本网络、环回、组播、保留 (含受限广播)
+const MARTIANS: [(Ipv4Addr, u8); 4] = [
+    (Ipv4Addr::new(0, 0, 0, 0), 8),
+    (Ipv4Addr::new(127, 0, 0, 0), 8),
+    (Ipv4Addr::new(224, 0, 0, 0), 4),
+    (Ipv4Addr::new(240, 0, 0, 0), 4),
+];
+
+// ==========================================
+// 火星源地址过滤器 (Bogon Filter Modifier)
+// 源地址落在 bogon 网段里的包多半是伪造的，入队前就让 main 直接 Drop，不占调度树的位置
+// 内置的只有到哪都不合法的几段；私网段在 LAN 口是正常的源地址，
+// 只有挂在 WAN 入方向的队列上才该额外加进来
+// ==========================================
+pub struct BogonFilterModifier {
+    prefixes: Vec<(u32, u32)>, // (网络号, 掩码)
+}
+
+impl BogonFilterModifier {
+    // extra：内置火星网段之外还要丢的源网段 (网络号, 前缀长度)，不需要就传空
+    pub fn new(extra: &[(Ipv4Addr, u8)]) -> Self {
+        let prefixes = MARTIANS
+            .iter()
+            .chain(extra)
+            .map(|&(net, len)| {
+                let mask = u32::MAX.checked_shl(32 - len.min(32) as u32).unwrap_or(0);
+                (net.to_bits() & mask, mask)
+            })
+            .collect();
+        Self { prefixes }
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for BogonFilterModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        let data = ctx.msg.as_ref();
+
+        // 只认完整的 IPv4 头，源地址在第 12~15 字节
+        if data.len() < 20 || data[0] >> 4 != 4 {
+            return;
+        }
+
+        let src = u32::from_be_bytes([data[12], data[13], data[14], data[15]]);
+        if self.prefixes.iter().any(|&(net, mask)| src & mask == net) {
+            ctx.ingress_drop = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(src: Ipv4Addr) -> PacketContext<Vec<u8>, u64> {
+        let mut data = vec![0u8; 20];
+        data[0] = 0x45;
+        data[12..16].copy_from_slice(&src.octets());
+        PacketContext::new(data, 1, 1, 0, 0, 20)
+    }
+
+    fn dropped(filter: &BogonFilterModifier, src: [u8; 4]) -> bool {
+        let mut ctx = from(Ipv4Addr::from(src));
+        filter.process(&mut ctx);
+        ctx.ingress_drop
+    }
+
+    #[test]
+    fn martian_and_private_sources_are_dropped_before_enqueue() {
+        // WAN 入方向：内置网段之外再加上 RFC1918
+        let wan = BogonFilterModifier::new(&[
+            (Ipv4Addr::new(10, 0, 0, 0), 8),
+            (Ipv4Addr::new(172, 16, 0, 0), 12),
+            (Ipv4Addr::new(192, 168, 0, 0), 16),
+        ]);
+        for src in [
+            [10, 1, 2, 3],
+            [172, 31, 255, 255],
+            [192, 168, 1, 1],
+            [127, 0, 0, 1],
+            [224, 0, 0, 251],
+            [239, 255, 255, 250],
+            [0, 0, 0, 0],
+            [255, 255, 255, 255],
+        ] {
+            assert!(dropped(&wan, src), "{src:?} 应该被丢");
+        }
+        for src in [[8, 8, 8, 8], [172, 32, 0, 1], [1, 1, 1, 1]] {
+            assert!(!dropped(&wan, src), "{src:?} 不该被丢");
+        }
+
+        // LAN 口不加私网段：私网源地址照常放行，火星地址照丢
+        let lan = BogonFilterModifier::new(&[]);
+        assert!(!dropped(&lan, [192, 168, 1, 1]));
+        assert!(dropped(&lan, [127, 0, 0, 1]));
+    }
+}
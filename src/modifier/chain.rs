@@ -0,0 +1,137 @@
+use crate::modifier::{
+    BogonFilterModifier, DnsPriorityModifier, FlowRateModifier, FragmentModifier, MarkModifier,
+    MssClampModifier, OverheadModifier, PacketModifier, PaddingModifier, QuicModifier,
+    TcpAckModifier, TcpControlModifier, TcpSeqModifier, TrueLengthModifier, TtlGuardModifier,
+};
+
+// ==========================================
+// 修改器链构建器 (Modifier Chain Builder)
+// 修改器之间有先后依赖：cost 要先由 TrueLength 按 IP 头校正，Padding 在它上面补齐，
+// Fragment 按补齐后的 cost 数帧，Overhead 再按帧数加每帧开销。顺序写反不会报错，只会把账算歪
+// 所以每种修改器归一个阶段，build 时按阶段稳定排序：同一阶段内保持添加顺序
+// ==========================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModifierStage {
+    Filter,   // 入口判死 (bogon / TTL 守卫)，判了死刑的包后面的账不用算
+    Length,   // 按 IP 头校正 cost
+    Classify, // 只盖戳 / 改包头、不碰 cost 的 (TCP 特征、DNS、QUIC、速率、mark、MSS 钳制)
+    Padding,  // 在真实长度上补齐
+    Fragment, // 按补齐后的 cost 数帧 (split 模式还会加分片头)
+    Overhead, // 按帧数加每帧开销，永远最后
+}
+
+// 已知修改器各自属于哪个阶段
+pub trait ChainStage {
+    const STAGE: ModifierStage;
+}
+
+macro_rules! chain_stage {
+    ($($modifier:ty => $stage:ident),* $(,)?) => {
+        $(impl ChainStage for $modifier {
+            const STAGE: ModifierStage = ModifierStage::$stage;
+        })*
+    };
+}
+
+chain_stage! {
+    BogonFilterModifier => Filter,
+    TtlGuardModifier => Filter,
+    TrueLengthModifier => Length,
+    TcpAckModifier => Classify,
+    TcpSeqModifier => Classify,
+    TcpControlModifier => Classify,
+    DnsPriorityModifier => Classify,
+    QuicModifier => Classify,
+    FlowRateModifier => Classify,
+    MarkModifier => Classify,
+    MssClampModifier => Classify,
+    PaddingModifier => Padding,
+    FragmentModifier => Fragment,
+    OverheadModifier => Overhead,
+}
+
+pub struct ModifierChainBuilder<T, K> {
+    chain: Vec<(ModifierStage, Box<dyn PacketModifier<T, K>>)>,
+}
+
+impl<T, K> ModifierChainBuilder<T, K> {
+    pub fn new() -> Self {
+        Self { chain: Vec::new() }
+    }
+
+    // 新写的修改器要先在上面的表里登记阶段才能加进来
+    pub fn with<M: PacketModifier<T, K> + ChainStage + 'static>(mut self, modifier: M) -> Self {
+        self.chain.push((M::STAGE, Box::new(modifier)));
+        self
+    }
+
+    // 添加顺序跟阶段顺序对不上 (build 会重排)，给配置加载打警告用
+    pub fn reordered(&self) -> bool {
+        self.chain.windows(2).any(|w| w[0].0 > w[1].0)
+    }
+
+    pub fn build(mut self) -> Vec<Box<dyn PacketModifier<T, K>>> {
+        self.chain.sort_by_key(|(stage, _)| *stage); // 稳定排序
+        self.chain
+            .into_iter()
+            .map(|(_, modifier)| modifier)
+            .collect()
+    }
+}
+
+impl<T, K> Default for ModifierChainBuilder<T, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifier::apply_modifiers;
+    use crate::packet_context::{PacketContext, test_packet};
+
+    // IP 头里写明总长 len 的包
+    fn ipv4(len: usize) -> PacketContext<Vec<u8>, u64> {
+        let mut ctx = test_packet(1, 0, len);
+        ctx.msg[0] = 0x45;
+        ctx.msg[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        ctx
+    }
+
+    fn cost(chain: ModifierChainBuilder<Vec<u8>, u64>, len: usize) -> (usize, usize) {
+        let mut ctx = ipv4(len);
+        apply_modifiers(&mut ctx, &chain.build());
+        (ctx.cost, ctx.frames)
+    }
+
+    #[test]
+    fn build_puts_fragment_before_overhead_whatever_the_insertion_order() {
+        let in_order = ModifierChainBuilder::new()
+            .with(TrueLengthModifier::new())
+            .with(PaddingModifier::new(16, 0, usize::MAX))
+            .with(FragmentModifier::new(1280))
+            .with(OverheadModifier::new(98));
+        assert!(!in_order.reordered());
+
+        let backwards = ModifierChainBuilder::new()
+            .with(OverheadModifier::new(98))
+            .with(FragmentModifier::new(1280))
+            .with(PaddingModifier::new(16, 0, usize::MAX))
+            .with(TrueLengthModifier::new());
+        assert!(backwards.reordered());
+
+        // 1400 补齐到 1408，切成 2 帧，每帧 98 字节开销
+        assert_eq!(cost(in_order, 1400), (1408 + 2 * 98, 2));
+        assert_eq!(cost(backwards, 1400), (1408 + 2 * 98, 2));
+
+        // 照添加顺序跑就是错的账：overhead 先看到 frames = 1 只加一份
+        let mut ctx = ipv4(1400);
+        let naive: Vec<Box<dyn PacketModifier<Vec<u8>, u64>>> = vec![
+            Box::new(OverheadModifier::new(98)),
+            Box::new(FragmentModifier::new(1280)),
+        ];
+        apply_modifiers(&mut ctx, &naive);
+        assert_eq!(ctx.cost, 1400 + 98);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::modifier::fragment::{IPV4_HEADER, MAX_FRAMES};
+
+// ==========================================
+// 记账模型 (Cost Model)
+// 把 true_length → padding → fragment → overhead 这条链的算术单独写一遍：
+// 给定真实包长，不跑修改器直接算出最终 cost，拿来跟修改器链实际算出来的对账
+// (nfq_shaper --cost <len>)。链上顺序一乱或者哪个修改器改了算法，两边就对不上
+// ==========================================
+#[derive(Debug, Clone, Copy)]
+pub struct CostModel {
+    block_size: usize, // 加密块对齐，1 就是不补
+    mtu: usize,
+    overhead: usize, // 每帧固定开销
+    precise: bool,   // 对应 FragmentModifier::precise
+}
+
+impl CostModel {
+    pub fn new(mtu: usize, overhead: usize) -> Self {
+        Self {
+            block_size: 1,
+            mtu: mtu.max(1),
+            overhead,
+            precise: false,
+        }
+    }
+
+    pub fn padded(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn precise(mut self) -> Self {
+        self.precise = true;
+        self
+    }
+
+    // 补齐后的长度按 MTU 切成几帧 (和 FragmentModifier 一样封顶)
+    pub fn frames(&self, len: usize) -> usize {
+        let len = self.padded_len(len);
+        let frames = if !self.precise {
+            len.div_ceil(self.mtu)
+        } else if len <= self.mtu {
+            1
+        } else {
+            // 原包的 IP 头只算一次，每片负载按 8 字节对齐
+            let room = (self.mtu.saturating_sub(IPV4_HEADER) / 8 * 8).max(8);
+            (len - IPV4_HEADER).div_ceil(room)
+        };
+        frames.clamp(1, MAX_FRAMES)
+    }
+
+    // 真实长度 len 的包最终记多少字节的账
+    pub fn cost(&self, len: usize) -> usize {
+        let frames = self.frames(len);
+        let headers = if self.precise {
+            (frames - 1) * IPV4_HEADER // 后续分片各带一份 IP 头
+        } else {
+            0
+        };
+        self.padded_len(len)
+            .saturating_add(headers)
+            .saturating_add(self.overhead.saturating_mul(frames))
+    }
+
+    fn padded_len(&self, len: usize) -> usize {
+        len.div_ceil(self.block_size) * self.block_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifier::{
+        FragmentModifier, ModifierChainBuilder, OverheadModifier, PaddingModifier,
+        TrueLengthModifier, apply_modifiers,
+    };
+    use crate::packet_context::test_packet;
+
+    // WG 路径的参数：1280 MTU、98 字节隧道开销、16 字节加密块
+    fn wg() -> CostModel {
+        CostModel::new(1280, 98).padded(16)
+    }
+
+    // 真跑一遍 true_length → padding → fragment → overhead
+    fn chain_cost(len: usize) -> (usize, usize) {
+        let chain = ModifierChainBuilder::new()
+            .with(TrueLengthModifier::new())
+            .with(PaddingModifier::new(16, 0, usize::MAX))
+            .with(FragmentModifier::new(1280))
+            .with(OverheadModifier::new(98))
+            .build();
+        let mut ctx = test_packet(1, 0, len);
+        ctx.msg[0] = 0x45;
+        ctx.msg[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        apply_modifiers(&mut ctx, &chain);
+        (ctx.cost, ctx.frames)
+    }
+
+    #[test]
+    fn wg_packet_of_1400_bytes_costs_1604() {
+        // 1400 补齐到 1408，切成 2 帧，每帧 98
+        assert_eq!(wg().frames(1400), 2);
+        assert_eq!(wg().cost(1400), 1604);
+        assert_eq!(chain_cost(1400), (1604, 2));
+    }
+
+    #[test]
+    fn model_agrees_with_the_modifier_chain() {
+        for len in [40, 60, 1264, 1280, 1281, 1500, 9000] {
+            let model = wg();
+            assert_eq!(
+                chain_cost(len),
+                (model.cost(len), model.frames(len)),
+                "{len}"
+            );
+        }
+        // 不补齐的以太网路径
+        let eth = CostModel::new(1500, 38);
+        assert_eq!(eth.cost(1400), 1438);
+        assert_eq!(eth.cost(3001), 3001 + 3 * 38);
+    }
+}
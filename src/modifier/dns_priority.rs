@@ -0,0 +1,74 @@
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+const DNS_PORT: u16 = 53;
+
+// ==========================================
+// DNS 快车道修改器 (负责盖 is_dns 戳)
+// 域名解析卡在大流后面，整个网页都跟着转圈
+// 只看端口不看内容，畸形的 DNS 报文也不会让它出错
+// ==========================================
+pub struct DnsPriorityModifier;
+
+impl DnsPriorityModifier {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for DnsPriorityModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        ctx.is_dns = false;
+
+        let data = ctx.msg.as_ref();
+
+        // 1. IPv4，协议是 TCP (6) 或 UDP (17)
+        if data.len() < 20 || data[0] >> 4 != 4 || (data[9] != 6 && data[9] != 17) {
+            return;
+        }
+
+        // 2. 两种协议的端口都在传输层头的前 4 个字节
+        let ihl = (data[0] & 0x0F) as usize * 4;
+        if data.len() < ihl + 4 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([data[ihl], data[ihl + 1]]);
+        let dst_port = u16::from_be_bytes([data[ihl + 2], data[ihl + 3]]);
+
+        // 查询 (dst=53) 和应答 (src=53) 都算
+        ctx.is_dns = src_port == DNS_PORT || dst_port == DNS_PORT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IPv4 包，传输层头前 4 字节是两个端口
+    fn packet(proto: u8, src_port: u16, dst_port: u16) -> PacketContext<Vec<u8>, u64> {
+        let mut data = vec![0u8; 28];
+        data[0] = 0x45;
+        data[9] = proto;
+        data[20..22].copy_from_slice(&src_port.to_be_bytes());
+        data[22..24].copy_from_slice(&dst_port.to_be_bytes());
+        PacketContext::new(data, 1, 1, 0, 0, 28)
+    }
+
+    fn is_dns(mut ctx: PacketContext<Vec<u8>, u64>) -> bool {
+        ctx.is_dns = true; // 上一个包留下的戳必须被清掉
+        DnsPriorityModifier::new().process(&mut ctx);
+        ctx.is_dns
+    }
+
+    #[test]
+    fn port_53_queries_and_answers_are_dns() {
+        assert!(is_dns(packet(17, 50000, 53)));
+        assert!(is_dns(packet(17, 53, 50000)));
+        assert!(is_dns(packet(6, 50000, 53))); // 大应答退回 TCP
+        assert!(!is_dns(packet(17, 50000, 443)));
+        assert!(!is_dns(packet(1, 50000, 53))); // ICMP 没有端口
+        let mut truncated = packet(17, 50000, 53);
+        truncated.msg.truncate(22);
+        assert!(!is_dns(truncated));
+    }
+}
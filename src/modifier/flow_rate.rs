@@ -0,0 +1,32 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+use crate::rate_estimator::RateEstimator;
+
+// ==========================================
+// 流速测量修改器 (负责盖 flow_rate_bps 戳)
+// 按 flow_hash 估计每条流最近的速率 (线上字节)，调度器的分类器据此区分大象流和老鼠流
+// 修改器接口是 &self，估计器的流表靠 RefCell 更新 (收包全在一个线程里)
+// ==========================================
+pub struct FlowRateModifier {
+    estimator: RefCell<RateEstimator>,
+}
+
+impl FlowRateModifier {
+    pub fn new(time_constant: Duration, idle_timeout: Duration) -> Self {
+        Self {
+            estimator: RefCell::new(RateEstimator::new(time_constant, idle_timeout)),
+        }
+    }
+}
+
+impl<T, K> PacketModifier<T, K> for FlowRateModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        ctx.flow_rate_bps = self
+            .estimator
+            .borrow_mut()
+            .update(ctx.flow_hash, ctx.pkt_len);
+    }
+}
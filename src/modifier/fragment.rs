@@ -1,13 +1,100 @@
 use crate::{modifier::PacketModifier, packet_context::PacketContext};
 
+// GSO 超级包最大 64KB，按最小 MTU 576 算也就一百来帧；再多一定是上游算错了
+pub(super) const MAX_FRAMES: usize = 1024;
+// 每个后续分片多出一份 IP 头 (不带选项时)，真拆或精确记账时算进 cost
+pub(super) const IPV4_HEADER: usize = 20;
+
 pub struct FragmentModifier {
     mtu: usize,
+    split: bool, // 出队时真切成 IP 分片 (DF + PMTUD 黑洞的路径)，否则只记账、分片交给内核
+    precise: bool, // 按真实 IP 分片规则数帧，并把后续分片的 IP 头记进账
 }
 impl FragmentModifier {
-    pub fn new(mtu: usize) -> Self { Self { mtu } }
+    pub fn new(mtu: usize) -> Self { Self { mtu, split: false, precise: false } }
+    // 真拆的包出去就是按 IP 分片的切法切的，帧数和分片头只能按精确模式算
+    pub fn splitting(mtu: usize) -> Self { Self { mtu, split: true, precise: true } }
+
+    // 默认的 ceil(cost / mtu) 没算每个分片自己带的 IP 头，也没算分片负载要按 8 字节对齐：
+    // 精确模式照 IP 分片的切法 (和出队时真拆用的同一套) 数帧，cost 加上多出来的那几份 IP 头
+    pub fn precise(mut self) -> Self {
+        self.precise = true;
+        self
+    }
+
+    fn frames(&self, cost: usize) -> usize {
+        if !self.precise {
+            return (cost as f64 / self.mtu as f64).ceil() as usize;
+        }
+        if cost <= self.mtu {
+            return 1;
+        }
+        // 原包的 IP 头只算一次，剩下的负载每片最多装 room 字节
+        let room = (self.mtu.saturating_sub(IPV4_HEADER) / 8 * 8).max(8);
+        cost.saturating_sub(IPV4_HEADER).div_ceil(room)
+    }
 }
 impl<T, K> PacketModifier<T, K> for FragmentModifier {
     fn process(&self, ctx: &mut PacketContext<T, K>) {
-        ctx.frames = ((ctx.cost as f64 / self.mtu as f64).ceil() as usize).max(1);
+        ctx.frames = self.frames(ctx.cost).clamp(1, MAX_FRAMES);
+        if self.split && ctx.frames > 1 {
+            ctx.split_mtu = Some(self.mtu);
+        }
+        if self.precise && ctx.frames > 1 {
+            ctx.cost = ctx.cost.saturating_add((ctx.frames - 1) * IPV4_HEADER);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifier::{CostModel, OverheadModifier};
+    use crate::packet_context::test_packet;
+
+    fn run(
+        fragment: &FragmentModifier,
+        overhead: &OverheadModifier,
+        cost: usize,
+    ) -> (usize, usize) {
+        let mut ctx = test_packet(1, 0, cost);
+        fragment.process(&mut ctx);
+        overhead.process(&mut ctx);
+        (ctx.cost, ctx.frames)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn splitting_counts_frames_like_the_splitter() {
+        // 每片最多装 (1280 - 20) / 8 * 8 = 1256 字节负载，2540 字节的包要拆 3 片；
+        // ceil(2540 / 1280) 只数出 2 帧
+        let split = FragmentModifier::splitting(1280);
+        let mut ctx = test_packet(1, 0, 2540);
+        split.process(&mut ctx);
+        assert_eq!(ctx.frames, 3);
+        assert_eq!(ctx.cost, 2540 + 2 * IPV4_HEADER);
+        assert_eq!(ctx.split_mtu, Some(1280));
+
+        let mut ctx = test_packet(1, 0, 2540);
+        FragmentModifier::new(1280).precise().process(&mut ctx);
+        assert_eq!((ctx.cost, ctx.frames), (2580, 3));
+    }
+
+    #[test]
+    fn precise_cost_of_a_three_fragment_packet_against_ceil_multiply() {
+        let overhead = OverheadModifier::new(98);
+        // 3000 字节：两种算法都数出 3 帧，精确模式多记两片各自的 IP 头
+        let flat = run(&FragmentModifier::new(1280), &overhead, 3000);
+        let precise = run(&FragmentModifier::new(1280).precise(), &overhead, 3000);
+        assert_eq!(flat, (3000 + 3 * 98, 3));
+        assert_eq!(precise, (3000 + 2 * IPV4_HEADER + 3 * 98, 3));
+
+        // 尾片开销另算时只有最后一片按 18 记
+        let tail = OverheadModifier::new(98).with_last_fragment(18);
+        let precise_tail = run(&FragmentModifier::new(1280).precise(), &tail, 3000);
+        assert_eq!(precise_tail, (3000 + 2 * IPV4_HEADER + 2 * 98 + 18, 3));
+
+        // 和 CostModel 的精确模式对得上
+        let model = CostModel::new(1280, 98).precise();
+        assert_eq!((model.cost(3000), model.frames(3000)), precise);
+    }
+}
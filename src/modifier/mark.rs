@@ -0,0 +1,24 @@
+use crate::{
+    modifier::PacketModifier,
+    packet_context::{PacketContext, ReleaseVerdict},
+};
+
+// ==========================================
+// 打标修改器：整形完放行时给包打上 nfmark，
+// 配合 Repeat 让 iptables 按 mark 把整过形的包再分一次类 (比如送去别的路由表)
+// ==========================================
+pub struct MarkModifier {
+    mark: u32,
+    release: ReleaseVerdict,
+}
+impl MarkModifier {
+    pub fn new(mark: u32, release: ReleaseVerdict) -> Self {
+        Self { mark, release }
+    }
+}
+impl<T, K> PacketModifier<T, K> for MarkModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        ctx.mark = Some(self.mark);
+        ctx.release = self.release;
+    }
+}
@@ -1,17 +1,81 @@
 use crate::packet_context::PacketContext;
 
+mod bogon_filter;
+mod chain;
+mod cost_model;
+mod dns_priority;
+mod flow_rate;
 mod fragment;
+mod mark;
+mod mss_clamp;
 mod overhead;
 mod padding;
+mod quic_modifier;
 mod tcp_ack_modifier;
+mod tcp_control;
+mod tcp_seq_modifier;
 mod true_length;
+mod ttl_guard;
 
+pub use bogon_filter::BogonFilterModifier;
+pub use chain::ModifierChainBuilder;
+pub use cost_model::CostModel;
+pub use dns_priority::DnsPriorityModifier;
+pub use flow_rate::FlowRateModifier;
 pub use fragment::FragmentModifier;
+pub use mark::MarkModifier;
+pub use mss_clamp::MssClampModifier;
 pub use overhead::OverheadModifier;
-pub use padding::PaddingModifier;
+pub use padding::{PaddingModifier, PaddingPolicy};
+pub use quic_modifier::QuicModifier;
 pub use tcp_ack_modifier::TcpAckModifier;
+pub use tcp_control::TcpControlModifier;
+pub use tcp_seq_modifier::TcpSeqModifier;
 pub use true_length::TrueLengthModifier;
+pub use ttl_guard::{TtlAction, TtlGuardModifier};
 
+// 只读的修改器约束 T: AsRef<[u8]> 就够了；要改包头 (MSS 钳制 / 重标 DSCP 之类) 的再加 AsMut<[u8]>，
+// 改过的字节会随 verdict 一起写回内核。ctx 本来就是 &mut，签名不用动
+// 要在入队前直接判死的 (TTL 守卫的 drop 模式、bogon 过滤) 就盖 ctx.ingress_drop，
+// 流水线过完整条修改器链后看到它就原样退回给 main 发 Drop，不进调度树
 pub trait PacketModifier<T, K> {
     fn process(&self, ctx: &mut PacketContext<T, K>);
 }
+
+// 按顺序把一条链过一遍。顺序本身是语义：cost 这条线必须是
+// 过滤 → true_length → 盖戳类 → padding → fragment → overhead，要保证顺序就用 ModifierChainBuilder 来搭
+pub fn apply_modifiers<T, K>(
+    ctx: &mut PacketContext<T, K>,
+    modifiers: &[Box<dyn PacketModifier<T, K>>],
+) {
+    for modifier in modifiers {
+        modifier.process(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+
+    // 只要 T: AsMut<[u8]> 就能就地改包：把 TOS 字节翻一下
+    struct FlipTos;
+
+    impl<T: AsRef<[u8]> + AsMut<[u8]>, K> PacketModifier<T, K> for FlipTos {
+        fn process(&self, ctx: &mut PacketContext<T, K>) {
+            if let Some(tos) = ctx.msg.as_mut().get_mut(1) {
+                *tos = !*tos;
+            }
+        }
+    }
+
+    #[test]
+    fn modifiers_rewrite_the_payload_in_place() {
+        let mut ctx = test_packet(1, 0, 40);
+        let chain: Vec<Box<dyn PacketModifier<Vec<u8>, u64>>> =
+            vec![Box::new(FlipTos), Box::new(FlipTos), Box::new(FlipTos)];
+        apply_modifiers(&mut ctx, &chain);
+        assert_eq!(ctx.msg[1], 0xFF);
+        assert!(ctx.msg.iter().enumerate().all(|(i, &b)| i == 1 || b == 0));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::checksum::incremental_update;
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+// TCP 选项类型
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+const OPT_MSS_LEN: usize = 4;
+
+const FLAG_SYN: u8 = 0x02;
+
+// ==========================================
+// MSS 钳制修改器 (MSS Clamp Modifier)
+// 握手包 (SYN / SYN-ACK) 里通告的 MSS 按以太网 1460 算，塞不进 1280 的 WG 隧道，
+// 偏偏 PMTUD 又常被半路的防火墙掐断；这里把超标的 MSS 就地改小，并增量修好 TCP 校验和
+// 只改真正拷全了的包：截断拷贝写回内核会把包尾巴剪掉
+// ==========================================
+pub struct MssClampModifier {
+    mss: u16,
+}
+
+impl MssClampModifier {
+    pub fn new(mss: u16) -> Self {
+        Self { mss }
+    }
+}
+
+// 在 TCP 头里找 MSS 选项的值在哪 (相对 TCP 头起点的偏移)，找不到或者选项区坏了返回 None
+fn find_mss(tcp: &[u8], data_offset: usize) -> Option<usize> {
+    let options = tcp.get(20..data_offset)?;
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            OPT_END => return None,
+            OPT_NOP => i += 1,
+            kind => {
+                let len = *options.get(i + 1)? as usize;
+                if len < 2 || i + len > options.len() {
+                    return None;
+                }
+                if kind == OPT_MSS && len == OPT_MSS_LEN {
+                    return Some(20 + i + 2);
+                }
+                i += len;
+            }
+        }
+    }
+    None
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>, K> PacketModifier<T, K> for MssClampModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        let data = ctx.msg.as_ref();
+
+        // 只认 IPv4 + TCP，而且要整包都在手上
+        if data.len() < 20 || data[0] >> 4 != 4 || data[9] != 6 {
+            return;
+        }
+        let ihl = (data[0] & 0x0F) as usize * 4;
+        let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if ihl < 20 || total_length > data.len() || total_length < ihl + 20 {
+            return;
+        }
+        // 非首片的负载是 TCP 段的中间部分，开头那 20 字节根本不是 TCP 头
+        if u16::from_be_bytes([data[6], data[7]]) & 0x1FFF != 0 {
+            return;
+        }
+        let tcp = &data[ihl..total_length];
+        if tcp[13] & FLAG_SYN == 0 {
+            return;
+        }
+        let data_offset = (tcp[12] >> 4) as usize * 4;
+        let Some(at) = find_mss(tcp, data_offset) else {
+            return;
+        };
+        let old = u16::from_be_bytes([tcp[at], tcp[at + 1]]);
+        if old <= self.mss {
+            return;
+        }
+
+        // 确定要改了才拿可写切片：NfqMessage 一旦借出可写 payload，放行时就会整包写回内核
+        let Some(packet) = ctx.msg.as_mut().get_mut(ihl..total_length) else {
+            return;
+        };
+        packet[at..at + 2].copy_from_slice(&self.mss.to_be_bytes());
+
+        // 校验和按 TCP 头起点对齐的 16 位字累加；MSS 落在奇数偏移时横跨两个字，
+        // 反码和与字节序无关，把新旧值都按字节交换一下再做增量更新就对了
+        let (old_word, new_word) = if at % 2 == 0 {
+            (old, self.mss)
+        } else {
+            (old.swap_bytes(), self.mss.swap_bytes())
+        };
+        let checksum = u16::from_be_bytes([packet[16], packet[17]]);
+        let checksum = incremental_update(checksum, old_word, new_word);
+        packet[16..18].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按 RFC 793 伪首部把整个 TCP 段的校验和从头算一遍，拿来核对增量更新
+    fn tcp_checksum(packet: &[u8]) -> u16 {
+        let tcp = &packet[20..];
+        let mut pseudo = packet[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, 6]);
+        pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        let mut sum: u32 = 0;
+        for chunk in pseudo.chunks(2).chain(tcp.chunks(2)) {
+            let word = [chunk[0], chunk.get(1).copied().unwrap_or(0)];
+            sum += u16::from_be_bytes(word) as u32;
+        }
+        while sum > 0xFFFF {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    // 10.0.0.1 -> 10.0.0.2 的 SYN，MSS 1460；options 就是 TCP 头后面跟的选项区 (已按 4 字节对齐)
+    fn syn(options: &[u8], flags_offset: u16) -> PacketContext<Vec<u8>, u64> {
+        let tcp_len = 20 + options.len();
+        let mut packet = vec![0u8; 20 + tcp_len];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&((20 + tcp_len) as u16).to_be_bytes());
+        packet[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+        packet[8] = 64;
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        let tcp = &mut packet[20..];
+        tcp[0..4].copy_from_slice(&[0xC3, 0x50, 0x01, 0xBB]);
+        tcp[12] = ((tcp_len / 4) as u8) << 4;
+        tcp[13] = FLAG_SYN;
+        tcp[14..16].copy_from_slice(&64240u16.to_be_bytes());
+        tcp[20..].copy_from_slice(options);
+        let checksum = tcp_checksum(&packet);
+        packet[36..38].copy_from_slice(&checksum.to_be_bytes());
+        let len = packet.len();
+        PacketContext::new(packet, 0, 0, 0, 0, len)
+    }
+
+    fn mss_at(ctx: &PacketContext<Vec<u8>, u64>, at: usize) -> u16 {
+        u16::from_be_bytes([ctx.msg[at], ctx.msg[at + 1]])
+    }
+
+    #[test]
+    fn clamps_mss_and_keeps_checksum_valid() {
+        // MSS 值落在偶数偏移 (紧跟 TCP 头) 和奇数偏移 (前面垫一个 NOP) 各来一次
+        for (options, at) in [
+            (&[2, 4, 0x05, 0xB4][..], 42),
+            (&[1, 2, 4, 0x05, 0xB4, 1, 1, 1][..], 43),
+        ] {
+            let mut ctx = syn(options, 0x4000);
+            MssClampModifier::new(1240).process(&mut ctx);
+            assert_eq!(mss_at(&ctx, at), 1240);
+            let stored = u16::from_be_bytes([ctx.msg[36], ctx.msg[37]]);
+            ctx.msg[36..38].fill(0);
+            assert_eq!(stored, tcp_checksum(&ctx.msg));
+        }
+    }
+
+    #[test]
+    fn leaves_small_mss_and_later_fragments_alone() {
+        let mut ctx = syn(&[2, 4, 0x04, 0xD8], 0x4000); // 1240，本来就不超
+        let before = ctx.msg.clone();
+        MssClampModifier::new(1240).process(&mut ctx);
+        assert_eq!(ctx.msg, before);
+
+        // 偏移 8 字节的后续分片：看着像 SYN 也不是 TCP 头
+        let mut ctx = syn(&[2, 4, 0x05, 0xB4], 0x2001);
+        let before = ctx.msg.clone();
+        MssClampModifier::new(1240).process(&mut ctx);
+        assert_eq!(ctx.msg, before);
+    }
+}
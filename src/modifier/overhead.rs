@@ -2,12 +2,63 @@ use crate::{modifier::PacketModifier, packet_context::PacketContext};
 
 pub struct OverheadModifier {
     overhead_bytes: usize,
+    last_fragment_bytes: Option<usize>, // 最后一个分片的开销另算 (有的封装尾片少带东西)，None 就和别的帧一样
 }
 impl OverheadModifier {
-    pub fn new(overhead_bytes: usize) -> Self { Self { overhead_bytes } }
+    pub fn new(overhead_bytes: usize) -> Self { Self { overhead_bytes, last_fragment_bytes: None } }
+
+    // 只对分成多帧的包生效：前 frames - 1 帧按 overhead_bytes，尾片按这个
+    pub fn with_last_fragment(mut self, bytes: usize) -> Self {
+        self.last_fragment_bytes = Some(bytes);
+        self
+    }
 }
 impl<T, K> PacketModifier<T, K> for OverheadModifier {
     fn process(&self, ctx: &mut PacketContext<T, K>) {
-        ctx.cost += self.overhead_bytes * ctx.frames;
+        // 饱和运算：溢出回绕会算出一个很小的 cost，让巨型包白嫖令牌桶
+        let overhead = match self.last_fragment_bytes {
+            Some(last) if ctx.frames > 1 => self
+                .overhead_bytes
+                .saturating_mul(ctx.frames - 1)
+                .saturating_add(last),
+            _ => self.overhead_bytes.saturating_mul(ctx.frames),
+        };
+        ctx.cost = ctx.cost.saturating_add(overhead);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifier::FragmentModifier;
+    use crate::modifier::fragment::MAX_FRAMES;
+    use crate::packet_context::test_packet;
+
+    // 载荷只有 1 字节，cost 直接改成离谱的值：模拟上游算错的巨型 GSO 包
+    fn giant(cost: usize) -> PacketContext<Vec<u8>, u64> {
+        let mut ctx = test_packet(1, 0, 1);
+        ctx.cost = cost;
+        ctx
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn giant_costs_saturate_instead_of_wrapping() {
+        let overhead = OverheadModifier::new(80).with_last_fragment(40);
+        for fragment in [
+            FragmentModifier::new(1280),
+            FragmentModifier::new(1280).precise(),
+            FragmentModifier::splitting(1280),
+        ] {
+            let mut ctx = giant(usize::MAX - 10);
+            fragment.process(&mut ctx);
+            assert_eq!(ctx.frames, MAX_FRAMES); // 帧数封顶，不是 usize::MAX / 1280
+            overhead.process(&mut ctx);
+            assert_eq!(ctx.cost, usize::MAX);
+        }
+
+        // 不经过 FragmentModifier、frames 被写成天文数字也一样：乘法饱和，不回绕成小数
+        let mut ctx = giant(1500);
+        ctx.frames = usize::MAX / 2;
+        OverheadModifier::new(80).process(&mut ctx);
+        assert_eq!(ctx.cost, usize::MAX);
+    }
+}
@@ -1,17 +1,94 @@
 use crate::{modifier::PacketModifier, packet_context::PacketContext};
 
+// 补齐到哪儿
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    BlockAlign(usize),   // 向上取整到块大小的整数倍 (加密块对齐)
+    Buckets(Vec<usize>), // 向上取到最近的一档固定尺寸 (抗流量分析：包长只剩几种)，比最大档还大的原样放过
+}
+
 // ==========================================
 // 1. 加密对齐修改器
+// 只对 cost 落在 [min_size, max_size] 里的包补齐：大包补那几个字节无关痛痒，不如不算
 // ==========================================
 pub struct PaddingModifier {
-    block_size: usize,
+    policy: PaddingPolicy,
+    min_size: usize,
+    max_size: usize,
 }
 impl PaddingModifier {
-    pub fn new(block_size: usize) -> Self { Self { block_size } }
+    pub fn new(block_size: usize, min_size: usize, max_size: usize) -> Self {
+        Self::with_policy(PaddingPolicy::BlockAlign(block_size), min_size, max_size)
+    }
+
+    pub fn with_policy(policy: PaddingPolicy, min_size: usize, max_size: usize) -> Self {
+        let policy = match policy {
+            PaddingPolicy::BlockAlign(block_size) => PaddingPolicy::BlockAlign(block_size.max(1)),
+            PaddingPolicy::Buckets(mut sizes) => {
+                sizes.sort_unstable();
+                sizes.dedup();
+                PaddingPolicy::Buckets(sizes)
+            }
+        };
+        Self {
+            policy,
+            min_size,
+            max_size,
+        }
+    }
 }
 impl<T, K> PacketModifier<T, K> for PaddingModifier {
     fn process(&self, ctx: &mut PacketContext<T, K>) {
+        if ctx.cost < self.min_size || ctx.cost > self.max_size {
+            return;
+        }
         // 直接修改 ctx.cost，不需要关心 queue_num！
-        ctx.cost = ((ctx.cost + self.block_size - 1) / self.block_size) * self.block_size;
+        match &self.policy {
+            PaddingPolicy::BlockAlign(block_size) => {
+                ctx.cost = ctx.cost.div_ceil(*block_size) * block_size;
+            }
+            PaddingPolicy::Buckets(sizes) => {
+                if let Some(&bucket) = sizes.iter().find(|&&size| size >= ctx.cost) {
+                    ctx.cost = bucket;
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(modifier: &PaddingModifier, cost: usize) -> usize {
+        let mut ctx: PacketContext<Vec<u8>, u64> =
+            PacketContext::new(vec![0; cost], 1, 1, 0, 0, cost);
+        modifier.process(&mut ctx);
+        ctx.cost
+    }
+
+    #[test]
+    fn only_costs_inside_the_window_are_aligned() {
+        let modifier = PaddingModifier::new(16, 64, 1024);
+        assert_eq!(padded(&modifier, 100), 112);
+        assert_eq!(padded(&modifier, 64), 64); // 下沿，本来就对齐
+        assert_eq!(padded(&modifier, 1024), 1024); // 上沿，本来就对齐
+        assert_eq!(padded(&modifier, 1000), 1008);
+        assert_eq!(padded(&modifier, 63), 63); // 窗口外：原样
+        assert_eq!(padded(&modifier, 1025), 1025);
+        assert_eq!(padded(&modifier, 1500), 1500);
+    }
+
+    #[test]
+    fn costs_snap_to_the_next_bucket() {
+        let modifier = PaddingModifier::with_policy(
+            PaddingPolicy::Buckets(vec![1280, 576, 576]),
+            0,
+            usize::MAX,
+        );
+        assert_eq!(padded(&modifier, 1), 576);
+        assert_eq!(padded(&modifier, 576), 576); // 正好一档，不往上跳
+        assert_eq!(padded(&modifier, 577), 1280);
+        assert_eq!(padded(&modifier, 1280), 1280);
+        assert_eq!(padded(&modifier, 1281), 1281); // 比最大档还大：原样放过
+    }
+}
@@ -0,0 +1,142 @@
+use crate::five_tuple::fnv1a;
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+const QUIC_PORT: u16 = 443;
+const MAX_CID_LEN: usize = 20; // RFC 9000 规定连接 ID 最长 20 字节
+
+// ==========================================
+// QUIC 连接识别修改器 (负责盖 quic_cid_hash 戳)
+// 同一个 4 元组上可能跑着好几条 QUIC 连接 (或者一条迁移过来的连接)，
+// 只看五元组分不清，得看头部里明文的目的连接 ID (DCID)
+// ==========================================
+pub struct QuicModifier {
+    // 短头部不带 DCID 长度，只能约定一个 (Chrome / quiche 默认都是 8)
+    short_cid_len: usize,
+}
+
+impl QuicModifier {
+    pub fn new(short_cid_len: usize) -> Self {
+        Self {
+            short_cid_len: short_cid_len.min(MAX_CID_LEN),
+        }
+    }
+
+    // 返回 UDP 负载里 DCID 的那一段；不像 QUIC 的一律返回 None
+    fn dcid<'a>(&self, quic: &'a [u8]) -> Option<&'a [u8]> {
+        let first = *quic.first()?;
+
+        // 固定位 (0x40) 必须为 1，顺手挡掉大部分跑在 443 上的非 QUIC UDP
+        if first & 0x40 == 0 {
+            return None;
+        }
+
+        if first & 0x80 != 0 {
+            // 长头部 (Initial / Handshake / 0-RTT / Retry)：
+            // 1 字节标志 + 4 字节版本 + 1 字节 DCID 长度 + DCID，全是明文
+            let dcid_len = *quic.get(5)? as usize;
+            if dcid_len > MAX_CID_LEN {
+                return None;
+            }
+            quic.get(6..6 + dcid_len)
+        } else {
+            // 短头部 (1-RTT)：标志字节后面紧跟 DCID
+            quic.get(1..1 + self.short_cid_len)
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for QuicModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        // 默认先清零，非 QUIC 包就保持 0
+        ctx.quic_cid_hash = 0;
+
+        let data = ctx.msg.as_ref();
+
+        // 1. IPv4 + UDP (协议号 17)
+        if data.len() < 20 || data[0] >> 4 != 4 || data[9] != 17 {
+            return;
+        }
+
+        // 2. UDP 头 8 字节，任意一端是 443 才当作 QUIC
+        let ihl = (data[0] & 0x0F) as usize * 4;
+        if data.len() < ihl + 8 {
+            return;
+        }
+        let udp = &data[ihl..];
+        let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+        let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+        if src_port != QUIC_PORT && dst_port != QUIC_PORT {
+            return;
+        }
+
+        // 3. 空 DCID 是合法的，但区分不了连接，和非 QUIC 一样留 0
+        if let Some(dcid) = self.dcid(&udp[8..]).filter(|c| !c.is_empty()) {
+            // 0 已经被 "不是 QUIC" 占了，撞上就挪到 1
+            ctx.quic_cid_hash = fnv1a(dcid.iter().copied()).max(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DCID: [u8; 8] = [0xc0, 0xff, 0xee, 0x00, 0x11, 0x22, 0x33, 0x44];
+
+    // IPv4/UDP 包，UDP 负载就是 quic
+    fn udp(dst_port: u16, quic: &[u8]) -> PacketContext<Vec<u8>, u64> {
+        let mut data = vec![0u8; 28];
+        data[0] = 0x45;
+        data[9] = 17;
+        data[20..22].copy_from_slice(&50000u16.to_be_bytes());
+        data[22..24].copy_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(quic);
+        let len = data.len();
+        PacketContext::new(data, 1, 1, 0, 0, len)
+    }
+
+    fn long_header(dcid: &[u8]) -> Vec<u8> {
+        let mut quic = vec![0xc3, 0x00, 0x00, 0x00, 0x01, dcid.len() as u8];
+        quic.extend_from_slice(dcid);
+        quic.extend_from_slice(&[0; 20]);
+        quic
+    }
+
+    fn short_header(dcid: &[u8]) -> Vec<u8> {
+        let mut quic = vec![0x41];
+        quic.extend_from_slice(dcid);
+        quic.extend_from_slice(&[0; 20]);
+        quic
+    }
+
+    fn cid_hash(mut ctx: PacketContext<Vec<u8>, u64>) -> u64 {
+        QuicModifier::new(8).process(&mut ctx);
+        ctx.quic_cid_hash
+    }
+
+    #[test]
+    fn long_and_short_headers_hash_the_same_connection_id() {
+        let long = cid_hash(udp(QUIC_PORT, &long_header(&DCID)));
+        assert_ne!(long, 0);
+        assert_eq!(cid_hash(udp(QUIC_PORT, &long_header(&DCID))), long);
+        // 握手完切到短头部，还是同一条连接
+        assert_eq!(cid_hash(udp(QUIC_PORT, &short_header(&DCID))), long);
+
+        let mut other = DCID;
+        other[7] ^= 1;
+        assert_ne!(cid_hash(udp(QUIC_PORT, &short_header(&other))), long);
+    }
+
+    #[test]
+    fn non_quic_udp_is_left_unstamped() {
+        // 不是 443 的 UDP
+        assert_eq!(cid_hash(udp(53, &long_header(&DCID))), 0);
+        // 443 上固定位为 0 的杂包、空 DCID、DCID 长度超标
+        assert_eq!(cid_hash(udp(QUIC_PORT, &[0x00; 30])), 0);
+        assert_eq!(cid_hash(udp(QUIC_PORT, &long_header(&[]))), 0);
+        assert_eq!(cid_hash(udp(QUIC_PORT, &long_header(&[0xaa; 21]))), 0);
+        // 负载被截得看不到整个 DCID
+        assert_eq!(cid_hash(udp(QUIC_PORT, &[0x41, 0xc0, 0xff])), 0);
+    }
+}
@@ -45,7 +45,10 @@ impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TcpAckModifier {
         let data_offset = (tcp_data[12] >> 4) as usize * 4;
 
         // 5. 核心判断 A：有且仅有 TCP 头，没有应用层 Payload！(也就是纯控制包)
-        if data.len() == ihl + data_offset {
+        // 按 IP 头里的 total_length 算，不看切片长度：NFQUEUE 可能只拷了前 copy_range 字节，
+        // 截断后的数据包切片可能刚好只剩头部；反过来以太网填充也会让切片比包长
+        let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if total_length == ihl + data_offset {
             // 6. 核心判断 B：检查 ACK 标志位是否被置为 1 (TCP 头的第 13 字节)
             // ACK flag 是 0x10 (也就是第 5 位)
             if (tcp_data[13] & 0x10) != 0 {
@@ -60,3 +63,44 @@ impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TcpAckModifier {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 只拷到 copied 字节的 IPv4/TCP 包，IP 头里写的总长是 total_length
+    fn ack(total_length: u16, copied: usize) -> PacketContext<Vec<u8>, u64> {
+        let mut data = vec![0u8; copied.max(40)];
+        data[0] = 0x45;
+        data[2..4].copy_from_slice(&total_length.to_be_bytes());
+        data[9] = 6;
+        data[20 + 8..20 + 12].copy_from_slice(&0xdead_beefu32.to_be_bytes());
+        data[20 + 12] = 5 << 4;
+        data[20 + 13] = 0x10;
+        data.truncate(copied);
+        PacketContext::new(data, 1, 1, 0, 1, total_length as usize)
+    }
+
+    fn stamp(mut ctx: PacketContext<Vec<u8>, u64>) -> PacketContext<Vec<u8>, u64> {
+        TcpAckModifier::new().process(&mut ctx);
+        ctx
+    }
+
+    #[test]
+    fn pure_ack_is_judged_by_the_ip_total_length() {
+        // 头部拷全了、后面的 payload 被 copy_range 截掉：带数据的 ACK，不算纯 ACK
+        let data_ack = stamp(ack(1400, 40));
+        assert!(!data_ack.is_pure_ack);
+        assert_eq!(data_ack.tcp_ack_num, 0);
+
+        // 纯 ACK 本来就只有 40 字节，拷多少都一样：剩下的是以太网填充
+        for copied in [40, 60, 128] {
+            let pure = stamp(ack(40, copied));
+            assert!(pure.is_pure_ack, "copied {copied}");
+            assert_eq!(pure.tcp_ack_num, 0xdead_beef);
+        }
+
+        // 连 TCP 头都没拷全的看不出来，宁可不盖戳
+        assert!(!stamp(ack(40, 30)).is_pure_ack);
+    }
+}
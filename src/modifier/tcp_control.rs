@@ -0,0 +1,81 @@
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+// ==========================================
+// TCP 控制包嗅探修改器 (专门负责盖 is_control 戳)
+// 握手 / 挥手 / 复位和不带数据的纯 ACK：个头小，但卡住一个整条连接都跟着卡
+// 纯 ACK 用的是 tcp_ack 修改器的 is_pure_ack 戳，所以链上 tcp_control 要排在 tcp_ack 后面
+// ==========================================
+pub struct TcpControlModifier;
+
+impl TcpControlModifier {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TcpControlModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        ctx.is_control = false;
+
+        let data = ctx.msg.as_ref();
+
+        // 只认 IPv4 上的 TCP
+        if data.len() < 20 || data[0] >> 4 != 4 || data[9] != 6 {
+            return;
+        }
+
+        let ihl = (data[0] & 0x0F) as usize * 4;
+        if data.len() < ihl + 20 {
+            return;
+        }
+
+        // 纯 ACK 不再自己判一遍，直接认 TcpAckModifier 盖的戳 (链上它得排在前面)
+        let flags = data[ihl + 13];
+        ctx.is_control = flags & (TCP_SYN | TCP_FIN | TCP_RST) != 0 || ctx.is_pure_ack;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifier::TcpAckModifier;
+
+    // 10.0.0.1 -> 10.0.0.2 的 TCP 段，带 payload 个字节的数据
+    fn segment(flags: u8, payload: usize) -> PacketContext<Vec<u8>, u64> {
+        let mut packet = vec![0u8; 40 + payload];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&((40 + payload) as u16).to_be_bytes());
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        packet[32] = 5 << 4;
+        packet[33] = flags;
+        let len = packet.len();
+        PacketContext::new(packet, 1, 1, 0, 1, len)
+    }
+
+    fn stamp(mut ctx: PacketContext<Vec<u8>, u64>) -> bool {
+        TcpAckModifier::new().process(&mut ctx);
+        TcpControlModifier::new().process(&mut ctx);
+        ctx.is_control
+    }
+
+    #[test]
+    fn handshakes_and_pure_acks_are_control() {
+        assert!(stamp(segment(TCP_SYN, 0)));
+        assert!(stamp(segment(TCP_FIN | 0x10, 0)));
+        assert!(stamp(segment(TCP_RST, 0)));
+        assert!(stamp(segment(0x10, 0)));
+        assert!(!stamp(segment(0x10, 100)));
+
+        // 纯 ACK 认的是 tcp_ack 盖的戳，没跑过它就不算
+        let mut ack = segment(0x10, 0);
+        TcpControlModifier::new().process(&mut ack);
+        assert!(!ack.is_control);
+    }
+}
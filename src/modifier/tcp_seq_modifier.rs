@@ -0,0 +1,164 @@
+use crate::modifier::PacketModifier;
+use crate::packet_context::{PacketContext, SackBlocks};
+
+// TCP 选项类型
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_SACK: u8 = 5;
+
+// 逐个扫 TCP 选项，只捡 SACK 块；长度字段不靠谱或者被截断就到此为止
+fn parse_sack(options: &[u8]) -> SackBlocks {
+    let mut sack = SackBlocks::default();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            OPT_END => break,
+            OPT_NOP => i += 1,
+            kind => {
+                let Some(&len) = options.get(i + 1) else {
+                    break;
+                };
+                let len = len as usize;
+                if len < 2 || i + len > options.len() {
+                    break;
+                }
+                if kind == OPT_SACK {
+                    for block in options[i + 2..i + len].chunks_exact(8) {
+                        let left = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+                        let right = u32::from_be_bytes([block[4], block[5], block[6], block[7]]);
+                        sack.push((left, right));
+                    }
+                }
+                i += len;
+            }
+        }
+    }
+    sack
+}
+
+// ==========================================
+// TCP 序号嗅探修改器 (负责盖 tcp_seq / payload_len / sack / tcp_window 戳)
+// 去重、SACK 感知过滤、BDP 估计都靠它打底
+// ==========================================
+pub struct TcpSeqModifier;
+
+impl TcpSeqModifier {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TcpSeqModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        // 默认先清零，非 TCP 包就保持 0
+        ctx.tcp_seq = 0;
+        ctx.payload_len = 0;
+        ctx.sack = SackBlocks::default();
+        ctx.tcp_window = 0;
+
+        let data = ctx.msg.as_ref();
+
+        // 1. 最基础的长度防御 (IPv4 头至少 20 字节)
+        if data.len() < 20 {
+            return;
+        }
+
+        // 2. 检查是不是 IPv4 以及协议是不是 TCP (协议号 6)
+        if data[0] >> 4 != 4 || data[9] != 6 {
+            return;
+        }
+
+        // 3. 计算 IP 头长度 (IHL)
+        let ihl = (data[0] & 0x0F) as usize * 4;
+        if data.len() < ihl + 20 {
+            return;
+        } // TCP 头至少也是 20 字节
+
+        let tcp_data = &data[ihl..];
+
+        // 4. 提取 Sequence Number (TCP 头的第 4~7 字节，网络字节序 大端)
+        ctx.tcp_seq = u32::from_be_bytes([tcp_data[4], tcp_data[5], tcp_data[6], tcp_data[7]]);
+
+        // 5. 应用层负载长度 = IP 总长 - IP 头 - TCP 头
+        // 用 IP 头里的 total_length 而不是切片长度，NFQUEUE 只拷了前 128 字节
+        let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let data_offset = (tcp_data[12] >> 4) as usize * 4;
+        ctx.payload_len = total_length.saturating_sub(ihl + data_offset);
+
+        // 6. 接收窗口 (TCP 头第 14~15 字节)，缩放因子只在握手里，这里拿到的是原始值
+        ctx.tcp_window = u16::from_be_bytes([tcp_data[14], tcp_data[15]]);
+
+        // 7. 选项区在固定 20 字节头之后，NFQUEUE 截断了多少就看多少
+        let options_end = data_offset.min(tcp_data.len());
+        if options_end > 20 {
+            ctx.sack = parse_sack(&tcp_data[20..options_end]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IPv4/TCP 包：IP 头 20 + TCP 头 (20 + options) + payload 个字节的数据，序号 0x01020304，窗口 5840
+    fn tcp(options: &[u8], payload: usize) -> Vec<u8> {
+        let tcp_len = 20 + options.len();
+        let total = 20 + tcp_len + payload;
+        let mut data = vec![0u8; total];
+        data[0] = 0x45;
+        data[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+        data[9] = 6;
+        data[24..28].copy_from_slice(&0x0102_0304u32.to_be_bytes());
+        data[32] = ((tcp_len / 4) as u8) << 4;
+        data[34..36].copy_from_slice(&5840u16.to_be_bytes());
+        data[40..40 + options.len()].copy_from_slice(options);
+        data
+    }
+
+    fn stamp(data: Vec<u8>) -> PacketContext<Vec<u8>, u64> {
+        let len = data.len();
+        let mut ctx = PacketContext::new(data, 1, 1, 0, 0, len);
+        // 上一个包留下的戳必须被清掉
+        ctx.tcp_seq = 7;
+        ctx.payload_len = 7;
+        TcpSeqModifier::new().process(&mut ctx);
+        ctx
+    }
+
+    #[test]
+    fn stamps_seq_payload_window_and_sack() {
+        let ctx = stamp(tcp(&[], 100));
+        assert_eq!(ctx.tcp_seq, 0x0102_0304);
+        assert_eq!(ctx.payload_len, 100);
+        assert_eq!(ctx.tcp_window, 5840);
+        assert_eq!(ctx.sack.iter().count(), 0);
+
+        // NOP NOP + 一块 SACK [1000, 2000)
+        let mut options = vec![1, 1, 5, 10];
+        options.extend_from_slice(&1000u32.to_be_bytes());
+        options.extend_from_slice(&2000u32.to_be_bytes());
+        let ctx = stamp(tcp(&options, 0));
+        assert_eq!(ctx.payload_len, 0);
+        assert_eq!(ctx.sack.iter().copied().collect::<Vec<_>>(), [(1000, 2000)]);
+
+        // 只拷了头部：payload_len 照样按 IP 总长算
+        let mut data = tcp(&[], 1400);
+        data.truncate(40);
+        assert_eq!(stamp(data).payload_len, 1400);
+    }
+
+    #[test]
+    fn non_tcp_and_truncated_packets_stay_unstamped() {
+        let mut udp = tcp(&[], 100);
+        udp[9] = 17;
+        let mut truncated = tcp(&[], 100);
+        truncated.truncate(30);
+        let mut ipv6 = tcp(&[], 100);
+        ipv6[0] = 0x60;
+
+        for data in [udp, truncated, ipv6, vec![0x45; 10]] {
+            let ctx = stamp(data);
+            assert_eq!((ctx.tcp_seq, ctx.payload_len, ctx.tcp_window), (0, 0, 0));
+        }
+    }
+}
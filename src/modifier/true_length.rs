@@ -1,6 +1,8 @@
 use crate::modifier::PacketModifier;
 use crate::packet_context::PacketContext;
 
+const MIN_IPV4_LEN: usize = 20;
+
 // ==========================================
 // 真实体积还原化妆师 (True Length Modifier)
 // 专治 NFQUEUE 截断拷贝导致的“体重造假”
@@ -18,21 +20,74 @@ impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TrueLengthModifier {
     fn process(&self, ctx: &mut PacketContext<T, K>) {
         let data = ctx.msg.as_ref();
 
-        // 基础防御：连 IP 报头都不够的残次品直接忽略
-        if data.is_empty() {
-            ctx.cost = 0;
-            panic!();
-        }
-
         // 提取 IP 版本号 (第 0 字节的高 4 位)
-        let version = data[0] >> 4;
+        // 解析不了的残次品 (空包 / 非 IPv4 / 总长度比最小头部还短)：
+        // 保留 main 填进来的 NFQUEUE 原始长度估算值，并挂上估算牌
+        if data.len() < 4 || data[0] >> 4 != 4 {
+            ctx.cost_is_estimated = true;
+            return;
+        }
+        let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if total_length < MIN_IPV4_LEN {
+            ctx.cost_is_estimated = true;
+            return;
+        }
 
-        if version == 4 && data.len() >= 4 {
-            let total_length = u16::from_be_bytes([data[2], data[3]]) as usize;
-            ctx.pkt_len = total_length;
-            ctx.cost = total_length;
+        let true_length = if total_length <= data.len() {
+            // 拷全了：多出来的是以太网尾部填充，不算数
+            total_length
+        } else if data.len() < ctx.pkt_len {
+            // 被 set_copy_range 截断了：header 才是线上真实体积，但不能超过内核报的原始长度
+            // 盖个戳让入口记一笔，退出时打印出来核对 copy_range
+            ctx.copy_truncated = true;
+            total_length.min(ctx.pkt_len)
         } else {
-            panic!()
-        }
+            // 明明拷全了 header 还说更长，是 header 在吹牛，按实际字节算
+            data.len()
+        };
+
+        // pkt_len 保持内核报的原始长度不动：这里还原的是计费基数，不是线上字节
+        ctx.cost = true_length;
+        ctx.cost_is_estimated = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 只拷了 64 字节的 IPv4 头，内核报原始长度 1500
+    fn truncated(total_length: u16) -> PacketContext<Vec<u8>, u64> {
+        let mut data = vec![0u8; 64];
+        data[0] = 0x45;
+        data[2..4].copy_from_slice(&total_length.to_be_bytes());
+        PacketContext::new(data, 0, 0, 0, 0, 1500)
+    }
+
+    #[test]
+    fn truncated_copy_is_costed_from_header_and_flagged() {
+        let mut ctx = truncated(1400);
+        TrueLengthModifier::new().process(&mut ctx);
+        assert!(ctx.copy_truncated);
+        assert!(!ctx.cost_is_estimated);
+        assert_eq!(ctx.cost, 1400);
+        assert_eq!(ctx.pkt_len, 1500);
+
+        // header 吹得比内核报的还大，封顶到原始长度
+        let mut ctx = truncated(9000);
+        TrueLengthModifier::new().process(&mut ctx);
+        assert!(ctx.copy_truncated);
+        assert_eq!(ctx.cost, 1500);
+    }
+
+    #[test]
+    fn full_copy_is_not_flagged() {
+        let mut data = vec![0u8; 80];
+        data[0] = 0x45;
+        data[2..4].copy_from_slice(&60u16.to_be_bytes());
+        let mut ctx = PacketContext::new(data, 0u64, 0, 0, 0, 80);
+        TrueLengthModifier::new().process(&mut ctx);
+        assert!(!ctx.copy_truncated);
+        assert_eq!(ctx.cost, 60);
     }
 }
@@ -0,0 +1,73 @@
+use crate::modifier::PacketModifier;
+use crate::packet_context::PacketContext;
+
+// 低 TTL 包的处置方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlAction {
+    Flag, // 只盖 low_ttl 戳，交给下游分类器决定
+    Drop, // 盖戳并要求 main 在入队前直接 Drop
+}
+
+// ==========================================
+// 路由环路探测器 (TTL Guard Modifier)
+// TTL 低于阈值的包多半在绕圈，别在它身上浪费带宽
+// ==========================================
+pub struct TtlGuardModifier {
+    threshold: u8,
+    action: TtlAction,
+}
+
+impl TtlGuardModifier {
+    pub fn new(threshold: u8, action: TtlAction) -> Self {
+        Self { threshold, action }
+    }
+}
+
+impl<T: AsRef<[u8]>, K> PacketModifier<T, K> for TtlGuardModifier {
+    fn process(&self, ctx: &mut PacketContext<T, K>) {
+        ctx.low_ttl = false;
+
+        let data = ctx.msg.as_ref();
+
+        // 只认完整的 IPv4 头，TTL 在第 8 字节
+        if data.len() < 20 || data[0] >> 4 != 4 {
+            return;
+        }
+
+        if data[8] < self.threshold {
+            ctx.low_ttl = true;
+            if self.action == TtlAction::Drop {
+                ctx.ingress_drop = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TTL 为 ttl 的 IPv4 包，过一遍阈值 5 的守卫：(low_ttl, ingress_drop)
+    fn guard(action: TtlAction, ttl: u8) -> (bool, bool) {
+        let mut data = vec![0u8; 40];
+        data[0] = 0x45;
+        data[8] = ttl;
+        let mut ctx = PacketContext::new(data, 1, 1, 0, 0, 40);
+        TtlGuardModifier::new(5, action).process(&mut ctx);
+        (ctx.low_ttl, ctx.ingress_drop)
+    }
+
+    #[test]
+    fn flag_mode_only_stamps_below_the_threshold() {
+        assert_eq!(guard(TtlAction::Flag, 4), (true, false));
+        assert_eq!(guard(TtlAction::Flag, 5), (false, false));
+        assert_eq!(guard(TtlAction::Flag, 6), (false, false));
+    }
+
+    #[test]
+    fn drop_mode_also_asks_for_an_ingress_drop() {
+        assert_eq!(guard(TtlAction::Drop, 4), (true, true));
+        assert_eq!(guard(TtlAction::Drop, 5), (false, false));
+        assert_eq!(guard(TtlAction::Drop, 6), (false, false));
+    }
+}
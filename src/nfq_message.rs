@@ -8,6 +8,13 @@ impl AsRef<[u8]> for NfqMessage {
     }
 }
 
+// 可写的 payload：nfq 一旦借出就会在放行时把整包写回内核 (不管改没改)，只在真要改的时候拿
+impl AsMut<[u8]> for NfqMessage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.0.get_payload_mut()
+    }
+}
+
 impl From<Message> for NfqMessage {
     fn from(value: Message) -> Self {
         Self(value)
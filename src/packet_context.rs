@@ -1,19 +1,226 @@
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
+
+use serde::Deserialize;
+
+use crate::clock;
+
+// 调度器替包选定的出口类别，盖一次就定死，后面谁想知道直接读，不用再跑一遍分类器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClassId {
+    Vip,                 // HTB 高优
+    Default,             // HTB 低优
+    Label(&'static str), // 其他调度器自己的分支名 (比如 DualFair 的 "a" / "b")
+    Partition(usize),    // PartitionQdisc 的分区下标
+    Lane(usize),         // FairQdisc 的通道下标
+}
+
+// 内核 conntrack 给这个包定的连接状态 (回程方向的也归到同一类)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    New,         // 建连中的包，TCP 就是 SYN / SYN-ACK
+    Established, // 已经双向见过包的连接
+    Related,     // 由别的连接派生出来的 (FTP 数据、ICMP 错误之类)
+}
+
+// 正常出队的包怎么回执给内核
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseVerdict {
+    #[default]
+    Accept, // 放行，继续走后面的规则
+    Repeat, // 带着新 mark 从当前 hook 重新走一遍 (整完形让 iptables 按 mark 再分一次)
+    Stop,   // 放行，但跳过这个 hook 后面的规则
+}
+
+// 包是因为什么死的，在判死的那一刻盖上
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DropReason {
+    LatencyExpired, // 排队太久 (TtlDropWrapper)
+    HardLimit,      // 叶子队列容量爆了 (HeadDropFifo)
+    Aqm,            // 主动队列管理判的
+    AckSuperseded,  // 被更新的 ACK 取代 (TcpAckFilterQdisc)
+    MemLimit,       // 全局内存上限，从最胖的流开刀 (ClassDrrQdisc)
+}
+
+// 一个 TCP 头里最多塞得下 4 个 SACK 块 (40 字节选项区 - 2 字节类型长度)
+const MAX_SACK_BLOCKS: usize = 4;
+
+// 定长的 SACK 块集合，[左沿, 右沿)，不进堆
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SackBlocks {
+    blocks: [(u32, u32); MAX_SACK_BLOCKS],
+    len: usize,
+}
+
+impl SackBlocks {
+    pub fn iter(&self) -> impl Iterator<Item = &(u32, u32)> {
+        self.blocks[..self.len].iter()
+    }
+
+    // 满了就挤掉最老的一块
+    pub fn push(&mut self, block: (u32, u32)) {
+        if self.iter().any(|b| *b == block) {
+            return;
+        }
+        if self.len == MAX_SACK_BLOCKS {
+            self.blocks.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.blocks[self.len] = block;
+        self.len += 1;
+    }
+
+    // 自己带的每一块，对方是不是都已经知道了：
+    // 要么在对方的累计确认号以下，要么被对方某一块完整盖住 (序号按 32 位回绕比较)
+    pub fn covered_by(&self, other: &SackBlocks, other_ack: u32) -> bool {
+        let le = |a: u32, b: u32| b.wrapping_sub(a) as i32 >= 0;
+        self.iter().all(|&(left, right)| {
+            le(right, other_ack) || other.iter().any(|&(l, r)| le(l, left) && le(right, r))
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct PacketContext<T, K> {
     // 1. 核心载体
     pub msg: T, // 数据包实体
     pub key: K, // 流标识 (用于黑盒内部 Hash 分配队列)
+    pub flow_hash: u64, // key 的哈希，入口算一次，各层 qdisc 的流表直接拿它当键
 
+    // 线上真实长度 (内核报的原始长度)，修改器一律不动，要改账只改 cost
     pub pkt_len: usize,
     pub cost: usize, // 计算完OVERHEAD后的数据包长度
+    pub cost_is_estimated: bool, // cost 来自 NFQUEUE 报告的原始长度 (估算)，而不是 IP 头实测
+    // 拷进来的字节比 IP 头报的短 (TrueLengthModifier 盖的)，入口据此核对 copy_range
+    pub copy_truncated: bool,
 
     // 3. 路由归还依据 (为 Verdict 准备)
     pub queue_num: usize, // 必须保留！出队后靠它找到对应的队列句柄发 verdict
+    pub packet_id: u32,   // 内核给的包 id (队列内递增)，日志对账用；将来批量 verdict 也靠它
     pub arrival_time: Instant, // ✅ 新增：记录包进入内存的时刻
+    pub arrival_wall: Option<SystemTime>, // 同一时刻的墙上时间，只给日志对 pcap 用，过期判断一律看 arrival_time
 
     pub frames: usize,
     pub is_pure_ack: bool,
     pub tcp_ack_num: u32,
+    pub tcp_seq: u32,
+    pub payload_len: usize, // TCP 应用层负载长度 (纯 ACK 为 0)
+    pub sack: SackBlocks,   // TCP 选项里携带的 SACK 块
+    pub tcp_window: u16,    // TCP 头里的原始接收窗口 (未乘缩放因子)
+    pub quic_cid_hash: u64, // QUIC 目的连接 ID 的哈希，非 QUIC 包为 0
+    pub flow_rate_bps: f64, // 这条流最近的速率估计 (FlowRateModifier 盖的)，没测就是 0
+
+    pub low_ttl: bool,      // TTL 低于阈值，疑似路由环路
+    pub is_dns: bool,       // TCP/UDP 53 端口，HTB 无视 queue_num 直接送进高优
+    pub ingress_drop: bool, // 修改器判了死刑，main 在入队前直接 Drop
+    pub drop_exempt: bool,  // 新流宽限期内：延迟类丢弃 (TTL 过期) 豁免，硬容量上限照旧
+    pub is_control: bool,   // TCP 控制包 (SYN / FIN / RST / 纯 ACK)，HTB 可以让它走专用小桶插队
+
+    // 入口从 NFQUEUE 带的 conntrack 信息里读，内核没给 (没开 / 没加载 conntrack) 就是 None
+    pub conn_state: Option<ConnState>,
+
+    // 最外层做分流的调度器盖的戳；内层调度器不覆盖，保证 VIP/默认 这一级判决不被冲掉
+    pub egress_class: Option<ClassId>,
+    pub drop_reason: Option<DropReason>, // 只有被丢弃的包才有，collect_dropped 吐出来时必定已盖好
+
+    // 出队放行时一起带回内核的 nfmark (None 就不动原来的 mark) 和回执方式；被丢的包不看这两个
+    pub mark: Option<u32>,
+    pub release: ReleaseVerdict,
+    // 放行前真按这个 MTU 切成 IP 分片 (FragmentModifier 的 split 模式盖的)，None 就原样交给内核
+    pub split_mtu: Option<usize>,
+}
+
+impl<T, K> PacketContext<T, K> {
+    // 入口刚收到的样子：账先按报上来的原始长度记 (估算)，到达时刻就是现在，其余的戳留给修改器去盖
+    pub fn new(
+        msg: T,
+        key: K,
+        flow_hash: u64,
+        queue_num: usize,
+        packet_id: u32,
+        pkt_len: usize,
+    ) -> Self {
+        Self {
+            msg,
+            key,
+            flow_hash,
+            pkt_len,
+            cost: pkt_len,
+            cost_is_estimated: true,
+            copy_truncated: false,
+            queue_num,
+            packet_id,
+            arrival_time: clock::now(),
+            arrival_wall: Some(clock::wall_now()),
+            frames: 1,
+            is_pure_ack: false,
+            tcp_ack_num: 0,
+            tcp_seq: 0,
+            payload_len: 0,
+            sack: SackBlocks::default(),
+            tcp_window: 0,
+            quic_cid_hash: 0,
+            flow_rate_bps: 0.0,
+            low_ttl: false,
+            is_dns: false,
+            ingress_drop: false,
+            drop_exempt: false,
+            is_control: false,
+            conn_state: None,
+            egress_class: None,
+            drop_reason: None,
+            mark: None,
+            release: ReleaseVerdict::Accept,
+            split_mtu: None,
+        }
+    }
+}
+
+// 测试用的包：载荷就是 len 个零字节，流键直接拿 flow_hash 充当
+#[cfg(test)]
+pub(crate) fn test_packet(
+    flow_hash: u64,
+    queue_num: usize,
+    len: usize,
+) -> PacketContext<Vec<u8>, u64> {
+    PacketContext::new(vec![0; len], flow_hash, flow_hash, queue_num, 0, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // 真时钟下两个戳都盖上，而且是同一时刻的两种读数
+    #[test]
+    fn arrival_is_stamped_on_both_clocks() {
+        let before = SystemTime::now();
+        let ctx = test_packet(1, 0, 64);
+        let after = SystemTime::now();
+
+        let wall = ctx.arrival_wall.expect("墙上时间没盖");
+        assert!(before <= wall && wall <= after);
+        assert!(ctx.arrival_time.elapsed() < Duration::from_secs(1));
+    }
+
+    // 回放拨虚拟时间时墙上时间跟着虚拟时间走，两个戳之间的间隔一模一样，不掺真时钟
+    #[test]
+    fn wall_stamp_follows_the_virtual_clock() {
+        let start = Instant::now();
+        clock::set_virtual_now(start);
+        let first = test_packet(1, 0, 64);
+        clock::set_virtual_now(start + Duration::from_secs(5));
+        let second = test_packet(1, 0, 64);
+
+        assert_eq!(
+            second.arrival_time - first.arrival_time,
+            Duration::from_secs(5)
+        );
+        let gap = second
+            .arrival_wall
+            .unwrap()
+            .duration_since(first.arrival_wall.unwrap())
+            .unwrap();
+        assert_eq!(gap, Duration::from_secs(5));
+    }
 }
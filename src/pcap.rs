@@ -0,0 +1,157 @@
+// ================= pcap 读取 (回放用) =================
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::time::Duration;
+
+// 经典 pcap 格式 (不是 pcapng)：文件头 24 字节，每个包前面 16 字节记录头
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+// 认得的链路层类型，其它的整个文件拒收
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113; // tcpdump -i any
+const LINKTYPE_IPV4: u32 = 228;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+// 抓包文件里的一个 IPv4 包 (链路层头已经剥掉)
+pub struct PcapPacket {
+    pub ts: Duration,    // 抓包时间戳 (相对 Unix 纪元)
+    pub ip: Vec<u8>,     // 从 IP 头开始的字节，抓包时被 snaplen 截断的话就是截断后的
+    pub orig_len: usize, // 线上原始的 IP 包长度，对应 NFQUEUE 报的原始长度
+}
+
+pub struct PcapReader<R> {
+    reader: R,
+    swapped: bool, // 文件是反字节序写的
+    nanos: bool,   // 时间戳的小数部分是纳秒而不是微秒
+    linktype: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => {
+                return Err(invalid(
+                    "不是经典 pcap 文件 (pcapng 请先用 editcap -F pcap 转一下)",
+                ));
+            }
+        };
+        let mut pcap = Self {
+            reader,
+            swapped,
+            nanos,
+            linktype: 0,
+        };
+        pcap.linktype = pcap.u32_at(&header, 20);
+        match pcap.linktype {
+            LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LINUX_SLL | LINKTYPE_IPV4 => Ok(pcap),
+            other => Err(invalid(&format!("不支持的链路层类型 {}", other))),
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = [
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ];
+        if self.swapped {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    // 读下一条记录；文件正好在记录边界上结束返回 None
+    fn next_record(&mut self) -> io::Result<Option<(Duration, Vec<u8>, usize)>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let secs = self.u32_at(&header, 0) as u64;
+        let frac = self.u32_at(&header, 4) as u64;
+        let incl_len = self.u32_at(&header, 8) as usize;
+        let orig_len = self.u32_at(&header, 12) as usize;
+        if incl_len > 256 * 1024 {
+            return Err(invalid("记录长度离谱，文件多半坏了"));
+        }
+
+        let mut frame = vec![0u8; incl_len];
+        self.reader.read_exact(&mut frame)?;
+        let ts = if self.nanos {
+            Duration::new(secs, frac as u32)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(frac)
+        };
+        Ok(Some((ts, frame, orig_len)))
+    }
+
+    // 剥掉链路层头，返回 IP 头的起点；不是 IPv4 的帧返回 None
+    fn ip_offset(&self, frame: &[u8]) -> Option<usize> {
+        let ethertype_at = |offset: usize| {
+            frame
+                .get(offset..offset + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        let offset = match self.linktype {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                while matches!(ethertype_at(offset)?, ETHERTYPE_VLAN | ETHERTYPE_QINQ) {
+                    offset += 4;
+                }
+                (ethertype_at(offset)? == ETHERTYPE_IPV4).then_some(offset + 2)?
+            }
+            LINKTYPE_LINUX_SLL => (ethertype_at(14)? == ETHERTYPE_IPV4).then_some(16)?,
+            _ => 0,
+        };
+        let version = frame.get(offset)? >> 4;
+        (version == 4).then_some(offset)
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapPacket>;
+
+    // 非 IPv4 的帧 (ARP、IPv6 之类) 直接跳过
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (ts, frame, orig_len) = match self.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(offset) = self.ip_offset(&frame) {
+                return Some(Ok(PcapPacket {
+                    ts,
+                    ip: frame[offset..].to_vec(),
+                    orig_len: orig_len.saturating_sub(offset),
+                }));
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
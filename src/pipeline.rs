@@ -0,0 +1,369 @@
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use nfq::{Message, conntrack::State};
+
+use crate::{
+    config::ModifierMap,
+    five_tuple::{FiveTuple, FlowKeyPolicy, flow_hash_seed},
+    modifier::apply_modifiers,
+    nfq_message::NfqMessage,
+    packet_context::{ConnState, PacketContext},
+    qdisc::{
+        Qdisc, QdiscExt, restore_tree, snapshot_tree,
+        wrapper::{DropEvent, MonitorQdisc},
+    },
+};
+
+// 钉死 K = FiveTuple (默认 T = NfqMessage) 之后的常用类型
+pub type Packet<T = NfqMessage> = PacketContext<T, FiveTuple>;
+pub type StandardQdisc<T = NfqMessage> = dyn Qdisc<T, FiveTuple>;
+pub type StandardModifiers<T = NfqMessage> = ModifierMap<T, FiveTuple>;
+
+// 进流水线的包要交代的几样东西：线上是内核给的 nfq::Message，
+// 它在 nfq 库外造不出来，测试里换成随手拼的字节，整条流水线照样走一遍
+pub trait IngressMessage {
+    fn payload(&self) -> &[u8];
+    fn original_len(&self) -> usize;
+    fn packet_id(&self) -> u32;
+    fn conn_state(&self) -> Option<ConnState>;
+}
+
+impl IngressMessage for Message {
+    fn payload(&self) -> &[u8] {
+        self.get_payload()
+    }
+
+    fn original_len(&self) -> usize {
+        self.get_original_len()
+    }
+
+    fn packet_id(&self) -> u32 {
+        self.get_packet_id()
+    }
+
+    fn conn_state(&self) -> Option<ConnState> {
+        self.get_conntrack()
+            .and_then(|ct| conn_state_of(ct.get_state()))
+    }
+}
+
+// ==========================================
+// 标准流水线门面 (Standard Pipeline)
+// 修改器 -> 调度树 -> 监控大屏 这一整套组装的外壳，
+// 只是省掉 main 里的泛型噪音，不带任何新的调度逻辑
+// 包类型留成泛型只是为了能喂假包测；main 用的都是 StandardPipeline
+// ==========================================
+pub struct Pipeline<T> {
+    root: MonitorQdisc<T, FiveTuple>,
+    modifiers: StandardModifiers<T>,
+    key_policy: FlowKeyPolicy,
+    truncated_copies: u64, // 截断拷贝 (header 比拷进来的字节多) 的次数，用来核对 copy_range
+}
+
+pub type StandardPipeline = Pipeline<NfqMessage>;
+
+impl<T: 'static> Pipeline<T> {
+    pub fn new(
+        root: Box<StandardQdisc<T>>,
+        modifiers: StandardModifiers<T>,
+        key_policy: FlowKeyPolicy,
+    ) -> Self {
+        Self {
+            root: MonitorQdisc::new("Root", root),
+            modifiers,
+            key_policy,
+            truncated_copies: 0,
+        }
+    }
+
+    pub fn set_report_interval(&mut self, interval: Duration) {
+        self.root.set_report_interval(interval);
+    }
+
+    pub fn enable_decision_latency(&mut self) {
+        self.root.enable_decision_latency();
+    }
+
+    pub fn subscribe_drops(&mut self, capacity: usize) -> Receiver<DropEvent> {
+        self.root.subscribe_drops(capacity)
+    }
+
+    // 控制通道、统计拆解之类还是要面对整棵树
+    pub fn root(&self) -> &StandardQdisc<T> {
+        &self.root
+    }
+
+    pub fn root_mut(&mut self) -> &mut StandardQdisc<T> {
+        &mut self.root
+    }
+
+    // 拆五元组、过修改器链、进调度树
+    // 被修改器当场判死刑 (ingress_drop) 的包原样退回，调用方直接给它发 Drop
+    pub fn enqueue<M>(&mut self, queue_num: usize, msg: M) -> Option<Packet<T>>
+    where
+        M: IngressMessage + Into<T>,
+    {
+        let ctx = self.ingest(queue_num, msg);
+        if ctx.ingress_drop {
+            return Some(ctx);
+        }
+        self.root.enqueue(ctx);
+        None
+    }
+
+    // 上一次 enqueue 把树挤到按容量丢包了：收包循环据此暂停读这个队列，让内核队列自己背压
+    pub fn last_enqueue_saturated(&self) -> bool {
+        self.root.last_enqueue_overflowed()
+    }
+
+    // 走 drain_ready，"先 peek 再 dequeue" 的约定不用调用方操心
+    pub fn dequeue(&mut self) -> Option<Packet<T>> {
+        self.root.drain_ready().next()
+    }
+
+    // dequeue 给了 None 之后，树里积着的包最早什么时候付得起令牌 (见 Qdisc::next_ready)
+    pub fn next_ready(&mut self) -> Option<Instant> {
+        self.root.next_ready()
+    }
+
+    pub fn collect_dropped(&mut self) -> Vec<Packet<T>> {
+        self.root.collect_dropped()
+    }
+
+    // 退出前清仓：(还在排队的, 判了死刑还没收尸的) 分开还，后者调用方要发 Drop，不能跟着放行
+    pub fn flush(&mut self) -> (Vec<Packet<T>>, Vec<Packet<T>>) {
+        let mut dropped = self.root.collect_dropped();
+        let (late, queued): (Vec<_>, Vec<_>) = self
+            .root
+            .flush()
+            .into_iter()
+            .partition(|ctx| ctx.drop_reason.is_some());
+        dropped.extend(late);
+        (queued, dropped)
+    }
+
+    // 只存分类 / 计数状态，不存包；灌回去的树拓扑得和存的时候一样才对得上号
+    // 最前面 8 字节是存档时的 flow_hash 种子：流表里存的都是按它算的哈希
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut blob = flow_hash_seed().to_le_bytes().to_vec();
+        blob.extend(snapshot_tree(&self.root));
+        blob
+    }
+
+    // 种子不沿用存档里的 (一直用同一个，外面的人迟早能摸出来)：存档的种子和现在的对不上，
+    // 里面按老种子算的哈希就全作废，哈希是单向的换算不回来，整份丢掉返回 None
+    // 只有写死了种子 (FLOW_HASH_SEED) 两边才对得上，这时原样灌回去，返回恢复了几个节点
+    pub fn restore(&mut self, blob: &[u8]) -> Option<usize> {
+        let (seed, tree) = blob.split_first_chunk::<8>()?;
+        if u64::from_le_bytes(*seed) != flow_hash_seed() {
+            return None;
+        }
+        Some(restore_tree(&mut self.root, tree))
+    }
+
+    pub fn truncated_copies(&self) -> u64 {
+        self.truncated_copies
+    }
+
+    fn ingest<M>(&mut self, queue_num: usize, msg: M) -> Packet<T>
+    where
+        M: IngressMessage + Into<T>,
+    {
+        let key = self.key_policy.apply(&FiveTuple::from(msg.payload()));
+        let original_len = msg.original_len();
+        let packet_id = msg.packet_id();
+        let conn_state = msg.conn_state();
+
+        let flow_hash = key.flow_hash();
+        let mut ctx = PacketContext::new(
+            msg.into(),
+            key,
+            flow_hash,
+            queue_num,
+            packet_id,
+            original_len,
+        );
+        ctx.conn_state = conn_state;
+
+        if let Some(modifiers) = self.modifiers.get(&queue_num) {
+            apply_modifiers(&mut ctx, modifiers);
+        }
+        if ctx.copy_truncated {
+            self.truncated_copies += 1;
+        }
+        ctx
+    }
+}
+
+// 没开 conntrack 的队列根本不带这段信息；内核给了个认不出的状态也当没有
+fn conn_state_of(state: State) -> Option<ConnState> {
+    match state {
+        State::New | State::NewReply => Some(ConnState::New),
+        State::Established | State::EstablishedReply => Some(ConnState::Established),
+        State::Related | State::RelatedReply => Some(ConnState::Related),
+        State::Invalid => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::clock;
+    use crate::modifier::{
+        DnsPriorityModifier, OverheadModifier, PacketModifier, TrueLengthModifier,
+    };
+    use crate::qdisc::{leaf::HeadDropFifo, scheduler::SparseQdisc};
+
+    // 假的内核消息：拷进来的字节，外加内核会一起报上来的原始长度和 conntrack 状态
+    struct Synthetic {
+        bytes: Vec<u8>,
+        original_len: usize,
+        conn_state: Option<ConnState>,
+    }
+
+    impl IngressMessage for Synthetic {
+        fn payload(&self) -> &[u8] {
+            &self.bytes
+        }
+
+        fn original_len(&self) -> usize {
+            self.original_len
+        }
+
+        fn packet_id(&self) -> u32 {
+            7
+        }
+
+        fn conn_state(&self) -> Option<ConnState> {
+            self.conn_state
+        }
+    }
+
+    impl From<Synthetic> for Vec<u8> {
+        fn from(msg: Synthetic) -> Self {
+            msg.bytes
+        }
+    }
+
+    // 10.0.0.1 -> 10.0.0.2 的 UDP 包
+    fn udp(src_port: u16, dst_port: u16) -> Synthetic {
+        let mut bytes = vec![0u8; 28];
+        bytes[0] = 0x45;
+        bytes[2..4].copy_from_slice(&28u16.to_be_bytes());
+        bytes[9] = 17;
+        bytes[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        bytes[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        bytes[20..22].copy_from_slice(&src_port.to_be_bytes());
+        bytes[22..24].copy_from_slice(&dst_port.to_be_bytes());
+        Synthetic {
+            original_len: bytes.len(),
+            bytes,
+            conn_state: None,
+        }
+    }
+
+    // 包类型换成 Vec<u8>，其余和 main 里的 StandardPipeline 一样组装
+    fn pipeline() -> Pipeline<Vec<u8>> {
+        pipeline_with(HashMap::new())
+    }
+
+    fn pipeline_with(modifiers: StandardModifiers<Vec<u8>>) -> Pipeline<Vec<u8>> {
+        let root = SparseQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+        );
+        Pipeline::new(Box::new(root), modifiers, FlowKeyPolicy::Full)
+    }
+
+    #[test]
+    fn synthetic_packet_comes_out_of_the_tree_stamped() {
+        let dns: Vec<Box<dyn PacketModifier<Vec<u8>, FiveTuple>>> =
+            vec![Box::new(DnsPriorityModifier::new())];
+        let mut pipeline = pipeline_with(HashMap::from([(0, dns)]));
+        assert!(pipeline.enqueue(0, udp(40000, 53)).is_none());
+        assert!(pipeline.enqueue(1, udp(40000, 53)).is_none());
+
+        // 0 号队列挂了修改器链，1 号没挂：出来的包拆好了五元组，只有 0 号的被盖了 DNS 戳
+        let ctx = pipeline.dequeue().expect("包应该从树里出来");
+        assert_eq!(ctx.key.src, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(ctx.key.dst, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!((ctx.key.proto, ctx.key.dst_port), (17, 53));
+        assert_eq!(ctx.flow_hash, ctx.key.flow_hash());
+        assert_eq!((ctx.queue_num, ctx.packet_id, ctx.msg.len()), (0, 7, 28));
+        assert!(ctx.is_dns);
+        let ctx = pipeline.dequeue().unwrap();
+        assert_eq!(ctx.queue_num, 1);
+        assert!(!ctx.is_dns);
+
+        assert!(pipeline.dequeue().is_none());
+        let totals = pipeline.root().monitor_report().unwrap().totals;
+        assert_eq!(totals.total_out, 2);
+    }
+
+    #[test]
+    fn restore_discards_state_hashed_under_another_seed() {
+        let blob = pipeline().snapshot();
+        assert_eq!(pipeline().restore(&blob), Some(1));
+
+        // 存档的种子和现在的对不上：整份作废，种子也不跟着换回去
+        let seed = flow_hash_seed();
+        let mut stale = blob.clone();
+        stale[..8].copy_from_slice(&(seed ^ 1).to_le_bytes());
+        assert_eq!(pipeline().restore(&stale), None);
+        assert_eq!(flow_hash_seed(), seed);
+    }
+
+    #[test]
+    fn pkt_len_stays_the_wire_length_while_cost_is_shaped() {
+        let start = Instant::now();
+        clock::set_virtual_now(start);
+        let chain: Vec<Box<dyn PacketModifier<Vec<u8>, FiveTuple>>> = vec![
+            Box::new(TrueLengthModifier::new()),
+            Box::new(OverheadModifier::new(98)),
+        ];
+        let mut pipeline = pipeline_with(HashMap::from([(0, chain)]));
+        // 只拷进来 28 字节的截断拷贝，IP 头和内核都说线上是 1400
+        let mut msg = udp(40000, 443);
+        msg.bytes[2..4].copy_from_slice(&1400u16.to_be_bytes());
+        msg.original_len = 1400;
+        assert!(pipeline.enqueue(0, msg).is_none());
+        assert_eq!(pipeline.truncated_copies(), 1);
+
+        let ctx = pipeline.dequeue().unwrap();
+        assert_eq!((ctx.pkt_len, ctx.cost), (1400, 1498));
+        // 监控两列分开记：整形用的 cost 和线上的真实字节
+        clock::set_virtual_now(start + Duration::from_secs(1));
+        let report = pipeline.root().monitor_report().unwrap();
+        let total = report.total();
+        assert_eq!((total.out_bytes, total.out_wire_bytes), (1498.0, 1400.0));
+        assert_eq!(report.shaped_mbps(&total), 1498.0 * 8.0 / 1e6);
+        assert_eq!(report.wire_mbps(&total), 1400.0 * 8.0 / 1e6);
+    }
+
+    #[test]
+    fn conntrack_state_is_stamped_on_ingress() {
+        // 回程方向归到同一类，认不出的当没有
+        assert_eq!(conn_state_of(State::New), Some(ConnState::New));
+        assert_eq!(conn_state_of(State::NewReply), Some(ConnState::New));
+        assert_eq!(
+            conn_state_of(State::EstablishedReply),
+            Some(ConnState::Established)
+        );
+        assert_eq!(conn_state_of(State::Related), Some(ConnState::Related));
+        assert_eq!(conn_state_of(State::Invalid), None);
+
+        let mut pipeline = pipeline();
+        let mut established = udp(40000, 443);
+        established.conn_state = Some(ConnState::Established);
+        pipeline.enqueue(0, established);
+        pipeline.enqueue(0, udp(40001, 443)); // 内核没带 conntrack 信息
+        let states: Vec<_> = std::iter::from_fn(|| pipeline.dequeue())
+            .map(|ctx| ctx.conn_state)
+            .collect();
+        assert_eq!(states, [Some(ConnState::Established), None]);
+    }
+}
@@ -1,6 +1,20 @@
 use std::collections::VecDeque;
 
-use crate::{packet_context::PacketContext, qdisc::Qdisc};
+use serde::Deserialize;
+
+use crate::{
+    packet_context::{DropReason, PacketContext},
+    qdisc::Qdisc,
+};
+
+// 队列满了丢谁
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    #[default]
+    Head, // 踢掉最老的队头，给新包腾位置 (对延迟敏感的流量更友好)
+    Tail, // 队列原封不动，直接拒收新来的包 (保住已经排好序的老数据)
+}
 
 // ==========================================
 // 纯粹的容量限制队列 (不管时间，只管空间)
@@ -8,33 +22,122 @@ use crate::{packet_context::PacketContext, qdisc::Qdisc};
 pub struct HeadDropFifo<T, K> {
     queue: VecDeque<PacketContext<T, K>>,
     hard_limit: usize,
-    dropped: Vec<PacketContext<T, K>>, // 被物理挤出去的包
+    policy: DropPolicy,
+    dropped: Vec<PacketContext<T, K>>, // 被物理挤出去 (或拒收) 的包
+    overflow_drops: u64,
 }
 
 impl<T, K> HeadDropFifo<T, K> {
     pub fn new(hard_limit: usize) -> Self {
+        Self::with_policy(hard_limit, DropPolicy::Head)
+    }
+
+    pub fn with_policy(hard_limit: usize, policy: DropPolicy) -> Self {
         Self {
             queue: VecDeque::new(),
             hard_limit,
+            policy,
             dropped: Vec::new(),
+            overflow_drops: 0,
         }
     }
 }
 
 impl<T, K> Qdisc<T, K> for HeadDropFifo<T, K> {
-    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
         if self.queue.len() >= self.hard_limit {
-            if let Some(old_ctx) = self.queue.pop_front() {
-                self.dropped.push(old_ctx); // 容量爆了，踢掉队头
+            match self.policy {
+                DropPolicy::Head => {
+                    if let Some(mut old_ctx) = self.queue.pop_front() {
+                        old_ctx.drop_reason = Some(DropReason::HardLimit);
+                        self.dropped.push(old_ctx); // 容量爆了，踢掉队头
+                        self.overflow_drops += 1;
+                    }
+                }
+                DropPolicy::Tail => {
+                    ctx.drop_reason = Some(DropReason::HardLimit);
+                    self.dropped.push(ctx); // 容量爆了，新包直接进回收站
+                    self.overflow_drops += 1;
+                    return;
+                }
             }
         }
         self.queue.push_back(ctx);
     }
     
     fn peek(&mut self) -> Option<&PacketContext<T, K>> { self.queue.front() }
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> { self.queue.front() }
     fn dequeue(&mut self) -> Option<PacketContext<T, K>> { self.queue.pop_front() }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&PacketContext<T, K>> { self.queue.get(n) }
+    fn dequeue_nth(&mut self, n: usize) -> Option<PacketContext<T, K>> { self.queue.remove(n) }
     
     fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
         std::mem::take(&mut self.dropped)
     }
-}
\ No newline at end of file
+
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        let i = self.queue.iter().position(|ctx| ctx.flow_hash == flow_hash)?;
+        let mut victim = self.queue.remove(i)?;
+        victim.drop_reason = Some(reason);
+        Some(victim)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.dropped);
+        all.extend(self.queue.drain(..));
+        all
+    }
+
+    fn describe(&self) -> String {
+        match self.policy {
+            DropPolicy::Head => format!("HeadDropFifo({})", self.hard_limit),
+            DropPolicy::Tail => format!("HeadDropFifo({}, tail)", self.hard_limit),
+        }
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        vec![(DropReason::HardLimit, self.overflow_drops)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+
+    // 容量 2 的队列塞 3 个包：(出队的流号, 被丢的流号)
+    fn overflow(policy: DropPolicy) -> (Vec<u64>, Vec<u64>) {
+        let mut fifo = HeadDropFifo::with_policy(2, policy);
+        for flow in 1..=3 {
+            fifo.enqueue(test_packet(flow, 0, 100));
+        }
+        assert_eq!(fifo.drop_counts(), vec![(DropReason::HardLimit, 1)]);
+        let dropped = fifo.collect_dropped();
+        assert!(
+            dropped
+                .iter()
+                .all(|ctx| ctx.drop_reason == Some(DropReason::HardLimit))
+        );
+        let mut sent = Vec::new();
+        while fifo.peek().is_some() {
+            sent.push(fifo.dequeue().unwrap().flow_hash);
+        }
+        (sent, dropped.iter().map(|ctx| ctx.flow_hash).collect())
+    }
+
+    #[test]
+    fn tail_policy_rejects_the_new_packet() {
+        assert_eq!(overflow(DropPolicy::Tail), (vec![1, 2], vec![3]));
+    }
+
+    #[test]
+    fn head_policy_drops_the_oldest() {
+        assert_eq!(overflow(DropPolicy::Head), (vec![2, 3], vec![1]));
+        assert_eq!(HeadDropFifo::<Vec<u8>, u64>::new(2).policy, DropPolicy::Head);
+    }
+}
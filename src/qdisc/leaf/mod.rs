@@ -1,3 +1,7 @@
 mod head_drop_fifo;
+mod pacing_qdisc;
+mod passthrough_qdisc;
 
-pub use head_drop_fifo::HeadDropFifo;
\ No newline at end of file
+pub use head_drop_fifo::{DropPolicy, HeadDropFifo};
+pub use pacing_qdisc::PacingQdisc;
+pub use passthrough_qdisc::PassthroughQdisc;
\ No newline at end of file
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::packet_context::{DropReason, PacketContext};
+use crate::qdisc::Qdisc;
+
+struct PacedFlow<T, K> {
+    queue: VecDeque<PacketContext<T, K>>,
+    next_send: Instant, // 这条流的队头最早什么时候能走
+}
+
+// ==========================================
+// 按流匀速发包的叶子 (Pacing Qdisc)
+// 单条贪婪流不再一口气把一串包倒给下游，而是按 per_flow_rate 把包均匀摊开：
+// 每走一个包，这条流的下次发送时间往后推 cost / rate
+// 和固定延迟不同，这里只管 "间隔"，闲着的流来的第一个包马上就能走
+// 多条流之间轮询，谁的时间到了谁走
+// ==========================================
+pub struct PacingQdisc<T, K> {
+    flows: HashMap<u64, PacedFlow<T, K>>,
+    active: VecDeque<u64>, // 有包排队的流，轮询顺序
+    rate: f64,             // 每条流的速率 (字节/秒)
+    limit: usize,          // 所有流加起来最多排多少个包
+    backlog: usize,
+    peeked: Option<u64>, // peek 选中的流，dequeue 直接从它拿
+    dropped: Vec<PacketContext<T, K>>,
+    overflow_drops: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl<T, K> PacingQdisc<T, K> {
+    pub fn new(per_flow_rate: f64) -> Self {
+        Self {
+            flows: HashMap::new(),
+            active: VecDeque::new(),
+            rate: per_flow_rate.max(1.0),
+            limit: 10_000,
+            backlog: 0,
+            peeked: None,
+            dropped: Vec::new(),
+            overflow_drops: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn interval(&self, cost: usize) -> Duration {
+        Duration::from_secs_f64(cost as f64 / self.rate)
+    }
+
+    // 轮询一圈，找第一个发送时间已到的流；找到的流转到队尾，下次从它后面开始
+    fn select(&mut self) -> Option<u64> {
+        if let Some(hash) = self.peeked {
+            return Some(hash);
+        }
+        let now = self.clock.now();
+        for _ in 0..self.active.len() {
+            let hash = self.active.pop_front()?;
+            self.active.push_back(hash);
+            if self.flows.get(&hash).is_some_and(|f| f.next_send <= now) {
+                self.peeked = Some(hash);
+                return Some(hash);
+            }
+        }
+        None
+    }
+}
+
+impl<T, K> Qdisc<T, K> for PacingQdisc<T, K> {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        if self.backlog >= self.limit {
+            ctx.drop_reason = Some(DropReason::HardLimit);
+            self.dropped.push(ctx);
+            self.overflow_drops += 1;
+            return;
+        }
+
+        let now = self.clock.now();
+        let flow = self
+            .flows
+            .entry(ctx.flow_hash)
+            .or_insert_with(|| PacedFlow {
+                queue: VecDeque::new(),
+                next_send: now,
+            });
+        if flow.queue.is_empty() {
+            self.active.push_back(ctx.flow_hash);
+        }
+        flow.queue.push_back(ctx);
+        self.backlog += 1;
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        let hash = self.select()?;
+        self.flows.get(&hash).and_then(|f| f.queue.front())
+    }
+
+    // 有 peek 选好的流就报它，否则报轮询顺序上的下一条流 (它的发送时间未必到了)
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        let hash = self.peeked.or_else(|| self.active.front().copied())?;
+        self.flows.get(&hash).and_then(|f| f.queue.front())
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        let hash = self.peeked.take()?;
+        let now = self.clock.now();
+        let cost = self.flows.get(&hash)?.queue.front()?.cost;
+        let interval = self.interval(cost);
+
+        let flow = self.flows.get_mut(&hash)?;
+        let ctx = flow.queue.pop_front()?;
+        self.backlog -= 1;
+
+        // 从 "本该发的时间" 往后推，出队晚了一点不至于把速率压低；
+        // 但最多追回一个间隔，闲置很久的流不会攒出一串突发
+        let base = now
+            .checked_sub(interval)
+            .map_or(now, |floor| flow.next_send.max(floor));
+        flow.next_send = base + interval;
+
+        if flow.queue.is_empty() {
+            self.active.retain(|&h| h != hash);
+            // 间隔已经走完的空流没必要留着，下一个包来了从现在算
+            if flow.next_send <= now {
+                self.flows.remove(&hash);
+            }
+        }
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        // 空流的发送时间到了就顺手回收，流表不会越攒越大
+        if self.flows.len() > self.active.len() {
+            let now = self.clock.now();
+            self.flows
+                .retain(|_, f| !f.queue.is_empty() || f.next_send > now);
+        }
+        std::mem::take(&mut self.dropped)
+    }
+
+    // 只摘包不动这条流的发送时间：被摘的包本来就没发出去，不占间隔
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        let flow = self.flows.get_mut(&flow_hash)?;
+        let mut victim = flow.queue.pop_front()?;
+        self.backlog -= 1;
+        if flow.queue.is_empty() {
+            self.active.retain(|&h| h != flow_hash);
+            if self.peeked == Some(flow_hash) {
+                self.peeked = None;
+            }
+        }
+        victim.drop_reason = Some(reason);
+        Some(victim)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.dropped);
+        for (_, flow) in self.flows.drain() {
+            all.extend(flow.queue);
+        }
+        self.active.clear();
+        self.backlog = 0;
+        self.peeked = None;
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Pacing({:.1} Mbit/s per flow, limit {})",
+            self.rate * 8.0 / 1_000_000.0,
+            self.limit
+        )
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        vec![(DropReason::HardLimit, self.overflow_drops)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet};
+
+    #[test]
+    fn flow_waits_one_interval_between_packets() {
+        let clock = MockClock::new();
+        let mut pacing: PacingQdisc<Vec<u8>, u64> = PacingQdisc::new(1000.0); // 100 字节 = 100ms
+        pacing.set_clock(Box::new(clock.clone()));
+        pacing.enqueue(test_packet(1, 0, 100));
+        pacing.enqueue(test_packet(1, 0, 100));
+        pacing.enqueue(test_packet(2, 0, 100));
+
+        // 两条流的第一个包马上能走
+        for _ in 0..2 {
+            assert!(pacing.peek().is_some());
+            assert!(pacing.dequeue().is_some());
+        }
+        assert!(pacing.peek().is_none());
+
+        clock.advance(Duration::from_millis(99));
+        assert!(pacing.peek().is_none());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(pacing.peek().map(|ctx| ctx.flow_hash), Some(1));
+        assert!(pacing.dequeue().is_some());
+        assert!(pacing.peek().is_none());
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use crate::{packet_context::PacketContext, qdisc::Qdisc};
+
+// ==========================================
+// 只量不管的直通队列 (Passthrough Qdisc)
+// 不限长、不限时、不丢包、不乱序：塞在本该放整形叶子的位置上，
+// 监控面板看到的就是 "没有这一层排队" 时的数字，方便逐层做 A/B 对比
+// ==========================================
+pub struct PassthroughQdisc<T, K> {
+    queue: VecDeque<PacketContext<T, K>>,
+}
+
+impl<T, K> PassthroughQdisc<T, K> {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for PassthroughQdisc<T, K> {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+        self.queue.push_back(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.queue.front()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.queue.front()
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        self.queue.pop_front()
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&PacketContext<T, K>> {
+        self.queue.get(n)
+    }
+
+    fn dequeue_nth(&mut self, n: usize) -> Option<PacketContext<T, K>> {
+        self.queue.remove(n)
+    }
+
+    // 从不丢包，回收站永远是空的
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        Vec::new()
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        self.queue.drain(..).collect()
+    }
+
+    fn describe(&self) -> String {
+        "Passthrough".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::wrapper::MonitorQdisc;
+
+    #[test]
+    fn packets_pass_through_in_order_and_are_counted() {
+        let mut monitor = MonitorQdisc::new("Passthrough", Box::new(PassthroughQdisc::new()));
+        monitor.set_silent(true);
+        for flow in 1..=5 {
+            let mut ctx = test_packet(flow, 0, 100 * flow as usize);
+            ctx.msg.fill(flow as u8);
+            monitor.enqueue(ctx);
+        }
+
+        // 不限长不丢包：进去多少出来多少，顺序、载荷、记账都原样
+        let mut out = Vec::new();
+        while monitor.peek().is_some() {
+            out.push(monitor.dequeue().unwrap());
+        }
+        let flows: Vec<u64> = out.iter().map(|ctx| ctx.flow_hash).collect();
+        assert_eq!(flows, [1, 2, 3, 4, 5]);
+        for ctx in &out {
+            assert_eq!(ctx.msg, vec![ctx.flow_hash as u8; ctx.pkt_len]);
+            assert_eq!(ctx.cost, ctx.pkt_len);
+            assert_eq!(ctx.drop_reason, None);
+        }
+        assert!(monitor.collect_dropped().is_empty());
+
+        let totals = monitor.totals();
+        assert_eq!((totals.total_in, totals.total_out), (5, 5));
+        assert_eq!(totals.total_dropped, 0);
+        assert_eq!(totals.total_bytes, 1500);
+    }
+}
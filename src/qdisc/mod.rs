@@ -1,4 +1,12 @@
-use crate::packet_context::PacketContext;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::{
+    control::ControlCommand,
+    packet_context::{DropReason, PacketContext},
+    qdisc::wrapper::MonitorSnapshot,
+    token_bucket::BucketStats,
+};
 
 pub mod leaf;
 pub mod scheduler;
@@ -9,7 +17,505 @@ pub trait Qdisc<T, K> {
     fn peek(&mut self) -> Option<&PacketContext<T, K>>;
     //执行dequeue前，必须先执行peek做检查
     fn dequeue(&mut self) -> Option<PacketContext<T, K>>;
+    // 只读偷看：给监控之类只拿得到 &self 的地方用，不补水、不充值、不排雷，不推进任何调度状态
+    // 尽力而为，可能是上一次 peek 备好的队头；要靠令牌 / 时间才知道谁是队头的节点返回 None
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        None
+    }
+    // 越过队头往后看第 n 个包 (n = 0 就是 peek)，给上层做 "队头太大先放后面小包" 用
+    // 默认不支持跳队，只有 n = 0 有结果；同样要先 peek_nth 再 dequeue_nth
+    fn peek_nth(&mut self, n: usize) -> Option<&PacketContext<T, K>> {
+        if n == 0 {
+            self.peek()
+        } else {
+            None
+        }
+    }
+    fn dequeue_nth(&mut self, n: usize) -> Option<PacketContext<T, K>> {
+        if n == 0 {
+            self.dequeue()
+        } else {
+            None
+        }
+    }
+    // 不看令牌，此刻排在最前的那个包：桶一干 peek 就是 None，套在外面的延迟死刑照样得看得见队头
+    // 默认就是 peek；自己攥着令牌桶的调度器要自己实现
+    fn peek_head(&mut self) -> Option<&PacketContext<T, K>> {
+        self.peek()
+    }
+    // 把 peek_head 看到的那个包摘下来，不扣令牌、不推进调度状态 (过期的包不该再花一份带宽)
+    // 调用方负责盖 drop_reason；默认就是 dequeue
+    fn drop_head(&mut self) -> Option<PacketContext<T, K>> {
+        self.dequeue()
+    }
     fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
         Vec::new()
     }
+    // 上层替这棵子树做淘汰 (比如 ClassDrr 的全局内存上限)：把 flow_hash 这条流排得最靠前的包摘出来，
+    // 盖上 reason 直接交回调用方，不扣令牌、不动赤字、不推进任何调度状态
+    // 默认不支持；这条流不在肚子里也返回 None。沿途记了在队账的包装层要自己实现，把账平掉再往上交
+    fn drop_flow_head(
+        &mut self,
+        _flow_hash: u64,
+        _reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        None
+    }
+
+    // peek 给了 None 但肚子里还有包时，最早什么时候可能放得出来 (给收包循环按令牌精确睡觉用)
+    // 估不出来 / 没包就是 None，调用方退回按 idle_timeout 小睡；默认取子树里最早的那个
+    fn next_ready(&mut self) -> Option<Instant> {
+        self.children_mut()
+            .into_iter()
+            .filter_map(|(_, child)| child.next_ready())
+            .min()
+    }
+    // 不看令牌、不看时间，把肚子里所有包 (含待收尸的) 一次性倒出来，用于退出前补发 verdict
+    // 倒出来的包里判过死刑的都带着 drop_reason，调用方据此给它们发 Drop 而不是放行
+    // 默认先收尸再把子树挨个倒空；自己手里攥着包或者有调度状态要清的节点得自己实现
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.collect_dropped();
+        for (_, child) in self.children_mut() {
+            all.extend(child.flush());
+        }
+        all
+    }
+    // 一行文字描述整棵子树的拓扑，方便启动时打印 / 核对配置
+    fn describe(&self) -> String;
+    // 运行时控制命令：认领并执行了返回 true，组合节点负责往下转发
+    fn apply_control(&mut self, _cmd: &ControlCommand) -> bool {
+        false
+    }
+    // 直属子节点 (边名, 节点)，给外部遍历整棵树用，叶子没有孩子
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        Vec::new()
+    }
+    // children 的可写版本，顺序必须和 children 一致
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        Vec::new()
+    }
+    // 本节点亲手判死的包数 (原因, 累计个数)，不含子树
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        Vec::new()
+    }
+    // 本节点自己持有的令牌桶 (桶名, 累计统计)，不含子树
+    fn bucket_stats(&self) -> Vec<(&'static str, BucketStats)> {
+        Vec::new()
+    }
+    // 本节点自己的分类 / 计数状态编码成二进制 (不含排队中的包，不含子树)，没有状态就返回空
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    // save_state 的逆操作；blob 解不开就当没有，宁可冷启动也别带着半截状态乱分类
+    fn load_state(&mut self, _state: &[u8]) {}
+    // 只有 MonitorQdisc 有：它最近一个周期的报表
+    fn monitor_report(&self) -> Option<MonitorSnapshot> {
+        None
+    }
+    // 树顶的监控结算一个周期时顺带把子树里静默的监控也结算掉，一张表上的各段周期对得齐
+    // (静默监控没流量经过时自己永远走不到结算点)；默认往下传，只有 MonitorQdisc 自己结算
+    fn close_window(&mut self) {
+        for (_, child) in self.children_mut() {
+            child.close_window();
+        }
+    }
+}
+
+// ==========================================
+// 出队迭代器：把 "先 peek 再 dequeue，直到 None" 包成 for 循环
+// 只掏当下令牌/配额允许发的包，不会像 flush 那样全倒空
+// ==========================================
+pub struct DrainReady<'a, T, K> {
+    qdisc: &'a mut dyn Qdisc<T, K>,
+}
+
+impl<T, K> Iterator for DrainReady<'_, T, K> {
+    type Item = PacketContext<T, K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.qdisc.peek()?;
+        self.qdisc.dequeue()
+    }
+}
+
+pub trait QdiscExt<T, K> {
+    fn drain_ready(&mut self) -> DrainReady<'_, T, K>;
+}
+
+impl<T, K, Q: Qdisc<T, K>> QdiscExt<T, K> for Q {
+    fn drain_ready(&mut self) -> DrainReady<'_, T, K> {
+        DrainReady { qdisc: self }
+    }
+}
+
+impl<T, K> QdiscExt<T, K> for dyn Qdisc<T, K> + '_ {
+    fn drain_ready(&mut self) -> DrainReady<'_, T, K> {
+        DrainReady { qdisc: self }
+    }
+}
+
+// ==========================================
+// 丢包溯源：按树路径汇总 "谁、为什么" 丢的包
+// 路径形如 Monitor/Htb/low:Sparse/bulk:TtlDrop，同路径同原因的计数合并
+// (比如 DRR 下面每个大类的叶子会累加到同一行)
+// ==========================================
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropOrigin {
+    pub path: String,
+    pub reason: DropReason,
+    pub count: u64,
+}
+
+pub fn drop_breakdown<T, K>(root: &dyn Qdisc<T, K>) -> Vec<DropOrigin> {
+    let mut acc: BTreeMap<(String, DropReason), u64> = BTreeMap::new();
+    walk(root, node_name(root), &mut |path, node| {
+        for (reason, count) in node.drop_counts() {
+            *acc.entry((path.to_string(), reason)).or_insert(0) += count;
+        }
+    });
+
+    let mut origins: Vec<DropOrigin> = acc
+        .into_iter()
+        .filter(|&(_, count)| count > 0)
+        .map(|((path, reason), count)| DropOrigin { path, reason, count })
+        .collect();
+    origins.sort_by_key(|o| std::cmp::Reverse(o.count));
+    origins
+}
+
+// 令牌桶体检：哪只桶最常卡脖子，按树路径一只一只列出来
+pub fn bucket_breakdown<T, K>(root: &dyn Qdisc<T, K>) -> Vec<(String, &'static str, BucketStats)> {
+    let mut buckets = Vec::new();
+    walk(root, node_name(root), &mut |path, node| {
+        for (name, stats) in node.bucket_stats() {
+            buckets.push((path.to_string(), name, stats));
+        }
+    });
+    buckets
+}
+
+// 树里插在各处的监控 (root 本身是监控的话也算)，按树路径列出各自最近一个周期的报表
+pub fn monitor_reports<T, K>(root: &dyn Qdisc<T, K>) -> Vec<(String, MonitorSnapshot)> {
+    let mut reports = Vec::new();
+    walk(root, node_name(root), &mut |path, node| {
+        if let Some(report) = node.monitor_report() {
+            reports.push((path.to_string(), report));
+        }
+    });
+    reports
+}
+
+// ==========================================
+// 状态快照：重启 / 热重载前把整棵树的流状态存下来，重建同样的树之后再灌回去
+// 格式 (小端)：[节点数 u32] 然后每个节点 [路径长 u16][路径][状态长 u32][状态]
+// 按路径对号入座，同路径的多个节点按先序顺序依次领取；
+// 新树里找不到的路径 (比如 DRR 按需创建、还没出现的大类) 直接丢弃
+// ==========================================
+pub fn snapshot_tree<T, K>(root: &dyn Qdisc<T, K>) -> Vec<u8> {
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    walk(root, node_name(root), &mut |path, node| {
+        let state = node.save_state();
+        if !state.is_empty() {
+            entries.push((path.to_string(), state));
+        }
+    });
+
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (path, state) in entries {
+        blob.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        blob.extend_from_slice(path.as_bytes());
+        blob.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&state);
+    }
+    blob
+}
+
+// 返回实际灌回去的节点数；blob 本身残缺就一个都不灌
+pub fn restore_tree<T, K>(root: &mut dyn Qdisc<T, K>, blob: &[u8]) -> usize {
+    let Some(mut entries) = parse_snapshot(blob) else {
+        return 0;
+    };
+    let mut restored = 0;
+    let path = node_name(root);
+    walk_mut(root, path, &mut |path, node| {
+        if let Some(queue) = entries.get_mut(path)
+            && let Some(state) = queue.pop_front()
+        {
+            node.load_state(state);
+            restored += 1;
+        }
+    });
+    restored
+}
+
+fn parse_snapshot(blob: &[u8]) -> Option<HashMap<String, VecDeque<&[u8]>>> {
+    let mut reader = StateReader::new(blob);
+    let mut entries: HashMap<String, VecDeque<&[u8]>> = HashMap::new();
+    for _ in 0..reader.u32()? {
+        let path_len = reader.u16()? as usize;
+        let path = std::str::from_utf8(reader.bytes(path_len)?).ok()?;
+        let state_len = reader.u32()? as usize;
+        let state = reader.bytes(state_len)?;
+        entries
+            .entry(path.to_string())
+            .or_default()
+            .push_back(state);
+    }
+    Some(entries)
+}
+
+// 节点解自己那段状态用的游标，越界一律 None
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < n {
+            return None;
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Some(head)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+}
+
+// 先序遍历整棵树，把每个节点连同它的路径交给 visit
+fn walk<T, K>(
+    node: &dyn Qdisc<T, K>,
+    path: String,
+    visit: &mut dyn FnMut(&str, &dyn Qdisc<T, K>),
+) {
+    visit(&path, node);
+    for (edge, child) in node.children() {
+        walk(child, format!("{}/{}:{}", path, edge, node_name(child)), visit);
+    }
+}
+
+fn walk_mut<T, K>(
+    node: &mut dyn Qdisc<T, K>,
+    path: String,
+    visit: &mut dyn FnMut(&str, &mut dyn Qdisc<T, K>),
+) {
+    visit(&path, node);
+    for (edge, child) in node.children_mut() {
+        let child_path = format!("{}/{}:{}", path, edge, node_name(child));
+        walk_mut(child, child_path, visit);
+    }
+}
+
+// describe() 的类型名部分，"TtlDrop(10ms, ...)" -> "TtlDrop"
+fn node_name<T, K>(node: &dyn Qdisc<T, K>) -> String {
+    let desc = node.describe();
+    let end = desc.find(['(', '[']).unwrap_or(desc.len());
+    desc[..end].to_string()
+}
+
+// 测试用的捣乱调度器：后进先出，专门用来造流内乱序
+#[cfg(test)]
+pub(crate) struct LifoQdisc<T, K> {
+    stack: Vec<PacketContext<T, K>>,
+}
+
+#[cfg(test)]
+impl<T, K> LifoQdisc<T, K> {
+    pub fn new() -> Self {
+        Self { stack: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+impl<T, K> Qdisc<T, K> for LifoQdisc<T, K> {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+        self.stack.push(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.stack.last()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.stack.last()
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        self.stack.pop()
+    }
+
+    // 第 n 个 = 从栈顶往下数第 n 个
+    fn peek_nth(&mut self, n: usize) -> Option<&PacketContext<T, K>> {
+        self.stack.iter().rev().nth(n)
+    }
+
+    fn dequeue_nth(&mut self, n: usize) -> Option<PacketContext<T, K>> {
+        let idx = self.stack.len().checked_sub(n + 1)?;
+        Some(self.stack.remove(idx))
+    }
+
+    fn describe(&self) -> String {
+        "Lifo".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::{
+        leaf::HeadDropFifo,
+        scheduler::{ClassDrrQdisc, PartitionQdisc, QuantumScaling},
+    };
+    use crate::token_bucket::TokenBucket;
+
+    #[test]
+    fn default_flush_returns_pending_drops_with_reason() {
+        // Partition 没有自己的 flush，走默认实现：先收尸再倒空各个分区
+        let mut part: PartitionQdisc<Vec<u8>, u64, TokenBucket> = PartitionQdisc::new(
+            vec![(
+                Box::new(HeadDropFifo::new(1)),
+                TokenBucket::new(1e9, 1e9, "test"),
+            )],
+            Box::new(|_: &PacketContext<Vec<u8>, u64>| 0),
+        );
+        part.enqueue(test_packet(1, 0, 100));
+        part.enqueue(test_packet(1, 0, 200)); // 挤掉队头那个 100 字节的
+
+        let all = part.flush();
+        assert_eq!(all.len(), 2);
+        let (dropped, queued): (Vec<_>, Vec<_>) =
+            all.into_iter().partition(|ctx| ctx.drop_reason.is_some());
+        assert_eq!(dropped[0].cost, 100);
+        assert_eq!(dropped[0].drop_reason, Some(DropReason::HardLimit));
+        assert_eq!(queued[0].cost, 200);
+        assert!(part.flush().is_empty());
+    }
+
+    // 按 peek_nth 从 0 往后数，一直数到 None
+    fn nth_order(qdisc: &mut dyn Qdisc<Vec<u8>, u64>) -> Vec<u64> {
+        (0..)
+            .map_while(|n| qdisc.peek_nth(n).map(|ctx| ctx.flow_hash))
+            .collect()
+    }
+
+    // 填好 4 个包的叶子 + 它自己的出队顺序
+    fn check_nth(qdisc: &mut dyn Qdisc<Vec<u8>, u64>, order: [u64; 4]) {
+        assert_eq!(nth_order(qdisc), order);
+        // 只读的 peek_ref 看到的队头和 peek_nth(0) 是同一个
+        assert_eq!(qdisc.peek_ref().map(|ctx| ctx.flow_hash), Some(order[0]));
+        assert!(qdisc.peek_nth(4).is_none());
+        assert!(qdisc.dequeue_nth(4).is_none());
+
+        // 从中间抽走第 1 个，剩下的相对顺序不变
+        let take =
+            |qdisc: &mut dyn Qdisc<Vec<u8>, u64>, n| qdisc.dequeue_nth(n).map(|ctx| ctx.flow_hash);
+        assert_eq!(take(qdisc, 1), Some(order[1]));
+        assert_eq!(nth_order(qdisc), [order[0], order[2], order[3]]);
+        assert_eq!(take(qdisc, 0), Some(order[0]));
+        assert_eq!(nth_order(qdisc), [order[2], order[3]]);
+    }
+
+    #[test]
+    fn nth_probes_follow_each_leafs_own_order() {
+        let mut fifo = HeadDropFifo::new(8);
+        let mut lifo = LifoQdisc::new();
+        for flow in 1..=4 {
+            fifo.enqueue(test_packet(flow, 0, 100));
+            lifo.enqueue(test_packet(flow, 0, 100));
+        }
+        check_nth(&mut fifo, [1, 2, 3, 4]);
+        check_nth(&mut lifo, [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn drain_stops_at_the_first_packet_not_ready() {
+        // 不补水的桶只够放两个 100 字节的包：第三个付不起，迭代器就此打住，不会像 flush 那样倒空
+        let mut part: PartitionQdisc<Vec<u8>, u64, TokenBucket> = PartitionQdisc::new(
+            vec![(
+                Box::new(HeadDropFifo::new(8)),
+                TokenBucket::new(0.0, 250.0, "test"),
+            )],
+            Box::new(|_: &PacketContext<Vec<u8>, u64>| 0),
+        );
+        for flow in 1..=4 {
+            part.enqueue(test_packet(flow, 0, 100));
+        }
+        let sent: Vec<u64> = part.drain_ready().map(|ctx| ctx.flow_hash).collect();
+        assert_eq!(sent, [1, 2]);
+        assert_eq!(part.drain_ready().count(), 0);
+
+        // 透过 dyn 用也一样，剩下的两个都还在
+        let dyn_part: &mut dyn Qdisc<Vec<u8>, u64> = &mut part;
+        assert_eq!(dyn_part.drain_ready().count(), 0);
+        assert_eq!(dyn_part.flush().len(), 2);
+
+        // 没有门槛的叶子：一口气倒完，顺序就是出队顺序
+        let mut lifo = LifoQdisc::new();
+        for flow in 1..=3 {
+            lifo.enqueue(test_packet(flow, 0, 100));
+        }
+        let order: Vec<u64> = lifo.drain_ready().map(|ctx| ctx.flow_hash).collect();
+        assert_eq!(order, [3, 2, 1]);
+    }
+
+    // 两个类各两条流的 ClassDrr，外面套一层 Monitor：peek_ref 要一路透传下去
+    fn drr_tree() -> wrapper::MonitorQdisc<Vec<u8>, u64> {
+        let mut drr: ClassDrrQdisc<Vec<u8>, u64, usize> = ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 300)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(16)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            QuantumScaling::Fixed,
+            None,
+        );
+        for round in 0..4 {
+            for flow in 1..=4 {
+                drr.enqueue(test_packet(flow, (flow as usize) % 2, 100 + round * 50));
+            }
+        }
+        let mut monitor = wrapper::MonitorQdisc::new("Test", Box::new(drr));
+        monitor.set_silent(true);
+        monitor
+    }
+
+    #[test]
+    fn peek_ref_agrees_with_peek_and_leaves_the_order_alone() {
+        let plain: Vec<(u64, usize)> = drr_tree()
+            .drain_ready()
+            .map(|ctx| (ctx.flow_hash, ctx.cost))
+            .collect();
+
+        let mut probed = drr_tree();
+        let mut order = Vec::new();
+        while let Some(head) = probed.peek().map(|ctx| ctx.flow_hash) {
+            // 只读偷看看到的就是 peek 刚备好的那个，看多少次都不推进调度
+            for _ in 0..3 {
+                assert_eq!(probed.peek_ref().map(|ctx| ctx.flow_hash), Some(head));
+            }
+            let ctx = probed.dequeue().unwrap();
+            assert_eq!(ctx.flow_hash, head);
+            order.push((ctx.flow_hash, ctx.cost));
+        }
+        assert_eq!(order, plain);
+        assert_eq!(order.len(), 16);
+        assert!(probed.peek_ref().is_none());
+    }
 }
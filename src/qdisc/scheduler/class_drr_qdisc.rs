@@ -1,30 +1,168 @@
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
 use std::hash::Hash;
 
-use crate::packet_context::PacketContext;
-use crate::qdisc::Qdisc;
+use serde::Deserialize;
+
+use crate::control::ControlCommand;
+use crate::packet_context::{DropReason, PacketContext};
+use crate::qdisc::{Qdisc, StateReader, drop_breakdown};
 
 // ==========================================
 // 终极大类调度器：ClassDrrQdisc (纯粹的带权轮询分发器)
 // ==========================================
 
+// 回收站最多囤这么多个空闲子队列，防止流量高峰过后一直霸占内存；满了挤掉最老的
+const SPARE_LIMIT: usize = 64;
+
+// 量子自动调优统计 "最近最大包" 的窗口 (包数)：取本窗口和上一个窗口里的最大值
+const COST_WINDOW: u32 = 1024;
+
+// 一次 peek 最多给每个活跃大类充值这么多轮；量子配得太小、队头包又特别大时，
+// 宁可这次先报 "没有可发的"，赤字留着下次接着攒，也不能把出队卡死在循环里
+const MAX_REFILL_ROUNDS: usize = 64;
+
+// 胖流堆里过期的条目攒到这么多才值得整堆重建；重建后水位线翻倍跟着活跃流数走
+const FAT_FLOWS_MIN_REBUILD: usize = 64;
+
+// 按 class_id 造子队列的工厂
+type InnerFactory<T, K, C> = Box<dyn Fn(&C) -> Box<dyn Qdisc<T, K>>>;
+
+// 给包分大类：(class_id, 量子)
+type ClassFn<T, K, C> = Box<dyn Fn(&PacketContext<T, K>) -> (C, i32)>;
+
+// 某个 class_id 的子队列是哪一种叶子：工厂对同一种给出的子队列可以互相顶替
+type LeafKind<C> = Box<dyn Fn(&C) -> usize>;
+
+// 大类量子随活跃流数缩放的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantumScaling {
+    #[default]
+    Fixed, // 量子完全由分类器决定 (大类之间 1:1)
+    PerFlow, // 量子 × 活跃流数：流越多的大类总带宽越大 (逼近全局按流公平)
+    Inverse, // 量子 ÷ 活跃流数：流越多，每次充值越少
+}
+
 // 内部缓冲区：现在装的是纯泛型 Inner
 struct ClassBuffer<T, K> {
     inner_qdisc: Box<dyn Qdisc<T, K>>, // ✅ 彻底泛型化，它可以是任何实现了 Qdisc 的东西！
     deficit: i32,
     quantum: i32,
+    flows: HashMap<u64, (usize, usize)>, // 大类内部每条流 (按 flow_hash) 的在队 (包数, 字节数)
+    backlog_bytes: usize,                // 大类在队字节数 (内层丢的包要等收尸时才扣)
+}
+
+impl<T, K> ClassBuffer<T, K> {
+    fn effective_quantum(&self, scaling: QuantumScaling) -> i32 {
+        let flows = self.flows.len().max(1) as i32;
+        match scaling {
+            QuantumScaling::Fixed => self.quantum,
+            QuantumScaling::PerFlow => self.quantum.saturating_mul(flows),
+            QuantumScaling::Inverse => (self.quantum / flows).max(1),
+        }
+    }
+
+    fn remember_packet(&mut self, ctx: &PacketContext<T, K>) {
+        self.backlog_bytes += ctx.cost;
+        let flow = self.flows.entry(ctx.flow_hash).or_insert((0, 0));
+        flow.0 += 1;
+        flow.1 += ctx.cost;
+    }
+
+    // 包离开大类 (出队或被收尸)：扣掉字节水位和这条流的账
+    fn forget_packet(&mut self, ctx: &PacketContext<T, K>) {
+        self.backlog_bytes = self.backlog_bytes.saturating_sub(ctx.cost);
+        if let Some(flow) = self.flows.get_mut(&ctx.flow_hash) {
+            flow.0 = flow.0.saturating_sub(1);
+            flow.1 = flow.1.saturating_sub(ctx.cost);
+            if flow.0 == 0 {
+                self.flows.remove(&ctx.flow_hash);
+            }
+        }
+    }
+}
+
+// 胖流堆的一条：入堆当时这条流在队多少字节。流后来瘦了 / 散了条目也不删，
+// 堆顶拿出来对一下真实账本，对不上再按现值放回去 (惰性删除)
+struct FatFlow<C> {
+    bytes: usize,
+    flow_hash: u64,
+    class_id: C,
+}
+
+// 只按 (字节数, flow_hash) 排：class_id 不要求能比大小
+impl<C> Ord for FatFlow<C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.bytes, self.flow_hash).cmp(&(other.bytes, other.flow_hash))
+    }
+}
+
+impl<C> PartialOrd for FatFlow<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C> PartialEq for FatFlow<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<C> Eq for FatFlow<C> {}
+
+// 回收站里的空壳，连同进站时并给父节点代报的那份丢包账 (壳子被复用时要退回去)
+struct Spare<T, K> {
+    kind: usize,
+    qdisc: Box<dyn Qdisc<T, K>>,
+    folded: Vec<(DropReason, u64)>,
+}
+
+// 整棵子树按原因合计的丢包数
+fn subtree_drops<T, K>(qdisc: &dyn Qdisc<T, K>) -> Vec<(DropReason, u64)> {
+    let mut totals: BTreeMap<DropReason, u64> = BTreeMap::new();
+    for origin in drop_breakdown(qdisc) {
+        *totals.entry(origin.reason).or_insert(0) += origin.count;
+    }
+    totals.into_iter().collect()
 }
 
 pub struct ClassDrrQdisc<T, K, C> {
     classes: HashMap<C, ClassBuffer<T, K>>,
     active_classes: VecDeque<C>,
     // 🚀 注入的分类器：接收面单，告诉你它属于哪个 class_id，以及量子配额是多少
-    classifier: Box<dyn Fn(&PacketContext<T, K>) -> (C, i32)>,
+    // 必须是全函数：任何包都得给出一个类，意外输入走兜底类而不是 panic
+    classifier: ClassFn<T, K, C>,
 
-    // 🚀 注入的兵工厂：当发现新的 class_id 时，动态制造底层队列
-    inner_factory: Box<dyn Fn() -> Box<dyn Qdisc<T, K>>>,
+    // 🚀 注入的兵工厂：当发现新的 class_id 时，按 class_id 动态制造底层队列 (不同大类可以用不同的叶子)
+    inner_factory: InnerFactory<T, K, C>,
     pending_drops: Vec<PacketContext<T, K>>,
+    scaling: QuantumScaling,
+
+    // 🚀 空闲子队列回收站：大类排空后不销毁，下一个同种叶子的大类来了直接复用，省掉反复 malloc
+    // 壳子按 leaf_kind 分拣：工厂按类造的叶子可能各不相同，不能张冠李戴；没配就当所有大类都是同一种
+    spare_qdiscs: Vec<Spare<T, K>>,
+    leaf_kind: Option<LeafKind<C>>,
+    // 进了回收站的壳子不再算子节点，它们生前的丢包账并到这里，由本节点代报 (壳子被挤掉也不丢账)
+    retired_drops: BTreeMap<DropReason, u64>,
+
+    // 🚀 全局内存上限：所有大类加起来超了，就去最胖的那条流开刀 (SFQ / FQ-CoDel 同款)
+    mem_limit_bytes: Option<usize>,
+    backlog_bytes: usize,
+    mem_drops: u64,
+    // 设了上限才维护：每次入队把这条流的新字节数压进来，超线时堆顶就是最胖的流，不用扫全部流
+    fat_flows: BinaryHeap<FatFlow<C>>,
+    fat_flows_watermark: usize,
+
+    // 🚀 量子自动调优：充值时量子至少取最近见过的最大包 cost (DRR 要求量子 ≥ 最大包长)
+    auto_quantum: bool,
+    recent_max_cost: usize, // 当前窗口
+    prev_max_cost: usize,   // 上一个窗口
+    cost_window_pkts: u32,
+
+    // 🚀 借额度：排空的大类把本轮没花完的赤字让给下一个大类，只剩一个大类时充值直接充够队头
+    borrow: bool,
 }
 
 impl<T, K, C> ClassDrrQdisc<T, K, C>
@@ -34,10 +172,16 @@ where
 {
     pub fn new(
         // 🚀 注入的分类器：接收面单，告诉你它属于哪个 class_id，以及量子配额是多少
-        classifier: Box<dyn Fn(&PacketContext<T, K>) -> (C, i32)>,
+        classifier: ClassFn<T, K, C>,
 
-        // 🚀 注入的兵工厂：当发现新的 class_id 时，动态制造底层队列
-        inner_factory: Box<dyn Fn() -> Box<dyn Qdisc<T, K>>>,
+        // 🚀 注入的兵工厂：当发现新的 class_id 时，按 class_id 动态制造底层队列
+        inner_factory: InnerFactory<T, K, C>,
+
+        // 🚀 量子缩放策略：Fixed 保持原样，PerFlow / Inverse 按大类内活跃流数调整
+        scaling: QuantumScaling,
+
+        // 🚀 所有大类合计的字节上限，None 表示不设防 (只靠各子队列自己的容量)
+        mem_limit_bytes: Option<usize>,
     ) -> Self {
         Self {
             classes: HashMap::new(),
@@ -45,6 +189,221 @@ where
             classifier,
             inner_factory,
             pending_drops: Vec::new(),
+            scaling,
+            spare_qdiscs: Vec::new(),
+            leaf_kind: None,
+            retired_drops: BTreeMap::new(),
+            mem_limit_bytes,
+            backlog_bytes: 0,
+            mem_drops: 0,
+            fat_flows: BinaryHeap::new(),
+            fat_flows_watermark: FAT_FLOWS_MIN_REBUILD,
+            auto_quantum: false,
+            recent_max_cost: 0,
+            prev_max_cost: 0,
+            cost_window_pkts: 0,
+            borrow: false,
+        }
+    }
+
+    // 打开后一个比量子还大的包 (比如 WG 路径加完开销的满 MTU 包) 一轮就能发走，
+    // 不用空转好几轮攒赤字；量子比最近的最大包还大时保持配置值不变
+    pub fn set_auto_quantum(&mut self, enabled: bool) {
+        self.auto_quantum = enabled;
+    }
+
+    // 告诉回收站哪些大类的子队列是同一种 (比如配置里命中的规则号)，同种的空壳才互相复用
+    pub fn set_leaf_kind(&mut self, leaf_kind: LeafKind<C>) {
+        self.leaf_kind = Some(leaf_kind);
+    }
+
+    fn kind_of(leaf_kind: &Option<LeafKind<C>>, class_id: &C) -> usize {
+        leaf_kind.as_ref().map_or(0, |kind| kind(class_id))
+    }
+
+    // 打开后空闲大类的份额不会随着它退出轮询白白作废，忙的大类也不用一轮轮空转攒赤字
+    pub fn set_borrow(&mut self, enabled: bool) {
+        self.borrow = enabled;
+    }
+
+    // 容量规划用的只读计数：所有大类里正在排队的流一共几条 (一条流分进两个大类就算两条)
+    #[allow(dead_code)]
+    pub fn active_flows(&self) -> usize {
+        self.classes.values().map(|class| class.flows.len()).sum()
+    }
+
+    // 某个大类里有几条流在排队
+    #[allow(dead_code)]
+    pub fn group_flow_count(&self, class_id: &C) -> usize {
+        self.classes
+            .get(class_id)
+            .map_or(0, |class| class.flows.len())
+    }
+
+    // 某个大类自己的子队列；大类是按需造出来的，还没来过包 (或者已经散了) 就是 None
+    #[allow(dead_code)]
+    pub fn class(&self, class_id: &C) -> Option<&dyn Qdisc<T, K>> {
+        self.classes
+            .get(class_id)
+            .map(|class| class.inner_qdisc.as_ref())
+    }
+
+    #[allow(dead_code)]
+    pub fn class_mut(&mut self, class_id: &C) -> Option<&mut dyn Qdisc<T, K>> {
+        self.classes
+            .get_mut(class_id)
+            .map(|class| class.inner_qdisc.as_mut() as &mut dyn Qdisc<T, K>)
+    }
+
+    // 新大类要一个子队列：回收站里有同种的空壳就复用，没有才找工厂现造
+    // 壳子重新挂回树上，它身上的账又由它自己报了，进站时代报的那份退回去
+    fn revive(
+        spares: &mut Vec<Spare<T, K>>,
+        retired_drops: &mut BTreeMap<DropReason, u64>,
+        factory: &InnerFactory<T, K, C>,
+        kind: usize,
+        class_id: &C,
+    ) -> Box<dyn Qdisc<T, K>> {
+        let Some(i) = spares.iter().position(|s| s.kind == kind) else {
+            return factory(class_id);
+        };
+        let spare = spares.swap_remove(i);
+        for (reason, count) in spare.folded {
+            if let Some(total) = retired_drops.get_mut(&reason) {
+                *total = total.saturating_sub(count);
+            }
+        }
+        spare.qdisc
+    }
+
+    // 排空的大类进回收站，它生前的丢包账并给本节点代报
+    fn retire(&mut self, class_id: C, qdisc: Box<dyn Qdisc<T, K>>) {
+        if self.spare_qdiscs.len() >= SPARE_LIMIT {
+            self.spare_qdiscs.remove(0); // 它的账进回收站时就并过来了
+        }
+        let folded = subtree_drops(qdisc.as_ref());
+        for &(reason, count) in &folded {
+            *self.retired_drops.entry(reason).or_insert(0) += count;
+        }
+        self.spare_qdiscs.push(Spare {
+            kind: Self::kind_of(&self.leaf_kind, &class_id),
+            qdisc,
+            folded,
+        });
+    }
+
+    fn observe_cost(&mut self, cost: usize) {
+        if !self.auto_quantum {
+            return;
+        }
+        self.recent_max_cost = self.recent_max_cost.max(cost);
+        self.cost_window_pkts += 1;
+        if self.cost_window_pkts >= COST_WINDOW {
+            self.prev_max_cost = self.recent_max_cost;
+            self.recent_max_cost = 0;
+            self.cost_window_pkts = 0;
+        }
+    }
+
+    // 自动调优关着时是 0，不影响配置的量子
+    fn quantum_floor(&self) -> i32 {
+        if !self.auto_quantum {
+            return 0;
+        }
+        self.recent_max_cost
+            .max(self.prev_max_cost)
+            .min(i32::MAX as usize) as i32
+    }
+
+    // 过期条目太多了就按眼下的账本整堆重建，堆的大小跟着活跃流数走，不随入队次数涨
+    fn note_fat_flow(&mut self, class_id: &C, flow_hash: u64, bytes: usize) {
+        self.fat_flows.push(FatFlow {
+            bytes,
+            flow_hash,
+            class_id: class_id.clone(),
+        });
+        if self.fat_flows.len() <= self.fat_flows_watermark {
+            return;
+        }
+        self.fat_flows = self
+            .classes
+            .iter()
+            .flat_map(|(id, class)| {
+                class
+                    .flows
+                    .iter()
+                    .map(move |(&flow_hash, &(_, bytes))| FatFlow {
+                        bytes,
+                        flow_hash,
+                        class_id: id.clone(),
+                    })
+            })
+            .collect();
+        self.fat_flows_watermark = (self.fat_flows.len() * 2).max(FAT_FLOWS_MIN_REBUILD);
+    }
+
+    // 全局积压最多的那条流：堆顶对得上账本就是它 (留在堆里，下次发现瘦了再更正)；
+    // 对不上的要么流已经散了直接扔，要么按现在的字节数放回去重新排
+    fn fattest_flow(&mut self) -> Option<(C, u64)> {
+        loop {
+            let top = self.fat_flows.peek()?;
+            let bytes = self
+                .classes
+                .get(&top.class_id)
+                .and_then(|class| class.flows.get(&top.flow_hash))
+                .map(|&(_, bytes)| bytes);
+            if bytes == Some(top.bytes) {
+                return Some((top.class_id.clone(), top.flow_hash));
+            }
+            let mut stale = self.fat_flows.pop()?;
+            if let Some(bytes) = bytes {
+                stale.bytes = bytes;
+                self.fat_flows.push(stale);
+            }
+        }
+    }
+
+    // 超了上限就反复找全局积压最多的那条流，从子队列里直接摘它最老的包，直到回到线下
+    // 走 drop_flow_head 而不是 peek / dequeue：不花令牌、不扣赤字，被淘汰的包不该占别人的份额
+    // 子队列不支持 drop_flow_head (见 Qdisc::drop_flow_head) 就只能靠它自己的容量上限
+    fn enforce_mem_limit(&mut self) {
+        let Some(limit) = self.mem_limit_bytes else {
+            return;
+        };
+
+        while self.backlog_bytes > limit {
+            let Some((class_id, flow_hash)) = self.fattest_flow() else {
+                return;
+            };
+            let Some(class) = self.classes.get_mut(&class_id) else {
+                return;
+            };
+
+            // 先把它肚子里已经死掉的包捞出来平账，水位可能就这么降下来了
+            let dead = class.inner_qdisc.collect_dropped();
+            let mut progressed = !dead.is_empty();
+            for ctx in &dead {
+                class.forget_packet(ctx);
+                self.backlog_bytes = self.backlog_bytes.saturating_sub(ctx.cost);
+            }
+            self.pending_drops.extend(dead);
+
+            if self.backlog_bytes > limit
+                && let Some(victim) = class
+                    .inner_qdisc
+                    .drop_flow_head(flow_hash, DropReason::MemLimit)
+            {
+                class.forget_packet(&victim);
+                self.backlog_bytes = self.backlog_bytes.saturating_sub(victim.cost);
+                self.mem_drops += 1;
+                self.pending_drops.push(victim);
+                progressed = true;
+            }
+
+            // 账面有积压但实际一个包都掏不出来，别死循环
+            if !progressed {
+                return;
+            }
         }
     }
 }
@@ -58,33 +417,55 @@ where
     K: Hash + Eq + Clone,
     C: Hash + Eq + Clone,
 {
-    fn enqueue(&mut self, ctx: PacketContext<T, K>) -> () {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
         let (class_id, class_quantum) = (self.classifier)(&ctx);
+        // 量子是分类器按包给的，构造时管不住：0 或负数永远攒不够赤字，兜底成 1
+        let class_quantum = class_quantum.max(1);
+        self.observe_cost(ctx.cost);
 
-        let class = match self.classes.entry(class_id.clone()) {
-            Entry::Occupied(entry) => entry.into_mut(),
-            Entry::Vacant(entry) => entry.insert(ClassBuffer {
-                inner_qdisc: (self.inner_factory)(),
+        // 🚀 老大类直接 get_mut，不克隆 class_id；只有新大类才克隆一次 (map 和轮询队列各存一份)
+        let class = match self.classes.get_mut(&class_id) {
+            Some(class) => class,
+            None => self.classes.entry(class_id.clone()).or_insert(ClassBuffer {
+                inner_qdisc: Self::revive(
+                    &mut self.spare_qdiscs,
+                    &mut self.retired_drops,
+                    &self.inner_factory,
+                    Self::kind_of(&self.leaf_kind, &class_id),
+                    &class_id,
+                ),
                 deficit: class_quantum,
                 quantum: class_quantum,
+                flows: HashMap::new(),
+                backlog_bytes: 0,
             }),
         };
 
         class.quantum = class_quantum;
+        class.remember_packet(&ctx);
+        let (flow_hash, flow_bytes) = (ctx.flow_hash, class.flows[&ctx.flow_hash].1);
+        self.backlog_bytes += ctx.cost;
         class.inner_qdisc.enqueue(ctx);
+        if self.mem_limit_bytes.is_some() {
+            self.note_fat_flow(&class_id, flow_hash, flow_bytes);
+        }
 
+        // class_id 是分类器按值给的，已经在轮询队列里就直接丢掉，不在才 move 进去
         if !self.active_classes.contains(&class_id) {
             self.active_classes.push_front(class_id);
         }
+
+        self.enforce_mem_limit();
     }
 
     fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        let mut refills = 0;
         loop {
-            let class_id = self.active_classes.front()?.clone();
+            let class_id = self.active_classes.front()?;
 
             // 🚀 第一步：在一个独立的作用域里，仅做状态判定！绝不在这里 return 引用！
-            let (has_packet, is_affordable) = {
-                let class = match self.classes.get_mut(&class_id) {
+            let (has_packet, is_affordable, head_cost) = {
+                let class = match self.classes.get_mut(class_id) {
                     Some(c) => c,
                     None => {
                         self.active_classes.pop_front();
@@ -93,43 +474,77 @@ where
                 };
 
                 if let Some(ctx) = class.inner_qdisc.peek() {
-                    (true, class.deficit >= ctx.cost as i32)
+                    let cost = ctx.cost.min(i32::MAX as usize) as i32;
+                    (true, class.deficit >= cost, cost)
                 } else {
-                    (false, false)
+                    (false, false, 0)
                 }
             }; // 👈 离开这个大括号，class 的可变借用被完美释放！
 
             // 🚀 第二步：根据状态，执行动作或返回
             if has_packet && is_affordable {
                 // 钱够货好，重新借用一次并直接返回，生命周期完全合法！
-                return self.classes.get_mut(&class_id)?.inner_qdisc.peek();
+                return self.classes.get_mut(class_id)?.inner_qdisc.peek();
             }
 
             // 如果没包，或者钱不够，需要处理状态转移
             let id = self.active_classes.pop_front().unwrap();
 
             if !has_packet {
-                // 货空了：物理超度幽灵
-                self.classes.remove(&id);
+                // 货空了：物理超度幽灵，先把它肚子里待收尸的包捞出来，空壳扔进回收站
+                if let Some(mut class) = self.classes.remove(&id) {
+                    // 本轮没花完的额度让给下一个大类，最多让一个量子，免得攒出大突发
+                    if self.borrow
+                        && class.deficit > 0
+                        && let Some(next) = self.active_classes.front()
+                        && let Some(next) = self.classes.get_mut(next)
+                    {
+                        let cap = next.effective_quantum(self.scaling);
+                        next.deficit = next.deficit.saturating_add(class.deficit.min(cap));
+                    }
+                    // 账面剩下的字节就是这些待收尸的包，整类一起核销
+                    self.backlog_bytes = self.backlog_bytes.saturating_sub(class.backlog_bytes);
+                    self.pending_drops.extend(class.inner_qdisc.collect_dropped());
+                    self.retire(id, class.inner_qdisc);
+                }
             } else {
                 // 有货但钱不够：充值，并发配到队尾
+                refills += 1;
+                if refills > MAX_REFILL_ROUNDS * self.active_classes.len().max(1) {
+                    self.active_classes.push_front(id);
+                    return None;
+                }
+                let floor = self.quantum_floor();
+                // 没有别的大类在排队：轮转也是转给自己，一次充到够付队头为止
+                let alone = self.borrow && self.active_classes.is_empty();
                 if let Some(class) = self.classes.get_mut(&id) {
-                    class.deficit += class.quantum;
+                    class.deficit += class.effective_quantum(self.scaling).max(floor);
+                    if alone {
+                        class.deficit = class.deficit.max(head_cost);
+                    }
                 }
                 self.active_classes.push_back(id);
             }
         }
     }
 
+    // peek 选中的大类留在轮询队头，看它的队头就是上次备好的包
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        let class_id = self.active_classes.front()?;
+        self.classes.get(class_id)?.inner_qdisc.peek_ref()
+    }
+
     fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
         // 🚀 极致盲从：刚 peek 过，队头绝对有钱有货！
-        let class_id = self.active_classes.front()?.clone();
-        let class = self.classes.get_mut(&class_id)?;
+        let class_id = self.active_classes.front()?;
+        let class = self.classes.get_mut(class_id)?;
 
         let ctx = class.inner_qdisc.dequeue()?;
 
         // 乖乖扣费
         class.deficit -= ctx.cost as i32;
+        class.forget_packet(&ctx);
+        self.backlog_bytes = self.backlog_bytes.saturating_sub(ctx.cost);
 
         Some(ctx)
     }
@@ -138,8 +553,577 @@ where
         let _ = self.peek(); // 级联打扫
         let mut all_drops = std::mem::take(&mut self.pending_drops);
         for class in self.classes.values_mut() {
-            all_drops.extend(class.inner_qdisc.collect_dropped());
+            let drops = class.inner_qdisc.collect_dropped();
+            for dead in &drops {
+                class.forget_packet(dead);
+                self.backlog_bytes = self.backlog_bytes.saturating_sub(dead.cost);
+            }
+            all_drops.extend(drops);
         }
         all_drops
     }
+
+    // 套在别的淘汰者下面时：找到这条流所在的大类往下摘，只平账，不算本节点亲手丢的
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        let class = self
+            .classes
+            .values_mut()
+            .find(|class| class.flows.contains_key(&flow_hash))?;
+        let victim = class.inner_qdisc.drop_flow_head(flow_hash, reason)?;
+        class.forget_packet(&victim);
+        self.backlog_bytes = self.backlog_bytes.saturating_sub(victim.cost);
+        Some(victim)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.pending_drops);
+        for (_, mut class) in self.classes.drain() {
+            all.extend(class.inner_qdisc.flush());
+        }
+        self.active_classes.clear();
+        self.backlog_bytes = 0;
+        self.fat_flows.clear();
+        all
+    }
+
+    fn describe(&self) -> String {
+        // 子队列是工厂按类现造的，没有 class_id 造不出样品：把眼下各大类用到的叶子列一遍
+        let mut kinds: Vec<String> = Vec::new();
+        for q in self
+            .classes
+            .values()
+            .map(|c| &c.inner_qdisc)
+            .chain(self.spare_qdiscs.iter().map(|s| &s.qdisc))
+        {
+            let d = q.describe();
+            if !kinds.contains(&d) {
+                kinds.push(d);
+            }
+        }
+        if kinds.is_empty() {
+            "ClassDrr(按类现造)".to_string()
+        } else {
+            format!("ClassDrr({})", kinds.join(" | "))
+        }
+    }
+
+    // 只有还挂着的大类；回收站里的空壳不算，它们的丢包账已经并进 drop_counts
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        self.classes
+            .values()
+            .map(|c| ("class", c.inner_qdisc.as_ref()))
+            .collect()
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        self.classes
+            .values_mut()
+            .map(|c| ("class", c.inner_qdisc.as_mut() as &mut dyn Qdisc<T, K>))
+            .collect()
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        let mut counts = self.retired_drops.clone();
+        *counts.entry(DropReason::MemLimit).or_insert(0) += self.mem_drops;
+        counts.into_iter().collect()
+    }
+
+    // [当前窗口最大 cost u64][上一个窗口最大 cost u64][当前窗口包数 u32]：只存自动量子的观测窗口，
+    // 重启后第一波大包不用再空转几轮把量子撑起来；各流的赤字跟着排队的包走，不存
+    fn save_state(&self) -> Vec<u8> {
+        if !self.auto_quantum {
+            return Vec::new();
+        }
+        let mut state = Vec::with_capacity(20);
+        state.extend_from_slice(&(self.recent_max_cost as u64).to_le_bytes());
+        state.extend_from_slice(&(self.prev_max_cost as u64).to_le_bytes());
+        state.extend_from_slice(&self.cost_window_pkts.to_le_bytes());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let mut reader = StateReader::new(state);
+        let (Some(recent), Some(prev), Some(pkts)) = (reader.u64(), reader.u64(), reader.u32())
+        else {
+            return;
+        };
+        self.recent_max_cost = recent as usize;
+        self.prev_max_cost = prev as usize;
+        self.cost_window_pkts = pkts.min(COST_WINDOW - 1);
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        // 只能通知到现存的大类，工厂新造出来的子队列还是出厂配置
+        let mut handled = false;
+        for class in self.classes.values_mut() {
+            handled |= class.inner_qdisc.apply_control(cmd);
+        }
+        handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::leaf::HeadDropFifo;
+    use crate::qdisc::scheduler::SparseQdisc;
+    use crate::qdisc::wrapper::TcpAckFilterQdisc;
+    use crate::qdisc::{QdiscExt, restore_tree, snapshot_tree};
+
+    // 按 queue_num 分大类：0 号大类里跑 3 条流，1 号大类只有 1 条，都塞满再看各自发走多少字节
+    fn sent_bytes(scaling: QuantumScaling) -> [usize; 2] {
+        let mut drr: ClassDrrQdisc<Vec<u8>, u64, usize> = ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 1000)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(1000)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            scaling,
+            None,
+        );
+        for _ in 0..100 {
+            for flow in 1..=3 {
+                drr.enqueue(test_packet(flow, 0, 1000));
+            }
+            drr.enqueue(test_packet(9, 1, 1000));
+        }
+        let mut sent = [0; 2];
+        for _ in 0..80 {
+            assert!(drr.peek().is_some());
+            let ctx = drr.dequeue().unwrap();
+            sent[ctx.queue_num] += ctx.cost;
+        }
+        sent
+    }
+
+    // 每个大类一个只装 1 个包的 FIFO，塞 2 个就挤掉 1 个
+    fn tiny_fifo_drr() -> ClassDrrQdisc<Vec<u8>, u64, usize> {
+        ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 1500)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(1)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            QuantumScaling::Fixed,
+            None,
+        )
+    }
+
+    fn hard_limit_drops(drr: &ClassDrrQdisc<Vec<u8>, u64, usize>) -> u64 {
+        drop_breakdown(drr)
+            .iter()
+            .filter(|o| o.reason == DropReason::HardLimit)
+            .map(|o| o.count)
+            .sum()
+    }
+
+    fn drain(drr: &mut ClassDrrQdisc<Vec<u8>, u64, usize>) {
+        while drr.peek().is_some() {
+            drr.dequeue();
+        }
+        drr.collect_dropped();
+    }
+
+    #[test]
+    fn retired_classes_keep_their_drop_counts() {
+        let mut drr = tiny_fifo_drr();
+        drr.enqueue(test_packet(1, 0, 100));
+        drr.enqueue(test_packet(1, 0, 100));
+        assert_eq!(hard_limit_drops(&drr), 1);
+
+        drain(&mut drr);
+        // 大类散了，空壳进回收站：不再是子节点，账由 ClassDrr 自己代报
+        assert!(drr.children().is_empty());
+        assert_eq!(hard_limit_drops(&drr), 1);
+        assert!(drop_breakdown(&drr).iter().all(|o| o.path == "ClassDrr"));
+
+        // 同一个大类回来，壳子复用，账退回壳子自己报，合计不变
+        drr.enqueue(test_packet(1, 0, 100));
+        assert_eq!(drr.children().len(), 1);
+        assert_eq!(hard_limit_drops(&drr), 1);
+    }
+
+    // 工厂每造一个子队列计一次数
+    fn counting_drr(built: &Rc<Cell<usize>>) -> ClassDrrQdisc<Vec<u8>, u64, usize> {
+        let built = Rc::clone(built);
+        ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 1500)),
+            Box::new(move |_: &usize| {
+                built.set(built.get() + 1);
+                Box::new(HeadDropFifo::new(8)) as Box<dyn Qdisc<Vec<u8>, u64>>
+            }),
+            QuantumScaling::Fixed,
+            None,
+        )
+    }
+
+    #[test]
+    fn spares_are_reused_across_classes_of_the_same_kind() {
+        // 默认所有大类同一种叶子：0 号散了，1 号直接接手它的空壳
+        let built = Rc::new(Cell::new(0));
+        let mut drr = counting_drr(&built);
+        drr.enqueue(test_packet(1, 0, 100));
+        drain(&mut drr);
+        drr.enqueue(test_packet(2, 1, 100));
+        assert_eq!(built.get(), 1);
+
+        // 按奇偶分两种：1 号的壳子给不了 2 号，3 号可以
+        let built = Rc::new(Cell::new(0));
+        let mut drr = counting_drr(&built);
+        drr.set_leaf_kind(Box::new(|class: &usize| class % 2));
+        drr.enqueue(test_packet(1, 1, 100));
+        drain(&mut drr);
+        drr.enqueue(test_packet(2, 2, 100));
+        assert_eq!(built.get(), 2);
+        drain(&mut drr);
+        drr.enqueue(test_packet(3, 3, 100));
+        assert_eq!(built.get(), 2);
+    }
+
+    #[test]
+    fn evicted_spares_do_not_lose_drop_counts() {
+        let mut drr = tiny_fifo_drr();
+        let classes = SPARE_LIMIT + 8;
+        for class in 0..classes {
+            drr.enqueue(test_packet(class as u64, class, 100));
+            drr.enqueue(test_packet(class as u64, class, 100));
+        }
+        drain(&mut drr);
+        assert_eq!(drr.spare_qdiscs.len(), SPARE_LIMIT);
+        assert_eq!(hard_limit_drops(&drr), classes as u64);
+    }
+
+    #[test]
+    fn mem_limit_evicts_the_fattest_flow_without_spending_deficit() {
+        let mut drr: ClassDrrQdisc<Vec<u8>, u64, usize> = ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 1500)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(100)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            QuantumScaling::Fixed,
+            Some(1000),
+        );
+        // 0 号大类 600 字节，但分在两条流上；1 号大类只有一条 400 字节的流
+        for _ in 0..3 {
+            drr.enqueue(test_packet(1, 0, 100));
+            drr.enqueue(test_packet(2, 0, 100));
+        }
+        for _ in 0..4 {
+            drr.enqueue(test_packet(3, 1, 100));
+        }
+        assert!(drr.collect_dropped().is_empty());
+
+        // 超线 100 字节：挨刀的是全局最胖的流 3，不是最胖的 0 号大类
+        drr.enqueue(test_packet(4, 1, 100));
+        let victim = drr.pending_drops.pop().expect("应该淘汰一个包");
+        assert!(drr.pending_drops.is_empty());
+        assert_eq!(victim.flow_hash, 3);
+        assert_eq!(victim.drop_reason, Some(DropReason::MemLimit));
+        assert_eq!(drr.backlog_bytes, 1000);
+        assert_eq!(drr.classes[&1].flows[&3], (3, 300));
+        // 被淘汰的包没经过出队，赤字一分没动
+        assert!(drr.classes.values().all(|class| class.deficit == 1500));
+        assert_eq!(drr.drop_counts(), vec![(DropReason::MemLimit, 1)]);
+    }
+
+    #[test]
+    fn mem_limit_skips_flows_that_have_shrunk_since_they_were_fattest() {
+        let mut drr: ClassDrrQdisc<Vec<u8>, u64, usize> = ClassDrrQdisc::new(
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 1500)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(100)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            QuantumScaling::Fixed,
+            Some(1000),
+        );
+        // 流 1 一度攒到 600 字节，出队走掉 500：堆里那条 600 的记录已经过期
+        for _ in 0..6 {
+            drr.enqueue(test_packet(1, 0, 100));
+        }
+        for _ in 0..5 {
+            assert!(drr.peek().is_some());
+            drr.dequeue();
+        }
+        for _ in 0..4 {
+            drr.enqueue(test_packet(2, 0, 100));
+        }
+        for _ in 0..5 {
+            drr.enqueue(test_packet(3, 0, 100));
+        }
+        assert!(drr.pending_drops.is_empty());
+
+        // 超线时流 2、流 3 都是 500，比过期的流 1 真实的 100 胖；一样胖按 flow_hash 大的挨刀
+        drr.enqueue(test_packet(2, 0, 100));
+        let victim = drr.pending_drops.pop().expect("应该淘汰一个包");
+        assert_eq!(victim.flow_hash, 3);
+        assert_eq!(drr.classes[&0].flows[&1], (1, 100));
+        assert_eq!(drr.classes[&0].flows[&2], (5, 500));
+
+        // 一直来包一直走，堆按活跃流数重建，不随入队次数涨
+        for round in 0..10_000 {
+            drr.enqueue(test_packet(10 + round % 8, 0, 100));
+            assert!(drr.peek().is_some());
+            drr.dequeue();
+        }
+        assert!(drr.fat_flows.len() <= FAT_FLOWS_MIN_REBUILD + 1);
+
+        drr.flush();
+        assert!(drr.fat_flows.is_empty());
+    }
+
+    #[test]
+    fn fixed_scaling_splits_classes_evenly() {
+        assert_eq!(sent_bytes(QuantumScaling::Fixed), [40_000, 40_000]);
+    }
+
+    #[test]
+    fn per_flow_scaling_follows_flow_count() {
+        // 新大类的头一笔赤字按原始量子给，之后每轮按 3 条流放大：接近 3:1
+        let [busy, thin] = sent_bytes(QuantumScaling::PerFlow);
+        assert!(busy > 2 * thin, "busy={busy} thin={thin}");
+    }
+
+    #[test]
+    fn inverse_scaling_favours_the_thin_class() {
+        let [busy, thin] = sent_bytes(QuantumScaling::Inverse);
+        assert!(thin > busy, "busy={busy} thin={thin}");
+    }
+
+    #[test]
+    fn flow_counts_follow_the_backlog() {
+        let mut drr = counting_drr(&Rc::new(Cell::new(0)));
+        drr.enqueue(test_packet(1, 0, 100));
+        for flow in 1..=3 {
+            drr.enqueue(test_packet(flow, 0, 100));
+        }
+        assert_eq!(drr.active_flows(), 3);
+        assert_eq!(drr.group_flow_count(&0), 3);
+        assert_eq!(drr.group_flow_count(&1), 0);
+        assert!(drr.class(&1).is_none());
+        assert!(
+            drr.class_mut(&0)
+                .is_some_and(|class| class.peek().is_some())
+        );
+
+        // FIFO 叶子先进先出：流 1 走掉一个包还剩一个在排，再走一个才少一条
+        assert!(drr.peek().is_some());
+        drr.dequeue();
+        assert_eq!(drr.active_flows(), 3);
+        assert!(drr.peek().is_some());
+        drr.dequeue();
+        assert_eq!(drr.active_flows(), 2);
+        assert_eq!(drr.group_flow_count(&0), 2);
+    }
+
+    #[test]
+    fn auto_quantum_window_survives_a_snapshot() {
+        let auto_drr = || {
+            let mut drr = tiny_fifo_drr();
+            drr.set_auto_quantum(true);
+            drr
+        };
+        let mut drr = auto_drr();
+        drr.enqueue(test_packet(1, 0, 9000));
+        drain(&mut drr);
+        assert_eq!(drr.quantum_floor(), 9000);
+
+        // 重建同样的树再灌回去：大类已经散了，只剩 ClassDrr 自己的那段状态
+        let blob = snapshot_tree(&drr);
+        let mut restored = auto_drr();
+        assert_eq!(restore_tree(&mut restored, &blob), 1);
+        assert_eq!(restored.quantum_floor(), 9000);
+        assert_eq!(restored.save_state(), drr.save_state());
+
+        // 残缺的状态宁可冷启动
+        let mut cold = auto_drr();
+        cold.load_state(&drr.save_state()[..10]);
+        assert_eq!(cold.quantum_floor(), 0);
+    }
+
+    // 按 queue_num 分大类、每类同一个量子，子队列是能装 16 个包的 FIFO
+    fn quantum_drr(quantum: i32) -> ClassDrrQdisc<Vec<u8>, u64, usize> {
+        ClassDrrQdisc::new(
+            Box::new(move |ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, quantum)),
+            Box::new(|_: &usize| Box::new(HeadDropFifo::new(16)) as Box<dyn Qdisc<Vec<u8>, u64>>),
+            QuantumScaling::Fixed,
+            None,
+        )
+    }
+
+    #[test]
+    fn auto_quantum_rises_to_the_largest_recent_packet() {
+        // 量子 300、队头是 64KB 的 GSO 大包：按 300 攒要两百多轮，一次 peek 的充值上限攒不够
+        let mut fixed = quantum_drr(300);
+        fixed.enqueue(test_packet(1, 0, 65_000));
+        assert!(fixed.peek().is_none());
+
+        // 打开自动调优：量子至少取最近见过的最大包，一轮就够
+        let mut auto = quantum_drr(300);
+        auto.set_auto_quantum(true);
+        auto.enqueue(test_packet(1, 0, 65_000));
+        assert_eq!(auto.peek().map(|ctx| ctx.cost), Some(65_000));
+        assert_eq!(auto.dequeue().map(|ctx| ctx.cost), Some(65_000));
+        assert_eq!(auto.quantum_floor(), 65_000);
+
+        // 两个大类都是满 MTU 的包：每轮各走一个，不用空转攒赤字
+        let mut auto = quantum_drr(300);
+        auto.set_auto_quantum(true);
+        for _ in 0..3 {
+            auto.enqueue(test_packet(1, 0, 1500));
+            auto.enqueue(test_packet(2, 1, 1500));
+        }
+        let order: Vec<usize> = auto.drain_ready().map(|ctx| ctx.queue_num).collect();
+        assert_eq!(order, [1, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn refills_per_peek_are_bounded_and_the_deficit_carries_over() {
+        // 分类器给了 0 量子：兜底成 1，1500 字节的队头要攒 1500 轮
+        let mut drr = quantum_drr(0);
+        drr.enqueue(test_packet(1, 0, 1500));
+        let mut peeks = 1;
+        while drr.peek().is_none() {
+            // 每次 peek 最多充 MAX_REFILL_ROUNDS 轮就放弃，攒下的赤字留给下一次
+            assert!(drr.classes[&0].deficit <= 1 + (MAX_REFILL_ROUNDS * peeks) as i32);
+            peeks += 1;
+            assert!(peeks <= 1500, "赤字没有跨 peek 累积");
+        }
+        assert_eq!(peeks, 1500usize.div_ceil(MAX_REFILL_ROUNDS));
+        assert_eq!(drr.dequeue().map(|ctx| ctx.cost), Some(1500));
+        assert_eq!(drr.classes[&0].quantum, 1);
+    }
+
+    #[test]
+    fn borrowing_hands_leftover_deficit_on_and_lets_a_lone_class_run_at_full_rate() {
+        // 只剩一个大类在排队：不借的话 64KB 的队头一次 peek 攒不够，借的话直接充到够付
+        for (borrow, ready) in [(false, false), (true, true)] {
+            let mut drr = quantum_drr(100);
+            drr.set_borrow(borrow);
+            drr.enqueue(test_packet(1, 0, 65_000));
+            assert_eq!(drr.peek().is_some(), ready);
+        }
+
+        // 0 号只有一个 200 字节的包，走完还剩 800 的赤字：借的话让给排在后面的 1 号
+        let deficit_left = |borrow| {
+            let mut drr = quantum_drr(1000);
+            drr.set_borrow(borrow);
+            drr.enqueue(test_packet(2, 1, 1700));
+            drr.enqueue(test_packet(1, 0, 200));
+            for class in [0, 1] {
+                assert!(drr.peek().is_some());
+                assert_eq!(drr.dequeue().map(|ctx| ctx.queue_num), Some(class));
+            }
+            // 下一次 peek 之前 1 号还没散，看得到它付完队头还剩多少
+            drr.classes[&1].deficit
+        };
+        // 1000 + 让过来的 800 - 1700，不用再充一轮
+        assert_eq!(deficit_left(true), 100);
+        // 1000 + 充值 1000 - 1700
+        assert_eq!(deficit_left(false), 300);
+    }
+
+    #[test]
+    fn factory_builds_a_different_leaf_per_class() {
+        // 0 号拿 FIFO 按到达顺序走；1 号拿一层按流分的 DRR，两条流轮着走
+        let tree = || {
+            ClassDrrQdisc::<Vec<u8>, u64, usize>::new(
+                Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.queue_num, 100)),
+                Box::new(|class: &usize| match class {
+                    0 => Box::new(HeadDropFifo::new(16)) as Box<dyn Qdisc<Vec<u8>, u64>>,
+                    _ => Box::new(ClassDrrQdisc::new(
+                        Box::new(|ctx: &PacketContext<Vec<u8>, u64>| (ctx.flow_hash, 100)),
+                        Box::new(|_: &u64| {
+                            Box::new(HeadDropFifo::new(16)) as Box<dyn Qdisc<Vec<u8>, u64>>
+                        }),
+                        QuantumScaling::Fixed,
+                        None,
+                    )),
+                }),
+                QuantumScaling::Fixed,
+                None,
+            )
+        };
+        let flows_out = |class| {
+            let mut drr = tree();
+            for flow in [1, 1, 2, 2] {
+                drr.enqueue(test_packet(flow, class, 100));
+            }
+            drr.drain_ready()
+                .map(|ctx| ctx.flow_hash)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(flows_out(0), [1, 1, 2, 2]);
+        // 谁先开轮由内层决定，只看两条流是不是一包一包交替
+        let interleaved = flows_out(1);
+        assert_eq!(interleaved.len(), 4);
+        assert!(interleaved.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+
+    // 克隆一次记一次数的 key：既当包的流 key，也当大类 id
+    struct CountedKey {
+        id: u64,
+        clones: Rc<Cell<usize>>,
+    }
+
+    impl Clone for CountedKey {
+        fn clone(&self) -> Self {
+            self.clones.set(self.clones.get() + 1);
+            Self {
+                id: self.id,
+                clones: Rc::clone(&self.clones),
+            }
+        }
+    }
+
+    impl PartialEq for CountedKey {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for CountedKey {}
+
+    impl Hash for CountedKey {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.id.hash(state);
+        }
+    }
+
+    #[test]
+    fn enqueue_clones_class_ids_once_per_new_class_and_never_flow_keys() {
+        let clones = Rc::new(Cell::new(0));
+        let key = |id| CountedKey {
+            id,
+            clones: Rc::clone(&clones),
+        };
+        // 分类器按 queue_num 现造 id，不算克隆；下面是 ACK 过滤套稀疏流分拣，和默认拓扑一样按 flow_hash 记账
+        let class_clones = Rc::clone(&clones);
+        let mut drr: ClassDrrQdisc<Vec<u8>, CountedKey, CountedKey> = ClassDrrQdisc::new(
+            Box::new(move |ctx: &PacketContext<Vec<u8>, CountedKey>| {
+                let id = CountedKey {
+                    id: ctx.queue_num as u64,
+                    clones: Rc::clone(&class_clones),
+                };
+                (id, 1500)
+            }),
+            Box::new(|_: &CountedKey| {
+                Box::new(TcpAckFilterQdisc::new(Box::new(SparseQdisc::new(
+                    Box::new(HeadDropFifo::new(64)),
+                    Box::new(HeadDropFifo::new(64)),
+                )))) as Box<dyn Qdisc<Vec<u8>, CountedKey>>
+            }),
+            QuantumScaling::Fixed,
+            None,
+        );
+
+        for i in 0..100u64 {
+            let flow = i % 4;
+            let mut ctx =
+                PacketContext::new(vec![0; 100], key(flow), flow, flow as usize % 2, 0, 100);
+            ctx.is_pure_ack = i % 2 == 0;
+            drr.enqueue(ctx);
+        }
+        // 两个大类各克隆一次 id (map 和轮询队列各存一份)，流 key 一次都没克隆
+        assert_eq!(clones.get(), 2);
+
+        assert!(drr.drain_ready().count() > 0);
+        assert_eq!(clones.get(), 2);
+    }
 }
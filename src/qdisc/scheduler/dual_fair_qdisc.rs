@@ -1,6 +1,26 @@
-use crate::packet_context::PacketContext;
+use std::time::Duration;
+
+use crate::classifier::Classifier;
+use crate::control::ControlCommand;
+use crate::packet_context::{ClassId, PacketContext};
 use crate::qdisc::Qdisc;
 
+// 一个包扣多少赤字
+type CostFn<T, K> = Box<dyn Fn(&PacketContext<T, K>) -> i32>;
+
+// 两个子队列之间怎么挑下一个包
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DualFairMode {
+    #[default]
+    ByteFair, // DRR，按字节 (或 cost_fn) 1:1
+    // 最早截止时间优先：每个包的截止时间是 到达时刻 + 它那一边的延迟预算，两边队头谁先到期谁走
+    // 不再保证带宽公平，预算紧的一边一直有包就能压住另一边
+    Edf {
+        budget_a: Duration,
+        budget_b: Duration,
+    },
+}
+
 // ==========================================
 // 双通道公平轮询队列 (Dual Fair Qdisc)
 // 保证两个子队列带宽 1:1 绝对公平，但内部逻辑互不干涉
@@ -8,13 +28,18 @@ use crate::qdisc::Qdisc;
 pub struct DualFairQdisc<T, K> {
     q_a: Box<dyn Qdisc<T, K>>,
     q_b: Box<dyn Qdisc<T, K>>,
-    classifier: Box<dyn Fn(&PacketContext<T, K>) -> bool>, // true进A，false进B
+    classifier: Classifier<T, K>, // true进A，false进B；必须对任何包都有答案，别 panic
 
     // DRR 公平账本
     deficit_a: i32,
     deficit_b: i32,
     quantum: i32, // 每次充值的配额 (通常设为 1500)
     turn_a: bool, // 记录当前是谁的回合 (true=A, false=B)
+
+    // 一个包扣多少赤字，默认就是 ctx.cost (按字节公平)；换成估算空口时长就是按时间公平
+    cost_fn: Option<CostFn<T, K>>,
+
+    mode: DualFairMode,
 }
 
 impl<T, K> DualFairQdisc<T, K> {
@@ -22,7 +47,7 @@ impl<T, K> DualFairQdisc<T, K> {
         q_a: Box<dyn Qdisc<T, K>>,
         q_b: Box<dyn Qdisc<T, K>>,
         quantum: i32,
-        classifier: Box<dyn Fn(&PacketContext<T, K>) -> bool>,
+        classifier: Classifier<T, K>,
     ) -> Self {
         Self {
             q_a,
@@ -32,21 +57,58 @@ impl<T, K> DualFairQdisc<T, K> {
             deficit_b: 0,
             quantum,
             turn_a: true,
+            cost_fn: None,
+            mode: DualFairMode::ByteFair,
+        }
+    }
+
+    pub fn set_cost_fn(&mut self, cost_fn: CostFn<T, K>) {
+        self.cost_fn = Some(cost_fn);
+    }
+
+    pub fn set_mode(&mut self, mode: DualFairMode) {
+        self.mode = mode;
+    }
+
+    // 借用拆开传：调用时 q_a / q_b 正被 peek 借着
+    fn charge(cost_fn: &Option<CostFn<T, K>>, ctx: &PacketContext<T, K>) -> i32 {
+        match cost_fn {
+            Some(f) => f(ctx).max(0),
+            None => ctx.cost as i32,
         }
     }
 }
 
 // 极其纯粹的入队出队实现
 impl<T, K> Qdisc<T, K> for DualFairQdisc<T, K> {
-    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
         if (self.classifier)(&ctx) {
+            ctx.egress_class.get_or_insert(ClassId::Label("a"));
             self.q_a.enqueue(ctx)
         } else {
+            ctx.egress_class.get_or_insert(ClassId::Label("b"));
             self.q_b.enqueue(ctx)
         }
     }
 
     fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        if let DualFairMode::Edf { budget_a, budget_b } = self.mode {
+            // EDF 不记赤字，turn_a 只用来告诉 dequeue 这次选中的是哪边；同时到期让 A 先走
+            let deadline_a = self.q_a.peek().map(|ctx| ctx.arrival_time + budget_a);
+            let deadline_b = self.q_b.peek().map(|ctx| ctx.arrival_time + budget_b);
+            self.turn_a = match (deadline_a, deadline_b) {
+                (Some(a), Some(b)) => a <= b,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            return if self.turn_a {
+                self.q_a.peek()
+            } else {
+                self.q_b.peek()
+            };
+        }
+
         loop {
             if self.q_a.peek().is_none() && self.q_b.peek().is_none() {
                 self.deficit_a = 0;
@@ -56,7 +118,7 @@ impl<T, K> Qdisc<T, K> for DualFairQdisc<T, K> {
 
             if self.turn_a {
                 if let Some(ctx) = self.q_a.peek() {
-                    if self.deficit_a >= ctx.cost as i32 {
+                    if self.deficit_a >= Self::charge(&self.cost_fn, ctx) {
                         return self.q_a.peek(); // 定格！
                     }
                     self.deficit_a += self.quantum;
@@ -66,7 +128,7 @@ impl<T, K> Qdisc<T, K> for DualFairQdisc<T, K> {
                 self.turn_a = false; // 换 B
             } else {
                 if let Some(ctx) = self.q_b.peek() {
-                    if self.deficit_b >= ctx.cost as i32 {
+                    if self.deficit_b >= Self::charge(&self.cost_fn, ctx) {
                         return self.q_b.peek(); // 定格！
                     }
                     self.deficit_b += self.quantum;
@@ -80,13 +142,20 @@ impl<T, K> Qdisc<T, K> for DualFairQdisc<T, K> {
 
     fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
         // 🚀 盲提货：peek 停在谁的回合，就扣谁的钱！
+        if self.mode != DualFairMode::ByteFair {
+            return if self.turn_a {
+                self.q_a.dequeue()
+            } else {
+                self.q_b.dequeue()
+            };
+        }
         if self.turn_a {
             let ctx = self.q_a.dequeue()?;
-            self.deficit_a -= ctx.cost as i32;
+            self.deficit_a -= Self::charge(&self.cost_fn, &ctx);
             Some(ctx)
         } else {
             let ctx = self.q_b.dequeue()?;
-            self.deficit_b -= ctx.cost as i32;
+            self.deficit_b -= Self::charge(&self.cost_fn, &ctx);
             Some(ctx)
         }
     }
@@ -96,4 +165,118 @@ impl<T, K> Qdisc<T, K> for DualFairQdisc<T, K> {
         drops.extend(self.q_b.collect_dropped());
         drops
     }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.q_a.flush();
+        all.extend(self.q_b.flush());
+        self.deficit_a = 0;
+        self.deficit_b = 0;
+        all
+    }
+
+    fn describe(&self) -> String {
+        let mode = match self.mode {
+            DualFairMode::ByteFair => format!("q={}", self.quantum),
+            DualFairMode::Edf { budget_a, budget_b } => {
+                format!("edf {:?}/{:?}", budget_a, budget_b)
+            }
+        };
+        format!(
+            "DualFair({}, a: {}, b: {})",
+            mode,
+            self.q_a.describe(),
+            self.q_b.describe()
+        )
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("a", self.q_a.as_ref()), ("b", self.q_b.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("a", self.q_a.as_mut()), ("b", self.q_b.as_mut())]
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        // 两边都要通知到，不能短路
+        let a = self.q_a.apply_control(cmd);
+        let b = self.q_b.apply_control(cmd);
+        a || b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{packet_context::test_packet, qdisc::QdiscExt, qdisc::leaf::HeadDropFifo};
+
+    // queue_num 0 进 A，其余进 B
+    fn dual(quantum: i32) -> DualFairQdisc<Vec<u8>, u64> {
+        DualFairQdisc::new(
+            Box::new(HeadDropFifo::new(1000)),
+            Box::new(HeadDropFifo::new(1000)),
+            quantum,
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| ctx.queue_num == 0),
+        )
+    }
+
+    #[test]
+    fn cost_fn_splits_airtime_instead_of_bytes() {
+        // A 边的链路慢 4 倍：250 字节的包和 B 边 1000 字节的包占一样长的空口
+        let airtime = |ctx: &PacketContext<Vec<u8>, u64>| {
+            let per_byte = if ctx.queue_num == 0 { 4 } else { 1 };
+            ctx.cost as i32 * per_byte
+        };
+        let mut dual = dual(1500);
+        dual.set_cost_fn(Box::new(airtime));
+        for _ in 0..400 {
+            dual.enqueue(test_packet(1, 0, 250));
+            dual.enqueue(test_packet(2, 1, 1000));
+        }
+
+        let (mut air, mut bytes) = ([0; 2], [0; 2]);
+        for _ in 0..300 {
+            assert!(dual.peek().is_some());
+            let ctx = dual.dequeue().unwrap();
+            let side = ctx.queue_num.min(1);
+            air[side] += airtime(&ctx);
+            bytes[side] += ctx.cost;
+        }
+        // 空口时长 1:1 (差不超过一个量子)，字节数差不多是 1:4
+        assert!((air[0] - air[1]).abs() <= 1500, "airtime {air:?}");
+        assert!(bytes[1] > 3 * bytes[0], "bytes {bytes:?}");
+    }
+
+    #[test]
+    fn edf_lets_the_tight_budget_preempt_a_larger_deficit() {
+        let base = Instant::now();
+        let serve_order = |mode| {
+            let mut dual = dual(1500);
+            dual.set_mode(mode);
+            let at = |queue_num, offset_ms| {
+                let mut ctx = test_packet(queue_num as u64, queue_num, 100);
+                ctx.arrival_time = base + Duration::from_millis(offset_ms);
+                ctx
+            };
+            // B 先来两个，走掉一个之后 B 手里还剩一大笔赤字，回合也停在 B
+            dual.enqueue(at(1, 0));
+            dual.enqueue(at(1, 0));
+            assert!(dual.peek().is_some());
+            assert_eq!(dual.dequeue().map(|ctx| ctx.queue_num), Some(1));
+            // 10ms 后 A 来了一个：预算只有 5ms，比 B 那个 50ms 的截止时间早得多
+            dual.enqueue(at(0, 10));
+            dual.drain_ready()
+                .map(|ctx| ctx.queue_num)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(serve_order(DualFairMode::ByteFair), [1, 0]);
+        let edf = DualFairMode::Edf {
+            budget_a: Duration::from_millis(5),
+            budget_b: Duration::from_millis(50),
+        };
+        assert_eq!(serve_order(edf), [0, 1]);
+    }
 }
@@ -0,0 +1,163 @@
+use crate::control::ControlCommand;
+use crate::packet_context::{ClassId, PacketContext};
+use crate::qdisc::Qdisc;
+
+// 返回通道下标，越界的归最后一条通道
+type LaneClassifier<T, K> = Box<dyn Fn(&PacketContext<T, K>) -> usize>;
+
+struct Lane<T, K> {
+    qdisc: Box<dyn Qdisc<T, K>>,
+    deficit: i32,
+}
+
+// ==========================================
+// 多通道公平轮询队列 (Fair Qdisc)
+// DualFairQdisc 的 N 路版本：几个平起平坐的子队列按字节 1:1:...:1 分带宽，
+// 记账和 DualFair 的 DRR 一模一样 (每条通道一本赤字账，轮到谁谁充一次 quantum)
+// 两路又要 EDF 的还是用 DualFairQdisc
+// ==========================================
+pub struct FairQdisc<T, K> {
+    lanes: Vec<Lane<T, K>>,
+    classifier: LaneClassifier<T, K>,
+    quantum: i32,
+    turn: usize, // 当前轮到哪条通道
+}
+
+impl<T, K> FairQdisc<T, K> {
+    pub fn new(
+        lanes: Vec<Box<dyn Qdisc<T, K>>>,
+        quantum: i32,
+        classifier: LaneClassifier<T, K>,
+    ) -> Self {
+        assert!(!lanes.is_empty(), "FairQdisc 至少需要一条通道");
+        Self {
+            lanes: lanes
+                .into_iter()
+                .map(|qdisc| Lane { qdisc, deficit: 0 })
+                .collect(),
+            classifier,
+            quantum,
+            turn: 0,
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for FairQdisc<T, K> {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        let idx = (self.classifier)(&ctx).min(self.lanes.len() - 1);
+        ctx.egress_class.get_or_insert(ClassId::Lane(idx));
+        self.lanes[idx].qdisc.enqueue(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        loop {
+            if self
+                .lanes
+                .iter_mut()
+                .all(|lane| lane.qdisc.peek().is_none())
+            {
+                for lane in &mut self.lanes {
+                    lane.deficit = 0;
+                }
+                return None;
+            }
+
+            let lane = &mut self.lanes[self.turn];
+            if let Some(ctx) = lane.qdisc.peek() {
+                if lane.deficit >= ctx.cost as i32 {
+                    return self.lanes[self.turn].qdisc.peek(); // 定格！
+                }
+                lane.deficit += self.quantum;
+            } else {
+                lane.deficit = 0;
+            }
+            self.turn = (self.turn + 1) % self.lanes.len();
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        // 和 DualFair 一样盲提货：peek 停在谁的回合，就扣谁的钱
+        let lane = &mut self.lanes[self.turn];
+        let ctx = lane.qdisc.dequeue()?;
+        lane.deficit -= ctx.cost as i32;
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        self.lanes
+            .iter_mut()
+            .flat_map(|lane| lane.qdisc.collect_dropped())
+            .collect()
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        self.lanes
+            .iter_mut()
+            .flat_map(|lane| {
+                lane.deficit = 0;
+                lane.qdisc.flush()
+            })
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        let inner: Vec<String> = self.lanes.iter().map(|l| l.qdisc.describe()).collect();
+        format!("Fair(q={}, {})", self.quantum, inner.join(", "))
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        self.lanes
+            .iter()
+            .map(|lane| ("lane", lane.qdisc.as_ref()))
+            .collect()
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        self.lanes
+            .iter_mut()
+            .map(|lane| ("lane", lane.qdisc.as_mut() as &mut dyn Qdisc<T, K>))
+            .collect()
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        // 每条通道都要通知到，不能短路
+        let mut handled = false;
+        for lane in &mut self.lanes {
+            handled |= lane.qdisc.apply_control(cmd);
+        }
+        handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    #[test]
+    fn three_saturated_lanes_split_bytes_evenly() {
+        let lanes: Vec<Box<dyn Qdisc<Vec<u8>, u64>>> = (0..3)
+            .map(|_| Box::new(HeadDropFifo::new(2000)) as Box<dyn Qdisc<Vec<u8>, u64>>)
+            .collect();
+        let mut fair = FairQdisc::new(
+            lanes,
+            1500,
+            Box::new(|ctx: &PacketContext<Vec<u8>, u64>| ctx.queue_num),
+        );
+        // 三条隧道包长各不相同，按包数轮就偏了，按字节才是 1:1:1；每条都备足 150KB
+        for (lane, len) in [100, 500, 1500].into_iter().enumerate() {
+            for _ in 0..150_000 / len {
+                fair.enqueue(test_packet(lane as u64, lane, len));
+            }
+        }
+
+        let mut sent = [0usize; 3];
+        while sent.iter().sum::<usize>() < 300_000 {
+            assert!(fair.peek().is_some());
+            let ctx = fair.dequeue().unwrap();
+            sent[ctx.queue_num] += ctx.cost;
+        }
+        let (lo, hi) = (sent.iter().min().unwrap(), sent.iter().max().unwrap());
+        assert!(hi - lo <= 1500, "sent {sent:?}");
+    }
+}
@@ -1,6 +1,65 @@
-use crate::packet_context::PacketContext;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::classifier::Classifier;
+use crate::clock::{Clock, SystemClock};
+use crate::control::{BucketId, ControlCommand};
+use crate::packet_context::{ClassId, PacketContext};
 use crate::qdisc::Qdisc;
-use crate::token_bucket::TokenBucketLimiter;
+use crate::token_bucket::{BucketStats, TokenBucketLimiter};
+
+// 一个包从某一层桶里扣多少字节
+type CostFn<T, K> = Box<dyn Fn(&PacketContext<T, K>) -> usize>;
+
+// 借用拆开传：调用时子队列正被 peek 借着；没配就是 ctx.cost
+fn cost_of<T, K>(cost_fn: &Option<CostFn<T, K>>, ctx: &PacketContext<T, K>) -> usize {
+    cost_fn.as_ref().map_or(ctx.cost, |f| f(ctx))
+}
+
+// ==========================================
+// 按入口队列分路的限速桶 (比如 WG 路径 0~3 和以太网路径 4/5 各封各的顶)
+// 几个队列可以共用一只桶；没登记的队列不受这一层约束
+// ==========================================
+struct QueueBuckets<B> {
+    buckets: Vec<B>,
+    by_queue: HashMap<usize, usize>, // queue_num -> buckets 下标
+}
+
+impl<B: TokenBucketLimiter> QueueBuckets<B> {
+    fn admits<T, K>(&mut self, ctx: &PacketContext<T, K>) -> bool {
+        match self.by_queue.get(&ctx.queue_num) {
+            Some(&idx) => self.buckets[idx].affords_frames(ctx.cost, ctx.frames),
+            None => true,
+        }
+    }
+
+    // 真卡住了才记拒绝 (见 HtbQdisc::note_stall)
+    fn note_stall<T, K>(&mut self, ctx: &PacketContext<T, K>) {
+        if let Some(&idx) = self.by_queue.get(&ctx.queue_num) {
+            let _ = self.buckets[idx].can_spend_frames(ctx.cost, ctx.frames);
+        }
+    }
+
+    fn charge<T, K>(&mut self, ctx: &PacketContext<T, K>) {
+        if let Some(&idx) = self.by_queue.get(&ctx.queue_num) {
+            self.buckets[idx].consume_frames(ctx.cost, ctx.frames);
+        }
+    }
+
+    fn bucket_mut(&mut self, queue_num: usize) -> Option<&mut B> {
+        let idx = *self.by_queue.get(&queue_num)?;
+        self.buckets.get_mut(idx)
+    }
+}
+
+// 这次放行走的是哪一档，决定扣哪几只桶
+#[derive(Clone, Copy)]
+enum Tier {
+    Control,   // 控制包专用小桶，其它桶一概不问 (放走之后全局桶照样记账，可以欠着)
+    Committed, // 保底桶 + 自己那一类的桶 (封顶) + 全局桶
+    Own,       // 自己那一类的桶 + 全局桶
+    Borrowed,  // 只借全局桶 (给对方留够准备金)
+}
 
 // ==========================================
 // 真正的分层令牌桶调度器 (True HTB Qdisc)
@@ -17,7 +76,49 @@ pub struct HtbQdisc<T, K, B: TokenBucketLimiter> {
     high_reserve: usize, // 🚀 新增：只允许 VIP 动用的全局准备金
     low_reserve: usize,  // 🚀 新增：只允许 VIP 动用的全局准备金
 
-    classifier: Box<dyn Fn(&PacketContext<T, K>) -> bool>,
+    classifier: Classifier<T, K>,
+
+    // 高优队头付不起高优桶时，最多往后再看几个包 (0 = 不跳队，严格 FIFO)
+    // 子队列得实现 peek_nth 才有效，而且被跳过的大包会被同类小包插队
+    skip_ahead: usize,
+
+    // 🚀 在全局桶之外再加一道按入口队列的闸：两道都放行才能出队
+    queue_buckets: QueueBuckets<B>,
+
+    // 🚀 保底速率 (HTB 的 rate)：保底桶付得起的包最先走；没付完保底的一类排着队时，
+    // 另一类除了保底以外的档位都得在全局桶里给它的队头留出位置，多出来的全局额度才轮得到别人
+    // None = 没有保底 (老行为)
+    high_committed: Option<B>,
+    low_committed: Option<B>,
+
+    // 🚀 动态准备金：对方空着、并且这么久没来过包，借全局桶时就不用给它留准备金
+    // None = 准备金永远生效 (老行为)
+    reserve_idle: Option<Duration>,
+    high_last_arrival: Instant,
+    low_last_arrival: Instant,
+    clock: Box<dyn Clock>,
+
+    // 🚀 交替出队：同一档里两类都放得行时，上一个走的是谁这次就先问另一类，
+    // 高优不会在一个桶窗口里一口气倒空、低优也不会周期性地卡一下
+    // false = 严格优先级 (老行为)，每一档都是高优先问
+    interleave: bool,
+    last_high: bool,
+
+    // 🚀 全局桶 / 分类桶 (自己的桶和保底桶) 各按什么扣费，None = ctx.cost (老行为)
+    // 全局桶代表共享的物理链路时，可以让它按线上真实字节 (pkt_len) 扣，分类桶照旧按整形后的 cost 扣
+    // 入口队列的闸不受影响，始终按 ctx.cost
+    global_cost_fn: Option<CostFn<T, K>>,
+    class_cost_fn: Option<CostFn<T, K>>,
+
+    // 🚀 控制包快车道：队头是 is_control 的包 (握手 / 挥手 / 纯 ACK，要挂 tcp_control 修改器) 时，
+    // 只要这只小桶付得起就直接放行，全局桶干了也不等；放走之后照样记在全局桶上 (扣成负数就是欠着，
+    // 后面的数据包先替它还账)，链路总量不会因为走了快车道就超；小桶本身限住了量，伪造的 ACK 洪水也挤不垮整形
+    // None = 控制包和数据包一样排队 (老行为)
+    control_bucket: Option<B>,
+
+    // peek 挑中的那一个 (route 的结果)，dequeue 直接照着提货，不再把每只桶重新问一遍
+    // 入队、收尸、倒空都可能换掉队头，一律作废重算
+    routed: Option<(bool, usize, Tier)>,
 }
 
 impl<T, K, B: TokenBucketLimiter> HtbQdisc<T, K, B> {
@@ -27,98 +128,989 @@ impl<T, K, B: TokenBucketLimiter> HtbQdisc<T, K, B> {
         high_bucket: B,
         low_bucket: B,
         global_bucket: B,
-        high_reserve: usize,
-        low_reserve: usize,
-        classifier: Box<dyn Fn(&PacketContext<T, K>) -> bool>,
+        classifier: Classifier<T, K>,
     ) -> Self {
+        let clock: Box<dyn Clock> = Box::new(SystemClock);
+        let now = clock.now();
         Self {
             high_qdisc,
             low_qdisc,
             high_bucket,
             low_bucket,
             global_bucket,
-            high_reserve,
-            low_reserve,
+            high_reserve: 0,
+            low_reserve: 0,
             classifier,
+            skip_ahead: 0,
+            queue_buckets: QueueBuckets {
+                buckets: Vec::new(),
+                by_queue: HashMap::new(),
+            },
+            high_committed: None,
+            low_committed: None,
+            reserve_idle: None,
+            high_last_arrival: now,
+            low_last_arrival: now,
+            clock,
+            interleave: false,
+            last_high: false,
+            global_cost_fn: None,
+            class_cost_fn: None,
+            control_bucket: None,
+            routed: None,
+        }
+    }
+
+    // 给一组入口队列挂一只共用的限速桶，已挂过桶的队列改挂新桶
+    pub fn add_queue_bucket(&mut self, queues: &[usize], bucket: B) {
+        let idx = self.queue_buckets.buckets.len();
+        self.queue_buckets.buckets.push(bucket);
+        for &q in queues {
+            self.queue_buckets.by_queue.insert(q, idx);
+        }
+    }
+
+    // 借全局桶时要给对方留着的准备金 (字节)，默认都是 0
+    pub fn set_reserves(&mut self, high_reserve: usize, low_reserve: usize) {
+        self.high_reserve = high_reserve;
+        self.low_reserve = low_reserve;
+    }
+
+    pub fn set_skip_ahead(&mut self, skip_ahead: usize) {
+        self.skip_ahead = skip_ahead;
+    }
+
+    // 给高优 / 低优挂保底桶：两类保底速率加起来不应超过全局桶，否则保底本身就兑现不了
+    pub fn set_high_committed(&mut self, bucket: B) {
+        self.high_committed = Some(bucket);
+    }
+
+    pub fn set_low_committed(&mut self, bucket: B) {
+        self.low_committed = Some(bucket);
+    }
+
+    // 高优 / 低优两棵子树，构造完之后查积压、调参用；桶和分类不受影响
+    #[allow(dead_code)]
+    pub fn high(&self) -> &dyn Qdisc<T, K> {
+        self.high_qdisc.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn high_mut(&mut self) -> &mut dyn Qdisc<T, K> {
+        self.high_qdisc.as_mut()
+    }
+
+    #[allow(dead_code)]
+    pub fn low(&self) -> &dyn Qdisc<T, K> {
+        self.low_qdisc.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn low_mut(&mut self) -> &mut dyn Qdisc<T, K> {
+        self.low_qdisc.as_mut()
+    }
+
+    // 只改同一档里谁先问，档位顺序 (保底 > 自己的桶 > 借全局桶) 和各档的准入条件都不变
+    pub fn set_interleave(&mut self, enabled: bool) {
+        self.interleave = enabled;
+    }
+
+    pub fn set_control_bucket(&mut self, bucket: B) {
+        self.control_bucket = Some(bucket);
+    }
+
+    pub fn set_global_cost_fn(&mut self, cost_fn: CostFn<T, K>) {
+        self.global_cost_fn = Some(cost_fn);
+    }
+
+    pub fn set_class_cost_fn(&mut self, cost_fn: CostFn<T, K>) {
+        self.class_cost_fn = Some(cost_fn);
+    }
+
+    // 打开动态准备金：一类队列空着并且闲了 idle 这么久，另一类借全局桶时不再给它留准备金
+    pub fn set_reserve_idle(&mut self, idle: Duration) {
+        self.reserve_idle = Some(idle);
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        let now = clock.now();
+        self.high_last_arrival = now;
+        self.low_last_arrival = now;
+        self.clock = clock;
+    }
+
+    // 热替换分类器：已经排队的包按老路由走完，只有之后入队的包看到新规则
+    pub fn set_classifier(&mut self, classifier: Classifier<T, K>) {
+        self.classifier = classifier;
+    }
+
+    // ==========================================
+    // 给外部调度器做规划用的 "试探" 接口 (在多个 HTB 之间挑谁先发)
+    // peek 桶一干就返回 None，看不出里面其实有包；这几个只看不扣费
+    // ==========================================
+
+    // 不管桶，按严格优先级报下一个包：高优有包就是高优队头，否则低优队头
+    #[allow(dead_code)]
+    pub fn peek_unshaped(&mut self) -> Option<&PacketContext<T, K>> {
+        if self.high_qdisc.peek().is_some() {
+            return self.high_qdisc.peek();
+        }
+        self.low_qdisc.peek()
+    }
+
+    #[allow(dead_code)]
+    pub fn next_cost(&mut self) -> Option<usize> {
+        self.peek_unshaped().map(|ctx| ctx.cost)
+    }
+
+    // peek_unshaped 那个包最早什么时候能放行；队列空了或者永远付不起时返回 None
+    // 只按它自己那一类的两条路估：自己的桶 + 全局桶，或者只走全局桶但给对方留够准备金
+    // (保底档还要多问一只保底桶，不会比前一条路早)
+    // 不考虑 skip_ahead 跳队，估出来的时间偏保守
+    pub fn eligible_at(&mut self) -> Option<Instant> {
+        let (high_reserve, low_reserve) = self.reserves();
+        let vip = self.high_qdisc.peek().is_some();
+        let ctx = if vip {
+            self.high_qdisc.peek()?
+        } else {
+            self.low_qdisc.peek()?
+        };
+        let (cost, frames, queue_num) = (ctx.cost, ctx.frames, ctx.queue_num);
+        let class_cost = cost_of(&self.class_cost_fn, ctx);
+        let global_cost = cost_of(&self.global_cost_fn, ctx);
+
+        let (own_bucket, reserve) = if vip {
+            (&mut self.high_bucket, low_reserve)
+        } else {
+            (&mut self.low_bucket, high_reserve)
+        };
+        let own = own_bucket.ready_at_frames(class_cost, frames);
+        let global = self.global_bucket.ready_at_frames(global_cost, frames);
+        let borrowed = self
+            .global_bucket
+            .ready_at_frames(global_cost + reserve, frames);
+
+        let guaranteed = own.zip(global).map(|(a, b)| a.max(b));
+        let at = [guaranteed, borrowed].into_iter().flatten().min()?;
+
+        // 入口队列的那道闸要同时放行
+        match self.queue_buckets.bucket_mut(queue_num) {
+            Some(gate) => gate.ready_at_frames(cost, frames).map(|g| at.max(g)),
+            None => Some(at),
         }
     }
 }
 
-impl<T, K, B> Qdisc<T, K> for HtbQdisc<T, K, B>
-where
-    B: TokenBucketLimiter,
-{
-    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
-        if (self.classifier)(&ctx) {
-            self.high_qdisc.enqueue(ctx);
+impl<T, K, B: TokenBucketLimiter> HtbQdisc<T, K, B> {
+    // 借全局桶时要给高优 / 低优各留多少准备金 (先算好，后面 peek 子队列时借用就不打架了)
+    fn reserves(&mut self) -> (usize, usize) {
+        let Some(idle) = self.reserve_idle else {
+            return (self.high_reserve, self.low_reserve);
+        };
+        let now = self.clock.now();
+        let high_idle = now.saturating_duration_since(self.high_last_arrival) >= idle
+            && self.high_qdisc.peek().is_none();
+        let low_idle = now.saturating_duration_since(self.low_last_arrival) >= idle
+            && self.low_qdisc.peek().is_none();
+        (
+            if high_idle { 0 } else { self.high_reserve },
+            if low_idle { 0 } else { self.low_reserve },
+        )
+    }
+
+    // 高优 / 低优各自还欠着多少保底：队头排着、保底桶付得起它，就得在全局桶里给它留够队头的大小
+    // 返回的是队头在全局桶里的价钱
+    fn owed(&mut self) -> (usize, usize) {
+        let (class_fn, global_fn) = (&self.class_cost_fn, &self.global_cost_fn);
+        let head = |ctx: &PacketContext<T, K>| {
+            (cost_of(class_fn, ctx), cost_of(global_fn, ctx), ctx.frames)
+        };
+        let high = self.high_qdisc.peek().map(head);
+        let low = self.low_qdisc.peek().map(head);
+        let owes = |bucket: &mut Option<B>, head: Option<(usize, usize, usize)>| {
+            let (b, (class_cost, global_cost, frames)) = bucket.as_mut().zip(head)?;
+            b.affords_frames(class_cost, frames).then_some(global_cost)
+        };
+        (
+            owes(&mut self.high_committed, high).unwrap_or(0),
+            owes(&mut self.low_committed, low).unwrap_or(0),
+        )
+    }
+
+    // 控制包档：队头是控制包、控制桶付得起 (只看队头，不跳队)
+    fn control_admits(&mut self, high: bool) -> bool {
+        let Some(bucket) = self.control_bucket.as_mut() else {
+            return false;
+        };
+        let qdisc = if high {
+            &mut self.high_qdisc
         } else {
-            self.low_qdisc.enqueue(ctx);
+            &mut self.low_qdisc
+        };
+        qdisc
+            .peek()
+            .is_some_and(|ctx| ctx.is_control && bucket.affords_frames(ctx.cost, ctx.frames))
+    }
+
+    // 保底档：保底桶 + 自己的桶 (封顶) + 全局桶 + 入口闸都放行 (只看队头，不跳队)
+    fn committed_admits(&mut self, high: bool) -> bool {
+        let (qdisc, own, committed) = if high {
+            (
+                &mut self.high_qdisc,
+                &mut self.high_bucket,
+                &mut self.high_committed,
+            )
+        } else {
+            (
+                &mut self.low_qdisc,
+                &mut self.low_bucket,
+                &mut self.low_committed,
+            )
+        };
+        let Some(committed) = committed.as_mut() else {
+            return false;
+        };
+        let Some(ctx) = qdisc.peek() else {
+            return false;
+        };
+        let class_cost = cost_of(&self.class_cost_fn, ctx);
+        committed.affords_frames(class_cost, ctx.frames)
+            && own.affords_frames(class_cost, ctx.frames)
+            && self
+                .global_bucket
+                .affords_frames(cost_of(&self.global_cost_fn, ctx), ctx.frames)
+            && self.queue_buckets.admits(ctx)
+    }
+
+    // 第一档 (高优自己的桶 + 全局桶，另外给低优欠着的保底留位置) 能放行的高优包是第几个，队头不行就往后找
+    fn eligible_high(&mut self, low_owed: usize) -> Option<usize> {
+        for n in 0..=self.skip_ahead {
+            let ctx = self.high_qdisc.peek_nth(n)?;
+            let global_cost = cost_of(&self.global_cost_fn, ctx);
+            if self
+                .high_bucket
+                .affords_frames(cost_of(&self.class_cost_fn, ctx), ctx.frames)
+                && self
+                    .global_bucket
+                    .affords_frames(global_cost + low_owed, ctx.frames)
+                && self.queue_buckets.admits(ctx)
+            {
+                return Some(n);
+            }
         }
+        None
     }
 
-    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
-        if let Some(ctx) = self.high_qdisc.peek() {
-            if self.high_bucket.can_spend(ctx.cost) && self.global_bucket.can_spend(ctx.cost) {
-                return self.high_qdisc.peek();
+    // 第一档的低优版本：低优自己的桶 + 全局桶，另外给高优欠着的保底留位置 (只看队头)
+    fn low_own_admits(&mut self, high_owed: usize) -> bool {
+        let Some(ctx) = self.low_qdisc.peek() else {
+            return false;
+        };
+        let global_cost = cost_of(&self.global_cost_fn, ctx);
+        self.low_bucket
+            .affords_frames(cost_of(&self.class_cost_fn, ctx), ctx.frames)
+            && self
+                .global_bucket
+                .affords_frames(global_cost + high_owed, ctx.frames)
+            && self.queue_buckets.admits(ctx)
+    }
+
+    // 借全局桶档：只扣全局桶，但得给另一类留出 guard 这么多 (准备金和它欠着的保底取大)
+    fn borrow_admits(&mut self, high: bool, guard: usize) -> bool {
+        let qdisc = if high {
+            &mut self.high_qdisc
+        } else {
+            &mut self.low_qdisc
+        };
+        let Some(ctx) = qdisc.peek() else {
+            return false;
+        };
+        let global_cost = cost_of(&self.global_cost_fn, ctx);
+        self.global_bucket
+            .affords_frames(global_cost + guard, ctx.frames)
+            && self.queue_buckets.admits(ctx)
+    }
+
+    // 按档位从高到低找这一次该放谁：(是不是高优, 高优里的第几个, 走的哪一档)
+    // 只用 affords 试探，不记拒绝；peek 把结果缓存下来，dequeue 按返回的档位扣费
+    fn route(&mut self) -> Option<(bool, usize, Tier)> {
+        let (high_reserve, low_reserve) = self.reserves();
+        let (high_owed, low_owed) = self.owed();
+        let order = if self.interleave && self.last_high {
+            [false, true]
+        } else {
+            [true, false]
+        };
+
+        for high in order {
+            if self.control_admits(high) {
+                return Some((high, 0, Tier::Control));
             }
         }
-        if let Some(ctx) = self.low_qdisc.peek() {
-            if self.low_bucket.can_spend(ctx.cost) && self.global_bucket.can_spend(ctx.cost) {
-                return self.low_qdisc.peek();
+        for high in order {
+            if self.committed_admits(high) {
+                return Some((high, 0, Tier::Committed));
             }
         }
-        if let Some(ctx) = self.high_qdisc.peek() {
-            if self.global_bucket.can_spend(ctx.cost + self.low_reserve) {
-                return self.high_qdisc.peek();
+        for high in order {
+            if high {
+                if let Some(n) = self.eligible_high(low_owed) {
+                    return Some((true, n, Tier::Own));
+                }
+            } else if self.low_own_admits(high_owed) {
+                return Some((false, 0, Tier::Own));
             }
         }
-        if let Some(ctx) = self.low_qdisc.peek() {
-            if self.global_bucket.can_spend(ctx.cost + self.high_reserve) {
-                return self.low_qdisc.peek();
+        for high in order {
+            let guard = if high {
+                low_reserve.max(low_owed)
+            } else {
+                high_reserve.max(high_owed)
+            };
+            if self.borrow_admits(high, guard) {
+                return Some((high, 0, Tier::Borrowed));
             }
         }
         None
     }
 
-    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
-        // 🚀 既然 peek 刚确认过，这里重新走一遍分支直接提货扣费即可
-        if let Some(ctx) = self.high_qdisc.peek() {
-            if self.high_bucket.can_spend(ctx.cost) && self.global_bucket.can_spend(ctx.cost) {
-                let real = self.high_qdisc.dequeue()?;
-                self.high_bucket.consume(real.cost);
-                self.global_bucket.consume(real.cost);
-                return Some(real);
+    // route 一个都挑不出来时才记拒绝：按严格优先级的那个队头，问它自己的桶、全局桶和入口闸
+    // 全局桶按它真正要付的价钱判：自己的桶付得起就是队头 + 对方欠着的保底，付不起就得借，再加上准备金
+    // 记下的只是队头本身的字节数；一回卡脖子桶自己只记一次，peek 每轮都来问也不会越记越多
+    fn note_stall(&mut self) {
+        let (high_reserve, low_reserve) = self.reserves();
+        let (high_owed, low_owed) = self.owed();
+        let (qdisc, own, owed, reserve) = if self.high_qdisc.peek().is_some() {
+            (
+                &mut self.high_qdisc,
+                &mut self.high_bucket,
+                low_owed,
+                low_reserve,
+            )
+        } else {
+            (
+                &mut self.low_qdisc,
+                &mut self.low_bucket,
+                high_owed,
+                high_reserve,
+            )
+        };
+        let Some(ctx) = qdisc.peek() else {
+            return;
+        };
+        let guard = if own.can_spend_frames(cost_of(&self.class_cost_fn, ctx), ctx.frames) {
+            owed
+        } else {
+            reserve.max(owed)
+        };
+        let global_cost = cost_of(&self.global_cost_fn, ctx);
+        if !self
+            .global_bucket
+            .affords_frames(global_cost + guard, ctx.frames)
+        {
+            self.global_bucket.record_denied(global_cost);
+        }
+        self.queue_buckets.note_stall(ctx);
+    }
+}
+
+impl<T, K, B> Qdisc<T, K> for HtbQdisc<T, K, B>
+where
+    B: TokenBucketLimiter,
+{
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        self.routed = None;
+        let high = (self.classifier)(&ctx);
+        if self.reserve_idle.is_some() {
+            let now = self.clock.now();
+            if high {
+                self.high_last_arrival = now;
+            } else {
+                self.low_last_arrival = now;
             }
         }
-        if let Some(ctx) = self.low_qdisc.peek() {
-            if self.low_bucket.can_spend(ctx.cost) && self.global_bucket.can_spend(ctx.cost) {
-                let real = self.low_qdisc.dequeue()?;
-                self.low_bucket.consume(real.cost);
-                self.global_bucket.consume(real.cost);
-                return Some(real);
+        if high {
+            ctx.egress_class.get_or_insert(ClassId::Vip);
+            self.high_qdisc.enqueue(ctx);
+        } else {
+            ctx.egress_class.get_or_insert(ClassId::Default);
+            self.low_qdisc.enqueue(ctx);
+        }
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.routed = self.route();
+        match self.routed {
+            Some((true, n, _)) => self.high_qdisc.peek_nth(n),
+            Some((false, _, _)) => self.low_qdisc.peek(),
+            None => {
+                self.note_stall();
+                None
             }
         }
-        if let Some(ctx) = self.high_qdisc.peek() {
-            if self.global_bucket.can_spend(ctx.cost + self.low_reserve) {
-                let real = self.high_qdisc.dequeue()?;
-                self.global_bucket.consume(real.cost);
-                return Some(real);
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        // 🚀 peek 刚挑好的直接提货扣费；没 peek 过才现挑
+        let (high, n, tier) = match self.routed.take() {
+            Some(routed) => routed,
+            None => self.route()?,
+        };
+        let real = if high {
+            self.high_qdisc.dequeue_nth(n)?
+        } else {
+            self.low_qdisc.dequeue()?
+        };
+        let (own, committed) = if high {
+            (&mut self.high_bucket, &mut self.high_committed)
+        } else {
+            (&mut self.low_bucket, &mut self.low_committed)
+        };
+        if let Tier::Control = tier {
+            if let Some(bucket) = self.control_bucket.as_mut() {
+                bucket.consume_frames(real.cost, real.frames);
             }
+            let global_cost = cost_of(&self.global_cost_fn, &real);
+            self.global_bucket
+                .force_consume_frames(global_cost, real.frames);
+            self.last_high = high;
+            return Some(real);
         }
-        if let Some(ctx) = self.low_qdisc.peek() {
-            if self.global_bucket.can_spend(ctx.cost + self.high_reserve) {
-                let real = self.low_qdisc.dequeue()?;
-                self.global_bucket.consume(real.cost);
-                return Some(real);
+        let class_cost = cost_of(&self.class_cost_fn, &real);
+        match tier {
+            Tier::Committed => {
+                if let Some(committed) = committed.as_mut() {
+                    committed.consume_frames(class_cost, real.frames);
+                }
+                own.consume_frames(class_cost, real.frames);
             }
+            Tier::Own => {
+                own.consume_frames(class_cost, real.frames);
+            }
+            Tier::Borrowed | Tier::Control => {}
+        }
+        let global_cost = cost_of(&self.global_cost_fn, &real);
+        self.global_bucket.consume_frames(global_cost, real.frames);
+        self.queue_buckets.charge(&real);
+        self.last_high = high;
+        Some(real)
+    }
+
+    // 严格优先级的那个队头：高优有包就是高优，否则低优
+    fn peek_head(&mut self) -> Option<&PacketContext<T, K>> {
+        if self.high_qdisc.peek_head().is_some() {
+            self.high_qdisc.peek_head()
+        } else {
+            self.low_qdisc.peek_head()
+        }
+    }
+
+    fn drop_head(&mut self) -> Option<PacketContext<T, K>> {
+        self.routed = None;
+        if self.high_qdisc.peek_head().is_some() {
+            self.high_qdisc.drop_head()
+        } else {
+            self.low_qdisc.drop_head()
         }
-        None
+    }
+
+    // 两边队头都还在子树里卡着 (比如下面还有一层限速) 时 eligible_at 给不出，就问子树
+    fn next_ready(&mut self) -> Option<Instant> {
+        self.eligible_at().or_else(|| {
+            [self.high_qdisc.next_ready(), self.low_qdisc.next_ready()]
+                .into_iter()
+                .flatten()
+                .min()
+        })
     }
 
     fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
         let _ = self.peek(); // 级联触发打扫
         let mut drops = self.high_qdisc.collect_dropped();
         drops.extend(self.low_qdisc.collect_dropped());
+        self.routed = None;
         drops
     }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.collect_dropped();
+        all.extend(self.high_qdisc.flush());
+        all.extend(self.low_qdisc.flush());
+        self.routed = None;
+        all
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        match cmd {
+            ControlCommand::SetRate { bucket, rate_bps } => {
+                let rate_bytes = rate_bps / 8.0;
+                let target = match bucket {
+                    BucketId::Global => &mut self.global_bucket,
+                    BucketId::High => &mut self.high_bucket,
+                    BucketId::Low => &mut self.low_bucket,
+                };
+                target.set_rate(rate_bytes);
+                // 速率都换了，老速率下的拒绝记录没有参考价值，从零记起
+                target.reset_stats();
+                true
+            }
+            ControlCommand::SetVip(queues) => {
+                let queues = queues.clone();
+                // DNS 不管从哪个队列来都留在高优
+                self.set_classifier(Box::new(move |ctx| {
+                    ctx.is_dns || queues.contains(&ctx.queue_num)
+                }));
+                true
+            }
+            ControlCommand::ResetCounters => {
+                // 子树里挂着的监控也要清，两边都得通知到，不能短路
+                let high = self.high_qdisc.apply_control(cmd);
+                let low = self.low_qdisc.apply_control(cmd);
+                high || low
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Htb(high: {}, low: {})",
+            self.high_qdisc.describe(),
+            self.low_qdisc.describe()
+        )
+    }
+
+    fn bucket_stats(&self) -> Vec<(&'static str, BucketStats)> {
+        vec![
+            ("global", self.global_bucket.stats()),
+            ("high", self.high_bucket.stats()),
+            ("low", self.low_bucket.stats()),
+        ]
+        .into_iter()
+        .chain(
+            self.high_committed
+                .iter()
+                .map(|b| ("high_committed", b.stats())),
+        )
+        .chain(
+            self.low_committed
+                .iter()
+                .map(|b| ("low_committed", b.stats())),
+        )
+        .chain(self.control_bucket.iter().map(|b| ("control", b.stats())))
+        .chain(
+            self.queue_buckets
+                .buckets
+                .iter()
+                .map(|b| ("queue", b.stats())),
+        )
+        .collect()
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![
+            ("high", self.high_qdisc.as_ref()),
+            ("low", self.low_qdisc.as_ref()),
+        ]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![
+            ("high", self.high_qdisc.as_mut()),
+            ("low", self.low_qdisc.as_mut()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        packet_context::test_packet,
+        qdisc::{leaf::HeadDropFifo, monitor_reports, wrapper::MonitorQdisc},
+        token_bucket::TokenBucket,
+    };
+
+    // 不补水的桶：放不放行只看初始余额和拨过的表
+    fn bucket(clock: &MockClock, burst: f64) -> TokenBucket {
+        let mut bucket = TokenBucket::new(0.0, burst, "test");
+        bucket.set_clock(Box::new(clock.clone()));
+        bucket
+    }
+
+    #[test]
+    fn idle_class_reserve_is_released_after_idle_window() {
+        let clock = MockClock::new();
+        // 高优 (queue 0) 准备金 1000，低优自己的桶是空的，只能借全局桶的 1500
+        let mut htb: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            bucket(&clock, 1500.0),
+            bucket(&clock, 0.0),
+            bucket(&clock, 1500.0),
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        htb.set_reserves(1000, 0);
+        htb.set_reserve_idle(Duration::from_millis(100));
+        htb.set_clock(Box::new(clock.clone()));
+        htb.enqueue(test_packet(1, 1, 1000));
+
+        // 高优刚 "来过" (set_clock 对齐了到达时间)，1000 + 准备金 1000 付不起
+        assert!(htb.peek().is_none());
+        clock.advance(Duration::from_millis(99));
+        assert!(htb.peek().is_none());
+
+        clock.advance(Duration::from_millis(1));
+        assert!(htb.peek().is_some());
+        assert_eq!(htb.dequeue().map(|ctx| ctx.cost), Some(1000));
+    }
+
+    #[test]
+    fn dry_bucket_still_reports_when_the_head_can_go() {
+        let clock = MockClock::new();
+        let mut global = TokenBucket::new(1000.0, 1000.0, "global");
+        global.set_clock(Box::new(clock.clone()));
+        let mut htb: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            bucket(&clock, 2000.0),
+            bucket(&clock, 2000.0),
+            global,
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        htb.enqueue(test_packet(1, 0, 1000));
+        htb.enqueue(test_packet(1, 0, 500));
+        assert!(htb.peek().is_some());
+        htb.dequeue();
+
+        // 全局桶干了，peek 看不出还有包，next_ready 按缺口 500 字节 / 1000 B/s 报半秒后
+        let start = clock.now();
+        assert!(htb.peek().is_none());
+        assert_eq!(htb.next_ready(), Some(start + Duration::from_millis(500)));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(htb.peek().map(|ctx| ctx.cost), Some(500));
+    }
+
+    fn htb(clock: &MockClock) -> HtbQdisc<Vec<u8>, u64, TokenBucket> {
+        HtbQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            bucket(clock, 1500.0),
+            bucket(clock, 3000.0),
+            bucket(clock, 10_000.0),
+            Box::new(|ctx| ctx.queue_num == 0),
+        )
+    }
+
+    #[test]
+    fn probes_see_the_head_without_spending_tokens() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.high_bucket.force_consume(1500);
+        htb.low_bucket.force_consume(3000);
+        htb.global_bucket.force_consume(10_000);
+
+        // 三只桶都干了：peek 报没有，probe 照样看得见队头
+        htb.enqueue(test_packet(1, 1, 100));
+        assert!(htb.peek().is_none());
+        assert_eq!(htb.peek_unshaped().map(|ctx| ctx.flow_hash), Some(1));
+        assert_eq!(htb.next_cost(), Some(100));
+        assert_eq!(htb.eligible_at(), None); // 桶不补水，永远付不起
+
+        // 高优来了包就报高优的队头
+        htb.enqueue(test_packet(2, 0, 200));
+        assert_eq!(htb.peek_unshaped().map(|ctx| ctx.flow_hash), Some(2));
+        assert_eq!(htb.next_cost(), Some(200));
+        assert_eq!(htb.high_bucket.tokens(), 0.0);
+        assert_eq!(htb.low_bucket.tokens(), 0.0);
+        assert_eq!(htb.global_bucket.tokens(), 0.0);
+    }
+
+    #[test]
+    fn class_accessors_see_each_subtree() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.enqueue(test_packet(1, 0, 100));
+        assert!(htb.high().peek_ref().is_some());
+        assert!(htb.low().peek_ref().is_none());
+        // 直接往低优子树塞的包照样从根上出来
+        htb.low_mut().enqueue(test_packet(2, 1, 100));
+        assert_eq!(htb.high_mut().flush().len(), 1);
+        assert_eq!(htb.peek().map(|ctx| ctx.flow_hash), Some(2));
+    }
+
+    #[test]
+    fn committed_tier_also_charges_the_ceiling() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.set_high_committed(bucket(&clock, 1000.0));
+        htb.enqueue(test_packet(1, 0, 1000));
+
+        assert!(htb.peek().is_some());
+        assert!(matches!(htb.routed, Some((true, 0, Tier::Committed))));
+        htb.dequeue();
+        // 保底桶扣了，封顶的那只桶也得跟着扣
+        assert_eq!(htb.high_committed.as_ref().map(|b| b.tokens()), Some(0.0));
+        assert_eq!(htb.high_bucket.tokens(), 500.0);
+    }
+
+    #[test]
+    fn probing_buckets_records_no_denials() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.set_low_committed(bucket(&clock, 0.0)); // 保底桶是干的，每轮都会被问到
+        for _ in 0..3 {
+            htb.enqueue(test_packet(1, 1, 1000));
+        }
+        for _ in 0..3 {
+            assert!(htb.peek().is_some());
+            htb.dequeue();
+        }
+        for (name, stats) in htb.bucket_stats() {
+            assert_eq!(stats.deny_events, 0, "{name}");
+        }
+
+        // 低优自己的桶已经扣光，再借光全局桶就卡住了：真卡住了才记，而且一回只记一次
+        htb.global_bucket = bucket(&clock, 1000.0);
+        htb.enqueue(test_packet(1, 1, 1000));
+        htb.enqueue(test_packet(1, 1, 1000));
+        assert!(htb.peek().is_some());
+        htb.dequeue();
+        assert!(htb.peek().is_none());
+        assert!(htb.peek().is_none());
+        assert_eq!(htb.low_bucket.stats().deny_events, 1);
+        assert_eq!(htb.global_bucket.stats().deny_events, 1);
+        assert_eq!(htb.global_bucket.stats().bytes_denied, 1000);
+    }
+
+    #[test]
+    fn dequeue_takes_what_peek_picked() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.enqueue(test_packet(1, 0, 1000));
+        assert!(htb.peek().is_some());
+        // peek 之后桶被别人扣干了也照样提货，不再重新问一遍
+        htb.global_bucket.consume(10_000);
+        assert_eq!(htb.dequeue().map(|ctx| ctx.cost), Some(1000));
+        assert!(htb.routed.is_none());
+
+        // 入队、倒空都会把缓存作废
+        htb.enqueue(test_packet(1, 1, 100));
+        assert!(htb.peek().is_none());
+        htb.global_bucket = bucket(&clock, 10_000.0);
+        assert!(htb.peek().is_some());
+        htb.enqueue(test_packet(1, 1, 100));
+        assert!(htb.routed.is_none());
+        assert!(htb.peek().is_some());
+        assert_eq!(htb.flush().len(), 2);
+        assert!(htb.routed.is_none());
+        assert!(htb.dequeue().is_none());
+    }
+
+    #[test]
+    fn control_packets_bypass_a_dry_global_bucket_and_leave_debt() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        htb.global_bucket = bucket(&clock, 1000.0);
+        htb.set_control_bucket(bucket(&clock, 500.0));
+        htb.enqueue(test_packet(1, 1, 1000));
+        assert!(htb.peek().is_some());
+        htb.dequeue();
+
+        // 全局桶干了，数据包等着；SYN 走控制桶照样放，全局桶记成欠 60
+        htb.enqueue(test_packet(2, 1, 100));
+        let mut syn = test_packet(3, 1, 60);
+        syn.is_control = true;
+        htb.enqueue(syn);
+        assert!(htb.peek().is_none());
+
+        let mut syn = test_packet(4, 0, 60);
+        syn.is_control = true;
+        htb.enqueue(syn);
+        assert_eq!(htb.peek().map(|ctx| ctx.flow_hash), Some(4));
+        assert!(matches!(htb.routed, Some((true, 0, Tier::Control))));
+        htb.dequeue();
+        assert_eq!(htb.global_bucket.tokens(), -60.0);
+        assert!(htb.peek().is_none());
+    }
+
+    #[test]
+    fn reset_counters_reaches_monitors_under_both_classes() {
+        let clock = MockClock::new();
+        let mut tree: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(MonitorQdisc::new("High", Box::new(HeadDropFifo::new(8)))),
+            Box::new(MonitorQdisc::new("Low", Box::new(HeadDropFifo::new(8)))),
+            bucket(&clock, 1500.0),
+            bucket(&clock, 3000.0),
+            bucket(&clock, 10_000.0),
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        tree.enqueue(test_packet(1, 0, 100));
+        tree.enqueue(test_packet(2, 1, 200));
+        let totals = |htb: &HtbQdisc<Vec<u8>, u64, TokenBucket>| -> Vec<u64> {
+            monitor_reports(htb)
+                .iter()
+                .map(|(_, snap)| snap.totals.total_in)
+                .collect()
+        };
+        assert_eq!(totals(&tree), [1, 1]);
+
+        assert!(tree.apply_control(&ControlCommand::ResetCounters));
+        assert_eq!(totals(&tree), [0, 0]);
+
+        // 子树里没有监控就没人认领
+        assert!(!htb(&clock).apply_control(&ControlCommand::ResetCounters));
+    }
+
+    #[test]
+    fn global_and_class_buckets_drain_by_their_own_cost() {
+        // 整形后 1000 字节、线上 600 字节的包
+        let packet = || {
+            let mut ctx = test_packet(1, 0, 600);
+            ctx.cost = 1000;
+            ctx
+        };
+        let clock = MockClock::new();
+
+        let mut wire_global = htb(&clock);
+        wire_global.set_global_cost_fn(Box::new(|ctx| ctx.pkt_len));
+        wire_global.enqueue(packet());
+        assert!(wire_global.peek().is_some());
+        wire_global.dequeue();
+        assert_eq!(wire_global.high_bucket.tokens(), 500.0);
+        assert_eq!(wire_global.global_bucket.tokens(), 9400.0);
+
+        let mut wire_class = htb(&clock);
+        wire_class.set_class_cost_fn(Box::new(|ctx| ctx.pkt_len));
+        wire_class.enqueue(packet());
+        assert!(wire_class.peek().is_some());
+        wire_class.dequeue();
+        assert_eq!(wire_class.high_bucket.tokens(), 900.0);
+        assert_eq!(wire_class.global_bucket.tokens(), 9000.0);
+    }
+
+    #[test]
+    fn dry_queue_bucket_does_not_block_other_queues() {
+        let clock = MockClock::new();
+        let mut htb = htb(&clock);
+        // 入口队列 0 (高优) 的闸见底了，要 1 秒才攒回 1000；队列 1 (低优) 的闸宽松
+        let mut gate_a = TokenBucket::new(1000.0, 1000.0, "gate_a");
+        gate_a.set_clock(Box::new(clock.clone()));
+        gate_a.force_consume(1000);
+        htb.add_queue_bucket(&[0], gate_a);
+        htb.add_queue_bucket(&[1], bucket(&clock, 10_000.0));
+
+        htb.enqueue(test_packet(1, 0, 1000));
+        htb.enqueue(test_packet(2, 1, 1000));
+        htb.enqueue(test_packet(2, 1, 1000));
+
+        // 高优排在前面也付不起自己的闸：低优照走，不陪着等
+        for _ in 0..2 {
+            assert!(htb.peek().is_some());
+            assert_eq!(htb.dequeue().map(|ctx| ctx.queue_num), Some(1));
+        }
+        assert!(htb.peek().is_none());
+        assert_eq!(htb.queue_buckets.bucket_mut(0).unwrap().tokens(), 0.0);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(htb.peek().is_some());
+        assert_eq!(htb.dequeue().map(|ctx| ctx.queue_num), Some(0));
+    }
+
+    // 两类各 16 个包、桶都宽裕，看每类相邻两个包之间隔了几个位置 (头一个从开头算起) 的方差
+    fn gap_variance(interleave: bool) -> f64 {
+        let clock = MockClock::new();
+        let mut htb: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(HeadDropFifo::new(16)),
+            Box::new(HeadDropFifo::new(16)),
+            bucket(&clock, 100_000.0),
+            bucket(&clock, 100_000.0),
+            bucket(&clock, 100_000.0),
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        htb.set_interleave(interleave);
+        for _ in 0..16 {
+            htb.enqueue(test_packet(1, 0, 1000));
+            htb.enqueue(test_packet(2, 1, 1000));
+        }
+
+        let mut last = [0usize; 2];
+        let mut gaps = Vec::new();
+        for pos in 1..=32 {
+            assert!(htb.peek().is_some());
+            let class = htb.dequeue().unwrap().queue_num;
+            gaps.push((pos - last[class]) as f64);
+            last[class] = pos;
+        }
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        gaps.iter().map(|gap| (gap - mean).powi(2)).sum::<f64>() / gaps.len() as f64
+    }
+
+    #[test]
+    fn interleave_spreads_both_classes_evenly() {
+        // 严格优先级先倒空高优：低优头一个要等 17 个位置，后面又挤成一坨
+        let strict = gap_variance(false);
+        let interleaved = gap_variance(true);
+        assert!(interleaved < 0.1, "interleaved={interleaved}");
+        assert!(strict > 10.0 * interleaved.max(0.1), "strict={strict}");
+    }
+
+    // 全局桶 8000 B/s、只攒得下一个 1000 字节的包：理想的出口是每 125ms 整走一个
+    // tick = None 时照 pump 开了 TX pacing 的样子一放空就拨到 next_ready；否则按固定节拍轮询。返回每次放行的时刻
+    fn release_times(tick: Option<Duration>) -> Vec<Instant> {
+        let clock = MockClock::new();
+        let mut global = TokenBucket::new(8000.0, 1000.0, "global");
+        global.set_clock(Box::new(clock.clone()));
+        let mut htb: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(HeadDropFifo::new(16)),
+            Box::new(HeadDropFifo::new(16)),
+            bucket(&clock, 100_000.0),
+            bucket(&clock, 100_000.0),
+            global,
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        for _ in 0..8 {
+            htb.enqueue(test_packet(1, 1, 1000));
+        }
+
+        let mut released = Vec::new();
+        while released.len() < 8 {
+            if htb.peek().is_some() {
+                htb.dequeue();
+                released.push(clock.now());
+                continue;
+            }
+            match tick {
+                Some(tick) => clock.advance(tick),
+                None => {
+                    let at = htb.next_ready().expect("积着包却估不出放行时刻");
+                    assert!(at > clock.now());
+                    clock.set(at);
+                }
+            }
+        }
+        released
+    }
+
+    fn gaps(released: &[Instant]) -> Vec<Duration> {
+        released.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    #[test]
+    fn paced_releases_land_exactly_when_tokens_are_available() {
+        // 对齐令牌的出口：拨到 next_ready 就放得出，每个间隔都正好是 1000 / 8000 秒，一点抖动都没有
+        let paced = release_times(None);
+        for gap in gaps(&paced) {
+            assert_eq!(gap, Duration::from_millis(125));
+        }
+
+        // 对照组：30ms 一轮询，令牌 125ms 就够了也要等到 150ms 那一拍，桶又只攒得下一个包，
+        // 多等的那截令牌溢出作废：每个包都晚 25ms，出口只剩 5/6 的速率
+        let polled = release_times(Some(Duration::from_millis(30)));
+        for gap in gaps(&polled) {
+            assert_eq!(gap, Duration::from_millis(150));
+        }
+        assert!(polled[7] - polled[0] > paced[7] - paced[0]);
+    }
 }
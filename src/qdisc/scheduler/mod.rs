@@ -1,11 +1,17 @@
 mod class_drr_qdisc;
 mod dual_fair_qdisc;
+mod fair_qdisc;
+mod partition_qdisc;
 // mod prio_qdisc;
+mod shaper_qdisc;
 mod sparse_qdisc;
 mod htb_qdisc;
 
-pub use class_drr_qdisc::ClassDrrQdisc;
-pub use dual_fair_qdisc::DualFairQdisc;
+pub use class_drr_qdisc::{ClassDrrQdisc, QuantumScaling};
+pub use dual_fair_qdisc::{DualFairMode, DualFairQdisc};
+pub use fair_qdisc::FairQdisc;
+pub use partition_qdisc::PartitionQdisc;
 // pub use prio_qdisc::PrioQdisc;
+pub use shaper_qdisc::ShaperQdisc;
 pub use sparse_qdisc::SparseQdisc;
 pub use htb_qdisc::HtbQdisc;
\ No newline at end of file
@@ -1,3 +1,4 @@
+use crate::control::ControlCommand;
 use crate::packet_context::PacketContext;
 use crate::qdisc::Qdisc;
 
@@ -64,4 +65,24 @@ impl<T, K> Qdisc<T, K> for PrioQdisc<T, K> {
         drops.extend(self.low_qdisc.collect_dropped());
         drops
     }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.high_qdisc.flush();
+        all.extend(self.low_qdisc.flush());
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Prio(high: {}, low: {})",
+            self.high_qdisc.describe(),
+            self.low_qdisc.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        let high = self.high_qdisc.apply_control(cmd);
+        let low = self.low_qdisc.apply_control(cmd);
+        high || low
+    }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::control::ControlCommand;
+use crate::packet_context::PacketContext;
+use crate::qdisc::Qdisc;
+use crate::rate_estimator::RateEstimator;
+
+// 测速用的平滑时间常数：够快能跟上一条流起量，"持续超标多久" 交给 window 去判
+const RATE_TIME_CONSTANT: Duration = Duration::from_millis(100);
+
+// 每条流的超标 / 降级状态 (速率本身在 RateEstimator 里)
+struct FlowState {
+    demoted: bool,
+    over_since: Option<Instant>, // 从什么时候开始一直超标
+    calm_since: Option<Instant>, // 降级之后从什么时候开始一直没超标
+    last_seen: Instant,
+    slow_backlog: usize, // 慢车道里还排着几个包：没排空就升回去，新包会越过老包先走
+}
+
+// ==========================================
+// 大象流自动降级调度器 (Shaper Qdisc)
+// 不靠静态分类器：入口按 flow_hash 测每条流的速率，持续超过 threshold 满 window 就降级进慢车道，
+// 降级之后速率回落到阈值以下 (闲着不发也算) 满 cooldown、并且慢车道里它的包都走完了，再升回快车道
+// 和 SparseQdisc 一样快车道绝对优先；区别是它看的是速率，不是在队包数
+// ==========================================
+pub struct ShaperQdisc<T, K> {
+    fast_qdisc: Box<dyn Qdisc<T, K>>,
+    slow_qdisc: Box<dyn Qdisc<T, K>>,
+    estimator: RateEstimator,
+    flows: HashMap<u64, FlowState>,
+    threshold_bps: f64,
+    window: Duration,
+    cooldown: Duration,
+    packet_counter: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl<T, K> ShaperQdisc<T, K> {
+    pub fn new(
+        fast_qdisc: Box<dyn Qdisc<T, K>>,
+        slow_qdisc: Box<dyn Qdisc<T, K>>,
+        threshold_bps: f64,
+        window: Duration,
+        cooldown: Duration,
+    ) -> Self {
+        Self {
+            fast_qdisc,
+            slow_qdisc,
+            estimator: RateEstimator::new(RATE_TIME_CONSTANT, window + cooldown),
+            flows: HashMap::new(),
+            threshold_bps,
+            window,
+            cooldown,
+            packet_counter: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 记一笔速率，顺带推进这条流的降级 / 升级状态，返回它现在该不该走慢车道
+    fn demoted(&mut self, flow_hash: u64, bytes: usize, now: Instant) -> bool {
+        let rate = self.estimator.update_at(flow_hash, bytes, now);
+        let flow = self.flows.entry(flow_hash).or_insert(FlowState {
+            demoted: false,
+            over_since: None,
+            calm_since: None,
+            last_seen: now,
+            slow_backlog: 0,
+        });
+
+        if rate > self.threshold_bps {
+            flow.calm_since = None;
+            let since = *flow.over_since.get_or_insert(now);
+            if now.duration_since(since) >= self.window {
+                flow.demoted = true;
+            }
+        } else {
+            flow.over_since = None;
+            if flow.demoted {
+                // 速率是在上一个包和这个包之间掉下来的，从上一个包算起：闲着的那段也算冷静期
+                let since = *flow.calm_since.get_or_insert(flow.last_seen);
+                if now.duration_since(since) >= self.cooldown && flow.slow_backlog == 0 {
+                    flow.demoted = false;
+                    flow.calm_since = None;
+                }
+            }
+        }
+        flow.last_seen = now;
+        if flow.demoted {
+            flow.slow_backlog += 1;
+        }
+        flow.demoted
+    }
+
+    // 包离开慢车道 (出队或被收尸)
+    fn forget_slow(&mut self, flow_hash: u64) {
+        if let Some(flow) = self.flows.get_mut(&flow_hash) {
+            flow.slow_backlog = flow.slow_backlog.saturating_sub(1);
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for ShaperQdisc<T, K> {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+        let now = self.clock.now();
+        self.packet_counter += 1;
+
+        // 和 SparseQdisc 一样每 1024 个包清一次：闲到超标计时和冷静期都已经没意义的流整条忘掉
+        if self.packet_counter.is_multiple_of(1024) {
+            let idle = self.window.max(self.cooldown);
+            self.flows
+                .retain(|_, f| f.slow_backlog > 0 || now.duration_since(f.last_seen) < idle);
+        }
+
+        if self.demoted(ctx.flow_hash, ctx.pkt_len, now) {
+            self.slow_qdisc.enqueue(ctx);
+        } else {
+            self.fast_qdisc.enqueue(ctx);
+        }
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        if self.fast_qdisc.peek().is_some() {
+            self.fast_qdisc.peek()
+        } else {
+            self.slow_qdisc.peek()
+        }
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.fast_qdisc
+            .peek_ref()
+            .or_else(|| self.slow_qdisc.peek_ref())
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        if self.fast_qdisc.peek().is_some() {
+            return self.fast_qdisc.dequeue();
+        }
+        let ctx = self.slow_qdisc.dequeue()?;
+        self.forget_slow(ctx.flow_hash);
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        let _ = self.peek(); // 级联打扫
+        let mut drops = self.fast_qdisc.collect_dropped();
+        let slow_drops = self.slow_qdisc.collect_dropped();
+        for dead in &slow_drops {
+            self.forget_slow(dead.flow_hash);
+        }
+        drops.extend(slow_drops);
+        drops
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.collect_dropped();
+        all.extend(self.fast_qdisc.flush());
+        all.extend(self.slow_qdisc.flush());
+        self.flows.clear();
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Shaper(>{:.1} Mbit/s for {:?}, cooldown {:?}, fast: {}, slow: {})",
+            self.threshold_bps / 1_000_000.0,
+            self.window,
+            self.cooldown,
+            self.fast_qdisc.describe(),
+            self.slow_qdisc.describe()
+        )
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![
+            ("fast", self.fast_qdisc.as_ref()),
+            ("slow", self.slow_qdisc.as_ref()),
+        ]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![
+            ("fast", self.fast_qdisc.as_mut()),
+            ("slow", self.slow_qdisc.as_mut()),
+        ]
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        let fast = self.fast_qdisc.apply_control(cmd);
+        let slow = self.slow_qdisc.apply_control(cmd);
+        fast || slow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    // 送进一个包，看它落在哪条车道，再把它取走
+    fn lane_of_next(shaper: &mut ShaperQdisc<Vec<u8>, u64>) -> &'static str {
+        shaper.enqueue(test_packet(1, 0, 1500));
+        let lane = shaper
+            .children()
+            .into_iter()
+            .find(|(_, child)| child.peek_ref().is_some())
+            .map(|(name, _)| name)
+            .unwrap();
+        assert!(shaper.peek().is_some());
+        shaper.dequeue();
+        lane
+    }
+
+    #[test]
+    fn sustained_overrate_demotes_and_idle_promotes() {
+        let clock = MockClock::new();
+        let mut shaper: ShaperQdisc<Vec<u8>, u64> = ShaperQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            1_000_000.0,
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        );
+        shaper.set_clock(Box::new(clock.clone()));
+
+        // 每毫秒 1500 字节 = 12 Mbit/s，远超阈值；要连续超满 window 才降级
+        let mut lanes = Vec::new();
+        for _ in 0..150 {
+            lanes.push(lane_of_next(&mut shaper));
+            clock.advance(Duration::from_millis(1));
+        }
+        assert_eq!(lanes[0], "fast");
+        assert_eq!(lanes[50], "fast");
+        assert_eq!(lanes[149], "slow");
+
+        // 闲过 cooldown，速率早就跌下来了，下一个包回快车道
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(lane_of_next(&mut shaper), "fast");
+    }
+
+    #[test]
+    fn demoted_flow_stays_slow_until_its_backlog_drains() {
+        let clock = MockClock::new();
+        let mut shaper: ShaperQdisc<Vec<u8>, u64> = ShaperQdisc::new(
+            Box::new(HeadDropFifo::new(1000)),
+            Box::new(HeadDropFifo::new(1000)),
+            1_000_000.0,
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        );
+        shaper.set_clock(Box::new(clock.clone()));
+        // 12 Mbit/s 灌 150ms，只进不出，降级后的包压在慢车道里
+        for _ in 0..150 {
+            shaper.enqueue(test_packet(1, 0, 1500));
+            clock.advance(Duration::from_millis(1));
+        }
+        let slow_backlog = |shaper: &ShaperQdisc<Vec<u8>, u64>| shaper.flows[&1].slow_backlog;
+        assert!(slow_backlog(&shaper) > 0);
+
+        // 闲过了 cooldown，但慢车道里还有它的老包：新包照样进慢车道排在后面
+        clock.advance(Duration::from_millis(300));
+        let before = slow_backlog(&shaper);
+        shaper.enqueue(test_packet(1, 0, 1500));
+        assert_eq!(slow_backlog(&shaper), before + 1);
+        while shaper.peek().is_some() {
+            shaper.dequeue();
+        }
+        assert_eq!(slow_backlog(&shaper), 0);
+        assert_eq!(lane_of_next(&mut shaper), "fast");
+    }
+}
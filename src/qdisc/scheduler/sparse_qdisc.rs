@@ -1,11 +1,23 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
+use crate::clock::{Clock, SystemClock};
+use crate::control::ControlCommand;
 use crate::packet_context::PacketContext;
-use crate::qdisc::Qdisc;
+use crate::qdisc::{Qdisc, StateReader};
+
+// 每条流的计步 + 降级状态
+#[derive(Default)]
+struct FlowState {
+    in_flight: usize,            // 两个子队列里加起来还排着几个包
+    demoted: bool,               // 已经被打成大流，进苦力营
+    drained_at: Option<Instant>, // 大流排空的时刻，冷却期过了才能重新当稀疏流
+}
 
 // ==========================================
 // 智能稀疏流识别调度器 (完全泛型版)
+// 一条流在队里不超过 threshold 个包就算稀疏流走 VIP 通道，超了就降级成大流；
+// 降级之后哪怕回落到阈值以下也一直走苦力营 (滞回)，直到排空并闲置满 cooldown 才恢复
 // ==========================================
 pub struct SparseQdisc<T, K> {
     // 1. VIP 专用高速通道 (✅ 现在它也是一个泛型的 Qdisc 了！)
@@ -14,16 +26,81 @@ pub struct SparseQdisc<T, K> {
     // 2. 苦力营 (底层平民队列)
     bulk_qdisc: Box<dyn Qdisc<T, K>>,
 
-    // 3. 全知计步器
-    flow_counts: HashMap<K, usize>,
+    // 3. 全知计步器 (按 flow_hash)
+    flows: HashMap<u64, FlowState>,
+    threshold: usize,
+    cooldown: Duration,
+    packet_counter: u64,
+    clock: Box<dyn Clock>,
+
+    // 4. 从快照里恢复的 "老熟人"：重启前还在排队的流，重启后第一个包直接进苦力营，
+    //    之后照常计数 (计数不能原样灌回去，那些包已经不在队里了，计数永远减不到 0)
+    warm_flows: HashSet<u64>,
 }
 
 impl<T, K> SparseQdisc<T, K> {
+    // 默认：队里只要有一个包没走，后面的包就进苦力营；排空立刻恢复
     pub fn new(sparse_qdisc: Box<dyn Qdisc<T, K>>, bulk_qdisc: Box<dyn Qdisc<T, K>>) -> Self {
+        Self::new_with(sparse_qdisc, bulk_qdisc, 1, Duration::ZERO)
+    }
+
+    pub fn new_with(
+        sparse_qdisc: Box<dyn Qdisc<T, K>>,
+        bulk_qdisc: Box<dyn Qdisc<T, K>>,
+        threshold: usize,
+        cooldown: Duration,
+    ) -> Self {
         Self {
             sparse_qdisc,
             bulk_qdisc,
-            flow_counts: HashMap::new(),
+            flows: HashMap::new(),
+            threshold: threshold.max(1),
+            cooldown,
+            packet_counter: 0,
+            clock: Box::new(SystemClock),
+            warm_flows: HashSet::new(),
+        }
+    }
+
+    // 两条车道各自的子树，构造完之后查积压、调参用；分流状态不受影响
+    #[allow(dead_code)]
+    pub fn sparse(&self) -> &dyn Qdisc<T, K> {
+        self.sparse_qdisc.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn sparse_mut(&mut self) -> &mut dyn Qdisc<T, K> {
+        self.sparse_qdisc.as_mut()
+    }
+
+    #[allow(dead_code)]
+    pub fn bulk(&self) -> &dyn Qdisc<T, K> {
+        self.bulk_qdisc.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn bulk_mut(&mut self) -> &mut dyn Qdisc<T, K> {
+        self.bulk_qdisc.as_mut()
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 包离开 (出队或被收尸)：计步减一，排空的稀疏流直接忘掉，排空的大流开始计冷却
+    fn forget(&mut self, flow_hash: u64) {
+        let Some(flow) = self.flows.get_mut(&flow_hash) else {
+            return;
+        };
+        flow.in_flight = flow.in_flight.saturating_sub(1);
+        if flow.in_flight > 0 {
+            return;
+        }
+        if flow.demoted && !self.cooldown.is_zero() {
+            flow.drained_at = Some(self.clock.now());
+        } else {
+            self.flows.remove(&flow_hash);
         }
     }
 }
@@ -32,19 +109,39 @@ impl<T, K> SparseQdisc<T, K> {
 // 实现统一的 Qdisc 接口
 // ✅ 参数变成了元组 (SparseParam, BulkParam)，分别喂给两个底层
 // ==========================================
-impl<T, K: Hash + Eq + Clone> Qdisc<T, K> for SparseQdisc<T, K> {
+impl<T, K> Qdisc<T, K> for SparseQdisc<T, K> {
     fn enqueue(&mut self, ctx: PacketContext<T, K>) {
-        let key = ctx.key.clone();
-        let count = self.flow_counts.get(&key).copied().unwrap_or(0);
+        let now = self.clock.now();
+        self.packet_counter += 1;
 
-        if count == 0 {
-            self.sparse_qdisc.enqueue(ctx);
-            self.flow_counts.insert(key, 1);
-        } else {
+        // 和 TcpAckFilterQdisc 一样，每 1024 个包顺手清理一次冷却期已过的空流
+        if self.packet_counter.is_multiple_of(1024) {
+            let cooldown = self.cooldown;
+            let cooling = |t: Instant| now.duration_since(t) < cooldown;
+            self.flows
+                .retain(|_, f| f.in_flight > 0 || f.drained_at.is_some_and(cooling));
+        }
+
+        let warm = self.warm_flows.remove(&ctx.flow_hash);
+        let flow = self.flows.entry(ctx.flow_hash).or_default();
+        if warm {
+            flow.demoted = true;
+        }
+        if let Some(drained_at) = flow.drained_at.take()
+            && now.duration_since(drained_at) >= self.cooldown
+        {
+            flow.demoted = false; // 闲够了，恢复稀疏流身份
+        }
+
+        flow.in_flight += 1;
+        if flow.in_flight > self.threshold {
+            flow.demoted = true;
+        }
+
+        if flow.demoted {
             self.bulk_qdisc.enqueue(ctx);
-            if let Some(c) = self.flow_counts.get_mut(&key) {
-                *c += 1;
-            }
+        } else {
+            self.sparse_qdisc.enqueue(ctx);
         }
     }
 
@@ -66,14 +163,7 @@ impl<T, K: Hash + Eq + Clone> Qdisc<T, K> for SparseQdisc<T, K> {
 
         // 安全扣减计步器
         if let Some(ctx) = &ctx_opt {
-            if let Some(count) = self.flow_counts.get_mut(&ctx.key) {
-                if *count > 0 {
-                    *count -= 1;
-                }
-                if *count == 0 {
-                    self.flow_counts.remove(&ctx.key);
-                }
-            }
+            self.forget(ctx.flow_hash);
         }
         ctx_opt
     }
@@ -85,15 +175,207 @@ impl<T, K: Hash + Eq + Clone> Qdisc<T, K> for SparseQdisc<T, K> {
 
         // 清理死包的计步器
         for dead in &drops {
-            if let Some(count) = self.flow_counts.get_mut(&dead.key) {
-                if *count > 0 {
-                    *count -= 1;
-                }
-                if *count == 0 {
-                    self.flow_counts.remove(&dead.key);
-                }
-            }
+            self.forget(dead.flow_hash);
         }
         drops
     }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = self.sparse_qdisc.flush();
+        all.extend(self.bulk_qdisc.flush());
+        self.flows.clear();
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Sparse(≤{}, cooldown {:?}, sparse: {}, bulk: {})",
+            self.threshold,
+            self.cooldown,
+            self.sparse_qdisc.describe(),
+            self.bulk_qdisc.describe()
+        )
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![
+            ("sparse", self.sparse_qdisc.as_ref()),
+            ("bulk", self.bulk_qdisc.as_ref()),
+        ]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![
+            ("sparse", self.sparse_qdisc.as_mut()),
+            ("bulk", self.bulk_qdisc.as_mut()),
+        ]
+    }
+
+    // [流数 u32] + 每条流 [flow_hash u64]，只记哪些流是大流，不记个数
+    fn save_state(&self) -> Vec<u8> {
+        let flows: Vec<u64> = self
+            .flows
+            .iter()
+            .filter(|(_, f)| f.demoted || f.in_flight > 0)
+            .map(|(&hash, _)| hash)
+            .chain(self.warm_flows.iter().copied())
+            .collect();
+        let mut state = Vec::with_capacity(4 + flows.len() * 8);
+        state.extend_from_slice(&(flows.len() as u32).to_le_bytes());
+        for hash in flows {
+            state.extend_from_slice(&hash.to_le_bytes());
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let mut reader = StateReader::new(state);
+        let mut flows = HashSet::new();
+        let Some(n) = reader.u32() else { return };
+        for _ in 0..n {
+            let Some(hash) = reader.u64() else { return };
+            flows.insert(hash);
+        }
+        self.warm_flows = flows;
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        let sparse = self.sparse_qdisc.apply_control(cmd);
+        let bulk = self.bulk_qdisc.apply_control(cmd);
+        sparse || bulk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo,
+        qdisc::wrapper::TcpAckFilterQdisc,
+    };
+
+    // 刚进来的那个包落在哪条车道
+    fn lane_of_next(sparse: &mut SparseQdisc<Vec<u8>, u64>) -> &'static str {
+        sparse.enqueue(test_packet(1, 0, 100));
+        let lane = sparse
+            .children()
+            .into_iter()
+            .find(|(_, child)| child.peek_ref().is_some())
+            .map(|(name, _)| name)
+            .unwrap();
+        assert!(sparse.peek().is_some());
+        sparse.dequeue();
+        lane
+    }
+
+    #[test]
+    fn demoted_flow_recovers_after_cooldown() {
+        let clock = MockClock::new();
+        let mut sparse: SparseQdisc<Vec<u8>, u64> = SparseQdisc::new_with(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            1,
+            Duration::from_millis(100),
+        );
+        sparse.set_clock(Box::new(clock.clone()));
+
+        // 两个包同时排着，超过阈值，流被降级
+        sparse.enqueue(test_packet(1, 0, 100));
+        sparse.enqueue(test_packet(1, 0, 100));
+        while sparse.peek().is_some() {
+            sparse.dequeue();
+        }
+
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(lane_of_next(&mut sparse), "bulk"); // 还在冷却，重新计时
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(lane_of_next(&mut sparse), "bulk");
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(lane_of_next(&mut sparse), "sparse");
+    }
+
+    // 两条车道各排着几个包
+    fn lanes(sparse: &mut SparseQdisc<Vec<u8>, u64>) -> (usize, usize) {
+        let backlog = |lane: &mut dyn Qdisc<Vec<u8>, u64>| {
+            (0..).take_while(|&n| lane.peek_nth(n).is_some()).count()
+        };
+        (backlog(sparse.sparse_mut()), backlog(sparse.bulk_mut()))
+    }
+
+    #[test]
+    fn hysteresis_demotes_above_threshold_and_promotes_after_idle_cooldown() {
+        let clock = MockClock::new();
+        let mut sparse: SparseQdisc<Vec<u8>, u64> = SparseQdisc::new_with(
+            Box::new(HeadDropFifo::new(16)),
+            Box::new(HeadDropFifo::new(16)),
+            3,
+            Duration::from_millis(100),
+        );
+        sparse.set_clock(Box::new(clock.clone()));
+
+        // 在队 3 个正好压线：走一个来一个，来回晃也一直是稀疏流
+        for _ in 0..3 {
+            sparse.enqueue(test_packet(1, 0, 100));
+        }
+        for _ in 0..5 {
+            assert!(sparse.peek().is_some());
+            sparse.dequeue();
+            sparse.enqueue(test_packet(1, 0, 100));
+        }
+        assert_eq!(lanes(&mut sparse), (3, 0));
+
+        // 第 4 个超线：降级
+        sparse.enqueue(test_packet(1, 0, 100));
+        assert_eq!(lanes(&mut sparse), (3, 1));
+
+        // 回落到阈值以下也不升回去，没排空就一直走苦力营
+        for _ in 0..3 {
+            assert!(sparse.peek().is_some());
+            sparse.dequeue();
+        }
+        sparse.enqueue(test_packet(1, 0, 100));
+        assert_eq!(lanes(&mut sparse), (0, 2));
+
+        // 排空并闲满冷却期：恢复稀疏流身份，又能攒 3 个再降级
+        while sparse.peek().is_some() {
+            sparse.dequeue();
+        }
+        clock.advance(Duration::from_millis(100));
+        for _ in 0..3 {
+            sparse.enqueue(test_packet(1, 0, 100));
+        }
+        assert_eq!(lanes(&mut sparse), (3, 0));
+        sparse.enqueue(test_packet(1, 0, 100));
+        assert_eq!(lanes(&mut sparse), (3, 1));
+    }
+
+    #[test]
+    fn accessors_reach_the_bulk_leaf_through_a_wrapper() {
+        let bulk: Box<dyn Qdisc<Vec<u8>, u64>> = Box::new(HeadDropFifo::new(8));
+        let bulk_addr = std::ptr::from_ref(bulk.as_ref());
+        let mut sparse = SparseQdisc::new(Box::new(HeadDropFifo::new(8)), bulk);
+        assert!(std::ptr::addr_eq(sparse.bulk(), bulk_addr));
+
+        // 第二个包超过阈值降级进苦力营：从 bulk() 看得到它，sparse() 那边只有头一个
+        sparse.enqueue(test_packet(1, 0, 100));
+        sparse.enqueue(test_packet(1, 0, 200));
+        assert_eq!(sparse.bulk().peek_ref().map(|ctx| ctx.cost), Some(200));
+        assert_eq!(sparse.sparse().peek_ref().map(|ctx| ctx.cost), Some(100));
+        assert_eq!(sparse.bulk_mut().flush().len(), 1);
+        assert!(sparse.sparse_mut().peek_ref().is_some());
+
+        // 套一层 ACK 过滤，inner() 拿到的还是原来那个 SparseQdisc，顺着往下就是同一个叶子
+        let sparse: Box<dyn Qdisc<Vec<u8>, u64>> = Box::new(sparse);
+        let sparse_addr = std::ptr::from_ref(sparse.as_ref());
+        let mut filter = TcpAckFilterQdisc::new(sparse);
+        assert!(std::ptr::addr_eq(filter.inner(), sparse_addr));
+        let (_, leaf) = filter
+            .inner()
+            .children()
+            .into_iter()
+            .find(|(name, _)| *name == "bulk")
+            .unwrap();
+        assert!(std::ptr::addr_eq(leaf, bulk_addr));
+        assert!(filter.inner_mut().peek().is_some());
+    }
 }
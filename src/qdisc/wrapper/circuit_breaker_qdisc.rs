@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::control::ControlCommand;
+use crate::packet_context::PacketContext;
+use crate::qdisc::Qdisc;
+
+// ==========================================
+// 熔断器 (Circuit Breaker Qdisc)
+// 整棵树积压超过 high_water 并且持续了 hold 这么久，说明整形已经严重跟不上：
+// 与其让延迟无限涨到连接断掉，不如先不整形，新来的包进直通车道，下一轮出队就放行；
+// 积压回落到 low_water 以下才合闸恢复整形 (两道水位之间有滞回，不会来回抖)
+// 熔断期间同一条流的新包会越过它还在树里排队的老包，乱序是换不卡死的代价
+// 一般套在最外层，积压只按这一层看得到的 cost 记账，不含直通车道
+// ==========================================
+pub struct CircuitBreakerQdisc<T, K> {
+    inner: Box<dyn Qdisc<T, K>>,
+    bypass: VecDeque<PacketContext<T, K>>, // 熔断期间的直通车道，永远先出
+    backlog_bytes: usize,                  // inner 里还压着多少字节
+    high_water: usize,
+    low_water: usize,
+    hold: Duration,
+    over_since: Option<Instant>, // 从什么时候开始一直超过 high_water
+    tripped: bool,
+    trips: u64,    // 累计熔断次数
+    bypassed: u64, // 累计走直通车道的包数
+    clock: Box<dyn Clock>,
+}
+
+impl<T, K> CircuitBreakerQdisc<T, K> {
+    pub fn new(
+        high_water: usize,
+        low_water: usize,
+        hold: Duration,
+        inner: Box<dyn Qdisc<T, K>>,
+    ) -> Self {
+        Self {
+            inner,
+            bypass: VecDeque::new(),
+            backlog_bytes: 0,
+            high_water,
+            low_water: low_water.min(high_water),
+            hold,
+            over_since: None,
+            tripped: false,
+            trips: 0,
+            bypassed: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 按当前积压推进开关状态，跳闸 / 合闸时各打一行
+    fn update(&mut self) {
+        if self.tripped {
+            if self.backlog_bytes <= self.low_water {
+                self.tripped = false;
+                println!(
+                    "🔌 熔断恢复：积压回落到 {:.1}KB，本次直通 {} 个包",
+                    self.backlog_bytes as f64 / 1024.0,
+                    self.bypassed
+                );
+            }
+            return;
+        }
+        if self.backlog_bytes <= self.high_water {
+            self.over_since = None;
+            return;
+        }
+        let now = self.clock.now();
+        let since = *self.over_since.get_or_insert(now);
+        if now.saturating_duration_since(since) >= self.hold {
+            self.tripped = true;
+            self.over_since = None;
+            self.trips += 1;
+            self.bypassed = 0;
+            println!(
+                "⚡ 熔断 (第 {} 次)：积压 {:.1}KB 超过 {:.1}KB 已 {:?}，暂停整形直接放行",
+                self.trips,
+                self.backlog_bytes as f64 / 1024.0,
+                self.high_water as f64 / 1024.0,
+                self.hold
+            );
+        }
+    }
+
+    fn forget(&mut self, cost: usize) {
+        self.backlog_bytes = self.backlog_bytes.saturating_sub(cost);
+    }
+}
+
+impl<T, K> Qdisc<T, K> for CircuitBreakerQdisc<T, K> {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+        self.update();
+        if self.tripped {
+            self.bypassed += 1;
+            self.bypass.push_back(ctx);
+            return;
+        }
+        self.backlog_bytes += ctx.cost;
+        self.inner.enqueue(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        if !self.bypass.is_empty() {
+            return self.bypass.front();
+        }
+        self.inner.peek()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.bypass.front().or_else(|| self.inner.peek_ref())
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        if let Some(ctx) = self.bypass.pop_front() {
+            return Some(ctx);
+        }
+        let ctx = self.inner.dequeue()?;
+        self.forget(ctx.cost);
+        self.update();
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        let drops = self.inner.collect_dropped();
+        for ctx in &drops {
+            self.forget(ctx.cost);
+        }
+        if !drops.is_empty() {
+            self.update();
+        }
+        drops
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all: Vec<_> = self.bypass.drain(..).collect();
+        all.extend(self.inner.flush());
+        self.backlog_bytes = 0;
+        self.over_since = None;
+        self.tripped = false;
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "CircuitBreaker({:.0}KB/{:.0}KB for {:?}, {})",
+            self.high_water as f64 / 1024.0,
+            self.low_water as f64 / 1024.0,
+            self.hold,
+            self.inner.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    #[test]
+    fn trips_after_hold_and_closes_below_low_water() {
+        let clock = MockClock::new();
+        let mut breaker: CircuitBreakerQdisc<Vec<u8>, u64> = CircuitBreakerQdisc::new(
+            2000,
+            500,
+            Duration::from_millis(50),
+            Box::new(HeadDropFifo::new(16)),
+        );
+        breaker.set_clock(Box::new(clock.clone()));
+        for _ in 0..4 {
+            breaker.enqueue(test_packet(1, 0, 1000)); // 第 4 个进来时开始计超标
+        }
+
+        clock.advance(Duration::from_millis(49));
+        breaker.enqueue(test_packet(2, 0, 200));
+        assert_eq!(breaker.peek().map(|ctx| ctx.cost), Some(1000));
+
+        clock.advance(Duration::from_millis(1));
+        breaker.enqueue(test_packet(2, 0, 300)); // 跳闸：走直通车道，越过排队的老包
+        assert_eq!(breaker.peek().map(|ctx| ctx.cost), Some(300));
+        breaker.dequeue();
+
+        // 积压掉到 low_water 以下才合闸，之后的包重新进树排队
+        while breaker.peek().is_some_and(|ctx| ctx.cost == 1000) {
+            breaker.dequeue();
+        }
+        breaker.enqueue(test_packet(2, 0, 400));
+        let costs: Vec<usize> = std::iter::from_fn(|| {
+            breaker.peek()?;
+            breaker.dequeue()
+        })
+        .map(|ctx| ctx.cost)
+        .collect();
+        assert_eq!(costs, vec![200, 400]);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::control::ControlCommand;
+use crate::packet_context::PacketContext;
+use crate::qdisc::Qdisc;
+
+// 当前 "拼车" 中的帧：哪条流开的头、已经装了多少字节
+struct OpenFrame {
+    flow_hash: u64,
+    bytes: usize,
+}
+
+// ==========================================
+// 小包拼帧记账器 (Coalesce Qdisc)
+// WG 隧道里同一条流紧挨着的一串小包，每个都按 OverheadModifier 交一整份帧开销，
+// 整形就算得比实际更悲观；这里把排在一起、同流、装得下一帧的小包合并记账：
+// 跟车的包退掉那份帧开销、不再多算一帧。真正的字节不动，内核照样一个个发
+// "排在一起" 按入队顺序算，所以 inner 应该是 FIFO 之类不乱序的叶子
+// ==========================================
+pub struct CoalesceQdisc<T, K> {
+    inner: Box<dyn Qdisc<T, K>>,
+    overhead_bytes: usize,  // 每帧开销，要和前面 OverheadModifier 的配置一致
+    max_frame_bytes: usize, // 一帧最多装多少真实字节 (一般就是隧道 MTU)
+    open: Option<OpenFrame>,
+    queued: usize, // inner 里还有几个包，空了就不能再往已经发走的帧上拼
+}
+
+impl<T, K> CoalesceQdisc<T, K> {
+    pub fn new(overhead_bytes: usize, max_frame_bytes: usize, inner: Box<dyn Qdisc<T, K>>) -> Self {
+        Self {
+            inner,
+            overhead_bytes,
+            max_frame_bytes,
+            open: None,
+            queued: 0,
+        }
+    }
+
+    fn forget(&mut self, n: usize) {
+        self.queued = self.queued.saturating_sub(n);
+        if self.queued == 0 {
+            self.open = None;
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for CoalesceQdisc<T, K> {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        // 本身就要分片的大包不参与拼车，也会打断前面那一帧
+        let small = ctx.frames <= 1 && ctx.pkt_len < self.max_frame_bytes;
+
+        let joins = small
+            && self.queued > 0
+            && self.open.as_ref().is_some_and(|f| {
+                f.flow_hash == ctx.flow_hash && f.bytes + ctx.pkt_len <= self.max_frame_bytes
+            });
+
+        if joins {
+            if let Some(frame) = self.open.as_mut() {
+                frame.bytes += ctx.pkt_len;
+            }
+            ctx.cost = ctx
+                .cost
+                .saturating_sub(self.overhead_bytes)
+                .max(ctx.pkt_len);
+            ctx.frames = 0;
+        } else if small {
+            self.open = Some(OpenFrame {
+                flow_hash: ctx.flow_hash,
+                bytes: ctx.pkt_len,
+            });
+        } else {
+            self.open = None;
+        }
+
+        self.queued += 1;
+        self.inner.enqueue(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek_ref()
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        let ctx = self.inner.dequeue()?;
+        self.forget(1);
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        let drops = self.inner.collect_dropped();
+        self.forget(drops.len());
+        drops
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        self.queued = 0;
+        self.open = None;
+        self.inner.flush()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Coalesce({}B/{}B, {})",
+            self.overhead_bytes,
+            self.max_frame_bytes,
+            self.inner.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::leaf::HeadDropFifo;
+
+    const OVERHEAD: usize = 60;
+
+    // OverheadModifier 盖过的样子：cost 里已经带着一整份帧开销
+    fn small(flow_hash: u64, len: usize) -> PacketContext<Vec<u8>, u64> {
+        let mut ctx = test_packet(flow_hash, 0, len);
+        ctx.cost += OVERHEAD;
+        ctx
+    }
+
+    fn drain(qdisc: &mut CoalesceQdisc<Vec<u8>, u64>) -> Vec<(usize, usize)> {
+        let mut out = Vec::new();
+        while qdisc.peek().is_some() {
+            let ctx = qdisc.dequeue().unwrap();
+            out.push((ctx.cost, ctx.frames));
+        }
+        out
+    }
+
+    #[test]
+    fn back_to_back_small_packets_share_one_frame_overhead() {
+        let mut qdisc = CoalesceQdisc::new(OVERHEAD, 1420, Box::new(HeadDropFifo::new(16)));
+        for _ in 0..4 {
+            qdisc.enqueue(small(1, 300));
+        }
+        // 头一个交整份开销，后面三个跟车只算自己的字节：1440 -> 1260
+        let sent = drain(&mut qdisc);
+        assert_eq!(sent, [(360, 1), (300, 0), (300, 0), (300, 0)]);
+        assert_eq!(sent.iter().map(|&(cost, _)| cost).sum::<usize>(), 1260);
+
+        // 插进来一条别的流、或者一帧装不下了，就另开一帧照交开销
+        for (flow, len) in [(1, 300), (2, 300), (2, 1000), (2, 300)] {
+            qdisc.enqueue(small(flow, len));
+        }
+        assert_eq!(drain(&mut qdisc), [(360, 1), (360, 1), (1000, 0), (360, 1)]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::control::ControlCommand;
+use crate::packet_context::PacketContext;
+use crate::qdisc::Qdisc;
+
+// ==========================================
+// 流内保序 (Flow Order Qdisc)
+// 里面的调度器照样在流与流之间自由排班，但同一条流 (flow_hash) 的包一定按到达顺序出去：
+// inner 先吐出来的包要是前面还有同流的包没走，就先扣在手里 (令牌已经付过了)，
+// 等前面的包出来 / 在 inner 里被丢掉，再按顺序接着放
+// 顺序按 arrival_time 认，同一条流时刻相同的两个包谁先走都算对
+// 扣着的包按到达时刻排好，一条流卡住几千个包时放行 / 划账也只是查一次表
+// ==========================================
+pub struct FlowOrderQdisc<T, K> {
+    inner: Box<dyn Qdisc<T, K>>,
+    outstanding: HashMap<u64, VecDeque<Instant>>, // 每条流还没放走的包的到达时刻，按到达顺序
+    held: HashMap<u64, Held<T, K>>,               // 已经从 inner 出来、但前面还有同流包没走的
+    ready: VecDeque<PacketContext<T, K>>,         // 排好顺序、下次出队直接给的
+    pending_drops: Vec<PacketContext<T, K>>,
+}
+
+// 一条流扣着的包，按到达时刻排 (同一时刻可能不止一个)
+type Held<T, K> = BTreeMap<Instant, Vec<PacketContext<T, K>>>;
+
+impl<T, K> FlowOrderQdisc<T, K> {
+    pub fn new(inner: Box<dyn Qdisc<T, K>>) -> Self {
+        Self {
+            inner,
+            outstanding: HashMap::new(),
+            held: HashMap::new(),
+            ready: VecDeque::new(),
+            pending_drops: Vec::new(),
+        }
+    }
+
+    // inner 刚吐出来的包：轮到它了就放进 ready (顺带放出扣着的后继)，没轮到就扣下，返回 false
+    fn accept(&mut self, ctx: PacketContext<T, K>) -> bool {
+        let flow = ctx.flow_hash;
+        let front = self.outstanding.get(&flow).and_then(|q| q.front());
+        if front.is_some_and(|&at| at != ctx.arrival_time) {
+            self.held
+                .entry(flow)
+                .or_default()
+                .entry(ctx.arrival_time)
+                .or_default()
+                .push(ctx);
+            return false;
+        }
+        self.ready.push_back(ctx);
+        self.advance(flow);
+        true
+    }
+
+    // 流头走了一个：把它从账上划掉，再看新的流头是不是已经扣在手里
+    fn advance(&mut self, flow: u64) {
+        let Some(queue) = self.outstanding.get_mut(&flow) else {
+            return;
+        };
+        queue.pop_front();
+        while let Some(&next) = queue.front() {
+            let Some(held) = self.held.get_mut(&flow) else {
+                break;
+            };
+            let Some(same_time) = held.get_mut(&next) else {
+                break;
+            };
+            if let Some(ctx) = same_time.pop() {
+                self.ready.push_back(ctx);
+            }
+            if same_time.is_empty() {
+                held.remove(&next);
+            }
+            queue.pop_front();
+        }
+        if queue.is_empty() {
+            self.outstanding.remove(&flow);
+        }
+        if self.held.get(&flow).is_some_and(|h| h.is_empty()) {
+            self.held.remove(&flow);
+        }
+    }
+
+    // inner 丢掉的包永远不会出来了：从账上划掉，不然同流后面的包会一直扣着
+    fn absorb_drops(&mut self) {
+        for ctx in self.inner.collect_dropped() {
+            let flow = ctx.flow_hash;
+            // 账上按到达时刻有序，二分找到它
+            if let Some(queue) = self.outstanding.get_mut(&flow)
+                && let pos = queue.partition_point(|&at| at < ctx.arrival_time)
+                && queue.get(pos) == Some(&ctx.arrival_time)
+            {
+                if pos == 0 {
+                    // 死的是流头：借 advance 划账，顺便放出已经扣着的后继
+                    self.advance(flow);
+                } else {
+                    queue.remove(pos);
+                }
+            }
+            self.pending_drops.push(ctx);
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for FlowOrderQdisc<T, K> {
+    fn enqueue(&mut self, ctx: PacketContext<T, K>) {
+        self.outstanding
+            .entry(ctx.flow_hash)
+            .or_default()
+            .push_back(ctx.arrival_time);
+        self.inner.enqueue(ctx);
+        self.absorb_drops(); // 入队当场挤掉的包也得划账
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        loop {
+            if !self.ready.is_empty() {
+                return self.ready.front();
+            }
+            self.inner.peek()?;
+            let ctx = self.inner.dequeue()?;
+            // 被扣下多半是流头刚在 inner 里死了、还没报上来：这时才去收一次尸
+            // (每次 peek 都收太贵，inner 的 collect_dropped 往往要把整棵子树 peek 一遍)
+            if !self.accept(ctx) {
+                self.absorb_drops();
+            }
+        }
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.ready.front().or_else(|| self.inner.peek_ref())
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        self.ready.pop_front()
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        self.absorb_drops();
+        std::mem::take(&mut self.pending_drops)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all: Vec<_> = self.ready.drain(..).collect();
+        all.extend(
+            self.held
+                .drain()
+                .flat_map(|(_, held)| held.into_values().flatten()),
+        );
+        all.append(&mut self.pending_drops);
+        all.extend(self.inner.flush());
+        self.outstanding.clear();
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!("FlowOrder({})", self.inner.describe())
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::{LifoQdisc, QdiscExt};
+    use std::time::Duration;
+
+    #[test]
+    fn flows_leave_in_arrival_order_through_a_reordering_inner() {
+        let mut order = FlowOrderQdisc::new(Box::new(LifoQdisc::new()));
+        let start = clock::now();
+        // 三条流交错到达，包长记序号；里面后进先出，原样放出来每条流都是倒着的
+        for (i, flow) in [1, 2, 1, 3, 2, 1, 3, 2].into_iter().enumerate() {
+            let mut ctx = test_packet(flow, 0, 100 + i);
+            ctx.arrival_time = start + Duration::from_millis(i as u64);
+            order.enqueue(ctx);
+        }
+        let out: Vec<(u64, usize)> = order
+            .drain_ready()
+            .map(|ctx| (ctx.flow_hash, ctx.cost))
+            .collect();
+        assert_eq!(out.len(), 8);
+        for flow in [1, 2, 3] {
+            let costs: Vec<usize> = out
+                .iter()
+                .filter(|&&(f, _)| f == flow)
+                .map(|&(_, cost)| cost)
+                .collect();
+            assert!(costs.is_sorted(), "流 {flow} 乱序: {costs:?}");
+        }
+        // 流与流之间的先后还是 inner 说了算：流 3 的头包最先被后进先出翻到，连着放出它扣着的后继
+        assert_eq!(out[..2], [(3, 103), (3, 106)]);
+        assert!(order.outstanding.is_empty() && order.held.is_empty());
+    }
+}
@@ -1,9 +1,19 @@
+mod circuit_breaker_qdisc;
+mod coalesce_qdisc;
+mod flow_order_qdisc;
 mod monitor_qdisc;
+mod new_flow_grace_qdisc;
 // mod rate_limit_qdisc;
+mod sfb_qdisc;
 mod tcp_ack_filter_qdisc;
 mod ttl_drop_wrapper;
 
-pub use monitor_qdisc::MonitorQdisc;
+pub use circuit_breaker_qdisc::CircuitBreakerQdisc;
+pub use coalesce_qdisc::CoalesceQdisc;
+pub use flow_order_qdisc::FlowOrderQdisc;
+pub use monitor_qdisc::{DropEvent, MonitorQdisc, MonitorSnapshot};
+pub use new_flow_grace_qdisc::NewFlowGraceQdisc;
 // pub use rate_limit_qdisc::RateLimitQdisc;
+pub use sfb_qdisc::SfbQdisc;
 pub use tcp_ack_filter_qdisc::TcpAckFilterQdisc;
 pub use ttl_drop_wrapper::TtlDropWrapper;
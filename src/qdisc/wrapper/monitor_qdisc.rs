@@ -1,24 +1,116 @@
 use chrono::Local;
 use std::collections::HashMap;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
 use std::time::{Duration, Instant};
 
-use crate::packet_context::PacketContext;
-use crate::qdisc::Qdisc;
+use crate::clock;
+use crate::control::ControlCommand;
+use crate::packet_context::{DropReason, PacketContext};
+use crate::qdisc::{Qdisc, bucket_breakdown, monitor_reports};
+
+// 每条流上一个出队包的到达时刻记这么久，过了就忘掉 (没有哪个包能在树里排这么久)
+const REORDER_MEMORY: Duration = Duration::from_secs(5);
 
 // ==========================================
 // 1. 升维的队列统计表 (速率 + 积压水位)
 // ==========================================
-#[derive(Default)]
-struct QueueStats {
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
     // 📈 瞬时速率 (每秒清零)
-    in_pkts: u64,
-    drop_pkts: u64,
-    out_pkts: u64,
-    out_bytes: f64,
+    pub in_pkts: u64,
+    pub drop_pkts: u64,
+    pub out_pkts: u64,
+    pub out_bytes: f64,      // 按 cost 算的整形字节 (含开销 / 填充等记账)
+    pub out_wire_bytes: f64, // 按 pkt_len 算的线上真实字节，对得上 iftop
+    pub out_est_bytes: f64,  // 出队字节里 cost 属于估算值的部分
+    pub reorder_pkts: u64,   // 比同一条流先前出队的包到得还早：调度器把流内顺序打乱了
 
     // 🌊 实时积压水位 (永远不清零，真实的物理库存)
-    backlog_pkts: i64,
-    backlog_bytes: i64,
+    pub backlog_pkts: i64,
+    pub backlog_bytes: i64,
+}
+
+// 从上次清零 (启动或 reset-counters) 到现在的累计，不跟着周期清零，压测前后各读一次就是净值
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Totals {
+    pub total_in: u64,
+    pub total_out: u64,
+    pub total_dropped: u64,
+    pub total_bytes: u64, // 出队的整形字节 (按 cost)
+}
+
+// 一个结算周期的完整报表，给外部汇总用 (多个监控拼成一张表)
+#[derive(Debug, Clone)]
+pub struct MonitorSnapshot {
+    pub name: String,
+    pub elapsed: Duration,                // 这个周期实际多长，算速率用
+    pub queues: Vec<(usize, QueueStats)>, // 按 queue_num 排好序
+    pub drop_reasons: Vec<(DropReason, u64)>,
+    pub decision_latency_us: Option<[f64; 4]>, // enqueue p50/p99, dequeue p50/p99；没开就是 None
+    pub head_wait: Option<Duration>, // 结算那一刻队头已经排了多久 (peek_ref 偷看，看不到就是 None)
+    pub bucket_headroom: Vec<(&'static str, f64)>, // 子树里每只桶结算那一刻的余额百分比，按树路径顺序
+    pub totals: Totals,
+    pub totals_elapsed: Duration, // 累计是多久攒下来的
+}
+
+// 估算字节占出队字节的百分比 (没流量时记 0)
+fn estimated_percent(est_bytes: f64, total_bytes: f64) -> f64 {
+    if total_bytes > 0.0 {
+        est_bytes / total_bytes * 100.0
+    } else {
+        0.0
+    }
+}
+
+// ==========================================
+// 调度决策耗时直方图 (按 2 的幂分桶，纳秒)
+// 用来区分“调度器自己慢”和“链路本来就满”
+// ==========================================
+#[derive(Default)]
+struct LatencyHistogram {
+    buckets: [u64; 32], // 第 i 桶装 [2^(i-1), 2^i) 纳秒
+    count: u64,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u32::MAX as u128) as u32;
+        let idx = (32 - nanos.leading_zeros() as usize).min(31);
+        self.buckets[idx] += 1;
+        self.count += 1;
+    }
+
+    // 返回该分位所在桶的上界 (微秒)，够粗但足够判断量级
+    fn percentile_us(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let target = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return (1u64 << i) as f64 / 1000.0;
+            }
+        }
+        (1u64 << 31) as f64 / 1000.0
+    }
+}
+
+#[derive(Default)]
+struct DecisionLatency {
+    enqueue: LatencyHistogram,
+    dequeue: LatencyHistogram,
+}
+
+// 丢包事件：给外面订阅调试用，只带几个标量，不扣着整个 PacketContext (里面有内核包)
+#[derive(Debug, Clone, Copy)]
+pub struct DropEvent {
+    pub flow_hash: u64,
+    pub queue_num: usize,
+    pub cost: usize,
+    pub reason: Option<DropReason>,
+    pub at: Instant, // 监控收尸的时刻，不是判死的时刻，最多差一次调度
 }
 
 // ==========================================
@@ -29,8 +121,25 @@ pub struct MonitorQdisc<T, K> {
     pub inner: Box<dyn Qdisc<T, K>>,
     stats: HashMap<usize, QueueStats>,
     last_report: Instant,
+    report_interval: Duration,
     // ✅ 新增：垃圾中转站
     pending_drops: Vec<PacketContext<T, K>>,
+    // 🗑️ 本周期丢包按原因分类 (每次报表清零)
+    drop_reasons: HashMap<DropReason, u64>,
+    // ⏱️ 调度耗时统计，默认关闭 (每次调用多两次 Instant::now())
+    decision_latency: Option<DecisionLatency>,
+    // 🧱 最近一次 enqueue 有没有把下面哪个队列挤爆 (容量 / 内存上限丢包)
+    last_enqueue_overflowed: bool,
+    // 🔇 静默：到点只结算不打印
+    silent: bool,
+    last_window: Option<MonitorSnapshot>,
+    // 📡 丢包事件流的订阅方，没人订阅就是 None
+    drop_tap: Option<SyncSender<DropEvent>>,
+    // 🔀 每条流 (flow_hash) 出过队的包里最晚的到达时刻，查流内乱序用
+    last_out: HashMap<u64, Instant>,
+    // 📦 累计计数，只有 reset_counters 才清零
+    totals: Totals,
+    totals_since: Instant,
 }
 
 impl<T, K> MonitorQdisc<T, K> {
@@ -39,8 +148,92 @@ impl<T, K> MonitorQdisc<T, K> {
             name: name.to_string(),
             inner,
             stats: HashMap::new(),
-            last_report: Instant::now(),
+            last_report: clock::now(),
+            report_interval: Duration::from_secs(1),
             pending_drops: Vec::new(),
+            drop_reasons: HashMap::new(),
+            decision_latency: None,
+            last_enqueue_overflowed: false,
+            silent: false,
+            last_window: None,
+            drop_tap: None,
+            last_out: HashMap::new(),
+            totals: Totals::default(),
+            totals_since: clock::now(),
+        }
+    }
+
+    // 报表最短间隔，默认 1 秒
+    pub fn set_report_interval(&mut self, interval: Duration) {
+        self.report_interval = interval;
+    }
+
+    fn interval_was_idle(&self) -> bool {
+        self.stats.values().all(|s| {
+            s.in_pkts == 0 && s.drop_pkts == 0 && s.out_pkts == 0 && s.backlog_pkts == 0
+        })
+    }
+
+    pub fn totals(&self) -> Totals {
+        self.totals
+    }
+
+    // 累计清零，从现在起重新攒；积压水位和本周期的速率不受影响
+    pub fn reset_counters(&mut self) {
+        self.totals = Totals::default();
+        self.totals_since = clock::now();
+    }
+
+    // 给收包循环做背压用：true 说明树里已经满到开始按容量丢包了
+    pub fn last_enqueue_overflowed(&self) -> bool {
+        self.last_enqueue_overflowed
+    }
+
+    // 打开调度决策耗时统计，每秒报表里多一行 p50/p99
+    pub fn enable_decision_latency(&mut self) {
+        self.decision_latency = Some(DecisionLatency::default());
+    }
+
+    // 订阅丢包事件流：子树里每丢一个包发一条 DropEvent，再订阅一次会顶掉上一个订阅方
+    // channel 满了 (订阅方跟不上) 就扔事件，绝不拖慢数据面；Receiver 扔掉就自动退订
+    pub fn subscribe_drops(&mut self, capacity: usize) -> mpsc::Receiver<DropEvent> {
+        let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+        self.drop_tap = Some(tx);
+        rx
+    }
+
+    // 子树里死掉的一个包：记丢包、扣积压水位、通知订阅方
+    fn account_drop(&mut self, ctx: &PacketContext<T, K>) {
+        let stat = self
+            .stats
+            .entry(ctx.queue_num)
+            .or_default();
+
+        // 记录一笔丢包
+        stat.drop_pkts += 1;
+        self.totals.total_dropped += 1;
+        if let Some(reason) = ctx.drop_reason {
+            *self.drop_reasons.entry(reason).or_insert(0) += 1;
+        }
+
+        // 🚨 核心平账：因为它曾经成功入队加了水位，现在死在里面了，必须把水位扣掉！
+        stat.backlog_pkts -= 1;
+        stat.backlog_bytes -= ctx.cost as i64;
+
+        // 可选：如果你想看暗杀细节，可以解开这行注释
+        // println!("🔪 [回收站] 队列 {} 内部释放 {} 字节", ctx.queue_num, ctx.cost);
+
+        if let Some(tap) = &self.drop_tap {
+            let event = DropEvent {
+                flow_hash: ctx.flow_hash,
+                queue_num: ctx.queue_num,
+                cost: ctx.cost,
+                reason: ctx.drop_reason,
+                at: clock::now(),
+            };
+            if let Err(TrySendError::Disconnected(_)) = tap.try_send(event) {
+                self.drop_tap = None;
+            }
         }
     }
 
@@ -48,104 +241,260 @@ impl<T, K> MonitorQdisc<T, K> {
     fn flush_internal_drops(&mut self) {
         let drops = self.inner.collect_dropped();
         for ctx in &drops {
-            let stat = self
-                .stats
-                .entry(ctx.queue_num)
-                .or_insert_with(QueueStats::default);
+            self.account_drop(ctx);
+        }
+        self.pending_drops.extend(drops);
+    }
+
+    // 把当前周期的增量收成一份快照，同时清零速率 (积压水位保留)
+    fn take_window(&mut self, elapsed: Duration) -> MonitorSnapshot {
+        let mut queues: Vec<(usize, QueueStats)> = self
+            .stats
+            .iter_mut()
+            .map(|(&q_num, stat)| {
+                let window = stat.clone();
+                stat.in_pkts = 0;
+                stat.drop_pkts = 0;
+                stat.out_pkts = 0;
+                stat.out_bytes = 0.0;
+                stat.out_wire_bytes = 0.0;
+                stat.out_est_bytes = 0.0;
+                stat.reorder_pkts = 0;
+                (q_num, window)
+            })
+            .collect();
+        queues.sort_unstable_by_key(|&(q_num, _)| q_num);
+
+        let mut drop_reasons: Vec<_> = self.drop_reasons.drain().collect();
+        drop_reasons.sort_unstable();
+        let now = clock::now();
+        self.last_out
+            .retain(|_, arrival| now.saturating_duration_since(*arrival) < REORDER_MEMORY);
+
+        let decision_latency_us = self.decision_latency.as_mut().map(|latency| {
+            let summary = [
+                latency.enqueue.percentile_us(0.50),
+                latency.enqueue.percentile_us(0.99),
+                latency.dequeue.percentile_us(0.50),
+                latency.dequeue.percentile_us(0.99),
+            ];
+            *latency = DecisionLatency::default();
+            summary
+        });
+
+        MonitorSnapshot {
+            name: self.name.clone(),
+            elapsed,
+            queues,
+            drop_reasons,
+            decision_latency_us,
+            head_wait: self.head_wait(),
+            bucket_headroom: self.bucket_headroom(),
+            totals: self.totals(),
+            totals_elapsed: clock::now().saturating_duration_since(self.totals_since),
+        }
+    }
 
-            // 记录一笔丢包
-            stat.drop_pkts += 1;
+    // 到点就结算一个周期：静默模式只存快照等人来取，否则连同树里其它 (静默的) 监控一起打一张表
+    fn check_and_report(&mut self) {
+        let elapsed = clock::now().saturating_duration_since(self.last_report);
+        if elapsed < self.report_interval {
+            return;
+        }
 
-            // 🚨 核心平账：因为它曾经成功入队加了水位，现在死在里面了，必须把水位扣掉！
-            stat.backlog_pkts -= 1;
-            stat.backlog_bytes -= ctx.cost as i64;
+        let idle = self.interval_was_idle();
+        let snapshot = self.take_window(elapsed);
+        self.last_report = clock::now();
+        self.inner.close_window();
 
-            // 可选：如果你想看暗杀细节，可以解开这行注释
-            // println!("🔪 [回收站] 队列 {} 内部释放 {} 字节", ctx.queue_num, ctx.cost);
+        if !self.silent && !idle {
+            // 整个周期一个包都没进没出没丢、也没有积压：打出来就是一张空表，不如不打
+            snapshot.print();
+            for (_, nested) in monitor_reports(self.inner.as_ref()) {
+                nested.print();
+            }
         }
-        self.pending_drops.extend(drops);
+        self.last_window = Some(snapshot);
     }
 
-    // 打印并重置报表
-    fn check_and_report(&mut self) {
-        let elapsed = self.last_report.elapsed();
+    fn bucket_headroom(&self) -> Vec<(&'static str, f64)> {
+        bucket_breakdown(self.inner.as_ref())
+            .into_iter()
+            .filter_map(|(_, name, stats)| Some((name, stats.headroom_percent()?)))
+            .collect()
+    }
 
-        if elapsed >= Duration::from_secs(1) {
-            let now_str = Local::now().format("%H:%M:%S").to_string();
+    fn head_wait(&self) -> Option<Duration> {
+        self.inner
+            .peek_ref()
+            .map(|head| clock::now().saturating_duration_since(head.arrival_time))
+    }
 
-            println!("\n📊 [{}] 监控面板: {}", now_str, self.name);
-            println!(
-                "---------------------------------------------------------------------------------"
-            );
-            println!(
-                "{:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<15}",
-                "QueueNum",
-                "入队(包/s)",
-                "丢弃(包/s)",
-                "出队(包/s)",
-                "速度(Mbps)",
-                "实时积压(包/KB)"
-            );
-            println!(
-                "---------------------------------------------------------------------------------"
-            );
+    // 静默模式：不再自己往终端打表，由外部 (或树顶上那个不静默的监控) 拉 report 统一打印
+    pub fn set_silent(&mut self, silent: bool) {
+        self.silent = silent;
+    }
 
-            let mut total_in = 0;
-            let mut total_drop = 0;
-            let mut total_out = 0;
-            let mut total_bytes = 0.0;
-            let mut total_backlog_bytes = 0;
-
-            let mut sorted_queues: Vec<_> = self.stats.keys().cloned().collect();
-            sorted_queues.sort_unstable();
-
-            // ⚠️ 核心改造 1：不用 stats.clear()，而是手动清空“速率”，保留“积压水位”
-            for q_num in sorted_queues {
-                if let Some(stat) = self.stats.get_mut(&q_num) {
-                    let mbps = (stat.out_bytes * 8.0) / 1_000_000.0 / elapsed.as_secs_f64();
-                    let backlog_kb = stat.backlog_bytes as f64 / 1024.0;
-
-                    println!(
-                        "{:<8} | {:<10} | {:<10} | {:<10} | {:<10.2} | {}包 / {:.1}KB",
-                        q_num,
-                        stat.in_pkts,
-                        stat.drop_pkts,
-                        stat.out_pkts,
-                        mbps,
-                        stat.backlog_pkts,
-                        backlog_kb
-                    );
-
-                    total_in += stat.in_pkts;
-                    total_drop += stat.drop_pkts;
-                    total_out += stat.out_pkts;
-                    total_bytes += stat.out_bytes;
-                    total_backlog_bytes += stat.backlog_bytes;
-
-                    // 只清空每秒的增量统计
-                    stat.in_pkts = 0;
-                    stat.drop_pkts = 0;
-                    stat.out_pkts = 0;
-                    stat.out_bytes = 0.0;
-                }
-            }
+    // 最近一个结算完的周期；第一个周期还没走完时给出到目前为止的累计
+    // 累计计数 (totals) 总是取此刻的，不跟周期走
+    pub fn report(&self) -> MonitorSnapshot {
+        if let Some(window) = &self.last_window {
+            return MonitorSnapshot {
+                totals: self.totals(),
+                totals_elapsed: clock::now().saturating_duration_since(self.totals_since),
+                ..window.clone()
+            };
+        }
+        let mut queues: Vec<(usize, QueueStats)> = self
+            .stats
+            .iter()
+            .map(|(&q_num, stat)| (q_num, stat.clone()))
+            .collect();
+        queues.sort_unstable_by_key(|&(q_num, _)| q_num);
+        let mut drop_reasons: Vec<_> = self.drop_reasons.iter().map(|(&r, &n)| (r, n)).collect();
+        drop_reasons.sort_unstable();
+        MonitorSnapshot {
+            name: self.name.clone(),
+            elapsed: clock::now().saturating_duration_since(self.last_report),
+            queues,
+            drop_reasons,
+            decision_latency_us: None,
+            head_wait: self.head_wait(),
+            bucket_headroom: self.bucket_headroom(),
+            totals: self.totals(),
+            totals_elapsed: clock::now().saturating_duration_since(self.totals_since),
+        }
+    }
+}
+
+impl MonitorSnapshot {
+    fn mbps(&self, bytes: f64) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (bytes * 8.0) / 1_000_000.0 / secs
+        } else {
+            0.0
+        }
+    }
 
-            let total_mbps = (total_bytes * 8.0) / 1_000_000.0 / elapsed.as_secs_f64();
-            let total_backlog_kb = total_backlog_bytes as f64 / 1024.0;
+    // 整形口径：令牌桶眼里的速度
+    pub fn shaped_mbps(&self, stat: &QueueStats) -> f64 {
+        self.mbps(stat.out_bytes)
+    }
 
+    // 线上口径：真正发出去的速度
+    pub fn wire_mbps(&self, stat: &QueueStats) -> f64 {
+        self.mbps(stat.out_wire_bytes)
+    }
+
+    // 所有队列加起来的一行
+    pub fn total(&self) -> QueueStats {
+        let mut total = QueueStats::default();
+        for (_, stat) in &self.queues {
+            total.in_pkts += stat.in_pkts;
+            total.drop_pkts += stat.drop_pkts;
+            total.out_pkts += stat.out_pkts;
+            total.out_bytes += stat.out_bytes;
+            total.out_wire_bytes += stat.out_wire_bytes;
+            total.out_est_bytes += stat.out_est_bytes;
+            total.reorder_pkts += stat.reorder_pkts;
+            total.backlog_pkts += stat.backlog_pkts;
+            total.backlog_bytes += stat.backlog_bytes;
+        }
+        total
+    }
+
+    pub fn print(&self) {
+        let now_str = Local::now().format("%H:%M:%S").to_string();
+
+        println!("\n📊 [{}] 监控面板: {}", now_str, self.name);
+        println!(
+            "-----------------------------------------------------------------------------------------------------------"
+        );
+        println!(
+            "{:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<8} | {:<15}",
+            "QueueNum",
+            "入队(包/s)",
+            "丢弃(包/s)",
+            "出队(包/s)",
+            "乱序(包/s)",
+            "整形(Mbps)",
+            "线上(Mbps)",
+            "估算(%)",
+            "实时积压(包/KB)"
+        );
+        println!(
+            "-----------------------------------------------------------------------------------------------------------"
+        );
+
+        for (q_num, stat) in &self.queues {
             println!(
-                "---------------------------------------------------------------------------------"
-            );
-            println!(
-                "{:<8} | {:<10} | {:<10} | {:<10} | {:<10.2} | {:.1}KB 总积压",
-                "TOTAL", total_in, total_drop, total_out, total_mbps, total_backlog_kb
+                "{:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10.2} | {:<10.2} | {:<8.1} | {}包 / {:.1}KB",
+                q_num,
+                stat.in_pkts,
+                stat.drop_pkts,
+                stat.out_pkts,
+                stat.reorder_pkts,
+                self.shaped_mbps(stat),
+                self.wire_mbps(stat),
+                estimated_percent(stat.out_est_bytes, stat.out_bytes),
+                stat.backlog_pkts,
+                stat.backlog_bytes as f64 / 1024.0
             );
+        }
+
+        let total = self.total();
+        println!(
+            "-----------------------------------------------------------------------------------------------------------"
+        );
+        println!(
+            "{:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10.2} | {:<10.2} | {:<8.1} | {:.1}KB 总积压",
+            "TOTAL",
+            total.in_pkts,
+            total.drop_pkts,
+            total.out_pkts,
+            total.reorder_pkts,
+            self.shaped_mbps(&total),
+            self.wire_mbps(&total),
+            estimated_percent(total.out_est_bytes, total.out_bytes),
+            total.backlog_bytes as f64 / 1024.0
+        );
+        if !self.drop_reasons.is_empty() {
+            let line: Vec<String> = self
+                .drop_reasons
+                .iter()
+                .map(|(reason, n)| format!("{:?} {}", reason, n))
+                .collect();
+            println!("🗑️ 丢弃原因: {}", line.join(" | "));
+        }
+        if !self.bucket_headroom.is_empty() {
+            let line: Vec<String> = self
+                .bucket_headroom
+                .iter()
+                .map(|(name, percent)| format!("{} {:.0}%", name, percent))
+                .collect();
+            println!("🪣 令牌余量: {}", line.join(" | "));
+        }
+        println!(
+            "📦 累计 ({:.0}s): 入队 {} | 出队 {} | 丢弃 {} | 整形 {:.1}MB",
+            self.totals_elapsed.as_secs_f64(),
+            self.totals.total_in,
+            self.totals.total_out,
+            self.totals.total_dropped,
+            self.totals.total_bytes as f64 / 1_000_000.0
+        );
+        if let Some(wait) = self.head_wait {
+            println!("⏳ 队头已排队 {:.1}ms", wait.as_secs_f64() * 1000.0);
+        }
+        if let Some([enq_p50, enq_p99, deq_p50, deq_p99]) = self.decision_latency_us {
             println!(
-                "=================================================================================\n"
+                "⏱️ 调度耗时 enqueue p50≤{:.1}µs p99≤{:.1}µs | dequeue p50≤{:.1}µs p99≤{:.1}µs",
+                enq_p50, enq_p99, deq_p50, deq_p99
             );
-
-            self.last_report = Instant::now();
         }
+        println!(
+            "===========================================================================================================\n"
+        );
     }
 }
 
@@ -158,14 +507,29 @@ impl<T, K> Qdisc<T, K> for MonitorQdisc<T, K> {
         let q_num = ctx.queue_num;
         let cost = ctx.cost as i64;
 
-        self.inner.enqueue(ctx);
+        match self.decision_latency.as_mut() {
+            Some(latency) => {
+                let started = Instant::now();
+                self.inner.enqueue(ctx);
+                latency.enqueue.record(started.elapsed());
+            }
+            None => self.inner.enqueue(ctx),
+        }
 
-        let stat = self.stats.entry(q_num).or_insert_with(QueueStats::default);
+        let stat = self.stats.entry(q_num).or_default();
         stat.in_pkts += 1;
         stat.backlog_pkts += 1;
+        self.totals.total_in += 1;
         stat.backlog_bytes += cost;
 
+        let drops_before = self.pending_drops.len();
         self.flush_internal_drops();
+        self.last_enqueue_overflowed = self.pending_drops[drops_before..].iter().any(|d| {
+            matches!(
+                d.drop_reason,
+                Some(DropReason::HardLimit | DropReason::MemLimit)
+            )
+        });
         self.check_and_report();
     }
 
@@ -181,19 +545,47 @@ impl<T, K> Qdisc<T, K> for MonitorQdisc<T, K> {
         self.inner.peek()
     }
 
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek_ref()
+    }
+
     fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
-        let result = self.inner.dequeue();
+        let result = match self.decision_latency.as_mut() {
+            Some(latency) => {
+                let started = Instant::now();
+                let result = self.inner.dequeue();
+                latency.dequeue.record(started.elapsed());
+                result
+            }
+            None => self.inner.dequeue(),
+        };
 
         // 正常出队，核销积压水位
         if let Some(ref ctx) = result {
             let stat = self
                 .stats
                 .entry(ctx.queue_num)
-                .or_insert_with(QueueStats::default);
+                .or_default();
             stat.out_pkts += 1;
             stat.out_bytes += ctx.cost as f64;
+            stat.out_wire_bytes += ctx.pkt_len as f64;
+            if ctx.cost_is_estimated {
+                stat.out_est_bytes += ctx.cost as f64;
+            }
             stat.backlog_pkts -= 1;
             stat.backlog_bytes -= ctx.cost as i64;
+            self.totals.total_out += 1;
+            self.totals.total_bytes += ctx.cost as u64;
+
+            let last = self
+                .last_out
+                .entry(ctx.flow_hash)
+                .or_insert(ctx.arrival_time);
+            if ctx.arrival_time < *last {
+                stat.reorder_pkts += 1;
+            } else {
+                *last = ctx.arrival_time;
+            }
         }
 
         self.flush_internal_drops();
@@ -206,4 +598,306 @@ impl<T, K> Qdisc<T, K> for MonitorQdisc<T, K> {
         let _ = self.peek();
         std::mem::take(&mut self.pending_drops)
     }
+
+    // 被上层摘走的包也是死在这棵子树里的，照样记账平水位，只是不进自己的回收站 (直接交给上层)
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        let victim = self.inner.drop_flow_head(flow_hash, reason)?;
+        self.account_drop(&victim);
+        Some(victim)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.pending_drops);
+        all.extend(self.inner.flush());
+
+        // 全倒空了，水位自然归零
+        for stat in self.stats.values_mut() {
+            stat.backlog_pkts = 0;
+            stat.backlog_bytes = 0;
+        }
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!("Monitor[{}]({})", self.name, self.inner.describe())
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        // 清零要一路传下去，树里嵌套的监控一起清
+        if *cmd == ControlCommand::ResetCounters {
+            self.reset_counters();
+            self.inner.apply_control(cmd);
+            return true;
+        }
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+
+    fn monitor_report(&self) -> Option<MonitorSnapshot> {
+        Some(self.report())
+    }
+
+    // 被上面的监控带着结算：静默的才跟着结算 (不静默的要按自己的节奏打表)，再往下传
+    fn close_window(&mut self) {
+        if self.silent {
+            let elapsed = clock::now().saturating_duration_since(self.last_report);
+            self.last_window = Some(self.take_window(elapsed));
+            self.last_report = clock::now();
+        }
+        self.inner.close_window();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::leaf::HeadDropFifo;
+    use crate::qdisc::wrapper::SfbQdisc;
+    use crate::qdisc::{LifoQdisc, QdiscExt};
+
+    fn fifo_monitor() -> MonitorQdisc<Vec<u8>, u64> {
+        MonitorQdisc::new("Test", Box::new(HeadDropFifo::new(16)))
+    }
+
+    #[test]
+    fn flow_head_drops_are_accounted_on_the_way_up() {
+        let mut monitor = fifo_monitor();
+        monitor.enqueue(test_packet(1, 0, 100));
+        monitor.enqueue(test_packet(2, 0, 200));
+
+        let victim = monitor.drop_flow_head(2, DropReason::MemLimit).unwrap();
+        assert_eq!(victim.cost, 200);
+        assert_eq!(victim.drop_reason, Some(DropReason::MemLimit));
+        assert!(monitor.drop_flow_head(2, DropReason::MemLimit).is_none());
+
+        // 被摘走的包直接交给上层，不进自己的回收站，但丢包和积压的账要平
+        assert!(monitor.collect_dropped().is_empty());
+        assert_eq!(monitor.totals().total_dropped, 1);
+        assert_eq!(monitor.stats[&0].backlog_pkts, 1);
+        assert_eq!(monitor.stats[&0].backlog_bytes, 100);
+    }
+
+    #[test]
+    fn root_report_closes_silent_nested_windows() {
+        let mut nested = fifo_monitor();
+        nested.set_silent(true);
+        nested.set_report_interval(Duration::from_secs(3600)); // 自己永远走不到结算点
+        let mut root = MonitorQdisc::new("Root", Box::new(nested));
+        root.set_silent(true);
+        root.set_report_interval(Duration::ZERO);
+        let nested_report = |root: &MonitorQdisc<Vec<u8>, u64>| {
+            let (_, report) = monitor_reports(root)
+                .into_iter()
+                .find(|(_, report)| report.name == "Test")
+                .unwrap();
+            report.total()
+        };
+
+        root.enqueue(test_packet(1, 0, 100));
+        assert_eq!(nested_report(&root).in_pkts, 1);
+
+        assert!(root.peek().is_some());
+        root.dequeue();
+        let window = nested_report(&root);
+        assert_eq!((window.in_pkts, window.out_pkts), (0, 1));
+    }
+
+    #[test]
+    fn reordered_dequeues_are_counted_per_flow() {
+        let mut monitor = MonitorQdisc::new("Test", Box::new(LifoQdisc::new()));
+        let start = clock::now();
+        // 流 1 三个包、流 2 一个包，后进先出：流 1 的 3 号先走，1、2 号出来时都比它到得早
+        for (i, flow) in [1, 1, 2, 1].into_iter().enumerate() {
+            let mut ctx = test_packet(flow, 0, 100);
+            ctx.arrival_time = start + Duration::from_millis(i as u64);
+            monitor.enqueue(ctx);
+        }
+        let order: Vec<u64> = monitor.drain_ready().map(|ctx| ctx.flow_hash).collect();
+        assert_eq!(order, [1, 2, 1, 1]);
+        assert_eq!(monitor.stats[&0].reorder_pkts, 2);
+
+        // 按序来的不算：流 1 再来个更晚的包，流 3 第一次露面
+        for (i, flow) in [1, 3].into_iter().enumerate() {
+            let mut ctx = test_packet(flow, 0, 100);
+            ctx.arrival_time = start + Duration::from_millis(10 + i as u64);
+            monitor.enqueue(ctx);
+            monitor.dequeue();
+        }
+        assert_eq!(monitor.stats[&0].reorder_pkts, 2);
+        assert_eq!(monitor.report().total().reorder_pkts, 2);
+    }
+
+    #[test]
+    fn totals_survive_windows_until_reset() {
+        let mut monitor = MonitorQdisc::new("Test", Box::new(HeadDropFifo::new(2)));
+        monitor.set_silent(true);
+        for flow in 1..=4 {
+            monitor.enqueue(test_packet(flow, 0, 100)); // 容量 2：挤掉两个
+        }
+        assert_eq!(monitor.drain_ready().count(), 2);
+        monitor.collect_dropped();
+        monitor.close_window(); // 周期结算不动累计
+
+        let totals = |m: &MonitorQdisc<Vec<u8>, u64>| {
+            let t = m.totals();
+            (t.total_in, t.total_out, t.total_dropped, t.total_bytes)
+        };
+        assert_eq!(totals(&monitor), (4, 2, 2, 200));
+
+        assert!(monitor.apply_control(&ControlCommand::ResetCounters));
+        assert_eq!(totals(&monitor), (0, 0, 0, 0));
+        monitor.enqueue(test_packet(5, 0, 100));
+        assert_eq!(monitor.totals().total_in, 1);
+    }
+
+    #[test]
+    fn report_breaks_drops_out_by_reason() {
+        // SFB 只有一个格子、目标 1 个包，inner 只装 2 个：先是 inner 溢出 (HardLimit)，
+        // 格子的丢包概率涨满以后就全是 SFB 自己丢 (Aqm)
+        let sfb = SfbQdisc::new(1, 1, 1, Box::new(HeadDropFifo::new(2)));
+        let mut monitor = MonitorQdisc::new("Test", Box::new(sfb));
+        monitor.set_silent(true);
+        for _ in 0..1000 {
+            monitor.enqueue(test_packet(1, 0, 100));
+        }
+
+        let reasons = monitor.report().drop_reasons;
+        let count = |reason| {
+            reasons
+                .iter()
+                .find(|&&(r, _)| r == reason)
+                .map_or(0, |&(_, n)| n)
+        };
+        assert!(count(DropReason::HardLimit) > 0);
+        assert!(count(DropReason::Aqm) > 0);
+        assert_eq!(reasons.len(), 2);
+        assert_eq!(
+            count(DropReason::HardLimit) + count(DropReason::Aqm),
+            monitor.totals().total_dropped
+        );
+        assert_eq!(monitor.totals().total_dropped, 998);
+    }
+
+    // 入队故意慢 2ms 的 FIFO：调度耗时直方图应该能把慢的入队和快的出队分开
+    struct SlowEnqueue(HeadDropFifo<Vec<u8>, u64>);
+
+    impl Qdisc<Vec<u8>, u64> for SlowEnqueue {
+        fn enqueue(&mut self, ctx: PacketContext<Vec<u8>, u64>) {
+            std::thread::sleep(Duration::from_millis(2));
+            self.0.enqueue(ctx);
+        }
+
+        fn peek(&mut self) -> Option<&PacketContext<Vec<u8>, u64>> {
+            self.0.peek()
+        }
+
+        fn dequeue(&mut self) -> Option<PacketContext<Vec<u8>, u64>> {
+            self.0.dequeue()
+        }
+
+        fn describe(&self) -> String {
+            format!("Slow({})", self.0.describe())
+        }
+    }
+
+    #[test]
+    fn decision_latency_separates_slow_enqueues_from_fast_dequeues() {
+        let mut monitor = MonitorQdisc::new("Test", Box::new(SlowEnqueue(HeadDropFifo::new(8))));
+        monitor.set_silent(true);
+        monitor.set_report_interval(Duration::from_secs(3600));
+        monitor.enqueue(test_packet(1, 0, 100));
+        monitor.close_window();
+        assert_eq!(monitor.report().decision_latency_us, None); // 没打开就不统计
+
+        monitor.enable_decision_latency();
+        for flow in 2..=4 {
+            monitor.enqueue(test_packet(flow, 0, 100));
+        }
+        assert_eq!(monitor.drain_ready().count(), 4);
+        monitor.close_window();
+        let [enq_p50, enq_p99, deq_p50, deq_p99] = monitor.report().decision_latency_us.unwrap();
+        // 2ms 落在 [2^20, 2^21) 纳秒那一桶，报的是桶上界
+        assert!(
+            enq_p50 >= 2000.0 && enq_p99 >= enq_p50,
+            "enqueue {enq_p50}/{enq_p99}"
+        );
+        assert!(
+            deq_p50 <= deq_p99 && deq_p99 < enq_p50,
+            "dequeue {deq_p50}/{deq_p99}"
+        );
+
+        // 每个周期重新攒，空周期就是 0
+        monitor.close_window();
+        assert_eq!(monitor.report().decision_latency_us, Some([0.0; 4]));
+    }
+
+    #[test]
+    fn only_windows_without_traffic_or_backlog_count_as_idle() {
+        let start = Instant::now();
+        clock::set_virtual_now(start);
+        let mut monitor = fifo_monitor();
+        monitor.set_silent(true);
+        assert!(monitor.interval_was_idle());
+
+        monitor.enqueue(test_packet(1, 0, 100));
+        assert!(!monitor.interval_was_idle());
+
+        // 到点出队，结算掉这个周期：什么都没剩，下一张表该省掉
+        clock::set_virtual_now(start + Duration::from_secs(1));
+        assert!(monitor.peek().is_some());
+        monitor.dequeue();
+        assert!(monitor.last_window.is_some());
+        assert!(monitor.interval_was_idle());
+
+        // 又一个周期结算完，新周期还没进没出，但还压着两个包：积压水位照样要报
+        monitor.enqueue(test_packet(2, 0, 100));
+        clock::set_virtual_now(start + Duration::from_secs(2));
+        monitor.enqueue(test_packet(3, 0, 100));
+        assert_eq!(monitor.report().total().in_pkts, 2);
+        assert!(!monitor.interval_was_idle());
+    }
+
+    #[test]
+    fn drop_events_go_to_the_latest_subscriber_without_blocking() {
+        let at = Instant::now();
+        clock::set_virtual_now(at);
+        let mut monitor = MonitorQdisc::new("Test", Box::new(HeadDropFifo::new(1)));
+        monitor.set_silent(true);
+        let replaced = monitor.subscribe_drops(8);
+        let events = monitor.subscribe_drops(2); // 顶掉上一个订阅方
+
+        // 容量 1：挤掉 1、2、3 号；channel 只装得下两条，第三条扔掉，数据面不等人
+        for flow in 1..=4 {
+            monitor.enqueue(test_packet(flow, 0, 100 * flow as usize));
+        }
+        let got: Vec<_> = events
+            .try_iter()
+            .map(|event| (event.flow_hash, event.cost, event.reason, event.at))
+            .collect();
+        let hard = Some(DropReason::HardLimit);
+        assert_eq!(got, [(1, 100, hard, at), (2, 200, hard, at)]);
+        assert_eq!(monitor.totals().total_dropped, 3);
+        assert!(matches!(
+            replaced.try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        ));
+
+        // 订阅方走了：下一次丢包时自动退订
+        drop(events);
+        monitor.enqueue(test_packet(5, 0, 100));
+        assert!(monitor.drop_tap.is_none());
+        assert_eq!(monitor.totals().total_dropped, 4);
+    }
 }
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::control::ControlCommand;
+use crate::packet_context::{DropReason, PacketContext};
+use crate::qdisc::Qdisc;
+
+// ==========================================
+// 新流免死金牌 (New Flow Grace Wrapper)
+// 流刚出现的头一段时间 (大约一个 RTT) 丢包代价极高，握手会被拖慢好几倍
+// 窗口内的包盖 drop_exempt 戳，TtlDropWrapper 之类的延迟丢弃会放它一马，
+// 只有叶子队列的硬容量上限还管得了它
+// ==========================================
+pub struct NewFlowGraceQdisc<T, K> {
+    inner: Box<dyn Qdisc<T, K>>,
+    grace: Duration,
+    flows: HashMap<K, (Instant, Instant)>, // (首次见到, 最近见到)
+    packet_counter: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl<T, K: Clone + Hash + Eq> NewFlowGraceQdisc<T, K> {
+    pub fn new(grace_ms: u64, inner: Box<dyn Qdisc<T, K>>) -> Self {
+        Self {
+            inner,
+            grace: Duration::from_millis(grace_ms),
+            flows: HashMap::new(),
+            packet_counter: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+impl<T, K: Clone + Hash + Eq> Qdisc<T, K> for NewFlowGraceQdisc<T, K> {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        self.packet_counter += 1;
+        let now = self.clock.now();
+
+        // 和 TcpAckFilterQdisc 一样，每 1024 个包顺手清理一次闲置太久的流
+        if self.packet_counter.is_multiple_of(1024) {
+            self.flows.retain(|_, &mut (_, last_seen)| {
+                now.saturating_duration_since(last_seen) < Duration::from_secs(120)
+            });
+        }
+
+        let entry = self.flows.entry(ctx.key.clone()).or_insert((now, now));
+        entry.1 = now;
+        ctx.drop_exempt = now.saturating_duration_since(entry.0) < self.grace;
+
+        self.inner.enqueue(ctx)
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek_ref()
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        self.inner.dequeue()
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&PacketContext<T, K>> {
+        self.inner.peek_nth(n)
+    }
+
+    fn dequeue_nth(&mut self, n: usize) -> Option<PacketContext<T, K>> {
+        self.inner.dequeue_nth(n)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        self.inner.collect_dropped()
+    }
+
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        self.inner.drop_flow_head(flow_hash, reason)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        self.flows.clear();
+        self.inner.flush()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "NewFlowGrace({}ms, {})",
+            self.grace.as_millis(),
+            self.inner.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    #[test]
+    fn exemption_ends_after_grace_window() {
+        let clock = MockClock::new();
+        let mut grace = NewFlowGraceQdisc::new(50, Box::new(HeadDropFifo::new(8)));
+        grace.set_clock(Box::new(clock.clone()));
+
+        grace.enqueue(test_packet(1, 0, 100));
+        clock.advance(Duration::from_millis(40));
+        grace.enqueue(test_packet(1, 0, 100));
+        grace.enqueue(test_packet(2, 0, 100)); // 新流自己的窗口从这里开始
+        clock.advance(Duration::from_millis(20));
+        grace.enqueue(test_packet(1, 0, 100));
+        grace.enqueue(test_packet(2, 0, 100));
+
+        let exempt: Vec<bool> = std::iter::from_fn(|| {
+            grace.peek()?;
+            grace.dequeue()
+        })
+        .map(|ctx| ctx.drop_exempt)
+        .collect();
+        assert_eq!(exempt, vec![true, true, true, false, true]);
+    }
+}
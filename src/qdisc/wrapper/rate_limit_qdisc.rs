@@ -1,3 +1,4 @@
+use crate::control::ControlCommand;
 use crate::packet_context::PacketContext;
 use crate::qdisc::Qdisc;
 use crate::token_bucket::TokenBucketLimiter;
@@ -55,4 +56,16 @@ where
     fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
         self.inner.collect_dropped()
     }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        self.inner.flush()
+    }
+
+    fn describe(&self) -> String {
+        format!("RateLimit({})", self.inner.describe())
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
 }
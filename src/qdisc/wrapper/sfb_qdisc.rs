@@ -0,0 +1,280 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::control::ControlCommand;
+use crate::packet_context::{DropReason, PacketContext};
+use crate::qdisc::Qdisc;
+
+// 每次超标 / 排空时丢包概率的调整步长 (和 Linux sch_sfb 的默认值一致)
+const INCREMENT: f64 = 1.0 / 400.0;
+const DECREMENT: f64 = 1.0 / 4000.0;
+
+#[derive(Clone, Copy, Default)]
+struct Bin {
+    qlen: u32, // 落在这个格子里、还在排队的包数
+    pm: f64,   // 丢包概率
+}
+
+// ==========================================
+// 随机公平蓝 (Stochastic Fair Blue)
+// 防洪用：伪造源地址的洪水会把按流建表的 DRR 撑爆，SFB 只有 levels × bins 个固定格子，
+// 每条流按 flow_hash 在每一层各落一个格子 (布隆过滤器)，格子积压超标就调高它的丢包概率
+// 一条流的丢包概率取它所有格子里最小的那个：小流总能在某一层碰到干净的格子，
+// 只有各层都被它自己撑满的大流 / 攻击流才会被持续惩罚
+// 真正排队的是 inner，SFB 只在入口决定收不收
+// ==========================================
+pub struct SfbQdisc<T, K> {
+    inner: Box<dyn Qdisc<T, K>>,
+    levels: usize,
+    bins_per_level: usize,
+    target_backlog: u32, // 单个格子的积压目标 (包数，不是速率)：超了就调高这个格子的丢包概率
+    bins: Vec<Bin>,
+    dropped: Vec<PacketContext<T, K>>,
+    aqm_drops: u64,
+    rng: u64, // xorshift64 状态，判概率用，不需要密码学强度
+}
+
+impl<T, K> SfbQdisc<T, K> {
+    pub fn new(
+        levels: usize,
+        bins_per_level: usize,
+        target_backlog: u32,
+        inner: Box<dyn Qdisc<T, K>>,
+    ) -> Self {
+        assert!(
+            levels > 0 && bins_per_level > 0,
+            "SfbQdisc 至少需要 1×1 个格子"
+        );
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self {
+            inner,
+            levels,
+            bins_per_level,
+            target_backlog,
+            bins: vec![Bin::default(); levels * bins_per_level],
+            dropped: Vec::new(),
+            aqm_drops: 0,
+            rng: seed | 1,
+        }
+    }
+
+    // 第 level 层里这条流落在哪个格子：每层把 flow_hash 重新搅一遍，层与层之间互不相关
+    fn bin_index(&self, flow_hash: u64, level: usize) -> usize {
+        let mut h = flow_hash ^ (level as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        h ^= h >> 33;
+        level * self.bins_per_level + (h % self.bins_per_level as u64) as usize
+    }
+
+    fn chance(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // 包离开 (出队 / 在 inner 里被丢) 时把它占的格子还回去
+    fn release(&mut self, flow_hash: u64) {
+        for level in 0..self.levels {
+            let idx = self.bin_index(flow_hash, level);
+            self.bins[idx].qlen = self.bins[idx].qlen.saturating_sub(1);
+        }
+    }
+
+    // inner 连容量都爆了：这条流的格子一起加罚
+    fn penalize(&mut self, flow_hash: u64) {
+        for level in 0..self.levels {
+            let idx = self.bin_index(flow_hash, level);
+            self.bins[idx].pm = (self.bins[idx].pm + INCREMENT).min(1.0);
+        }
+    }
+}
+
+impl<T, K> Qdisc<T, K> for SfbQdisc<T, K> {
+    fn enqueue(&mut self, mut ctx: PacketContext<T, K>) {
+        let mut p_min: f64 = 1.0;
+        for level in 0..self.levels {
+            let idx = self.bin_index(ctx.flow_hash, level);
+            let bin = &mut self.bins[idx];
+            if bin.qlen == 0 {
+                bin.pm = (bin.pm - DECREMENT).max(0.0);
+            } else if bin.qlen >= self.target_backlog {
+                bin.pm = (bin.pm + INCREMENT).min(1.0);
+            }
+            p_min = p_min.min(bin.pm);
+        }
+
+        if p_min > 0.0 && self.chance() < p_min {
+            ctx.drop_reason = Some(DropReason::Aqm);
+            self.dropped.push(ctx);
+            self.aqm_drops += 1;
+            return;
+        }
+
+        for level in 0..self.levels {
+            let idx = self.bin_index(ctx.flow_hash, level);
+            self.bins[idx].qlen += 1;
+        }
+        self.inner.enqueue(ctx);
+    }
+
+    fn peek(&mut self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek()
+    }
+
+    fn peek_ref(&self) -> Option<&PacketContext<T, K>> {
+        self.inner.peek_ref()
+    }
+
+    fn dequeue(&mut self) -> Option<PacketContext<T, K>> {
+        let ctx = self.inner.dequeue()?;
+        self.release(ctx.flow_hash);
+        Some(ctx)
+    }
+
+    fn collect_dropped(&mut self) -> Vec<PacketContext<T, K>> {
+        let _ = self.peek(); // 级联打扫
+        let inner_drops = self.inner.collect_dropped();
+        for dead in &inner_drops {
+            self.release(dead.flow_hash);
+            if dead.drop_reason == Some(DropReason::HardLimit) {
+                self.penalize(dead.flow_hash);
+            }
+        }
+        let mut drops = std::mem::take(&mut self.dropped);
+        drops.extend(inner_drops);
+        drops
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.dropped);
+        all.extend(self.inner.flush());
+        self.bins.fill(Bin::default());
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Sfb({}x{}, backlog {}, {})",
+            self.levels,
+            self.bins_per_level,
+            self.target_backlog,
+            self.inner.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        vec![(DropReason::Aqm, self.aqm_drops)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::leaf::HeadDropFifo;
+
+    #[test]
+    fn backlogged_bin_drops_are_stamped_aqm() {
+        // 1×1 个格子、目标 1 个包：同一条流一直堆，每次入队丢包概率涨一档，400 次后必丢
+        let mut sfb: SfbQdisc<Vec<u8>, u64> =
+            SfbQdisc::new(1, 1, 1, Box::new(HeadDropFifo::new(10_000)));
+        for _ in 0..1000 {
+            sfb.enqueue(test_packet(1, 0, 100));
+        }
+        let dropped = sfb.collect_dropped();
+        assert!(dropped.len() >= 600);
+        assert!(
+            dropped
+                .iter()
+                .all(|ctx| ctx.drop_reason == Some(DropReason::Aqm))
+        );
+        assert_eq!(
+            sfb.drop_counts(),
+            vec![(DropReason::Aqm, dropped.len() as u64)]
+        );
+
+        let mut queued = 0;
+        while sfb.dequeue().is_some() {
+            queued += 1;
+        }
+        assert_eq!(queued + dropped.len(), 1000);
+    }
+
+    #[test]
+    fn inner_overflow_keeps_its_own_reason() {
+        // 目标放得很宽，SFB 自己不丢；inner 只装 2 个，第 3 个由 inner 按 HardLimit 挤掉
+        let mut sfb: SfbQdisc<Vec<u8>, u64> =
+            SfbQdisc::new(2, 8, 100, Box::new(HeadDropFifo::new(2)));
+        for _ in 0..3 {
+            sfb.enqueue(test_packet(1, 0, 100));
+        }
+        let dropped = sfb.collect_dropped();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].drop_reason, Some(DropReason::HardLimit));
+        assert_eq!(sfb.drop_counts(), vec![(DropReason::Aqm, 0)]);
+    }
+
+    #[test]
+    fn unresponsive_flow_is_penalised_while_a_polite_flow_keeps_its_share() {
+        let mut sfb: SfbQdisc<Vec<u8>, u64> =
+            SfbQdisc::new(8, 16, 5, Box::new(HeadDropFifo::new(100_000)));
+        let (heavy, polite) = (1, 2);
+        let mut sent = [0usize; 3];
+        let mut offered = [0usize; 3];
+        let mut spoofed = 1_000;
+        // 出口每轮走 8 个：大流每轮灌 10 个不管丢不丢，小流每轮 1 个，
+        // 另外每轮两个伪造源地址的一次性包 (每个都是新的 flow_hash)
+        for _ in 0..2000 {
+            for _ in 0..10 {
+                sfb.enqueue(test_packet(heavy, 0, 100));
+            }
+            sfb.enqueue(test_packet(polite, 0, 100));
+            for _ in 0..2 {
+                spoofed += 1;
+                sfb.enqueue(test_packet(spoofed, 0, 100));
+            }
+            offered[0] += 10;
+            offered[1] += 1;
+            offered[2] += 2;
+            for _ in 0..8 {
+                let Some(ctx) = sfb.dequeue() else { break };
+                sent[(ctx.flow_hash as usize).min(3) - 1] += 1;
+            }
+            sfb.collect_dropped();
+        }
+
+        // 几千个不同的 key 也只占固定的 levels × bins 个格子
+        assert_eq!(sfb.bins.len(), 8 * 16);
+        let heavy_share = sent[0] as f64 / offered[0] as f64;
+        let polite_share = sent[1] as f64 / offered[1] as f64;
+        assert!(
+            heavy_share < 0.5,
+            "heavy={heavy_share} polite={polite_share}"
+        );
+        assert!(
+            polite_share > 0.95,
+            "heavy={heavy_share} polite={polite_share}"
+        );
+        // 大流在每一层的格子都被它自己撑着，丢包概率一直压在高位
+        let heavy_pm = (0..8)
+            .map(|level| sfb.bins[sfb.bin_index(heavy, level)].pm)
+            .fold(1.0, f64::min);
+        assert!(heavy_pm > 0.3, "heavy_pm={heavy_pm}");
+    }
+}
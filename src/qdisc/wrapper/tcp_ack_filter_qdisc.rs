@@ -1,40 +1,129 @@
 // tcp_ack_filter_qdisc.rs 终极版
-use crate::packet_context::PacketContext;
-use crate::qdisc::Qdisc;
+use crate::clock::{Clock, SystemClock};
+use crate::control::ControlCommand;
+use crate::packet_context::{DropReason, PacketContext, SackBlocks};
+use crate::qdisc::{Qdisc, StateReader};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
+// 默认最多记这么多条流的 ACK 情报
+const DEFAULT_MAX_TRACKED: usize = 65_536;
+
+// 每条流当前 "幸存者" (最高确认号那批 ACK) 的情报
+struct AckState {
+    highest: u32,
+    last_seen: Instant,
+    sack: SackBlocks, // 这批 ACK 带过的 SACK 块
+    window: u16,      // 最近一个同号 ACK 通告的原始窗口
+}
+
+// 窗口缩放因子只在握手里出现，这里看不到，只能比原始字段：
+// 一边为 0 另一边不是 (零窗口 / 重新开窗) 一律算更新，否则相差超过 1/4 才算
+fn window_differs(a: u16, b: u16) -> bool {
+    let (lo, hi) = (a.min(b), a.max(b));
+    if lo == 0 {
+        return hi != 0;
+    }
+    hi - lo > lo / 4
+}
+
 pub struct TcpAckFilterQdisc<T, K> {
     inner: Box<dyn Qdisc<T, K>>,
-    highest_acks: HashMap<K, (u32, Instant)>,
+    highest_acks: HashMap<u64, AckState>, // 按 flow_hash 记账
+    max_tracked: usize, // 账本上限：伪造源地址的 ACK 洪水不等 120 秒老化，超了立刻踢最久没见的
     dropped: Vec<PacketContext<T, K>>,
     packet_counter: u64,
+    stale_acks: u64,
+    clock: Box<dyn Clock>,
 }
 
-impl<T, K: Clone + std::hash::Hash + Eq> TcpAckFilterQdisc<T, K> {
+impl<T, K> TcpAckFilterQdisc<T, K> {
     pub fn new(inner: Box<dyn Qdisc<T, K>>) -> Self {
-        Self { inner, highest_acks: HashMap::new(), dropped: Vec::new(), packet_counter: 0 }
+        Self {
+            inner,
+            highest_acks: HashMap::new(),
+            max_tracked: DEFAULT_MAX_TRACKED,
+            dropped: Vec::new(),
+            packet_counter: 0,
+            stale_acks: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn set_max_tracked(&mut self, max_tracked: usize) {
+        self.max_tracked = max_tracked.max(1);
+    }
+
+    // 构造完之后还想查里面的积压、改里面的参数，从这里伸进去；不影响过滤逻辑
+    #[allow(dead_code)]
+    pub fn inner(&self) -> &dyn Qdisc<T, K> {
+        self.inner.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn inner_mut(&mut self) -> &mut dyn Qdisc<T, K> {
+        self.inner.as_mut()
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 账本满了：一次踢掉最久没见的 1/8，摊到每条新流上是 O(1)，洪水里不用每个包扫一遍全表
+    fn evict_stale(&mut self) {
+        let n = (self.max_tracked / 8).max(1);
+        let mut seen: Vec<Instant> = self.highest_acks.values().map(|s| s.last_seen).collect();
+        if seen.len() < n {
+            return;
+        }
+        let (_, &mut cutoff, _) = seen.select_nth_unstable(n - 1);
+        self.highest_acks.retain(|_, s| s.last_seen > cutoff);
     }
 }
 
-impl<T, K: Clone + std::hash::Hash + Eq> Qdisc<T, K> for TcpAckFilterQdisc<T, K> {
+impl<T, K> Qdisc<T, K> for TcpAckFilterQdisc<T, K> {
     fn enqueue(&mut self, ctx: PacketContext<T, K>) {
         self.packet_counter += 1;
 
-        if self.packet_counter % 1024 == 0 {
-            let now = Instant::now();
-            self.highest_acks.retain(|_, &mut (_, last_seen)| {
-                now.saturating_duration_since(last_seen) < Duration::from_secs(120)
+        if self.packet_counter.is_multiple_of(1024) {
+            let now = self.clock.now();
+            self.highest_acks.retain(|_, state| {
+                now.saturating_duration_since(state.last_seen) < Duration::from_secs(120)
             });
         }
 
         if ctx.is_pure_ack {
-            let now = Instant::now();
-            let entry = self.highest_acks.entry(ctx.key.clone()).or_insert((ctx.tcp_ack_num, now));
-            if ctx.tcp_ack_num.wrapping_sub(entry.0) as i32 > 0 {
-                entry.0 = ctx.tcp_ack_num;
+            let now = self.clock.now();
+            match self.highest_acks.get_mut(&ctx.flow_hash) {
+                Some(state) => {
+                    let delta = ctx.tcp_ack_num.wrapping_sub(state.highest) as i32;
+                    if delta > 0 {
+                        state.highest = ctx.tcp_ack_num;
+                        state.sack = ctx.sack;
+                        state.window = ctx.tcp_window;
+                    } else if delta == 0 {
+                        // 同一个确认号的重复 ACK 不会被丢，它们带的 SACK 都算幸存者知道的
+                        for block in ctx.sack.iter() {
+                            state.sack.push(*block);
+                        }
+                        state.window = ctx.tcp_window;
+                    }
+                    state.last_seen = now;
+                }
+                None => {
+                    if self.highest_acks.len() >= self.max_tracked {
+                        self.evict_stale();
+                    }
+                    let state = AckState {
+                        highest: ctx.tcp_ack_num,
+                        last_seen: now,
+                        sack: ctx.sack,
+                        window: ctx.tcp_window,
+                    };
+                    self.highest_acks.insert(ctx.flow_hash, state);
+                }
             }
-            entry.1 = now;
         }
 
         self.inner.enqueue(ctx)
@@ -44,14 +133,21 @@ impl<T, K: Clone + std::hash::Hash + Eq> Qdisc<T, K> for TcpAckFilterQdisc<T, K>
         // 🚀 核心修改：Peek 承担所有排雷工作！
         loop {
             if let Some(ctx) = self.inner.peek() {
-                if ctx.is_pure_ack {
-                    if let Some(&(highest, _)) = self.highest_acks.get(&ctx.key) {
-                        if highest.wrapping_sub(ctx.tcp_ack_num) as i32 > 0 {
-                            // 发现过期 ACK，行使超度权！
-                            let dead = self.inner.dequeue().unwrap();
-                            self.dropped.push(dead);
-                            continue; // 继续查探下一个包
-                        }
+                if ctx.is_pure_ack
+                    && let Some(state) = self.highest_acks.get(&ctx.flow_hash)
+                {
+                    // 老 ACK 带着幸存者没有的 SACK 块，丢了会拖慢快速重传，留着
+                    // 窗口和幸存者差得多的也留着，可能是一次窗口更新
+                    if state.highest.wrapping_sub(ctx.tcp_ack_num) as i32 > 0
+                        && ctx.sack.covered_by(&state.sack, state.highest)
+                        && !window_differs(ctx.tcp_window, state.window)
+                    {
+                        // 发现过期 ACK，行使超度权！
+                        let mut dead = self.inner.dequeue().unwrap();
+                        dead.drop_reason = Some(DropReason::AckSuperseded);
+                        self.dropped.push(dead);
+                        self.stale_acks += 1;
+                        continue; // 继续查探下一个包
                     }
                 }
                 return self.inner.peek(); // 绝对合法，展示给外面
@@ -71,4 +167,279 @@ impl<T, K: Clone + std::hash::Hash + Eq> Qdisc<T, K> for TcpAckFilterQdisc<T, K>
         all_drops.extend(self.inner.collect_dropped());
         all_drops
     }
-}
\ No newline at end of file
+
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        self.inner.drop_flow_head(flow_hash, reason)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.dropped);
+        all.extend(self.inner.flush());
+        self.highest_acks.clear();
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!("TcpAckFilter({})", self.inner.describe())
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+
+    // [流数 u32] + 每条流 [flow_hash u64][highest u32][闲置毫秒 u32][window u16][SACK 块数 u8][块 (u32, u32)...]
+    // Instant 没法落盘，存成 "多久没见过"，恢复时再从现在往回倒
+    fn save_state(&self) -> Vec<u8> {
+        let now = self.clock.now();
+        let mut state = Vec::new();
+        state.extend_from_slice(&(self.highest_acks.len() as u32).to_le_bytes());
+        // 按 flow_hash 排好再写：同样的账本每次存出来字节一样，快照才好比对
+        let mut acks: Vec<_> = self.highest_acks.iter().collect();
+        acks.sort_unstable_by_key(|&(hash, _)| *hash);
+        for (hash, ack) in acks {
+            let idle_ms = now.saturating_duration_since(ack.last_seen).as_millis();
+            state.extend_from_slice(&hash.to_le_bytes());
+            state.extend_from_slice(&ack.highest.to_le_bytes());
+            state.extend_from_slice(&(idle_ms.min(u32::MAX as u128) as u32).to_le_bytes());
+            state.extend_from_slice(&ack.window.to_le_bytes());
+            state.push(ack.sack.iter().count() as u8);
+            for &(left, right) in ack.sack.iter() {
+                state.extend_from_slice(&left.to_le_bytes());
+                state.extend_from_slice(&right.to_le_bytes());
+            }
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let now = self.clock.now();
+        let mut reader = StateReader::new(state);
+        let mut restore = || -> Option<HashMap<u64, AckState>> {
+            let mut acks = HashMap::new();
+            for _ in 0..reader.u32()? {
+                let hash = reader.u64()?;
+                let highest = reader.u32()?;
+                let idle = Duration::from_millis(reader.u32()? as u64);
+                let window = reader.u16()?;
+                let mut sack = SackBlocks::default();
+                for _ in 0..reader.u8()? {
+                    sack.push((reader.u32()?, reader.u32()?));
+                }
+                let state = AckState {
+                    highest,
+                    last_seen: now.checked_sub(idle).unwrap_or(now),
+                    sack,
+                    window,
+                };
+                acks.insert(hash, state);
+            }
+            Some(acks)
+        };
+        if let Some(acks) = restore() {
+            self.highest_acks = acks;
+        }
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        vec![(DropReason::AckSuperseded, self.stale_acks)]
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    fn ack(flow_hash: u64, ack_num: u32) -> PacketContext<Vec<u8>, u64> {
+        let mut ctx = test_packet(flow_hash, 0, 40);
+        ctx.is_pure_ack = true;
+        ctx.tcp_ack_num = ack_num;
+        ctx
+    }
+
+    fn tracked(filter: &TcpAckFilterQdisc<Vec<u8>, u64>) -> u32 {
+        let state = filter.save_state();
+        u32::from_le_bytes(state[..4].try_into().unwrap())
+    }
+
+    // 账本里有哪些流：按 save_state 的格式跳着读 flow_hash
+    fn tracked_flows(filter: &TcpAckFilterQdisc<Vec<u8>, u64>) -> Vec<u64> {
+        let state = filter.save_state();
+        let mut flows = Vec::new();
+        let mut pos = 4;
+        while pos < state.len() {
+            flows.push(u64::from_le_bytes(state[pos..pos + 8].try_into().unwrap()));
+            let sack_blocks = state[pos + 18] as usize;
+            pos += 19 + sack_blocks * 8;
+        }
+        flows.sort_unstable();
+        flows
+    }
+
+    #[test]
+    fn idle_flows_age_out_and_save_idle_time() {
+        let clock = MockClock::new();
+        let mut filter = TcpAckFilterQdisc::new(Box::new(HeadDropFifo::new(2048)));
+        filter.set_clock(Box::new(clock.clone()));
+        filter.enqueue(ack(1, 100));
+
+        // 闲置时长按拨过的表存: [流数][hash][highest][闲置毫秒]
+        clock.advance(Duration::from_secs(5));
+        let state = filter.save_state();
+        assert_eq!(u32::from_le_bytes(state[16..20].try_into().unwrap()), 5000);
+
+        // 每 1024 个包扫一次，120 秒没见的流忘掉
+        clock.advance(Duration::from_secs(116));
+        for _ in 0..1022 {
+            filter.enqueue(test_packet(2, 0, 1500));
+        }
+        assert_eq!(tracked(&filter), 1);
+        filter.enqueue(test_packet(2, 0, 1500));
+        assert_eq!(tracked(&filter), 0);
+    }
+
+    #[test]
+    fn ack_flood_is_capped_by_evicting_the_oldest_flows() {
+        let clock = MockClock::new();
+        let mut filter = TcpAckFilterQdisc::new(Box::new(HeadDropFifo::new(2048)));
+        filter.set_clock(Box::new(clock.clone()));
+        filter.set_max_tracked(16);
+
+        for flow in 0..16 {
+            filter.enqueue(ack(flow, 1));
+            clock.advance(Duration::from_millis(1));
+        }
+        // 0 号又来了一个 ACK，成了最近见过的
+        filter.enqueue(ack(0, 2));
+        assert_eq!(tracked(&filter), 16);
+
+        // 第 17 条流挤进来：一次踢掉最久没见的 16 / 8 = 2 条 (1 号和 2 号)
+        filter.enqueue(ack(100, 1));
+        let flows = tracked_flows(&filter);
+        assert_eq!(flows.len(), 15);
+        assert!(flows.contains(&0) && flows.contains(&100));
+        assert!(!flows.contains(&1) && !flows.contains(&2));
+
+        // 伪造源地址的洪水：账本始终不超过上限
+        for flow in 1000..5000 {
+            clock.advance(Duration::from_millis(1));
+            filter.enqueue(ack(flow, 1));
+            assert!(tracked(&filter) <= 16);
+        }
+        assert!(tracked_flows(&filter).contains(&4999));
+    }
+
+    // 灌进一串 ACK，看哪些活着出来 (按确认号)，哪些被判了过期
+    fn survivors(acks: Vec<PacketContext<Vec<u8>, u64>>) -> (Vec<u32>, Vec<u32>) {
+        let mut filter = TcpAckFilterQdisc::new(Box::new(HeadDropFifo::new(2048)));
+        for ctx in acks {
+            filter.enqueue(ctx);
+        }
+        let mut sent = Vec::new();
+        while filter.peek().is_some() {
+            sent.push(filter.dequeue().unwrap().tcp_ack_num);
+        }
+        let dropped = filter.collect_dropped();
+        assert!(
+            dropped
+                .iter()
+                .all(|ctx| ctx.drop_reason == Some(DropReason::AckSuperseded))
+        );
+        assert_eq!(filter.drop_counts()[0].1, dropped.len() as u64);
+        (sent, dropped.iter().map(|ctx| ctx.tcp_ack_num).collect())
+    }
+
+    #[test]
+    fn older_ack_with_unknown_sack_blocks_is_kept() {
+        let mut old = ack(1, 100);
+        old.sack.push((200, 300));
+        assert_eq!(survivors(vec![old, ack(1, 150)]), (vec![100, 150], vec![]));
+
+        // 同样两个 ACK，老的不带 SACK：被新的取代
+        assert_eq!(
+            survivors(vec![ack(1, 100), ack(1, 150)]),
+            (vec![150], vec![100])
+        );
+
+        // 老 ACK 的 SACK 块新 ACK 也带着：没有独家情报，照丢
+        let mut old = ack(1, 100);
+        old.sack.push((200, 300));
+        let mut new = ack(1, 150);
+        new.sack.push((200, 300));
+        assert_eq!(survivors(vec![old, new]), (vec![150], vec![100]));
+    }
+
+    fn window_ack(ack_num: u32, window: u16) -> PacketContext<Vec<u8>, u64> {
+        let mut ctx = ack(1, ack_num);
+        ctx.tcp_window = window;
+        ctx
+    }
+
+    #[test]
+    fn stale_ack_with_a_changed_window_survives() {
+        // 老 ACK 通告的窗口比幸存者大得多 (一次窗口更新)：留着
+        assert_eq!(
+            survivors(vec![window_ack(100, 5000), window_ack(150, 1000)]),
+            (vec![100, 150], vec![])
+        );
+        // 差不到 1/4 的不算更新，照丢
+        assert_eq!(
+            survivors(vec![window_ack(100, 1100), window_ack(150, 1000)]),
+            (vec![150], vec![100])
+        );
+        // 零窗口之后同号的重新开窗
+        assert_eq!(
+            survivors(vec![window_ack(100, 0), window_ack(100, 4000)]),
+            (vec![100, 100], vec![])
+        );
+
+        assert!(window_differs(0, 1) && window_differs(1, 0));
+        assert!(!window_differs(0, 0));
+        assert!(!window_differs(1000, 1250) && window_differs(1000, 1251));
+    }
+
+    #[test]
+    fn restored_ack_state_still_supersedes_old_acks() {
+        let clock = MockClock::new();
+        let filter = || {
+            let mut filter = TcpAckFilterQdisc::new(Box::new(HeadDropFifo::new(64)));
+            filter.set_clock(Box::new(clock.clone()));
+            filter
+        };
+        let mut before = filter();
+        let mut latest = window_ack(500, 1000);
+        latest.sack.push((600, 700));
+        before.enqueue(latest);
+        before.enqueue(ack(2, 10));
+        while before.peek().is_some() {
+            before.dequeue();
+        }
+        clock.advance(Duration::from_secs(3));
+
+        let state = before.save_state();
+        let mut after = filter();
+        after.load_state(&state);
+        assert_eq!(after.save_state(), state);
+        assert_eq!(tracked_flows(&after), [1, 2]);
+
+        // 重启后晚到的老 ACK：没有恢复的话它是这条流的第一个 ACK，会被当成幸存者
+        after.enqueue(window_ack(400, 1000));
+        assert!(after.peek().is_none());
+        assert_eq!(after.collect_dropped().len(), 1);
+
+        // 解不开的 blob 不动现有账本
+        after.load_state(&state[..state.len() - 1]);
+        assert_eq!(tracked_flows(&after), [1, 2]);
+    }
+}
@@ -1,11 +1,40 @@
 use std::time::{Duration, Instant};
 
-use crate::{packet_context::PacketContext, qdisc::Qdisc};
+use crate::{
+    clock::{Clock, SystemClock},
+    control::ControlCommand,
+    packet_context::{DropReason, PacketContext},
+    qdisc::Qdisc,
+};
+
+// 按包定制的延迟上限
+type LatencyFn<T, K> = Box<dyn Fn(&PacketContext<T, K>) -> Duration>;
+
+// 字段拆开传：调用时 inner 正被 peek 借着
+fn expired<T, K>(
+    latency_fn: &Option<LatencyFn<T, K>>,
+    max_latency: Duration,
+    strict: bool,
+    ctx: &PacketContext<T, K>,
+    now: Instant,
+) -> bool {
+    let max_latency = latency_fn.as_ref().map_or(max_latency, |f| f(ctx));
+    // 新流宽限期内的包不吃延迟死刑 (strict 模式除外)
+    let exempt = ctx.drop_exempt && !strict;
+    !exempt && now.saturating_duration_since(ctx.arrival_time) > max_latency
+}
 
 pub struct TtlDropWrapper<T, K> {
     pub inner: Box<dyn Qdisc<T, K>>,
     pub max_latency: Duration,
+    // 按包定制的延迟上限 (交互流 20ms、大流 500ms 之类)，没给就统一用 max_latency
+    latency_fn: Option<LatencyFn<T, K>>,
     pending_expired: Vec<PacketContext<T, K>>,
+    expired_drops: u64,
+    // 连新流宽限期的豁免也不认：套在整棵树最外层当全流水线的最终延迟上限用
+    // arrival_time 是入口盖的，这里量到的就是包在所有嵌套层里排队的总时长
+    strict: bool,
+    clock: Box<dyn Clock>,
 }
 
 impl<T, K> TtlDropWrapper<T, K> {
@@ -13,9 +42,39 @@ impl<T, K> TtlDropWrapper<T, K> {
         Self {
             inner,
             max_latency: Duration::from_millis(max_latency_ms),
+            latency_fn: None,
             pending_expired: Vec::new(),
+            expired_drops: 0,
+            strict: false,
+            clock: Box::new(SystemClock),
         }
     }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn bury(&mut self, mut dead: PacketContext<T, K>) {
+        dead.drop_reason = Some(DropReason::LatencyExpired);
+        self.pending_expired.push(dead);
+        self.expired_drops += 1;
+    }
+
+    // 注意只检查队头：队头的宽松包没过期时，排在它后面的严格包要等它走了才会被判
+    pub fn with_latency_fn(
+        max_latency_ms: u64,
+        inner: Box<dyn Qdisc<T, K>>,
+        latency_fn: LatencyFn<T, K>,
+    ) -> Self {
+        let mut wrapper = Self::new(max_latency_ms, inner);
+        wrapper.latency_fn = Some(latency_fn);
+        wrapper
+    }
 }
 
 impl<T, K> Qdisc<T, K> for TtlDropWrapper<T, K> {
@@ -24,13 +83,23 @@ impl<T, K> Qdisc<T, K> for TtlDropWrapper<T, K> {
     }
 
     fn peek(&mut self) -> Option<&PacketContext<T, K>> {
-        let now = Instant::now();
+        let now = self.clock.now();
         // 🚀 Peek 独占权力：循环排雷，直到挖出新鲜包！
         loop {
+            // 先判队头：不看令牌摘 (套在 HTB 外面时桶干了也照判，过期的包不花令牌)
+            if let Some(head) = self.inner.peek_head()
+                && expired(&self.latency_fn, self.max_latency, self.strict, head, now)
+            {
+                if let Some(dead) = self.inner.drop_head() {
+                    self.bury(dead);
+                }
+                continue;
+            }
+            // 调度器挑中的不一定是队头 (HTB 高优付不起、低优借道)，只能按出队摘
             if let Some(ctx) = self.inner.peek() {
-                if now.saturating_duration_since(ctx.arrival_time) > self.max_latency {
+                if expired(&self.latency_fn, self.max_latency, self.strict, ctx, now) {
                     if let Some(dead) = self.inner.dequeue() {
-                        self.pending_expired.push(dead);
+                        self.bury(dead);
                     }
                     continue;
                 }
@@ -51,4 +120,157 @@ impl<T, K> Qdisc<T, K> for TtlDropWrapper<T, K> {
         drops.extend(self.inner.collect_dropped());
         drops
     }
+
+    fn drop_flow_head(
+        &mut self,
+        flow_hash: u64,
+        reason: DropReason,
+    ) -> Option<PacketContext<T, K>> {
+        self.inner.drop_flow_head(flow_hash, reason)
+    }
+
+    fn flush(&mut self) -> Vec<PacketContext<T, K>> {
+        let mut all = std::mem::take(&mut self.pending_expired);
+        all.extend(self.inner.flush());
+        all
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "TtlDrop({}ms{}, {})",
+            self.max_latency.as_millis(),
+            if self.strict { " strict" } else { "" },
+            self.inner.describe()
+        )
+    }
+
+    fn apply_control(&mut self, cmd: &ControlCommand) -> bool {
+        self.inner.apply_control(cmd)
+    }
+
+    fn children(&self) -> Vec<(&'static str, &dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_ref())]
+    }
+
+    fn children_mut(&mut self) -> Vec<(&'static str, &mut dyn Qdisc<T, K>)> {
+        vec![("inner", self.inner.as_mut())]
+    }
+
+    fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        vec![(DropReason::LatencyExpired, self.expired_drops)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, packet_context::test_packet, qdisc::leaf::HeadDropFifo};
+
+    #[test]
+    fn head_expires_once_the_clock_passes_max_latency() {
+        let clock = MockClock::new();
+        let mut ttl: TtlDropWrapper<Vec<u8>, u64> =
+            TtlDropWrapper::new(10, Box::new(HeadDropFifo::new(8)));
+        ttl.set_clock(Box::new(clock.clone()));
+        ttl.enqueue(test_packet(1, 0, 100));
+
+        clock.advance(Duration::from_millis(5));
+        assert!(ttl.peek().is_some());
+
+        clock.advance(Duration::from_millis(10));
+        assert!(ttl.peek().is_none());
+        let dropped = ttl.collect_dropped();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].drop_reason, Some(DropReason::LatencyExpired));
+        assert_eq!(ttl.drop_counts(), vec![(DropReason::LatencyExpired, 1)]);
+    }
+
+    #[test]
+    fn strict_ignores_the_grace_exemption() {
+        let clock = MockClock::new();
+        let mut lenient: TtlDropWrapper<Vec<u8>, u64> =
+            TtlDropWrapper::new(10, Box::new(HeadDropFifo::new(8)));
+        let mut strict: TtlDropWrapper<Vec<u8>, u64> =
+            TtlDropWrapper::new(10, Box::new(HeadDropFifo::new(8)));
+        strict.set_strict(true);
+        for ttl in [&mut lenient, &mut strict] {
+            ttl.set_clock(Box::new(clock.clone()));
+            let mut ctx = test_packet(1, 0, 100);
+            ctx.drop_exempt = true;
+            ttl.enqueue(ctx);
+        }
+
+        clock.advance(Duration::from_millis(20));
+        assert!(lenient.peek().is_some());
+        assert!(strict.peek().is_none());
+    }
+
+    #[test]
+    fn expired_heads_behind_a_dry_htb_are_dropped_without_tokens() {
+        use crate::{qdisc::scheduler::HtbQdisc, token_bucket::TokenBucket};
+
+        let clock = MockClock::new();
+        let bucket = |burst| {
+            let mut bucket = TokenBucket::new(0.0, burst, "test");
+            bucket.set_clock(Box::new(clock.clone()));
+            bucket
+        };
+        let htb: HtbQdisc<Vec<u8>, u64, TokenBucket> = HtbQdisc::new(
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(HeadDropFifo::new(8)),
+            bucket(10_000.0),
+            bucket(10_000.0),
+            bucket(1000.0), // 全局桶只够放一个包，之后就干了
+            Box::new(|ctx| ctx.queue_num == 0),
+        );
+        let mut ttl = TtlDropWrapper::new(50, Box::new(htb));
+        ttl.set_strict(true);
+        ttl.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            ttl.enqueue(test_packet(1, 1, 1000));
+        }
+        assert!(ttl.peek().is_some());
+        assert!(ttl.dequeue().is_some());
+        assert!(ttl.peek().is_none());
+
+        // 桶一直是干的，过了总延迟上限照样判：两个都丢，而且一个令牌都没花
+        clock.advance(Duration::from_millis(60));
+        assert!(ttl.peek().is_none());
+        let dropped = ttl.collect_dropped();
+        assert_eq!(dropped.len(), 2);
+        assert!(
+            dropped
+                .iter()
+                .all(|ctx| ctx.drop_reason == Some(DropReason::LatencyExpired))
+        );
+        let global = ttl.inner.bucket_stats()[0].1;
+        assert_eq!(global.bytes_passed, 1000);
+        assert_eq!(global.tokens, 0);
+    }
+
+    #[test]
+    fn per_packet_latency_targets_expire_at_different_times() {
+        let clock = MockClock::new();
+        // 流 1 是交互流 20ms，其它的按大流 500ms；构造参数里的 10ms 被闭包盖掉
+        let mut ttl: TtlDropWrapper<Vec<u8>, u64> = TtlDropWrapper::with_latency_fn(
+            10,
+            Box::new(HeadDropFifo::new(8)),
+            Box::new(|ctx| Duration::from_millis(if ctx.flow_hash == 1 { 20 } else { 500 })),
+        );
+        ttl.set_clock(Box::new(clock.clone()));
+        for flow in [1, 1, 2, 2] {
+            ttl.enqueue(test_packet(flow, 0, 100));
+        }
+
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(ttl.peek().map(|ctx| ctx.flow_hash), Some(2));
+        let dropped = ttl.collect_dropped();
+        assert!(dropped.iter().all(|ctx| ctx.flow_hash == 1));
+        assert_eq!(dropped.len(), 2);
+
+        clock.advance(Duration::from_millis(480));
+        assert!(ttl.peek().is_none());
+        assert_eq!(ttl.collect_dropped().len(), 2);
+        assert_eq!(ttl.drop_counts(), vec![(DropReason::LatencyExpired, 4)]);
+    }
 }
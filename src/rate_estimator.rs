@@ -0,0 +1,93 @@
+// ================= 按流速率估计 =================
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+
+struct FlowRate {
+    bytes_per_sec: f64,
+    last_seen: Instant,
+}
+
+// 指数衰减的字节计数器：rate ← rate · e^(-Δt/τ) + bytes / τ
+// 包到得再不规律也不用攒窗口，稳定流量下收敛到真实速率；τ 越大越平滑、反应越慢
+// 闲置超过 idle_timeout 的流整条忘掉，流表不会随着扫描 / 洪水无限长
+pub struct RateEstimator {
+    flows: HashMap<u64, FlowRate>,
+    time_constant: Duration,
+    idle_timeout: Duration,
+    packet_counter: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl RateEstimator {
+    pub fn new(time_constant: Duration, idle_timeout: Duration) -> Self {
+        Self {
+            flows: HashMap::new(),
+            time_constant: time_constant.max(Duration::from_millis(1)),
+            idle_timeout,
+            packet_counter: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 记一笔，返回这条流更新后的速率 (比特/秒)
+    pub fn update(&mut self, flow_hash: u64, bytes: usize) -> f64 {
+        let now = self.clock.now();
+        self.update_at(flow_hash, bytes, now)
+    }
+
+    // 同上，"现在" 由调用方给：自己带时钟的 qdisc 拿它和自己的时间线对齐
+    pub fn update_at(&mut self, flow_hash: u64, bytes: usize, now: Instant) -> f64 {
+        let tau = self.time_constant.as_secs_f64();
+
+        // 和流表类 qdisc 一样，每 1024 个包顺手清一次闲置的流
+        self.packet_counter += 1;
+        if self.packet_counter.is_multiple_of(1024) {
+            let idle_timeout = self.idle_timeout;
+            self.flows
+                .retain(|_, f| now.duration_since(f.last_seen) < idle_timeout);
+        }
+
+        let flow = self.flows.entry(flow_hash).or_insert(FlowRate {
+            bytes_per_sec: 0.0,
+            last_seen: now,
+        });
+        let elapsed = now.duration_since(flow.last_seen).as_secs_f64();
+        flow.bytes_per_sec = flow.bytes_per_sec * (-elapsed / tau).exp() + bytes as f64 / tau;
+        flow.last_seen = now;
+        flow.bytes_per_sec * 8.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn rate_decays_with_time_and_converges_on_steady_flow() {
+        let clock = MockClock::new();
+        let mut est = RateEstimator::new(Duration::from_secs(1), Duration::from_secs(60));
+        est.set_clock(Box::new(clock.clone()));
+
+        assert_eq!(est.update(1, 1000), 8000.0);
+        clock.advance(Duration::from_secs(1));
+        let decayed = est.update(1, 0);
+        assert!((decayed - 8000.0 / std::f64::consts::E).abs() < 1e-6);
+
+        // 每 10ms 1000 字节 = 800 kbit/s，跑 10 个时间常数
+        let mut rate = 0.0;
+        for _ in 0..1000 {
+            clock.advance(Duration::from_millis(10));
+            rate = est.update(2, 1000);
+        }
+        assert!((rate - 800_000.0).abs() < 800_000.0 * 0.01, "{rate}");
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+// 同一个队列连续出这么多次错，日志才再吼一次，重绑失败时不至于每圈刷屏
+const PERSISTENT_FAULTS: u64 = 100;
+
+// 一次 recv 失败属于哪一类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvFault {
+    Drained, // EAGAIN / EINTR：非阻塞 socket 暂时没包，正常现象
+    Overrun, // ENOBUFS：socket 接收缓冲满了，内核已经丢了一批包，但 socket 还能接着用
+    // ENOENT / EINVAL / ENOSPC 之类：某一条消息出了问题 (包 id 已经被内核放掉、消息截断)，
+    // socket 本身没坏，记账打日志就行，重建反而会把排在里面的包全丢了
+    Transient,
+    Fatal, // EBADF / ENOTSOCK / ENETDOWN：这个 socket 确实废了，需要重建
+}
+
+pub fn classify(e: &io::Error) -> RecvFault {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => RecvFault::Drained,
+        _ => match e.raw_os_error() {
+            Some(libc::ENOBUFS) => RecvFault::Overrun,
+            Some(libc::EBADF | libc::ENOTSOCK | libc::ENETDOWN) => RecvFault::Fatal,
+            _ => RecvFault::Transient,
+        },
+    }
+}
+
+// ==========================================
+// recv 故障账本
+// 以前所有 recv 错误一律当 "没包" 吞掉，缓冲溢出和 socket 坏掉都看不出来
+// ==========================================
+#[derive(Default)]
+pub struct RecvStats {
+    pub overruns: u64,
+    pub faults: u64,
+    pub rebinds: u64,
+    consecutive: Vec<u64>, // 每个队列当前的连续出错次数 (不算 Drained / Overrun)
+}
+
+impl RecvStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 收到包了，连续故障清零
+    pub fn record_ok(&mut self, queue_num: usize) {
+        if let Some(n) = self.consecutive.get_mut(queue_num) {
+            *n = 0;
+        }
+    }
+
+    pub fn record_err(&mut self, queue_num: usize, e: &io::Error) -> RecvFault {
+        if self.consecutive.len() <= queue_num {
+            self.consecutive.resize(queue_num + 1, 0);
+        }
+
+        let fault = classify(e);
+        match fault {
+            RecvFault::Drained => {}
+            RecvFault::Overrun => self.overruns += 1, // 只计数，退出时汇总
+            RecvFault::Transient | RecvFault::Fatal => {
+                self.faults += 1;
+                self.consecutive[queue_num] += 1;
+                let n = self.consecutive[queue_num];
+                if n == 1 || n.is_multiple_of(PERSISTENT_FAULTS) {
+                    eprintln!("⚠️ 队列 {} recv 出错 (连续 {} 次): {}", queue_num, n, e);
+                }
+            }
+        }
+        fault
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_dead_sockets_are_fatal() {
+        let fault = |errno| classify(&io::Error::from_raw_os_error(errno));
+        assert_eq!(fault(libc::EAGAIN), RecvFault::Drained);
+        assert_eq!(fault(libc::EINTR), RecvFault::Drained);
+        assert_eq!(fault(libc::ENOBUFS), RecvFault::Overrun);
+        for errno in [libc::ENOENT, libc::EINVAL, libc::ENOSPC] {
+            assert_eq!(fault(errno), RecvFault::Transient);
+        }
+        for errno in [libc::EBADF, libc::ENOTSOCK, libc::ENETDOWN] {
+            assert_eq!(fault(errno), RecvFault::Fatal);
+        }
+
+        let mut stats = RecvStats::new();
+        stats.record_err(0, &io::Error::from_raw_os_error(libc::ENOENT));
+        stats.record_err(0, &io::Error::from_raw_os_error(libc::EBADF));
+        assert_eq!(stats.faults, 2);
+    }
+}
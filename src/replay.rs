@@ -0,0 +1,298 @@
+// ================= pcap 回放 =================
+// 不碰 NFQUEUE、不要 root：把抓包文件里的 IPv4 包按原来的时间间隔灌进同一棵调度树，
+// 包内容就是一段 Vec<u8>，"放行" / "丢弃" 只记数，最后打印监控面板和丢包溯源
+// 时间是虚拟的 (clock::set_virtual_now)：没活干就直接把表拨到下一个时刻，不睡不等，同一个文件每次跑出来都一样
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    clock,
+    config::ModifierMap,
+    five_tuple::{FiveTuple, FlowKeyPolicy},
+    modifier::apply_modifiers,
+    packet_context::PacketContext,
+    pcap::PcapReader,
+    qdisc::{Qdisc, QdiscExt, bucket_breakdown, drop_breakdown, wrapper::MonitorQdisc},
+};
+
+// 文件放完之后树里还压着包 (限速、pacing 在放慢)：这么久一个都没走掉就不等了，剩下的算滞留
+const DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplaySummary {
+    pub fed: u64,         // 灌进去的包
+    pub sent: u64,        // 调度树放行的包
+    pub dropped: u64,     // 修改器当场判死 + 调度树丢的
+    pub leftover: u64,    // 最后还没走掉、被 flush 出来的
+    pub gaps: Spread,     // 相邻两次放行隔了多久：标准差就是出口抖动
+    pub lateness: Spread, // 树估的最早放行时刻 (next_ready) 到真放出来晚了多久：轮询 / 睡过头的代价
+}
+
+// 均值 / 标准差 / 最大值，Welford 在线算法不存样本，单位微秒
+// 同一个文件开 / 关 TX pacing 各回放一遍，对比这几个数就知道出口匀不匀
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Spread {
+    count: u64,
+    mean_us: f64,
+    m2: f64,
+    max_us: f64,
+}
+
+impl Spread {
+    fn record(&mut self, d: Duration) {
+        let us = d.as_secs_f64() * 1e6;
+        self.count += 1;
+        let delta = us - self.mean_us;
+        self.mean_us += delta / self.count as f64;
+        self.m2 += delta * (us - self.mean_us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    pub fn mean_us(&self) -> f64 {
+        self.mean_us
+    }
+
+    pub fn stddev_us(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+
+    pub fn max_us(&self) -> f64 {
+        self.max_us
+    }
+}
+
+pub struct Replayer {
+    root: MonitorQdisc<Vec<u8>, FiveTuple>,
+    modifiers: ModifierMap<Vec<u8>, FiveTuple>,
+    key_policy: FlowKeyPolicy,
+    queue_num: usize, // 整个文件都当成从这个 NFQUEUE 队列收上来的，分类器照常按它分
+    idle_timeout: Duration,
+    tx_pacing: bool,
+    last_departure: Option<Instant>,
+    promised: Option<Instant>, // 上一次没活干时树估的最早放行时刻
+    summary: ReplaySummary,
+}
+
+impl Replayer {
+    pub fn new(
+        root: Box<dyn Qdisc<Vec<u8>, FiveTuple>>,
+        modifiers: ModifierMap<Vec<u8>, FiveTuple>,
+        key_policy: FlowKeyPolicy,
+        queue_num: usize,
+    ) -> Self {
+        Self {
+            root: MonitorQdisc::new("Root", root),
+            modifiers,
+            key_policy,
+            queue_num,
+            idle_timeout: Duration::from_micros(100),
+            tx_pacing: false,
+            last_departure: None,
+            promised: None,
+            summary: ReplaySummary::default(),
+        }
+    }
+
+    pub fn set_report_interval(&mut self, interval: Duration) {
+        self.root.set_report_interval(interval);
+    }
+
+    // 没活干时虚拟时间每轮往前拨多久，和 main 收包循环的 idle_timeout 一个意思 (收包循环就是醒这么勤)
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    // 和 main 的 TX_PACING 一样：树里积着包只差令牌时直接拨到 next_ready，不按 idle_timeout 轮询
+    pub fn set_tx_pacing(&mut self, enabled: bool) {
+        self.tx_pacing = enabled;
+    }
+
+    pub fn root(&self) -> &dyn Qdisc<Vec<u8>, FiveTuple> {
+        &self.root
+    }
+
+    // 按抓包时间戳在虚拟时间上回放：TTL、令牌桶这些都跟着抓包的时间线走，跑多快都不影响结果
+    // 调用之后本线程就一直走虚拟时间了
+    pub fn run(&mut self, path: &str) -> io::Result<ReplaySummary> {
+        let t0 = clock::now();
+        clock::set_virtual_now(t0);
+        let mut ts0: Option<Duration> = None;
+        for packet in PcapReader::open(path)? {
+            let packet = packet?;
+            let ts0 = *ts0.get_or_insert(packet.ts);
+            // 时间戳倒退的包 (多网卡合并的抓包常见) 不等，马上灌
+            let due = t0 + packet.ts.saturating_sub(ts0);
+            while clock::now() < due {
+                if !self.pump() {
+                    self.wait(due);
+                }
+            }
+            self.feed(packet.ip, packet.orig_len, packet.ts);
+        }
+
+        let mut last_progress = clock::now();
+        while self.outstanding() > 0 && clock::now() < last_progress + DRAIN_GRACE {
+            let moved = self.pump();
+            if moved {
+                last_progress = clock::now();
+            }
+            if !moved {
+                self.wait(last_progress + DRAIN_GRACE);
+            }
+        }
+        self.summary.leftover += self.root.flush().len() as u64;
+        Ok(self.summary)
+    }
+
+    // 和 StandardPipeline::ingest 一样：拆五元组、过修改器链，只是包内容换成了抓包里的字节
+    fn feed(&mut self, ip: Vec<u8>, orig_len: usize, ts: Duration) {
+        let key = self.key_policy.apply(&FiveTuple::from(ip.as_slice()));
+        let flow_hash = key.flow_hash();
+        let packet_id = self.summary.fed as u32 + 1;
+        let mut ctx = PacketContext::new(ip, key, flow_hash, self.queue_num, packet_id, orig_len);
+        ctx.arrival_wall = Some(SystemTime::UNIX_EPOCH + ts); // 丢包日志能直接和 pcap 对时间线
+
+        if let Some(modifiers) = self.modifiers.get(&self.queue_num) {
+            apply_modifiers(&mut ctx, modifiers);
+        }
+        self.summary.fed += 1;
+        if ctx.ingress_drop {
+            self.summary.dropped += 1;
+            return;
+        }
+        self.root.enqueue(ctx);
+    }
+
+    // 没活干时把表拨到哪：开了 pacing 且树能估出下一个包的时刻就拨到那 (最晚 until)，否则往前拨 idle_timeout
+    fn wait(&mut self, until: Instant) {
+        // 不开 pacing 也估一下，只为记 lateness 好对比
+        self.promised = self.root.next_ready();
+        let now = clock::now();
+        // 估出来就是现在却还放不出 (差一丁点浮点尾数之类)：别原地空转，照旧拨 idle_timeout
+        let pacing = self.tx_pacing;
+        let wake = match self.promised.filter(|at| pacing && *at > now) {
+            Some(at) => at,
+            None => now + self.idle_timeout,
+        };
+        clock::set_virtual_now(wake.min(until));
+    }
+
+    // 放行能走的、收掉该丢的；有包动过就返回 true
+    fn pump(&mut self) -> bool {
+        let mut sent = 0;
+        for _ in self.root.drain_ready() {
+            let now = clock::now();
+            if let Some(last) = self.last_departure.replace(now) {
+                self.summary.gaps.record(now - last);
+            }
+            if let Some(promised) = self.promised.take() {
+                self.summary
+                    .lateness
+                    .record(now.saturating_duration_since(promised));
+            }
+            sent += 1;
+        }
+        let dropped = self.root.collect_dropped().len() as u64;
+        self.summary.sent += sent;
+        self.summary.dropped += dropped;
+        sent + dropped > 0
+    }
+
+    fn outstanding(&self) -> u64 {
+        self.summary.fed - self.summary.sent - self.summary.dropped
+    }
+
+    pub fn print_summary(&self) {
+        self.root.report().print();
+        let s = &self.summary;
+        println!(
+            "🎞️ 回放：灌入 {} 个包，放行 {}，丢弃 {}，滞留 {}",
+            s.fed, s.sent, s.dropped, s.leftover
+        );
+        println!(
+            "⏱️ 出包间隔：平均 {:.1}µs，抖动 (标准差) {:.1}µs；比令牌到位晚：平均 {:.1}µs，最多 {:.1}µs (TX pacing {})",
+            s.gaps.mean_us(),
+            s.gaps.stddev_us(),
+            s.lateness.mean_us(),
+            s.lateness.max_us(),
+            if self.tx_pacing { "开" } else { "关" }
+        );
+        for (path, name, stats) in bucket_breakdown(self.root()) {
+            println!(
+                "🪣 {} @ {}: 放行 {:.1}MB，拒绝 {} 次 / {:.1}MB",
+                name,
+                path,
+                stats.bytes_passed as f64 / 1e6,
+                stats.deny_events,
+                stats.bytes_denied as f64 / 1e6
+            );
+        }
+        for origin in drop_breakdown(self.root()) {
+            println!(
+                "🪦 {:>8} 个 [{:?}] @ {}",
+                origin.count, origin.reason, origin.path
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PipelineConfig, build_modifiers, build_qdisc};
+    use crate::packet_context::DropReason;
+
+    // 4 条 UDP 流、200 个 500 字节的包，每毫秒一个 (4Mbps)，全当 queue 0 收上来
+    const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/replay_sample.pcap");
+
+    // 1Mbps 的全局桶顶着 4Mbps 的流量，排队超过 20ms 的包被整棵树外面那层 ttl_drop 丢掉
+    const PIPELINE: &str = r#"
+        [[modifiers]]
+        queues = [0]
+        chain = [{ type = "true_length" }]
+
+        [root]
+        type = "ttl_drop"
+        max_latency_ms = 20
+        strict = true
+
+        [root.inner]
+        type = "htb"
+        high_queues = [1]
+        global_bucket = { rate_mbps = 1.0, burst_kb = 4 }
+        high_bucket = { rate_mbps = 1.0, burst_kb = 4 }
+        low_bucket = { rate_mbps = 1.0, burst_kb = 4 }
+        high = { type = "fifo", limit = 64 }
+        low = { type = "fifo", limit = 64 }
+    "#;
+
+    fn replay(tx_pacing: bool) -> (Replayer, ReplaySummary) {
+        let config = PipelineConfig::from_toml(PIPELINE).expect("回放配置应能解析");
+        let root = build_qdisc(&config.root).expect("回放配置应能装配");
+        let mut replayer = Replayer::new(root, build_modifiers(&config), FlowKeyPolicy::Full, 0);
+        replayer.set_tx_pacing(tx_pacing);
+        let summary = replayer.run(SAMPLE).expect("样例抓包应能读");
+        (replayer, summary)
+    }
+
+    #[test]
+    fn sample_capture_replays_deterministically() {
+        let (replayer, summary) = replay(false);
+        assert_eq!(summary.fed, 200);
+        assert_eq!(summary.dropped, 138);
+        assert_eq!(summary.sent, 62);
+        assert_eq!(summary.leftover, 0);
+        let drops = drop_breakdown(replayer.root());
+        assert_eq!(drops.len(), 1);
+        assert_eq!(drops[0].reason, DropReason::LatencyExpired);
+        assert_eq!(drops[0].count, 138);
+
+        // 虚拟时间上跑，同一个文件每次都丢一样多；开不开 pacing 只影响出包时刻
+        assert_eq!(replay(false).1.dropped, 138);
+        assert_eq!(replay(true).1.dropped, 138);
+    }
+}
@@ -0,0 +1,345 @@
+// ==========================================
+// 统计 HTTP 接口 (feature = "stats-http")
+//   curl http://127.0.0.1:9100/stats   # 树里每个监控最近一个周期的报表，JSON
+// 监控活在单线程的主循环里，HTTP 线程碰不到它：主循环每个周期把 monitor_reports
+// 拉一份推进 Arc<Mutex<..>>，HTTP 线程只读这份副本，慢客户端拖不住调度
+// ==========================================
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::qdisc::{Qdisc, monitor_reports, wrapper::MonitorSnapshot};
+
+type Published = Arc<Mutex<Vec<(String, MonitorSnapshot)>>>;
+
+pub struct StatsServer {
+    published: Published,
+    interval: Duration,
+    last_publish: Option<Instant>,
+}
+
+impl StatsServer {
+    pub fn bind(addr: &str, interval: Duration) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let published: Published = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&published);
+        std::thread::Builder::new()
+            .name("stats-http".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Err(e) = serve(stream, &shared) {
+                        eprintln!("⚠️ 统计接口连接异常: {}", e);
+                    }
+                }
+            })?;
+        Ok(Self {
+            published,
+            interval,
+            last_publish: None,
+        })
+    }
+
+    // 主循环每一轮调一次：不到周期立刻返回，到了才走一遍树拷一份报表
+    pub fn publish<T, K>(&mut self, root: &dyn Qdisc<T, K>) {
+        if self
+            .last_publish
+            .is_some_and(|at| at.elapsed() < self.interval)
+        {
+            return;
+        }
+        self.last_publish = Some(Instant::now());
+        let reports = monitor_reports(root);
+        if let Ok(mut published) = self.published.lock() {
+            *published = reports;
+        }
+    }
+}
+
+// 一个连接只答一个请求，读完请求行就回，头部一概不看
+fn serve(stream: TcpStream, published: &Published) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let mut writer = stream.try_clone()?;
+    let mut request_line = String::new();
+    BufReader::new(stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/stats")) => {
+            let reports = published.lock().map(|r| r.clone()).unwrap_or_default();
+            ("200 OK", render(&reports))
+        }
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// 手拼 JSON：字段全是数字和树路径，不值得为它多拉一个依赖
+fn render(reports: &[(String, MonitorSnapshot)]) -> String {
+    let monitors: Vec<String> = reports
+        .iter()
+        .map(|(path, snap)| {
+            let queues: Vec<String> = snap
+                .queues
+                .iter()
+                .map(|(q_num, stat)| {
+                    format!(
+                        "{{\"queue\":{},\"in_pkts\":{},\"drop_pkts\":{},\"out_pkts\":{},\"reorder_pkts\":{},\"shaped_mbps\":{:.3},\"wire_mbps\":{:.3},\"backlog_pkts\":{},\"backlog_bytes\":{}}}",
+                        q_num,
+                        stat.in_pkts,
+                        stat.drop_pkts,
+                        stat.out_pkts,
+                        stat.reorder_pkts,
+                        snap.shaped_mbps(stat),
+                        snap.wire_mbps(stat),
+                        stat.backlog_pkts,
+                        stat.backlog_bytes
+                    )
+                })
+                .collect();
+            let total = snap.total();
+            let drops: Vec<String> = snap
+                .drop_reasons
+                .iter()
+                .map(|(reason, n)| format!("\"{:?}\":{}", reason, n))
+                .collect();
+            // 同名的桶 (比如几个 HTB 各有一只 global) 会重复，所以是数组不是对象
+            let headroom: Vec<String> = snap
+                .bucket_headroom
+                .iter()
+                .map(|(name, percent)| format!("{{\"bucket\":{},\"percent\":{:.1}}}", quote(name), percent))
+                .collect();
+            let head_wait_ms = snap
+                .head_wait
+                .map_or("null".to_string(), |w| format!("{:.3}", w.as_secs_f64() * 1e3));
+
+            let totals = snap.totals;
+
+            let mut out = String::new();
+            let _ = write!(
+                out,
+                "{{\"path\":{},\"name\":{},\"elapsed_ms\":{:.1},\"queues\":[{}],",
+                quote(path),
+                quote(&snap.name),
+                snap.elapsed.as_secs_f64() * 1e3,
+                queues.join(",")
+            );
+            let _ = write!(
+                out,
+                "\"total\":{{\"in_pkts\":{},\"drop_pkts\":{},\"out_pkts\":{},\"reorder_pkts\":{},\"shaped_mbps\":{:.3},\"wire_mbps\":{:.3},\"backlog_pkts\":{},\"backlog_bytes\":{}}},",
+                total.in_pkts,
+                total.drop_pkts,
+                total.out_pkts,
+                total.reorder_pkts,
+                snap.shaped_mbps(&total),
+                snap.wire_mbps(&total),
+                total.backlog_pkts,
+                total.backlog_bytes
+            );
+            let _ = write!(
+                out,
+                "\"drop_reasons\":{{{}}},\"bucket_headroom\":[{}],\"head_wait_ms\":{},",
+                drops.join(","),
+                headroom.join(","),
+                head_wait_ms
+            );
+            let _ = write!(
+                out,
+                "\"totals\":{{\"elapsed_ms\":{:.1},\"total_in\":{},\"total_out\":{},\"total_dropped\":{},\"total_bytes\":{}}}}}",
+                snap.totals_elapsed.as_secs_f64() * 1e3,
+                totals.total_in,
+                totals.total_out,
+                totals.total_dropped,
+                totals.total_bytes
+            );
+            out
+        })
+        .collect();
+    format!("{{\"monitors\":[{}]}}", monitors.join(","))
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_context::test_packet;
+    use crate::qdisc::leaf::HeadDropFifo;
+    use crate::qdisc::wrapper::MonitorQdisc;
+    use std::io::Read;
+
+    // 起一个只接一个连接的 serve，发 request_line 过去，拿回 (状态行, body)
+    fn request(published: &Published, request_line: &str) -> (String, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = Arc::clone(published);
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &shared).unwrap();
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(client, "{}\r\nHost: test\r\n\r\n", request_line).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let length = format!("Content-Length: {}", body.len());
+        assert!(head.lines().any(|line| line == length), "{}", head);
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    // 够用的 JSON 语法检查：对象 / 数组 / 字符串 / 数字 / null，吃掉一个值返回 true
+    fn json_value(s: &[u8], i: &mut usize) -> bool {
+        let skip_ws = |i: &mut usize| {
+            while *i < s.len() && s[*i].is_ascii_whitespace() {
+                *i += 1;
+            }
+        };
+        let list = |i: &mut usize, close: u8, item: &dyn Fn(&mut usize) -> bool| {
+            *i += 1;
+            skip_ws(i);
+            if s.get(*i) == Some(&close) {
+                *i += 1;
+                return true;
+            }
+            loop {
+                skip_ws(i);
+                if !item(i) {
+                    return false;
+                }
+                skip_ws(i);
+                match s.get(*i) {
+                    Some(b',') => *i += 1,
+                    Some(c) if *c == close => {
+                        *i += 1;
+                        return true;
+                    }
+                    _ => return false,
+                }
+            }
+        };
+        skip_ws(i);
+        match s.get(*i) {
+            Some(b'{') => list(i, b'}', &|i| {
+                s.get(*i) == Some(&b'"')
+                    && json_value(s, i)
+                    && {
+                        skip_ws(i);
+                        s.get(*i) == Some(&b':')
+                    }
+                    && {
+                        *i += 1;
+                        json_value(s, i)
+                    }
+            }),
+            Some(b'[') => list(i, b']', &|i| json_value(s, i)),
+            Some(b'"') => {
+                *i += 1;
+                while let Some(&c) = s.get(*i) {
+                    *i += if c == b'\\' { 2 } else { 1 };
+                    if c == b'"' {
+                        return true;
+                    }
+                    if c < 0x20 {
+                        return false;
+                    }
+                }
+                false
+            }
+            Some(b'n') if s[*i..].starts_with(b"null") => {
+                *i += 4;
+                true
+            }
+            _ => {
+                let start = *i;
+                while s.get(*i).is_some_and(|c| b"-+.eE0123456789".contains(c)) {
+                    *i += 1;
+                }
+                *i > start
+                    && std::str::from_utf8(&s[start..*i])
+                        .unwrap()
+                        .parse::<f64>()
+                        .is_ok()
+            }
+        }
+    }
+
+    fn is_json(body: &str) -> bool {
+        let mut i = 0;
+        json_value(body.as_bytes(), &mut i) && body[i..].trim().is_empty()
+    }
+
+    #[test]
+    fn stats_endpoint_serves_published_reports_as_json() {
+        // 名字里带引号和换行，顺带看转义
+        let mut monitor = MonitorQdisc::new("Edge \"wan\"\n", Box::new(HeadDropFifo::new(16)));
+        monitor.enqueue(test_packet(1, 0, 100));
+        monitor.enqueue(test_packet(2, 0, 200));
+        monitor.enqueue(test_packet(3, 1, 300));
+        monitor.dequeue();
+
+        let mut server = StatsServer {
+            published: Arc::new(Mutex::new(Vec::new())),
+            interval: Duration::from_secs(60),
+            last_publish: None,
+        };
+        server.publish(&monitor);
+
+        let (status, body) = request(&server.published, "GET /stats HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(is_json(&body), "{}", body);
+        assert!(body.starts_with("{\"monitors\":[{\"path\":"), "{}", body);
+        assert!(
+            body.contains("\"name\":\"Edge \\\"wan\\\"\\u000a\""),
+            "{}",
+            body
+        );
+        assert!(body.contains("{\"queue\":0,\"in_pkts\":2,\"drop_pkts\":0,\"out_pkts\":1,"));
+        assert!(body.contains("{\"queue\":1,\"in_pkts\":1,"));
+        assert!(body.contains("\"total\":{\"in_pkts\":3,\"drop_pkts\":0,\"out_pkts\":1,"));
+        assert!(body.contains("\"backlog_pkts\":2,\"backlog_bytes\":500}"));
+        assert!(body.contains("\"total_in\":3,\"total_out\":1,\"total_dropped\":0"));
+
+        // 周期没到，树再怎么变发布出去的也还是旧副本
+        monitor.dequeue();
+        server.publish(&monitor);
+        let (_, again) = request(&server.published, "GET /stats HTTP/1.1");
+        assert!(again.contains("\"total_out\":1,"));
+
+        let (status, body) = request(&server.published, "GET /metrics HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert!(is_json(&body));
+    }
+
+    #[test]
+    fn json_check_rejects_broken_documents() {
+        assert!(is_json("{\"a\":[1,-2.5e3,null,\"x\\\"y\"],\"b\":{}}"));
+        assert!(!is_json("{\"a\":1,}"));
+        assert!(!is_json("{\"a\" 1}"));
+        assert!(!is_json("[1,2"));
+        assert!(!is_json("{\"a\":NaN}"));
+    }
+}
@@ -1,11 +1,69 @@
 // ================= 极简令牌桶 =================
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock, SystemClock};
 
 // 为了解耦，定义一个令牌桶的 Trait (你的全局或局部 Bucket 都能用)
 pub trait TokenBucketLimiter {
     fn can_spend(&mut self, cost: usize) -> bool;
+    // 现在付不付得起 cost，只问不扣，也不记拒绝：调度器挑包时一轮要试探好几只桶、好几种价钱，
+    // 用 can_spend 试探的话没挑中的那几次都会被记成卡脖子
+    fn affords(&mut self, cost: usize) -> bool;
+    // 调度器自己判定这个包卡住了 (价钱里还含着给别人留的准备金，can_spend 问不出来) 时补记一笔拒绝，
+    // 记的是包本身的字节数；和 can_spend 一样，一回卡脖子只记一次
+    fn record_denied(&mut self, cost: usize);
     fn consume(&mut self, cost: usize) -> bool;
+    // 不看余额硬扣，付不起就欠着 (余额扣成负数)，之后补水先还账：已经放走的包 (控制包快车道) 事后记账用
+    fn force_consume(&mut self, cost: usize);
+    fn set_rate(&mut self, rate_bytes_per_sec: f64);
+    fn stats(&self) -> BucketStats;
+    fn reset_stats(&mut self);
+    // 最早什么时候付得起 cost (只问不扣，也不记拒绝)；现在就付得起返回当前时刻，永远付不起返回 None
+    fn ready_at(&mut self, cost: usize) -> Option<Instant>;
+    // 此刻桶里有多少字节的余额 / 最多能攒多少 (只读，按现在的时间折算，不改桶的状态)
+    fn tokens(&self) -> f64;
+    fn capacity(&self) -> f64;
+
+    // 按帧计费的介质 (无线空口之类) 要同时看字节数和帧数；普通桶只认字节
+    fn can_spend_frames(&mut self, cost: usize, _frames: usize) -> bool {
+        self.can_spend(cost)
+    }
+    fn affords_frames(&mut self, cost: usize, _frames: usize) -> bool {
+        self.affords(cost)
+    }
+    fn consume_frames(&mut self, cost: usize, _frames: usize) -> bool {
+        self.consume(cost)
+    }
+    fn force_consume_frames(&mut self, cost: usize, _frames: usize) {
+        self.force_consume(cost)
+    }
+    fn ready_at_frames(&mut self, cost: usize, _frames: usize) -> Option<Instant> {
+        self.ready_at(cost)
+    }
+}
+
+// 桶的体检表：放行了多少、拒了多少
+// peek 每轮都会问一次 can_spend，同一个包等令牌期间会被拒很多次，这里只记头一次：
+// 从被拒到下一次放行算一次卡脖子，deny_events 是卡了几回，bytes_denied 是每回卡住的那个包的字节数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BucketStats {
+    pub bytes_passed: u64,
+    pub bytes_denied: u64,
+    pub deny_events: u64,
+    // 取体检表那一刻的余额和容量 (字节)，看离封顶还有多少余量
+    pub tokens: i64,
+    pub capacity: u64,
+}
+
+impl BucketStats {
+    // 余额占容量的百分比：100 = 满桶 (一点都没在卡)，0 = 见底；容量为 0 的桶没有意义，返回 None
+    pub fn headroom_percent(&self) -> Option<f64> {
+        if self.capacity == 0 {
+            return None;
+        }
+        Some((self.tokens as f64 / self.capacity as f64 * 100.0).clamp(0.0, 100.0))
+    }
 }
 
 pub struct TokenBucket {
@@ -13,7 +71,13 @@ pub struct TokenBucket {
     rate: f64,       // 速率 (字节/秒)
     capacity: f64,   // 桶容量 (突发限制)
     last_update: Instant,
+    // 闲置衰减：两次补水之间隔了这么久 (没人来问过桶，也就是真闲着)，余额就只认这一段时间的量，
+    // 之前攒满的不算数，闲了很久之后第一波突发不会是一整桶；None = 闲多久都能攒满 (老行为)
+    idle_decay: Option<Duration>,
     _name: String,
+    stats: BucketStats,
+    stalled: bool, // 已经记过这回卡脖子了，放行之前再被拒不重复记
+    clock: Box<dyn Clock>,
 }
 
 impl TokenBucket {
@@ -23,22 +87,62 @@ impl TokenBucket {
             tokens: burst_bytes, // 初始给满
             rate: rate_bytes_per_sec,
             capacity: burst_bytes,
-            last_update: Instant::now(),
+            last_update: clock::now(),
+            idle_decay: None,
             _name: bucket_name.to_string(),
+            stats: BucketStats::default(),
+            stalled: false,
+            clock: Box::new(SystemClock),
         }
     }
+
+    // 闲过 decay 之后余额最多只认 rate × decay (不超过 capacity，欠着的账不一笔勾销)；恢复流量后照常按 rate 攒回满桶
+    // 付不起的大包也不会卡死：被拒的那次询问本身就结束了闲置，之后的补水不再衰减
+    pub fn set_idle_decay(&mut self, decay: Duration) {
+        self.idle_decay = Some(decay);
+    }
+
+    // 换表的同时把补水起点对齐到新表，否则两只表的差值会被当成流逝的时间
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.last_update = clock.now();
+        self.clock = clock;
+    }
+
     fn refill(&mut self) {
-        let now = Instant::now();
+        let now = self.clock.now();
+        // 表往回跳了 (挂起恢复、某些虚拟机的时钟)：这次不补水，起点对齐到现在，
+        // 不然桶要一直冻到表追上旧的 last_update
+        if now < self.last_update {
+            self.last_update = now;
+            return;
+        }
         // 使用高精度时间差
-        let elapsed = now.duration_since(self.last_update).as_secs_f64();
+        let elapsed = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
 
         // 只有时间流逝大于微小阈值才计算，避免浮点误差（虽然Rust f64精度很高，这算是个好习惯）
         if elapsed > 0.0001 {
-            let new_tokens = self.rate * elapsed;
-            self.tokens = (self.tokens + new_tokens).min(self.capacity);
+            self.tokens = self.refilled(elapsed);
             self.last_update = now;
         }
     }
+
+    // 流逝 elapsed 秒之后的余额 (不写回)
+    fn refilled(&self, elapsed: f64) -> f64 {
+        // 睡了很久回来 rate × elapsed 可能大得离谱，先夹到 capacity；
+        // f64::min 遇到 NaN 返回另一边，速率被配成 inf / NaN 时也就是直接装满
+        let new_tokens = (self.rate * elapsed).min(self.capacity);
+        let tokens = (self.tokens + new_tokens).min(self.capacity);
+        match self.idle_decay {
+            // 闲置衰减只压低攒出来的余额，欠着的账 (force_consume 扣成的负数) 照样按速率慢慢还
+            Some(decay) if elapsed >= decay.as_secs_f64() => {
+                tokens.min(self.rate * decay.as_secs_f64())
+            }
+            _ => tokens,
+        }
+    }
 }
 
 impl TokenBucketLimiter for TokenBucket {
@@ -52,16 +156,573 @@ impl TokenBucketLimiter for TokenBucket {
         // 2. 再判断
         if self.tokens >= amount {
             self.tokens -= amount;
+            self.stats.bytes_passed += amount as u64;
+            self.stalled = false;
             // println!("[{}]桶令牌剩余 {}", self.name, self.tokens);
             true
         } else {
+            self.record_denied(amount as usize);
             // println!("[{}]桶令牌不足", self.name);
             false
         }
     }
 
     fn can_spend(&mut self, amount: usize) -> bool {
+        self.refill();
+        let ok = self.tokens >= amount as f64;
+        if ok {
+            self.stalled = false;
+        } else {
+            self.record_denied(amount);
+        }
+        ok
+    }
+
+    fn affords(&mut self, amount: usize) -> bool {
         self.refill();
         self.tokens >= amount as f64
     }
+
+    fn force_consume(&mut self, amount: usize) {
+        self.refill();
+        self.tokens -= amount as f64;
+        self.stats.bytes_passed += amount as u64;
+    }
+
+    fn record_denied(&mut self, amount: usize) {
+        if self.stalled {
+            return;
+        }
+        self.stalled = true;
+        self.stats.bytes_denied += amount as u64;
+        self.stats.deny_events += 1;
+    }
+
+    fn set_rate(&mut self, rate_bytes_per_sec: f64) {
+        // 先按老速率把欠的水补上，再切新速率，避免新速率倒算过去的时间
+        self.refill();
+        self.rate = rate_bytes_per_sec;
+    }
+
+    fn stats(&self) -> BucketStats {
+        BucketStats {
+            tokens: self.tokens() as i64,
+            capacity: self.capacity() as u64,
+            ..self.stats
+        }
+    }
+
+    fn reset_stats(&mut self) {
+        self.stats = BucketStats::default();
+    }
+
+    fn tokens(&self) -> f64 {
+        // 和 refill 一样折算，只是不写回
+        let elapsed = self
+            .clock
+            .now()
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        // 和 refill 同一个阈值：刚结算过就是账面余额，也躲开速率 inf 时 inf × 0 = NaN 被 min 当成满桶
+        if elapsed <= 0.0001 {
+            return self.tokens;
+        }
+        self.refilled(elapsed)
+    }
+
+    fn capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    fn ready_at(&mut self, amount: usize) -> Option<Instant> {
+        self.refill();
+        let deficit = amount as f64 - self.tokens;
+        if deficit <= 0.0 {
+            return Some(self.clock.now());
+        }
+        // 比桶还大的包、速率被调成 0 的桶，等多久都攒不够
+        if amount as f64 > self.capacity || self.rate <= 0.0 {
+            return None;
+        }
+        // tokens 是按 last_update 那一刻结算的，从那里往后算缺口补满的时间
+        Some(self.last_update + Duration::from_secs_f64(deficit / self.rate))
+    }
+}
+
+// ================= 漏桶 (严格匀速) =================
+// 大突发的令牌桶会一口气放走几百 KB，下游排队抖一阵，然后桶空了又静默一阵，出口成了锯齿
+// 漏桶按虚拟时间排班：每放一个包，下一个包的最早放行时间往后推 cost / rate
+// 出队来晚了最多追回一个 MTU 的时长，闲置再久也攒不出突发
+// 比 MTU 大的包 (GSO 聚合包之类) 照样能过，只是之后要等更久
+pub struct LeakyBucket {
+    rate: f64,          // 速率 (字节/秒)
+    mtu: usize,         // 最多攒多少字节的余量
+    next_free: Instant, // 下一个包最早什么时候能走
+    _name: String,
+    stats: BucketStats,
+    stalled: bool, // 同 TokenBucket::stalled
+    clock: Box<dyn Clock>,
+}
+
+impl LeakyBucket {
+    pub fn new(rate_bytes_per_sec: f64, mtu: usize, bucket_name: &str) -> Self {
+        Self {
+            rate: rate_bytes_per_sec.max(1.0),
+            mtu,
+            next_free: clock::now(),
+            _name: bucket_name.to_string(),
+            stats: BucketStats::default(),
+            stalled: false,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.next_free = clock.now();
+        self.clock = clock;
+    }
+
+    fn duration_of(&self, bytes: usize) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / self.rate)
+    }
+}
+
+impl TokenBucketLimiter for LeakyBucket {
+    fn can_spend(&mut self, amount: usize) -> bool {
+        let ok = self.clock.now() >= self.next_free;
+        if ok {
+            self.stalled = false;
+        } else {
+            self.record_denied(amount);
+        }
+        ok
+    }
+
+    fn affords(&mut self, _amount: usize) -> bool {
+        self.clock.now() >= self.next_free
+    }
+
+    fn record_denied(&mut self, amount: usize) {
+        if self.stalled {
+            return;
+        }
+        self.stalled = true;
+        self.stats.bytes_denied += amount as u64;
+        self.stats.deny_events += 1;
+    }
+
+    fn consume(&mut self, amount: usize) -> bool {
+        let now = self.clock.now();
+        if now < self.next_free {
+            self.record_denied(amount);
+            return false;
+        }
+        self.force_consume(amount);
+        self.stalled = false;
+        true
+    }
+
+    // 还没到 next_free 就硬扣：下一班照样往后推，等于欠着
+    fn force_consume(&mut self, amount: usize) {
+        let now = self.clock.now();
+        // 从 "本该放行的时间" 往后排，但最多欠一个 MTU，闲置期间的额度不累积
+        let slack = self.duration_of(self.mtu);
+        let base = now
+            .checked_sub(slack)
+            .map_or(now, |floor| self.next_free.max(floor));
+        self.next_free = base + self.duration_of(amount);
+        self.stats.bytes_passed += amount as u64;
+    }
+
+    fn set_rate(&mut self, rate_bytes_per_sec: f64) {
+        // 已经排好的下一班时间不动，从下一个包开始按新速率排
+        self.rate = rate_bytes_per_sec.max(1.0);
+    }
+
+    fn stats(&self) -> BucketStats {
+        BucketStats {
+            tokens: self.tokens() as i64,
+            capacity: self.capacity() as u64,
+            ..self.stats
+        }
+    }
+
+    fn reset_stats(&mut self) {
+        self.stats = BucketStats::default();
+    }
+
+    // 漏桶的 "余额"：过了 next_free 多久就攒了多少字节，最多一个 MTU；还没到 next_free 就是欠着 (负数)
+    fn tokens(&self) -> f64 {
+        let now = self.clock.now();
+        let behind = now.saturating_duration_since(self.next_free).as_secs_f64();
+        let ahead = self.next_free.saturating_duration_since(now).as_secs_f64();
+        ((behind - ahead) * self.rate).min(self.mtu as f64)
+    }
+
+    fn capacity(&self) -> f64 {
+        self.mtu as f64
+    }
+
+    fn ready_at(&mut self, _amount: usize) -> Option<Instant> {
+        Some(self.next_free.max(self.clock.now()))
+    }
+}
+
+// ================= 按帧计费的令牌桶 =================
+// 每一帧额外收 frame_cost 字节的 "空口费"：一个大帧和一堆小帧字节数一样，后者更贵
+// frame_cost = 0 时和普通 TokenBucket 完全一样
+pub struct FrameAwareTokenBucket {
+    inner: Box<dyn TokenBucketLimiter>,
+    frame_cost: usize,
+}
+
+impl FrameAwareTokenBucket {
+    pub fn new(
+        rate_bytes_per_sec: f64,
+        burst_bytes: f64,
+        frame_cost: usize,
+        bucket_name: &str,
+    ) -> Self {
+        Self {
+            inner: Box::new(TokenBucket::new(
+                rate_bytes_per_sec,
+                burst_bytes,
+                bucket_name,
+            )),
+            frame_cost,
+        }
+    }
+
+    // 同样按帧计费，底下换成严格匀速的漏桶
+    pub fn leaky(
+        rate_bytes_per_sec: f64,
+        mtu: usize,
+        frame_cost: usize,
+        bucket_name: &str,
+    ) -> Self {
+        Self {
+            inner: Box::new(LeakyBucket::new(rate_bytes_per_sec, mtu, bucket_name)),
+            frame_cost,
+        }
+    }
+
+    // 带闲置衰减的普通令牌桶，见 TokenBucket::set_idle_decay
+    pub fn decaying(
+        rate_bytes_per_sec: f64,
+        burst_bytes: f64,
+        idle_decay: Duration,
+        frame_cost: usize,
+        bucket_name: &str,
+    ) -> Self {
+        let mut bucket = TokenBucket::new(rate_bytes_per_sec, burst_bytes, bucket_name);
+        bucket.set_idle_decay(idle_decay);
+        Self {
+            inner: Box::new(bucket),
+            frame_cost,
+        }
+    }
+
+    fn charge(&self, cost: usize, frames: usize) -> usize {
+        cost.saturating_add(frames.saturating_mul(self.frame_cost))
+    }
+}
+
+impl TokenBucketLimiter for FrameAwareTokenBucket {
+    fn can_spend(&mut self, cost: usize) -> bool {
+        self.inner.can_spend(cost)
+    }
+
+    fn affords(&mut self, cost: usize) -> bool {
+        self.inner.affords(cost)
+    }
+
+    fn record_denied(&mut self, cost: usize) {
+        self.inner.record_denied(cost);
+    }
+
+    fn consume(&mut self, cost: usize) -> bool {
+        self.inner.consume(cost)
+    }
+
+    fn force_consume(&mut self, cost: usize) {
+        self.inner.force_consume(cost);
+    }
+
+    fn set_rate(&mut self, rate_bytes_per_sec: f64) {
+        self.inner.set_rate(rate_bytes_per_sec);
+    }
+
+    fn stats(&self) -> BucketStats {
+        self.inner.stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats();
+    }
+
+    fn tokens(&self) -> f64 {
+        self.inner.tokens()
+    }
+
+    fn capacity(&self) -> f64 {
+        self.inner.capacity()
+    }
+
+    fn can_spend_frames(&mut self, cost: usize, frames: usize) -> bool {
+        let charge = self.charge(cost, frames);
+        self.inner.can_spend(charge)
+    }
+
+    fn affords_frames(&mut self, cost: usize, frames: usize) -> bool {
+        let charge = self.charge(cost, frames);
+        self.inner.affords(charge)
+    }
+
+    fn consume_frames(&mut self, cost: usize, frames: usize) -> bool {
+        let charge = self.charge(cost, frames);
+        self.inner.consume(charge)
+    }
+
+    fn force_consume_frames(&mut self, cost: usize, frames: usize) {
+        let charge = self.charge(cost, frames);
+        self.inner.force_consume(charge);
+    }
+
+    fn ready_at(&mut self, cost: usize) -> Option<Instant> {
+        self.inner.ready_at(cost)
+    }
+
+    fn ready_at_frames(&mut self, cost: usize, frames: usize) -> Option<Instant> {
+        let charge = self.charge(cost, frames);
+        self.inner.ready_at(charge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn bucket(clock: &MockClock) -> TokenBucket {
+        let mut bucket = TokenBucket::new(1000.0, 1000.0, "test");
+        bucket.set_clock(Box::new(clock.clone()));
+        bucket
+    }
+
+    #[test]
+    fn consuming_above_rate_records_denials() {
+        let clock = MockClock::new();
+        let mut bucket = bucket(&clock);
+        assert!(bucket.consume(1000));
+        assert!(!bucket.consume(500));
+        let stats = bucket.stats();
+        assert_eq!(stats.bytes_passed, 1000);
+        assert_eq!(stats.bytes_denied, 500);
+        assert_eq!(stats.deny_events, 1);
+
+        bucket.reset_stats();
+        assert_eq!(bucket.stats().bytes_passed, 0);
+    }
+
+    #[test]
+    fn one_stall_counts_as_one_denial() {
+        let clock = MockClock::new();
+        let mut bucket = bucket(&clock);
+        assert!(bucket.consume(1000));
+        // 同一个队头包等令牌期间 peek 问了一轮又一轮
+        for _ in 0..10 {
+            assert!(!bucket.can_spend(600));
+            clock.advance(Duration::from_millis(50));
+        }
+        assert_eq!(bucket.stats().deny_events, 1);
+        assert_eq!(bucket.stats().bytes_denied, 600);
+
+        // 攒够了放行，再卡住就是新的一回
+        clock.advance(Duration::from_millis(100));
+        assert!(bucket.can_spend(600));
+        assert!(bucket.consume(600));
+        assert!(!bucket.can_spend(600));
+        assert!(!bucket.can_spend(600));
+        assert_eq!(bucket.stats().deny_events, 2);
+    }
+
+    #[test]
+    fn refill_is_driven_by_the_clock() {
+        let clock = MockClock::new();
+        let mut bucket = bucket(&clock);
+        assert!(bucket.consume(1000));
+        assert_eq!(bucket.tokens(), 0.0);
+        assert_eq!(
+            bucket.ready_at(500),
+            Some(clock.now() + Duration::from_millis(500))
+        );
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(bucket.tokens(), 250.0);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(bucket.tokens(), 1000.0); // 封顶在容量
+    }
+
+    #[test]
+    fn leaky_bucket_counts_one_denial_per_stall() {
+        let clock = MockClock::new();
+        let mut bucket = LeakyBucket::new(1000.0, 1500, "leaky");
+        bucket.set_clock(Box::new(clock.clone()));
+        assert!(bucket.consume(1000)); // 下一班排到 1 秒后
+        for _ in 0..5 {
+            assert!(!bucket.can_spend(100));
+            clock.advance(Duration::from_millis(100));
+        }
+        assert_eq!(bucket.stats().deny_events, 1);
+        clock.advance(Duration::from_millis(500));
+        assert!(bucket.can_spend(100));
+        assert!(bucket.consume(100));
+        assert_eq!(bucket.stats().bytes_passed, 1100);
+    }
+
+    #[test]
+    fn headroom_follows_tokens_over_capacity() {
+        let clock = MockClock::new();
+        let mut bucket = bucket(&clock);
+        assert_eq!(bucket.capacity(), 1000.0);
+        assert_eq!(bucket.stats().headroom_percent(), Some(100.0));
+        assert!(bucket.consume(995));
+        let drained = bucket.stats().headroom_percent().unwrap();
+        assert!(drained < 1.0, "drained={drained}");
+
+        // 漏桶的容量是一个 MTU，刚放走一个包时还欠着，读数夹在 0
+        let mut leaky = LeakyBucket::new(1000.0, 1500, "leaky");
+        leaky.set_clock(Box::new(clock.clone()));
+        assert_eq!(leaky.capacity(), 1500.0);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(leaky.stats().headroom_percent(), Some(100.0));
+        assert!(leaky.consume(1500));
+        assert!(leaky.consume(1500));
+        assert_eq!(leaky.stats().headroom_percent(), Some(0.0));
+    }
+
+    #[test]
+    fn backwards_clock_mints_nothing_and_refill_resumes() {
+        let clock = MockClock::new();
+        let mut bucket = bucket(&clock);
+        let start = clock.now();
+        assert!(bucket.consume(1000));
+
+        // 表倒退 10 秒：不 panic，不凭空补水，也不会因为 now < last_update 算出负的流逝
+        clock.rewind(Duration::from_secs(10));
+        assert_eq!(bucket.tokens(), 0.0);
+        assert!(!bucket.can_spend(1));
+        assert_eq!(bucket.tokens(), 0.0);
+
+        // 起点已经对齐到倒退后的时刻，从这里往后照常补水，不用等表追回 start
+        clock.advance(Duration::from_millis(500));
+        assert!(clock.now() < start);
+        assert_eq!(bucket.tokens(), 500.0);
+        assert!(bucket.consume(500));
+
+        // 直接拨回原来的时刻：中间这 9.5 秒算正常流逝，夹在容量
+        clock.set(start);
+        assert_eq!(bucket.tokens(), 1000.0);
+
+        // 睡了一年、速率被配成 inf：都夹在容量，不会冒出 inf / NaN
+        clock.advance(Duration::from_secs(365 * 86_400));
+        assert_eq!(bucket.tokens(), 1000.0);
+        assert!(bucket.consume(1000));
+        bucket.set_rate(f64::INFINITY);
+        clock.advance(Duration::from_millis(1));
+        assert!(bucket.consume(1000));
+        assert_eq!(bucket.tokens(), 0.0);
+    }
+
+    #[test]
+    fn idle_decay_caps_the_first_burst_but_keeps_debt() {
+        let clock = MockClock::new();
+        let mut bucket = TokenBucket::new(1000.0, 10_000.0, "decay");
+        bucket.set_clock(Box::new(clock.clone()));
+        bucket.set_idle_decay(Duration::from_secs(2));
+
+        // 满桶闲了 30 秒：第一波只剩 2 秒的量，不是整桶
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(bucket.tokens(), 2000.0);
+        assert!(!bucket.consume(2001));
+        assert!(bucket.consume(2000));
+
+        // 没闲够 decay 照常按速率攒
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(bucket.tokens(), 1000.0);
+
+        // 欠了 8000 再闲 3 秒：按速率还了 3000，还欠 5000，不会被衰减直接抹成 +2000
+        bucket.force_consume(9000);
+        assert_eq!(bucket.tokens(), -8000.0);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(bucket.tokens(), -5000.0);
+        assert!(!bucket.consume(1));
+        assert_eq!(bucket.tokens(), -5000.0);
+    }
+
+    #[test]
+    fn many_small_frames_cost_more_airtime_than_one_big_frame() {
+        // 速率 0：不补水，余额的差就是收费的差
+        let drained = |frame_cost: usize, frames: usize| {
+            let mut bucket: Box<dyn TokenBucketLimiter> = Box::new(FrameAwareTokenBucket::new(
+                0.0, 100_000.0, frame_cost, "air",
+            ));
+            assert!(bucket.consume_frames(9000, frames));
+            100_000.0 - bucket.tokens()
+        };
+        assert_eq!(drained(40, 1), 9040.0);
+        assert_eq!(drained(40, 6), 9240.0);
+        // 不收空口费就和帧数无关
+        assert_eq!(drained(0, 1), drained(0, 6));
+
+        // 字节数付得起、加上帧费就付不起
+        let mut bucket = FrameAwareTokenBucket::new(0.0, 9100.0, 40, "air");
+        assert!(bucket.affords(9000));
+        assert!(bucket.affords_frames(9000, 2));
+        assert!(!bucket.affords_frames(9000, 3));
+        assert!(!bucket.consume_frames(9000, 3));
+        assert_eq!(bucket.tokens(), 9100.0);
+    }
+
+    // 20 个 1000 字节的包同一时刻到齐，每 1ms 问一次桶，付得起就放一个：返回相邻两次放行的间隔 (ms)
+    fn release_gaps(bucket: &mut dyn TokenBucketLimiter, clock: &MockClock) -> Vec<f64> {
+        let mut released = Vec::new();
+        while released.len() < 20 {
+            if bucket.consume(1000) {
+                released.push(clock.now());
+            } else {
+                clock.advance(Duration::from_millis(1));
+            }
+        }
+        released
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_secs_f64() * 1000.0)
+            .collect()
+    }
+
+    fn variance(xs: &[f64]) -> f64 {
+        let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+        xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / xs.len() as f64
+    }
+
+    #[test]
+    fn leaky_bucket_spaces_a_burst_evenly() {
+        // 同样 100KB/s：令牌桶先一口气放走 10KB 的突发再按 10ms 一个，漏桶从头到尾都是 10ms 一个
+        let clock = MockClock::new();
+        let mut token = TokenBucket::new(100_000.0, 10_000.0, "token");
+        token.set_clock(Box::new(clock.clone()));
+        let token_gaps = release_gaps(&mut token, &clock);
+
+        let mut leaky = LeakyBucket::new(100_000.0, 1500, "leaky");
+        leaky.set_clock(Box::new(clock.clone()));
+        let leaky_gaps = release_gaps(&mut leaky, &clock);
+
+        assert!(
+            leaky_gaps.iter().all(|&gap| (gap - 10.0).abs() < 1e-6),
+            "{leaky_gaps:?}"
+        );
+        assert!(token_gaps.iter().filter(|&&gap| gap == 0.0).count() >= 9);
+        assert!(variance(&leaky_gaps) < 1e-9);
+        assert!(variance(&token_gaps) > 10.0);
+    }
 }
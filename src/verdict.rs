@@ -0,0 +1,238 @@
+use nfq::{Message, Queue, Verdict};
+
+use crate::{
+    fragment::FragmentSender,
+    packet_context::{PacketContext, ReleaseVerdict},
+};
+
+// 同一个队列连续失败这么多次才算“持续性故障”，值得吼一嗓子
+const PERSISTENT_FAILURES: u64 = 100;
+
+// ==========================================
+// verdict 回执账本
+// 内核拒收 verdict (比如包早就在内核队列里超时了) 不能再默默吞掉
+// ==========================================
+#[derive(Default)]
+pub struct VerdictStats {
+    pub sent: u64,
+    pub failed: u64,
+    consecutive: Vec<u64>, // 每个队列当前的连续失败次数
+}
+
+impl VerdictStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, queue_num: usize, result: std::io::Result<()>) {
+        if self.consecutive.len() <= queue_num {
+            self.consecutive.resize(queue_num + 1, 0);
+        }
+
+        match result {
+            Ok(()) => {
+                self.sent += 1;
+                self.consecutive[queue_num] = 0;
+            }
+            Err(e) => {
+                self.failed += 1;
+                self.consecutive[queue_num] += 1;
+                // 偶发失败只计数，连续失败每满一轮打一次日志，防止刷屏
+                if self.consecutive[queue_num].is_multiple_of(PERSISTENT_FAILURES) {
+                    eprintln!(
+                        "⚠️ 队列 {} 已连续 {} 次 verdict 失败 (累计 {}): {}",
+                        queue_num, self.consecutive[queue_num], self.failed, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+// verdict 发到哪：线上就是 nfq 的 Queue；nfq::Message 在库外造不出来，测试里换成收下消息的假队列
+pub trait VerdictSink {
+    type Message: VerdictMessage;
+
+    fn verdict(&mut self, msg: Self::Message) -> std::io::Result<()>;
+}
+
+// 发 verdict 之前要对消息做的几件事
+pub trait VerdictMessage {
+    fn set_verdict(&mut self, verdict: Verdict);
+    fn set_nfmark(&mut self, mark: u32);
+    fn payload(&self) -> &[u8];
+    fn set_payload(&mut self, payload: Vec<u8>);
+}
+
+impl VerdictSink for Queue {
+    type Message = Message;
+
+    fn verdict(&mut self, msg: Message) -> std::io::Result<()> {
+        Queue::verdict(self, msg)
+    }
+}
+
+impl VerdictMessage for Message {
+    fn set_verdict(&mut self, verdict: Verdict) {
+        Message::set_verdict(self, verdict);
+    }
+
+    fn set_nfmark(&mut self, mark: u32) {
+        Message::set_nfmark(self, mark);
+    }
+
+    fn payload(&self) -> &[u8] {
+        self.get_payload()
+    }
+
+    fn set_payload(&mut self, payload: Vec<u8>) {
+        Message::set_payload(self, payload);
+    }
+}
+
+// 统一的 verdict 出口：盖章、发送、记账
+// 每个包一次 sendmsg。内核的 NFQNL_MSG_VERDICT_BATCH 只能按 "id ≤ N 全部同一判决" 批量回执，
+// nfq 0.2.5 既没封装它也不暴露 fd；而且整形会打乱出队顺序，能凑成连续一段的机会本来就少
+// 包在出队/丢弃时已经从 Monitor 的积压水位里核销过了，这里失败也不会留下账目窟窿
+pub fn send_verdict<S: VerdictSink>(
+    queue: &mut S,
+    queue_num: usize,
+    mut msg: S::Message,
+    verdict: Verdict,
+    stats: &mut VerdictStats,
+) {
+    msg.set_verdict(verdict);
+    stats.record(queue_num, queue.verdict(msg));
+}
+
+// 正常出队的包：按 ctx 上挂的 mark / 回执方式放行
+// 要求真分片的包先拆好：后续分片由 fragmenter 直接发走，这里只放行改写成首片的原包
+pub fn send_release<S: VerdictSink, T: Into<S::Message>, K>(
+    queue: &mut S,
+    ctx: PacketContext<T, K>,
+    fragmenter: Option<&mut FragmentSender>,
+    stats: &mut VerdictStats,
+) {
+    let verdict = match ctx.release {
+        ReleaseVerdict::Accept => Verdict::Accept,
+        ReleaseVerdict::Repeat => Verdict::Repeat,
+        ReleaseVerdict::Stop => Verdict::Stop,
+    };
+    let mut msg: S::Message = ctx.msg.into();
+    if let Some(mark) = ctx.mark {
+        msg.set_nfmark(mark);
+    }
+    if let Some(mtu) = ctx.split_mtu
+        && let Some(fragmenter) = fragmenter
+        && let Some(first) = fragmenter.split(msg.payload(), mtu)
+    {
+        msg.set_payload(first);
+    }
+    send_verdict(queue, ctx.queue_num, msg, verdict, stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 假消息：记下盖了什么章
+    #[derive(Default)]
+    struct Stamped {
+        verdict: Option<Verdict>,
+        mark: Option<u32>,
+        payload: Vec<u8>,
+    }
+
+    impl VerdictMessage for Stamped {
+        fn set_verdict(&mut self, verdict: Verdict) {
+            self.verdict = Some(verdict);
+        }
+
+        fn set_nfmark(&mut self, mark: u32) {
+            self.mark = Some(mark);
+        }
+
+        fn payload(&self) -> &[u8] {
+            &self.payload
+        }
+
+        fn set_payload(&mut self, payload: Vec<u8>) {
+            self.payload = payload;
+        }
+    }
+
+    // 假队列：接下来 fail_next 次像内核那样回 ENOENT，之后照单全收
+    #[derive(Default)]
+    struct ScriptedSink {
+        fail_next: usize,
+        accepted: Vec<Stamped>,
+    }
+
+    impl VerdictSink for ScriptedSink {
+        type Message = Stamped;
+
+        fn verdict(&mut self, msg: Stamped) -> std::io::Result<()> {
+            if self.fail_next > 0 {
+                self.fail_next -= 1;
+                return Err(std::io::Error::from_raw_os_error(libc::ENOENT));
+            }
+            self.accepted.push(msg);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_verdicts_are_counted_and_a_success_ends_the_streak() {
+        let mut sink = ScriptedSink {
+            fail_next: 3,
+            ..Default::default()
+        };
+        let mut stats = VerdictStats::new();
+        let send = |sink: &mut ScriptedSink, stats: &mut VerdictStats, queue_num| {
+            send_verdict(sink, queue_num, Stamped::default(), Verdict::Accept, stats);
+        };
+        for _ in 0..3 {
+            send(&mut sink, &mut stats, 2);
+        }
+        assert_eq!((stats.sent, stats.failed), (0, 3));
+        assert_eq!(stats.consecutive, [0, 0, 3]);
+
+        send(&mut sink, &mut stats, 2);
+        assert_eq!((stats.sent, stats.failed), (1, 3));
+        assert_eq!(stats.consecutive, [0, 0, 0]);
+        assert_eq!(sink.accepted[0].verdict, Some(Verdict::Accept));
+
+        // 连续失败按队列各记各的
+        sink.fail_next = 2;
+        send(&mut sink, &mut stats, 0);
+        send(&mut sink, &mut stats, 2);
+        assert_eq!(stats.consecutive, [1, 0, 1]);
+        assert_eq!(stats.failed, 5);
+    }
+
+    #[test]
+    fn release_stamps_the_mark_together_with_the_verdict() {
+        let mut sink = ScriptedSink::default();
+        let mut stats = VerdictStats::new();
+        let mut marked = PacketContext::new(Stamped::default(), 0u64, 1, 0, 0, 100);
+        marked.mark = Some(0x42);
+        marked.release = ReleaseVerdict::Repeat;
+        send_release(&mut sink, marked, None, &mut stats);
+        send_release(
+            &mut sink,
+            PacketContext::new(Stamped::default(), 0u64, 2, 0, 0, 100),
+            None,
+            &mut stats,
+        );
+
+        let stamps: Vec<_> = sink.accepted.iter().map(|m| (m.verdict, m.mark)).collect();
+        assert_eq!(
+            stamps,
+            [
+                (Some(Verdict::Repeat), Some(0x42)),
+                (Some(Verdict::Accept), None)
+            ]
+        );
+        assert_eq!(stats.sent, 2);
+    }
+}